@@ -0,0 +1,81 @@
+//! Time of reads through a [`MappedMemory`] with a few and with many devices mapped, run with
+//! `cargo bench --bench memory`.
+//!
+//! Accesses look up their region in a page table, so reads of RAM and ROM take as long with 64 devices as with 4.
+//! A linear scan of the regions got slower with every device mapped over the memory. RAM and ROM mapped with
+//! `add_ram` and `add_rom` are read directly, the same memory mapped with `add_memory` goes through [`Memory`].
+//! Updates only reach the regions that are due, memory and these devices never are, so they take no longer with
+//! 64 devices either.
+
+use cody_emulator::memory::Memory;
+use cody_emulator::memory::contiguous::Contiguous;
+use cody_emulator::memory::mapped::{MappedMemory, PRIORITY_DEVICE, PRIORITY_MEMORY};
+use std::cell::RefCell;
+use std::hint::black_box;
+use std::rc::Rc;
+use std::time::Instant;
+
+const READS: u32 = 20_000_000;
+
+/// The Cody's RAM, propeller RAM and ROM with `devices` devices of 16 registers from 0xD000
+fn memory(devices: u16, plain: bool) -> MappedMemory {
+    let mut memory = MappedMemory::new();
+    let ram = Rc::new(RefCell::new(Contiguous::new_ram(0xA000)));
+    let propeller_ram = Rc::new(RefCell::new(Contiguous::new_ram(0x4000)));
+    let rom = Rc::new(RefCell::new(Contiguous::new_rom(0x2000)));
+    if plain {
+        memory.add_ram(0x0000, 0xA000, PRIORITY_MEMORY, ram);
+        memory.add_ram(0xA000, 0x4000, PRIORITY_MEMORY, propeller_ram);
+        memory.add_rom(0xE000, 0x2000, PRIORITY_MEMORY, rom);
+    } else {
+        memory.add_memory(0x0000, 0xA000, PRIORITY_MEMORY, ram);
+        memory.add_memory(0xA000, 0x4000, PRIORITY_MEMORY, propeller_ram);
+        memory.add_memory(0xE000, 0x2000, PRIORITY_MEMORY, rom);
+    }
+    for device in 0..devices {
+        memory.add_memory(
+            0xD000 + device * 0x10,
+            0x10,
+            PRIORITY_DEVICE,
+            Contiguous::new_ram(0x10),
+        );
+    }
+    memory
+}
+
+fn bench(name: &str, memory: &mut MappedMemory, address: impl Fn(u32) -> u16) {
+    let start = Instant::now();
+    let mut sum = 0u8;
+    for i in 0..READS {
+        sum = sum.wrapping_add(memory.read_u8(black_box(address(i))));
+    }
+    black_box(sum);
+    let nanos = start.elapsed().as_nanos() as f64 / READS as f64;
+    println!("{name:<36} {nanos:>6.2} ns/read");
+}
+
+fn bench_updates(name: &str, memory: &mut MappedMemory) {
+    let start = Instant::now();
+    for i in 0..READS {
+        // instructions of 4 cycles
+        black_box(memory.update(black_box(4 * i as usize)));
+    }
+    let nanos = start.elapsed().as_nanos() as f64 / READS as f64;
+    println!("{name:<36} {nanos:>6.2} ns/update");
+}
+
+fn main() {
+    for (plain, mapping) in [(false, "add_memory"), (true, "add_ram")] {
+        for devices in [4, 64] {
+            let mut memory = memory(devices, plain);
+            let registers = devices as u32 * 0x10;
+            let name = |what: &str| format!("{what}, {mapping}, {devices} devices");
+            bench(&name("zero page"), &mut memory, |i| (i & 0xFF) as u16);
+            bench(&name("ROM"), &mut memory, |i| 0xE000 + (i & 0x1FFF) as u16);
+            bench(&name("devices"), &mut memory, |i| {
+                0xD000 + (i % registers) as u16
+            });
+            bench_updates(&name("updates"), &mut memory);
+        }
+    }
+}
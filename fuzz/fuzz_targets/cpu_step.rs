@@ -0,0 +1,91 @@
+//! Execute one instruction from a random cpu state and compare the registers, memory and cycles
+//! of both engines with each other and with the reference model.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use cody_emulator::cpu::{Cpu, Engine, Status};
+use cody_emulator::memory::contiguous::Contiguous;
+use cody_emulator_fuzz::{ALWAYS_SET, State};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    /// opcode and operand bytes at the program counter
+    instruction: [u8; 3],
+    /// written before the instruction, e.g. zero page pointers and the values they point to
+    memory: Vec<(u16, u8)>,
+}
+
+impl Input {
+    fn memory(&self) -> Box<[u8]> {
+        let mut memory = vec![0; 0x10000].into_boxed_slice();
+        for &(address, value) in &self.memory {
+            memory[address as usize] = value;
+        }
+        for (offset, &byte) in (0..).zip(&self.instruction) {
+            memory[self.pc.wrapping_add(offset) as usize] = byte;
+        }
+        memory
+    }
+
+    fn step(&self, engine: Engine) -> (Cpu<Contiguous>, u8) {
+        let mut ram = Contiguous::new_ram(0x10000);
+        ram.memory = self.memory();
+        let mut cpu = Cpu::new(ram);
+        cpu.set_engine(engine);
+        cpu.pc = self.pc;
+        cpu.s = self.s;
+        cpu.a = self.a;
+        cpu.x = self.x;
+        cpu.y = self.y;
+        cpu.p = Status::from_bits(self.p | ALWAYS_SET);
+        let cycles = cpu.step_instruction();
+        (cpu, cycles)
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let (cpu, cycles) = input.step(Engine::Interpreter);
+    let (cached, cached_cycles) = input.step(Engine::Cached);
+    assert_eq!(
+        cpu.state(),
+        cached.state(),
+        "registers of the cached engine"
+    );
+    assert_eq!(cycles, cached_cycles, "cycles of the cached engine");
+    assert!(
+        cpu.memory.memory == cached.memory.memory,
+        "memory of the cached engine"
+    );
+
+    let mut reference = State::new(input.memory());
+    reference.pc = input.pc;
+    reference.s = input.s;
+    reference.a = input.a;
+    reference.x = input.x;
+    reference.y = input.y;
+    reference.p = input.p | ALWAYS_SET;
+    let Some(reference_cycles) = reference.step() else {
+        return;
+    };
+    assert_eq!(cycles, reference_cycles, "cycles");
+    assert_eq!(cpu.pc, reference.pc, "pc");
+    assert_eq!(cpu.s, reference.s, "s");
+    assert_eq!(cpu.a, reference.a, "a");
+    assert_eq!(cpu.x, reference.x, "x");
+    assert_eq!(cpu.y, reference.y, "y");
+    assert_eq!(cpu.p, Status::from_bits(reference.p), "p");
+    if let Some(address) = (0..=0xFFFF).find(|&i| cpu.memory.memory[i] != reference.memory[i]) {
+        panic!(
+            "mem[0x{address:04X}]: expected={}, actual={}",
+            reference.memory[address], cpu.memory.memory[address]
+        );
+    }
+});
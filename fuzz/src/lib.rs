@@ -0,0 +1,515 @@
+//! Reference model of the 65C02 the fuzz targets compare the emulated cpu against.
+//!
+//! It is written straight from the data sheet and kept simple instead of fast. Instructions it
+//! does not model (interrupts, BBR/BBS, PLP/RTI, WAI/STP and decimal mode ADC/SBC) are left to
+//! the curated tests and the single step tests.
+
+use cody_emulator::opcode::{AddressingMode, Opcode, get_instruction};
+
+pub const CARRY: u8 = 0x01;
+pub const ZERO: u8 = 0x02;
+pub const IRQB_DISABLE: u8 = 0x04;
+pub const DECIMAL: u8 = 0x08;
+/// the break and unused bits always read as set
+pub const ALWAYS_SET: u8 = 0x30;
+pub const OVERFLOW: u8 = 0x40;
+pub const NEGATIVE: u8 = 0x80;
+
+/// Registers and the whole address space as plain RAM
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct State {
+    pub pc: u16,
+    pub s: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub memory: Box<[u8]>,
+}
+
+impl State {
+    pub fn new(memory: Box<[u8]>) -> Self {
+        assert_eq!(memory.len(), 0x10000);
+        Self {
+            pc: 0,
+            s: 0,
+            a: 0,
+            x: 0,
+            y: 0,
+            p: ALWAYS_SET,
+            memory,
+        }
+    }
+
+    fn read(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn read_u16(&self, address: u16) -> u16 {
+        u16::from_le_bytes([self.read(address), self.read(address.wrapping_add(1))])
+    }
+
+    /// Pointers in the zero page wrap around within it
+    fn read_u16_zp(&self, address: u8) -> u16 {
+        u16::from_le_bytes([
+            self.read(address as u16),
+            self.read(address.wrapping_add(1) as u16),
+        ])
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(0x0100 | self.s as u16, value);
+        self.s = self.s.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.s = self.s.wrapping_add(1);
+        self.read(0x0100 | self.s as u16)
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.p & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u8, value: bool) {
+        if value {
+            self.p |= flag;
+        } else {
+            self.p &= !flag;
+        }
+    }
+
+    fn set_nz(&mut self, value: u8) {
+        self.set_flag(ZERO, value == 0);
+        self.set_flag(NEGATIVE, value & 0x80 != 0);
+    }
+
+    /// Effective address of a memory operand and whether indexing crossed a page
+    fn address(&self, mode: AddressingMode, operand: u16) -> (u16, bool) {
+        let indexed = |base: u16, index: u8| {
+            let address = base.wrapping_add(index as u16);
+            (address, (base ^ address) & 0xFF00 != 0)
+        };
+        match mode {
+            AddressingMode::ZeroPage | AddressingMode::Absolute => (operand, false),
+            AddressingMode::ZeroPageIndexedX => {
+                ((operand as u8).wrapping_add(self.x) as u16, false)
+            }
+            AddressingMode::ZeroPageIndexedY => {
+                ((operand as u8).wrapping_add(self.y) as u16, false)
+            }
+            AddressingMode::AbsoluteIndexedX => indexed(operand, self.x),
+            AddressingMode::AbsoluteIndexedY => indexed(operand, self.y),
+            AddressingMode::AbsoluteIndirect => (self.read_u16(operand), false),
+            AddressingMode::AbsoluteIndexedIndirectX => {
+                (self.read_u16(operand.wrapping_add(self.x as u16)), false)
+            }
+            AddressingMode::ZeroPageIndirect => (self.read_u16_zp(operand as u8), false),
+            AddressingMode::ZeroPageIndexedIndirectX => (
+                self.read_u16_zp((operand as u8).wrapping_add(self.x)),
+                false,
+            ),
+            AddressingMode::ZeroPageIndirectIndexedY => {
+                indexed(self.read_u16_zp(operand as u8), self.y)
+            }
+            AddressingMode::None
+            | AddressingMode::Accumulator
+            | AddressingMode::Immediate
+            | AddressingMode::ProgramCounterRelative => {
+                unreachable!("{mode:?} has no effective address")
+            }
+        }
+    }
+
+    /// Value of an operand and whether indexing crossed a page
+    fn value(&self, mode: AddressingMode, operand: u16) -> (u8, bool) {
+        match mode {
+            AddressingMode::Immediate => (operand as u8, false),
+            AddressingMode::Accumulator => (self.a, false),
+            _ => {
+                let (address, page_cross) = self.address(mode, operand);
+                (self.read(address), page_cross)
+            }
+        }
+    }
+
+    /// Replace an operand with `f` of its value, returns the cycles
+    fn modify(
+        &mut self,
+        mode: AddressingMode,
+        operand: u16,
+        f: impl FnOnce(&mut Self, u8) -> u8,
+    ) -> u8 {
+        if mode == AddressingMode::Accumulator {
+            let a = self.a;
+            self.a = f(self, a);
+            return 2;
+        }
+        let (address, page_cross) = self.address(mode, operand);
+        let m = self.read(address);
+        let value = f(self, m);
+        self.write(address, value);
+        modify_cycles(mode, page_cross)
+    }
+
+    fn add(&mut self, m: u8) {
+        let sum = self.a as u16 + m as u16 + self.flag(CARRY) as u16;
+        let result = sum as u8;
+        self.set_flag(CARRY, sum > 0xFF);
+        self.set_flag(OVERFLOW, (self.a ^ result) & (m ^ result) & 0x80 != 0);
+        self.a = result;
+        self.set_nz(result);
+    }
+
+    fn compare(&mut self, register: u8, m: u8) {
+        self.set_flag(CARRY, register >= m);
+        self.set_nz(register.wrapping_sub(m));
+    }
+
+    fn branch(&mut self, condition: bool, operand: u16) -> u8 {
+        if !condition {
+            return 2;
+        }
+        let target = self.pc.wrapping_add(operand as u8 as i8 as u16);
+        let page_cross = (self.pc ^ target) & 0xFF00 != 0;
+        self.pc = target;
+        3 + page_cross as u8
+    }
+
+    /// Execute the instruction at the program counter, returns its cycles or `None` if it is not
+    /// modeled, the state is unchanged then
+    pub fn step(&mut self) -> Option<u8> {
+        let meta = get_instruction(self.read(self.pc))?;
+        let mode = meta.parameter_1;
+        if meta.parameter_2 != AddressingMode::None {
+            return None;
+        }
+        let operand = match mode.width() {
+            0 => 0,
+            1 => self.read(self.pc.wrapping_add(1)) as u16,
+            _ => self.read_u16(self.pc.wrapping_add(1)),
+        };
+        if matches!(meta.opcode, Opcode::ADC | Opcode::SBC) && self.flag(DECIMAL) {
+            return None;
+        }
+        if matches!(
+            meta.opcode,
+            Opcode::BRK | Opcode::RTI | Opcode::PLP | Opcode::WAI | Opcode::STP
+        ) {
+            return None;
+        }
+        let bit = (meta.byte >> 4) & 0x07;
+        self.pc = self.pc.wrapping_add(meta.width());
+
+        let cycles = match meta.opcode {
+            Opcode::LDA | Opcode::LDX | Opcode::LDY => {
+                let (m, page_cross) = self.value(mode, operand);
+                match meta.opcode {
+                    Opcode::LDA => self.a = m,
+                    Opcode::LDX => self.x = m,
+                    _ => self.y = m,
+                }
+                self.set_nz(m);
+                read_cycles(mode, page_cross)
+            }
+            Opcode::STA | Opcode::STX | Opcode::STY | Opcode::STZ => {
+                let value = match meta.opcode {
+                    Opcode::STA => self.a,
+                    Opcode::STX => self.x,
+                    Opcode::STY => self.y,
+                    _ => 0,
+                };
+                let (address, _) = self.address(mode, operand);
+                self.write(address, value);
+                store_cycles(mode)
+            }
+            Opcode::ADC | Opcode::SBC => {
+                let (m, page_cross) = self.value(mode, operand);
+                self.add(if meta.opcode == Opcode::SBC { !m } else { m });
+                read_cycles(mode, page_cross)
+            }
+            Opcode::AND | Opcode::ORA | Opcode::EOR => {
+                let (m, page_cross) = self.value(mode, operand);
+                self.a = match meta.opcode {
+                    Opcode::AND => self.a & m,
+                    Opcode::ORA => self.a | m,
+                    _ => self.a ^ m,
+                };
+                self.set_nz(self.a);
+                read_cycles(mode, page_cross)
+            }
+            Opcode::CMP | Opcode::CPX | Opcode::CPY => {
+                let (m, page_cross) = self.value(mode, operand);
+                let register = match meta.opcode {
+                    Opcode::CMP => self.a,
+                    Opcode::CPX => self.x,
+                    _ => self.y,
+                };
+                self.compare(register, m);
+                read_cycles(mode, page_cross)
+            }
+            Opcode::BIT => {
+                let (m, page_cross) = self.value(mode, operand);
+                self.set_flag(ZERO, self.a & m == 0);
+                if mode != AddressingMode::Immediate {
+                    self.set_flag(NEGATIVE, m & 0x80 != 0);
+                    self.set_flag(OVERFLOW, m & 0x40 != 0);
+                }
+                read_cycles(mode, page_cross)
+            }
+            Opcode::ASL => self.modify(mode, operand, |cpu, m| {
+                cpu.set_flag(CARRY, m & 0x80 != 0);
+                cpu.set_nz(m << 1);
+                m << 1
+            }),
+            Opcode::LSR => self.modify(mode, operand, |cpu, m| {
+                cpu.set_flag(CARRY, m & 0x01 != 0);
+                cpu.set_nz(m >> 1);
+                m >> 1
+            }),
+            Opcode::ROL => self.modify(mode, operand, |cpu, m| {
+                let value = (m << 1) | cpu.flag(CARRY) as u8;
+                cpu.set_flag(CARRY, m & 0x80 != 0);
+                cpu.set_nz(value);
+                value
+            }),
+            Opcode::ROR => self.modify(mode, operand, |cpu, m| {
+                let value = (m >> 1) | ((cpu.flag(CARRY) as u8) << 7);
+                cpu.set_flag(CARRY, m & 0x01 != 0);
+                cpu.set_nz(value);
+                value
+            }),
+            Opcode::INC | Opcode::DEC => {
+                let delta = if meta.opcode == Opcode::INC { 1 } else { 0xFF };
+                let cycles = self.modify(mode, operand, |cpu, m| {
+                    cpu.set_nz(m.wrapping_add(delta));
+                    m.wrapping_add(delta)
+                });
+                // unlike the shifts they never skip the extra cycle of the indexed mode
+                if mode == AddressingMode::AbsoluteIndexedX {
+                    7
+                } else {
+                    cycles
+                }
+            }
+            Opcode::TSB | Opcode::TRB => self.modify(mode, operand, |cpu, m| {
+                cpu.set_flag(ZERO, cpu.a & m == 0);
+                if meta.opcode == Opcode::TSB {
+                    m | cpu.a
+                } else {
+                    m & !cpu.a
+                }
+            }),
+            Opcode::RMB0
+            | Opcode::RMB1
+            | Opcode::RMB2
+            | Opcode::RMB3
+            | Opcode::RMB4
+            | Opcode::RMB5
+            | Opcode::RMB6
+            | Opcode::RMB7 => self.modify(mode, operand, |_, m| m & !(1 << bit)),
+            Opcode::SMB0
+            | Opcode::SMB1
+            | Opcode::SMB2
+            | Opcode::SMB3
+            | Opcode::SMB4
+            | Opcode::SMB5
+            | Opcode::SMB6
+            | Opcode::SMB7 => self.modify(mode, operand, |_, m| m | (1 << bit)),
+            Opcode::TAX | Opcode::TAY | Opcode::TXA | Opcode::TYA | Opcode::TSX => {
+                let value = match meta.opcode {
+                    Opcode::TAX | Opcode::TAY => self.a,
+                    Opcode::TXA => self.x,
+                    Opcode::TYA => self.y,
+                    _ => self.s,
+                };
+                match meta.opcode {
+                    Opcode::TAX | Opcode::TSX => self.x = value,
+                    Opcode::TAY => self.y = value,
+                    _ => self.a = value,
+                }
+                self.set_nz(value);
+                2
+            }
+            Opcode::TXS => {
+                self.s = self.x;
+                2
+            }
+            Opcode::INX | Opcode::DEX => {
+                let delta = if meta.opcode == Opcode::INX { 1 } else { 0xFF };
+                self.x = self.x.wrapping_add(delta);
+                self.set_nz(self.x);
+                2
+            }
+            Opcode::INY | Opcode::DEY => {
+                let delta = if meta.opcode == Opcode::INY { 1 } else { 0xFF };
+                self.y = self.y.wrapping_add(delta);
+                self.set_nz(self.y);
+                2
+            }
+            Opcode::CLC | Opcode::SEC => {
+                self.set_flag(CARRY, meta.opcode == Opcode::SEC);
+                2
+            }
+            Opcode::CLI | Opcode::SEI => {
+                self.set_flag(IRQB_DISABLE, meta.opcode == Opcode::SEI);
+                2
+            }
+            Opcode::CLD | Opcode::SED => {
+                self.set_flag(DECIMAL, meta.opcode == Opcode::SED);
+                2
+            }
+            Opcode::CLV => {
+                self.set_flag(OVERFLOW, false);
+                2
+            }
+            Opcode::NOP => 2,
+            Opcode::BCC => self.branch(!self.flag(CARRY), operand),
+            Opcode::BCS => self.branch(self.flag(CARRY), operand),
+            Opcode::BNE => self.branch(!self.flag(ZERO), operand),
+            Opcode::BEQ => self.branch(self.flag(ZERO), operand),
+            Opcode::BPL => self.branch(!self.flag(NEGATIVE), operand),
+            Opcode::BMI => self.branch(self.flag(NEGATIVE), operand),
+            Opcode::BVC => self.branch(!self.flag(OVERFLOW), operand),
+            Opcode::BVS => self.branch(self.flag(OVERFLOW), operand),
+            Opcode::BRA => self.branch(true, operand),
+            Opcode::JMP => {
+                self.pc = self.address(mode, operand).0;
+                if mode == AddressingMode::Absolute {
+                    3
+                } else {
+                    6
+                }
+            }
+            Opcode::JSR => {
+                let [low, high] = self.pc.wrapping_sub(1).to_le_bytes();
+                self.push(high);
+                self.push(low);
+                self.pc = operand;
+                6
+            }
+            Opcode::RTS => {
+                let low = self.pull();
+                let high = self.pull();
+                self.pc = u16::from_le_bytes([low, high]).wrapping_add(1);
+                6
+            }
+            Opcode::PHA | Opcode::PHX | Opcode::PHY | Opcode::PHP => {
+                let value = match meta.opcode {
+                    Opcode::PHA => self.a,
+                    Opcode::PHX => self.x,
+                    Opcode::PHY => self.y,
+                    _ => self.p | ALWAYS_SET,
+                };
+                self.push(value);
+                3
+            }
+            Opcode::PLA | Opcode::PLX | Opcode::PLY => {
+                let value = self.pull();
+                match meta.opcode {
+                    Opcode::PLA => self.a = value,
+                    Opcode::PLX => self.x = value,
+                    _ => self.y = value,
+                }
+                self.set_nz(value);
+                4
+            }
+            _ => unreachable!("{:?} is filtered out above", meta.opcode),
+        };
+        Some(cycles)
+    }
+}
+
+/// Cycles of instructions that only read their operand, crossing a page while indexing costs one more
+fn read_cycles(mode: AddressingMode, page_cross: bool) -> u8 {
+    let cycles = match mode {
+        AddressingMode::Immediate => 2,
+        AddressingMode::ZeroPage => 3,
+        AddressingMode::ZeroPageIndexedX
+        | AddressingMode::ZeroPageIndexedY
+        | AddressingMode::Absolute
+        | AddressingMode::AbsoluteIndexedX
+        | AddressingMode::AbsoluteIndexedY => 4,
+        AddressingMode::ZeroPageIndirect | AddressingMode::ZeroPageIndirectIndexedY => 5,
+        AddressingMode::ZeroPageIndexedIndirectX => 6,
+        _ => unreachable!("{mode:?} is not a read operand"),
+    };
+    cycles + page_cross as u8
+}
+
+/// Cycles of stores, indexed stores always take the extra cycle
+fn store_cycles(mode: AddressingMode) -> u8 {
+    match mode {
+        AddressingMode::ZeroPage => 3,
+        AddressingMode::ZeroPageIndexedX
+        | AddressingMode::ZeroPageIndexedY
+        | AddressingMode::Absolute => 4,
+        AddressingMode::AbsoluteIndexedX
+        | AddressingMode::AbsoluteIndexedY
+        | AddressingMode::ZeroPageIndirect => 5,
+        AddressingMode::ZeroPageIndexedIndirectX | AddressingMode::ZeroPageIndirectIndexedY => 6,
+        _ => unreachable!("{mode:?} is not a store operand"),
+    }
+}
+
+/// Cycles of read-modify-write instructions on memory
+fn modify_cycles(mode: AddressingMode, page_cross: bool) -> u8 {
+    match mode {
+        AddressingMode::ZeroPage => 5,
+        AddressingMode::ZeroPageIndexedX | AddressingMode::Absolute => 6,
+        AddressingMode::AbsoluteIndexedX => 6 + page_cross as u8,
+        _ => unreachable!("{mode:?} is not a read-modify-write operand"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(program: &[u8]) -> State {
+        let mut state = State::new(vec![0; 0x10000].into_boxed_slice());
+        state.pc = 0x0200;
+        state.s = 0xFF;
+        state.memory[0x0200..0x0200 + program.len()].copy_from_slice(program);
+        state
+    }
+
+    #[test]
+    fn test_page_cross() {
+        // LDA $12F0,X
+        let mut state = state(&[0xBD, 0xF0, 0x12]);
+        state.x = 0x20;
+        state.memory[0x1310] = 0x80;
+        assert_eq!(state.step(), Some(5));
+        assert_eq!(state.a, 0x80);
+        assert_eq!(state.p, ALWAYS_SET | NEGATIVE);
+        assert_eq!(state.pc, 0x0203);
+    }
+
+    #[test]
+    fn test_jsr_and_rts() {
+        // JSR $0300
+        let mut state = state(&[0x20, 0x00, 0x03]);
+        // RTS
+        state.memory[0x0300] = 0x60;
+        assert_eq!(state.step(), Some(6));
+        assert_eq!((state.pc, state.s), (0x0300, 0xFD));
+        assert_eq!(state.memory[0x01FE..0x0200], [0x02, 0x02]);
+        assert_eq!(state.step(), Some(6));
+        assert_eq!((state.pc, state.s), (0x0203, 0xFF));
+    }
+
+    #[test]
+    fn test_unmodeled() {
+        // SED, ADC #$01
+        let mut state = state(&[0xF8, 0x69, 0x01]);
+        assert_eq!(state.step(), Some(2));
+        let before = state.clone();
+        assert_eq!(state.step(), None);
+        assert_eq!(state, before);
+    }
+}
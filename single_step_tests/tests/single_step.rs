@@ -0,0 +1,308 @@
+use anyhow::{Context, anyhow};
+use clap::Parser;
+use cody_emulator::cpu::{Cpu, Status};
+use cody_emulator::memory::Memory;
+use cody_emulator::memory::contiguous::Contiguous;
+use cody_emulator::memory::logging::{LoggingMemory, MemoryAccess, MemoryAccessType};
+use cody_emulator::opcode::{AddressingMode, OPCODES, Opcode, get_instruction};
+use rayon::prelude::*;
+use single_step_tests::{CycleOp, TestCase};
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::io::BufReader;
+use std::panic::catch_unwind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{fs, io, panic};
+
+/// Instructions whose bus accesses are compared cycle by cycle, all others only have to match in their results
+const CHECK_MEMORY_ACCESSES: &[Opcode] = &[
+    Opcode::ASL,
+    Opcode::DEC,
+    Opcode::INC,
+    Opcode::LSR,
+    Opcode::ROL,
+    Opcode::ROR,
+    Opcode::TRB,
+    Opcode::TSB,
+    Opcode::RMB0,
+    Opcode::RMB1,
+    Opcode::RMB2,
+    Opcode::RMB3,
+    Opcode::RMB4,
+    Opcode::RMB5,
+    Opcode::RMB6,
+    Opcode::RMB7,
+    Opcode::SMB0,
+    Opcode::SMB1,
+    Opcode::SMB2,
+    Opcode::SMB3,
+    Opcode::SMB4,
+    Opcode::SMB5,
+    Opcode::SMB6,
+    Opcode::SMB7,
+];
+
+/// Whether the bus accesses of the instruction at the start of `test_case` are checked
+fn check_memory_accesses(test_case: &TestCase) -> bool {
+    test_case
+        .initial
+        .ram
+        .iter()
+        .find(|ram_value| ram_value.address() == test_case.initial.pc)
+        .and_then(|ram_value| get_instruction(ram_value.value()))
+        .is_some_and(|meta| {
+            CHECK_MEMORY_ACCESSES.contains(&meta.opcode)
+                && meta.parameter_1 != AddressingMode::Accumulator
+        })
+}
+
+/// Where missing test definitions are downloaded from, one file per opcode
+const CORPUS_URL: &str = "https://raw.githubusercontent.com/SingleStepTests/65x02/main/wdc65c02/v1";
+
+/// Failing test cases that are printed per opcode, the rest are only counted
+const REPORTED_FAILURES: usize = 5;
+
+#[derive(Parser, Debug)]
+#[command(about = "Run the 65x02 SingleStepTests against the emulated cpu")]
+// cargo test passes libtest arguments like --nocapture to every test binary
+#[command(ignore_errors = true)]
+struct Cli {
+    /// Only run the tests of these opcodes, all documented opcodes by default
+    #[arg(long, value_parser = clap_num::maybe_hex::<u8>)]
+    opcode: Vec<u8>,
+    /// Directory with one json file per opcode
+    #[arg(long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/65x02/wdc65c02/v1"))]
+    corpus: PathBuf,
+    /// Download missing test definitions into the corpus directory
+    #[arg(long)]
+    download: bool,
+}
+
+/// Outcome of all test cases of one opcode
+struct OpcodeResult {
+    byte: u8,
+    passed: usize,
+    failures: Vec<(String, String)>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    // only documented opcodes
+    let opcodes: Vec<u8> = OPCODES
+        .iter()
+        .map(|opc| opc.byte)
+        .filter(|byte| cli.opcode.is_empty() || cli.opcode.contains(byte))
+        .collect();
+    if let Some(byte) = cli.opcode.iter().find(|byte| !opcodes.contains(byte)) {
+        return Err(anyhow!("0x{byte:02X} is not a documented opcode"));
+    }
+
+    if cli.download {
+        fs::create_dir_all(&cli.corpus).context(cli.corpus.display().to_string())?;
+        for &byte in &opcodes {
+            download(&cli.corpus, byte)?;
+        }
+    } else if !cli.corpus.is_dir() {
+        println!(
+            "Skipping single step tests, {} does not exist, run with --download to fetch them",
+            cli.corpus.display()
+        );
+        return Ok(());
+    }
+
+    // failures are reported with their message, the default hook would print every panic
+    panic::set_hook(Box::new(|_| {}));
+    let done = AtomicUsize::new(0);
+    let results = opcodes
+        .par_iter()
+        .map(|&byte| {
+            let path = cli.corpus.join(format!("{byte:02x}.json"));
+            let result = run_opcode(byte, collect_test_cases(path)?);
+            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+            println!(
+                "[{done:>3}/{}] 0x{byte:02X} {}: {} passed, {} failed",
+                opcodes.len(),
+                opcode_name(byte),
+                result.passed,
+                result.failures.len()
+            );
+            Ok(result)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let _ = panic::take_hook();
+
+    let mut failed = 0;
+    for result in results.iter().filter(|result| !result.failures.is_empty()) {
+        failed += result.failures.len();
+        println!();
+        println!(
+            "0x{:02X} {}: {} of {} failed",
+            result.byte,
+            opcode_name(result.byte),
+            result.failures.len(),
+            result.passed + result.failures.len()
+        );
+        for (name, message) in result.failures.iter().take(REPORTED_FAILURES) {
+            println!("    {name}: {message}");
+        }
+    }
+    let passed: usize = results.iter().map(|result| result.passed).sum();
+    println!();
+    println!("{passed} passed, {failed} failed");
+
+    if failed > 0 {
+        Err(anyhow!("{failed} test cases failed"))
+    } else {
+        Ok(())
+    }
+}
+
+fn opcode_name(byte: u8) -> String {
+    get_instruction(byte).map_or_else(String::new, |meta| {
+        format!("{:?} {:?}", meta.opcode, meta.parameter_1)
+    })
+}
+
+/// Fetch the test definitions of `byte` unless they are already cached in `corpus`
+fn download(corpus: &Path, byte: u8) -> anyhow::Result<()> {
+    let path = corpus.join(format!("{byte:02x}.json"));
+    if path.is_file() {
+        return Ok(());
+    }
+
+    let url = format!("{CORPUS_URL}/{byte:02x}.json");
+    println!("Downloading {url}");
+    let mut response = ureq::get(&url).call().context(url.clone())?;
+    // write to a temporary file first so an interrupted download is not mistaken for a cached one
+    let partial = path.with_extension("json.part");
+    let mut file = File::create(&partial).context(partial.display().to_string())?;
+    io::copy(&mut response.body_mut().as_reader(), &mut file).context(url)?;
+    fs::rename(&partial, &path).context(path.display().to_string())
+}
+
+fn run_opcode(byte: u8, test_cases: Vec<TestCase>) -> OpcodeResult {
+    let failures: Vec<_> = test_cases
+        .par_iter()
+        .filter_map(|test_case| {
+            let result = catch_unwind(|| execute_test_case(test_case)).unwrap_or_else(|payload| {
+                let message = payload
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+                    .unwrap_or_default();
+                Err(format!("panicked: {message}"))
+            });
+            result
+                .err()
+                .map(|message| (test_case.name.clone(), message))
+        })
+        .collect();
+    OpcodeResult {
+        byte,
+        passed: test_cases.len() - failures.len(),
+        failures,
+    }
+}
+
+/// Compare one part of the final state
+fn check<T: PartialEq + Debug>(what: impl Display, expected: T, actual: T) -> Result<(), String> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(format!("{what}: expected={expected:?}, actual={actual:?}"))
+    }
+}
+
+fn collect_test_cases(path: impl AsRef<Path>) -> anyhow::Result<Vec<TestCase>> {
+    let path = path.as_ref();
+    if path.is_dir() {
+        collect_test_cases_from_dir(path)
+    } else {
+        collect_test_cases_from_file(path)
+    }
+}
+
+fn collect_test_cases_from_dir(path: impl AsRef<Path>) -> anyhow::Result<Vec<TestCase>> {
+    let path = path.as_ref();
+    let ctx = path.display().to_string();
+
+    let mut test_cases = vec![];
+    for e in fs::read_dir(path).context(ctx.clone())? {
+        let e = e.context(ctx.clone())?;
+        let path = e.path();
+        let metadata = fs::metadata(&path).context(ctx.clone())?;
+        if metadata.is_file() && path.extension().is_some_and(|ext| ext == "json") {
+            test_cases.extend(collect_test_cases_from_file(path)?);
+        }
+    }
+
+    Ok(test_cases)
+}
+
+fn collect_test_cases_from_file(path: impl AsRef<Path>) -> anyhow::Result<Vec<TestCase>> {
+    let path = path.as_ref();
+    let ctx = path.display().to_string();
+
+    if fs::metadata(path).context(ctx.clone())?.len() == 0 {
+        return Ok(vec![]);
+    }
+
+    let file = File::open(path).context(ctx.clone())?;
+    serde_json::from_reader(BufReader::new(file)).context(ctx)
+}
+
+fn execute_test_case(test_case: &TestCase) -> Result<(), String> {
+    let memory = LoggingMemory::new(Contiguous::new_ram(0x10000));
+    let mut cpu = Cpu::new(memory);
+    cpu.pc = test_case.initial.pc;
+    cpu.s = test_case.initial.s;
+    cpu.a = test_case.initial.a;
+    cpu.x = test_case.initial.x;
+    cpu.y = test_case.initial.y;
+    cpu.p = Status::from_bits(test_case.initial.p);
+
+    for ram_value in &test_case.initial.ram {
+        cpu.memory.write_u8(ram_value.address(), ram_value.value());
+    }
+
+    cpu.memory.reset_log();
+    let cycles = cpu.step_instruction();
+
+    check("cycles", test_case.cycles.len(), cycles as usize)?;
+    if check_memory_accesses(test_case) {
+        check(
+            "memory accesses",
+            test_case.cycles.len(),
+            cpu.memory.log().len(),
+        )?;
+        for (idx, (cycle, &memory_access)) in
+            test_case.cycles.iter().zip(cpu.memory.log()).enumerate()
+        {
+            let expected = MemoryAccess {
+                access_type: match cycle.op() {
+                    CycleOp::Read => MemoryAccessType::Read,
+                    CycleOp::Write => MemoryAccessType::Write,
+                },
+                address: cycle.address(),
+                value: cycle.value(),
+            };
+            check(format!("cycle[{}]", idx + 1), expected, memory_access)?;
+        }
+    }
+    check("pc", test_case.r#final.pc, cpu.pc)?;
+    check("s", test_case.r#final.s, cpu.s)?;
+    check("a", test_case.r#final.a, cpu.a)?;
+    check("x", test_case.r#final.x, cpu.x)?;
+    check("y", test_case.r#final.y, cpu.y)?;
+    check("p", Status::from_bits(test_case.r#final.p), cpu.p)?;
+    for ram_value in &test_case.r#final.ram {
+        check(
+            format!("mem[0x{:04X}]", ram_value.address()),
+            ram_value.value(),
+            cpu.memory.read_u8(ram_value.address()),
+        )?;
+    }
+    Ok(())
+}
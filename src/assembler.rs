@@ -1,8 +1,12 @@
-use crate::opcode::{AddressingMode, InstructionMeta, Opcode, get_instructions};
+use crate::cpu::{IRQ_VECTOR, NMI_VECTOR, RESET_VECTOR};
+use crate::opcode::{
+    AddressingMode, InstructionMeta, OPCODES, Opcode, get_instruction, get_instructions,
+};
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{Display, Formatter};
-use std::io::{Read, Write};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
 use strum::Display;
 use thiserror::Error;
 
@@ -22,10 +26,95 @@ pub enum AssemblerError {
     ParameterMismatch(String),
     #[error("jump too far")]
     JumpTooFar,
+    #[error("{location}: {message}")]
+    Syntax {
+        location: SourceLocation,
+        message: String,
+    },
+    /// error while assembling the instruction at `index` of the program
+    #[error("{}: {error}", instruction_position(*index, *location))]
+    Instruction {
+        index: usize,
+        location: Option<SourceLocation>,
+        error: Box<AssemblerError>,
+    },
     #[error("io error: {0}")]
     IO(#[from] std::io::Error),
 }
 
+impl AssemblerError {
+    /// Where in the source text the error is, if it came from [`parse`]d text
+    pub fn location(&self) -> Option<SourceLocation> {
+        match self {
+            Self::Syntax { location, .. } => Some(*location),
+            Self::Instruction { location, .. } => *location,
+            _ => None,
+        }
+    }
+
+    /// Multi-line diagnostic that quotes and underlines the located part of `text`, the source of `name`
+    pub fn render(&self, name: &str, text: &str) -> String {
+        let Some(location) = self.location() else {
+            return format!("error: {self}");
+        };
+        let message = match self {
+            Self::Syntax { message, .. } => message.clone(),
+            Self::Instruction { error, .. } => error.to_string(),
+            _ => self.to_string(),
+        };
+        let source = text.lines().nth(location.line - 1).unwrap_or_default();
+        let number = location.line.to_string();
+        let gutter = " ".repeat(number.len());
+        // keep tabs so the underline lines up with the quoted source
+        let indent: String = source
+            .chars()
+            .take(location.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underline = "^".repeat(location.len.max(1));
+        format!(
+            "error: {message}\n{gutter}--> {name}:{}:{}\n{gutter} |\n{number} | {source}\n{gutter} | {indent}{underline}",
+            location.line, location.column
+        )
+    }
+}
+
+fn instruction_position(index: usize, location: Option<SourceLocation>) -> String {
+    location.map_or_else(
+        || format!("instruction {}", index + 1),
+        |location| location.to_string(),
+    )
+}
+
+/// Position of an instruction or an error in the source text
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct SourceLocation {
+    /// starts at 1
+    pub line: usize,
+    /// in characters, starts at 1
+    pub column: usize,
+    /// number of characters of the located text
+    pub len: usize,
+}
+
+impl SourceLocation {
+    /// Location of `part`, which has to be a slice of `line`
+    fn of(line_number: usize, line: &str, part: &str) -> Self {
+        let offset = part.as_ptr() as usize - line.as_ptr() as usize;
+        Self {
+            line: line_number,
+            column: line[..offset].chars().count() + 1,
+            len: part.chars().count(),
+        }
+    }
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
 pub trait MnemonicDSL: Sized {
     fn labelled(self, label: impl Into<String>) -> Instruction {
         self.labelled_with(label, Parameter::None)
@@ -52,6 +141,7 @@ impl<T: Into<Mnemonic>> MnemonicDSL for T {
             label: Some(label.into()),
             mnemonic: self.into(),
             parameter,
+            location: None,
         }
     }
 
@@ -60,6 +150,7 @@ impl<T: Into<Mnemonic>> MnemonicDSL for T {
             label: None,
             mnemonic: self.into(),
             parameter,
+            location: None,
         }
     }
 }
@@ -70,6 +161,10 @@ pub enum PseudoInstruction {
     BBS,
     RMB,
     SMB,
+    /// `.org address`, continues the program at `address`, the gap is filled with zeros
+    Org,
+    /// `.byte value,...`, a list of [`Parameter::Immediate`] bytes
+    Byte,
 }
 
 impl From<PseudoInstruction> for Mnemonic {
@@ -112,11 +207,45 @@ impl Parameter {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+/// One instruction of a program, instructions are equal regardless of their location
+#[derive(Debug, Clone, Eq)]
 pub struct Instruction {
     label: Option<String>,
     mnemonic: Mnemonic,
     parameter: Parameter,
+    /// `None` for instructions that were not [`parse`]d from text
+    location: Option<SourceLocation>,
+}
+
+impl Instruction {
+    pub const fn location(&self) -> Option<SourceLocation> {
+        self.location
+    }
+
+    /// Wrap an error with the position of this instruction in the program
+    fn error(&self, index: usize, error: AssemblerError) -> AssemblerError {
+        AssemblerError::Instruction {
+            index,
+            location: self.location,
+            error: Box::new(error),
+        }
+    }
+}
+
+impl PartialEq for Instruction {
+    fn eq(&self, other: &Self) -> bool {
+        self.label == other.label
+            && self.mnemonic == other.mnemonic
+            && self.parameter == other.parameter
+    }
+}
+
+impl Hash for Instruction {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.label.hash(state);
+        self.mnemonic.hash(state);
+        self.parameter.hash(state);
+    }
 }
 
 impl Display for Instruction {
@@ -194,14 +323,27 @@ impl AssembledInstruction {
                 if matches!(parameter_1, Some(AssembledParameter::Label(_)))
                     || matches!(parameter_2, Some(AssembledParameter::Label(_)))
                 {
-                    // only works when there is one possible opcode
-                    // TODO: implement selection between relative and absolute
-                    let candidate = candidates.iter().exactly_one().map_err(|_| {
-                        AssemblerError::ParameterMismatch(format!(
-                            "multiple candidates for labelled instruction {:?}",
-                            instruction.mnemonic
-                        ))
-                    })?;
+                    // labels always use absolute addressing, a bare label selects between relative and absolute
+                    let candidate = candidates
+                        .iter()
+                        .filter(|c| {
+                            if mode_1 == AddressingMode::None {
+                                matches!(
+                                    c.parameter_1,
+                                    AddressingMode::Absolute
+                                        | AddressingMode::ProgramCounterRelative
+                                )
+                            } else {
+                                c.parameter_1 == mode_1 && c.parameter_2 == mode_2
+                            }
+                        })
+                        .exactly_one()
+                        .map_err(|_| {
+                            AssemblerError::ParameterMismatch(format!(
+                                "multiple candidates for labelled instruction {:?}",
+                                instruction.mnemonic
+                            ))
+                        })?;
                     return Ok(AssembledInstruction {
                         instruction: candidate,
                         parameter_1,
@@ -224,16 +366,18 @@ impl AssembledInstruction {
                         });
                     }
 
-                    // TODO: better matching for not exactly fitting addressing modes
-                    match (candidate.parameter_1, mode_1, candidate.parameter_2, mode_2) {
-                        (p1, m1, p2, m2) if p1 == m1 && p2 == m2 => {
-                            return Ok(AssembledInstruction {
-                                instruction: candidate,
-                                parameter_1,
-                                parameter_2,
-                            });
-                        }
-                        _ => {}
+                    // a branch to a numeric address is resolved to an offset like a label in pass 2
+                    let branch_target = candidate.parameter_1
+                        == AddressingMode::ProgramCounterRelative
+                        && mode_1 == AddressingMode::Absolute;
+                    if (candidate.parameter_1 == mode_1 || branch_target)
+                        && candidate.parameter_2 == mode_2
+                    {
+                        return Ok(AssembledInstruction {
+                            instruction: candidate,
+                            parameter_1,
+                            parameter_2,
+                        });
                     }
                 }
 
@@ -247,6 +391,9 @@ impl AssembledInstruction {
                 PseudoInstruction::BBS => todo!(),
                 PseudoInstruction::RMB => todo!(),
                 PseudoInstruction::SMB => todo!(),
+                PseudoInstruction::Org | PseudoInstruction::Byte => {
+                    unreachable!("directives are handled by the assembly")
+                }
             },
         }
     }
@@ -293,6 +440,13 @@ impl AssembledInstruction {
                     ),
                     None,
                 ),
+                Parameter::Label(label) => (
+                    (
+                        AddressingMode::AbsoluteIndirect,
+                        Some(AssembledParameter::Label(label.to_string())),
+                    ),
+                    None,
+                ),
                 Parameter::List(inner) => match inner.as_slice() {
                     [Parameter::Absolute(number), Parameter::X] => (
                         (
@@ -355,7 +509,7 @@ impl AssembledInstruction {
                     Parameter::Absolute(number) if (0..=u8::MAX as u16).contains(number) => (
                         (
                             AddressingMode::ZeroPageIndirectIndexedY,
-                            Some(AssembledParameter::U16(*number)),
+                            Some(AssembledParameter::U8(*number as u8)),
                         ),
                         None,
                     ),
@@ -376,6 +530,16 @@ impl AssembledInstruction {
                         Some(AssembledParameter::Label(label.to_string())),
                     )),
                 ),
+                [Parameter::Absolute(number), Parameter::Absolute(target)] => (
+                    (
+                        AddressingMode::Absolute,
+                        Some(AssembledParameter::U16(*number)),
+                    ),
+                    Some((
+                        AddressingMode::ProgramCounterRelative,
+                        Some(AssembledParameter::U16(*target)),
+                    )),
+                ),
                 _ => {
                     return Err(AssemblerError::ParameterMismatch(format!(
                         "could not match parameters with addressing mode: {:?}",
@@ -398,45 +562,51 @@ impl AssembledInstruction {
         address: u16,
         labels: &HashMap<String, u16>,
     ) -> Result<(), AssemblerError> {
-        if let AssembledParameter::Label(label) = parameter {
-            let resolved = labels
+        let resolved = match parameter {
+            AssembledParameter::Label(label) => labels
                 .get(label)
                 .copied()
-                .ok_or_else(|| AssemblerError::UnknownLabel((*label).to_string()))?;
-            match addressing_mode {
-                AddressingMode::ProgramCounterRelative => {
-                    // pc + n = resolved <=> n = resolved - pc
-                    let diff = if resolved < address {
-                        let d = address - resolved;
-                        if (0..=128).contains(&d) {
-                            // 128u16 as i8 is -128
-                            // (-128i8).wrapping_neg() is -128
-                            Ok((d as i8).wrapping_neg())
-                        } else {
-                            Err(AssemblerError::JumpTooFar)
-                        }
+                .ok_or_else(|| AssemblerError::UnknownLabel((*label).to_string()))?,
+            AssembledParameter::U16(target)
+                if addressing_mode == AddressingMode::ProgramCounterRelative =>
+            {
+                *target
+            }
+            _ => return Ok(()),
+        };
+        match addressing_mode {
+            AddressingMode::ProgramCounterRelative => {
+                // pc + n = resolved <=> n = resolved - pc
+                let diff = if resolved < address {
+                    let d = address - resolved;
+                    if (0..=128).contains(&d) {
+                        // 128u16 as i8 is -128
+                        // (-128i8).wrapping_neg() is -128
+                        Ok((d as i8).wrapping_neg())
                     } else {
-                        let d = resolved - address;
-                        if (0..=127).contains(&d) {
-                            Ok(d as i8)
-                        } else {
-                            Err(AssemblerError::JumpTooFar)
-                        }
-                    }?;
-                    *parameter = AssembledParameter::U8(diff as u8);
-                }
-                AddressingMode::Absolute
-                | AddressingMode::AbsoluteIndexedX
-                | AddressingMode::AbsoluteIndexedY
-                | AddressingMode::AbsoluteIndirect
-                | AddressingMode::AbsoluteIndexedIndirectX => {
-                    *parameter = AssembledParameter::U16(resolved);
-                }
-                _ => {
-                    return Err(AssemblerError::ParameterMismatch(format!(
-                        "could not replace label with actual address: {addressing_mode:?}"
-                    )));
-                }
+                        Err(AssemblerError::JumpTooFar)
+                    }
+                } else {
+                    let d = resolved - address;
+                    if (0..=127).contains(&d) {
+                        Ok(d as i8)
+                    } else {
+                        Err(AssemblerError::JumpTooFar)
+                    }
+                }?;
+                *parameter = AssembledParameter::U8(diff as u8);
+            }
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteIndexedX
+            | AddressingMode::AbsoluteIndexedY
+            | AddressingMode::AbsoluteIndirect
+            | AddressingMode::AbsoluteIndexedIndirectX => {
+                *parameter = AssembledParameter::U16(resolved);
+            }
+            _ => {
+                return Err(AssemblerError::ParameterMismatch(format!(
+                    "could not replace label with actual address: {addressing_mode:?}"
+                )));
             }
         }
         Ok(())
@@ -457,17 +627,58 @@ impl AssembledInstruction {
     }
 }
 
+/// An instruction or the bytes of a directive after pass 1
+#[derive(Debug, Clone)]
+enum Assembled {
+    Instruction(AssembledInstruction),
+    Data(Vec<u8>),
+}
+
+impl Assembled {
+    fn width(&self) -> u16 {
+        match self {
+            Self::Instruction(assembled) => assembled.instruction.width(),
+            Self::Data(data) => data.len() as u16,
+        }
+    }
+
+    /// Bytes of a `.byte` directive
+    fn data(instruction: &Instruction) -> Result<Self, AssemblerError> {
+        let bytes = match &instruction.parameter {
+            Parameter::Immediate(byte) => vec![*byte],
+            Parameter::List(parameters) => parameters
+                .iter()
+                .map(|parameter| match parameter {
+                    Parameter::Immediate(byte) => Ok(*byte),
+                    _ => Err(()),
+                })
+                .collect::<Result<_, _>>()
+                .map_err(|_| {
+                    AssemblerError::ParameterMismatch(".byte only takes bytes".to_string())
+                })?,
+            _ => {
+                return Err(AssemblerError::ParameterMismatch(
+                    ".byte only takes bytes".to_string(),
+                ));
+            }
+        };
+        Ok(Self::Data(bytes))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Assembly {
     instructions: Vec<Instruction>,
+    origin: u16,
     labels: HashMap<String, u16>,
-    assembled_instructions: Vec<AssembledInstruction>,
+    assembled_instructions: Vec<Assembled>,
 }
 
 impl Assembly {
-    fn from_instructions(instructions: impl Into<Vec<Instruction>>) -> Self {
+    fn from_instructions(instructions: impl Into<Vec<Instruction>>, origin: u16) -> Self {
         Self {
             instructions: instructions.into(),
+            origin,
             labels: HashMap::new(),
             assembled_instructions: vec![],
         }
@@ -475,28 +686,65 @@ impl Assembly {
 
     fn assemble(&mut self) -> Result<(), AssemblerError> {
         // pass 1: find opcodes and offsets, collect params
-        let mut address = 0u16;
-        for instruction in &self.instructions {
+        let mut address = self.origin;
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            if instruction.mnemonic == Mnemonic::PseudoOp(PseudoInstruction::Org) {
+                let Parameter::Absolute(target) = instruction.parameter else {
+                    return Err(instruction.error(
+                        index,
+                        AssemblerError::ParameterMismatch(".org takes an address".to_string()),
+                    ));
+                };
+                if self.assembled_instructions.iter().all(|a| a.width() == 0) {
+                    // nothing was emitted yet, so the program starts there
+                    self.origin = target;
+                    address = target;
+                }
+                let gap = target.checked_sub(address).ok_or_else(|| {
+                    instruction.error(
+                        index,
+                        AssemblerError::Generic(format!(
+                            ".org ${target:04X} is before the current address ${address:04X}"
+                        )),
+                    )
+                })?;
+                self.assembled_instructions
+                    .push(Assembled::Data(vec![0; gap as usize]));
+                address = target;
+            }
+
             if let Some(label) = &instruction.label
                 && self.labels.insert(label.to_string(), address).is_some()
             {
-                return Err(AssemblerError::DoubleLabel(label.to_string()));
+                return Err(
+                    instruction.error(index, AssemblerError::DoubleLabel(label.to_string()))
+                );
             }
 
-            let assembled = AssembledInstruction::assemble(instruction)?;
+            let assembled = match instruction.mnemonic {
+                // the gap is already added
+                Mnemonic::PseudoOp(PseudoInstruction::Org) => continue,
+                Mnemonic::PseudoOp(PseudoInstruction::Byte) => Assembled::data(instruction),
+                _ => AssembledInstruction::assemble(instruction).map(Assembled::Instruction),
+            }
+            .map_err(|e| instruction.error(index, e))?;
             address = address
-                .checked_add(assembled.instruction.width())
-                .ok_or(AssemblerError::AddressOverflow)?;
+                .checked_add(assembled.width())
+                .ok_or_else(|| instruction.error(index, AssemblerError::AddressOverflow))?;
             self.assembled_instructions.push(assembled);
         }
 
         // pass 2: labels
-        let mut address = 0u16;
-        for (_instruction, assembled) in
-            std::iter::zip(&self.instructions, &mut self.assembled_instructions)
+        let mut address = self.origin;
+        for (index, (instruction, assembled)) in
+            std::iter::zip(&self.instructions, &mut self.assembled_instructions).enumerate()
         {
-            address += assembled.instruction.width();
-            assembled.fill_labels(address, &self.labels)?;
+            address += assembled.width();
+            if let Assembled::Instruction(assembled) = assembled {
+                assembled
+                    .fill_labels(address, &self.labels)
+                    .map_err(|e| instruction.error(index, e))?;
+            }
         }
 
         Ok(())
@@ -504,6 +752,13 @@ impl Assembly {
 
     fn write(&self, mut w: impl Write) -> std::io::Result<()> {
         for assembled in &self.assembled_instructions {
+            let assembled = match assembled {
+                Assembled::Instruction(assembled) => assembled,
+                Assembled::Data(data) => {
+                    w.write_all(data)?;
+                    continue;
+                }
+            };
             w.write_all(&[assembled.instruction.byte])?;
             for p in [
                 assembled.parameter_1.as_ref(),
@@ -524,35 +779,602 @@ impl Assembly {
 }
 
 pub fn assemble(instructions: &[Instruction], w: impl Write) -> Result<(), AssemblerError> {
-    let mut assembly = Assembly::from_instructions(instructions);
+    assemble_at(instructions, 0, w)
+}
+
+/// Assemble `instructions` so they run when loaded at `origin`
+pub fn assemble_at(
+    instructions: &[Instruction],
+    origin: u16,
+    w: impl Write,
+) -> Result<(), AssemblerError> {
+    let mut assembly = Assembly::from_instructions(instructions, origin);
     assembly.assemble()?;
     assembly.write(w)?;
     Ok(())
 }
 
-pub fn disassemble(_r: impl Read) -> Vec<Instruction> {
-    let instructions = vec![];
-    // TODO
-    /*loop {
-        let mut buf = [0];
-        let result = r.read_exact(&mut buf);
-        if let Err(e) = &result {
-            if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                break;
+/// Parse assembler source text.
+///
+/// Each line holds an optional `label:` followed by an optional instruction, `;` starts a comment.
+/// Constants are defined with `name = value` or `.equ name = value` before they are used,
+/// `.set name = value` defines one that can be redefined later on.
+/// Values and operands can add and subtract constants and numbers, e.g. `VIA_BASE + $0D`.
+/// `.org address` continues the program at `address`, at the start it replaces the origin,
+/// `.byte value,...` inserts bytes.
+/// Numbers are decimal or hex with a `$` or `0x` prefix or binary with a `%` prefix.
+pub fn parse(text: &str) -> Result<Vec<Instruction>, AssemblerError> {
+    parse_with_constants(text, HashMap::new())
+}
+
+/// [`parse`] with predefined `constants`, e.g. [`crate::symbols::cody_symbols`], the source may redefine them
+pub fn parse_with_constants(
+    text: &str,
+    mut constants: HashMap<String, u16>,
+) -> Result<Vec<Instruction>, AssemblerError> {
+    let mut instructions = vec![];
+    // predefined constants and those defined with `.set` can be redefined
+    let mut predefined: HashSet<_> = constants.keys().cloned().collect();
+    let mut variables = HashSet::new();
+    for (i, source) in text.lines().enumerate() {
+        let location = |part: &str| SourceLocation::of(i + 1, source, part);
+        let syntax_error = |part: &str, message: String| AssemblerError::Syntax {
+            location: location(part),
+            message,
+        };
+
+        let mut line = source.split(';').next().unwrap().trim();
+        if let Some((name, value)) = line.split_once('=') {
+            let (directive, name) = match name.trim().split_once(char::is_whitespace) {
+                Some((directive, name)) => (directive, name.trim()),
+                None => ("", name.trim()),
+            };
+            let reassignable = match directive.to_ascii_lowercase().as_str() {
+                "" | ".equ" => false,
+                ".set" => true,
+                _ => {
+                    return Err(syntax_error(
+                        directive,
+                        format!("unknown directive {directive:?}"),
+                    ));
+                }
+            };
+            if !is_identifier(name) {
+                return Err(syntax_error(
+                    name,
+                    format!("invalid constant name {name:?}"),
+                ));
+            }
+            let value = value.trim();
+            let value =
+                evaluate(value, &constants).map_err(|message| syntax_error(value, message))?;
+            let redefinable = predefined.remove(name) || (reassignable && variables.contains(name));
+            if constants.contains_key(name) && !redefinable {
+                return Err(syntax_error(name, format!("{name:?} is already defined")));
+            }
+            if reassignable {
+                variables.insert(name.to_string());
+            }
+            constants.insert(name.to_string(), value);
+            continue;
+        }
+
+        let mut label = None;
+        if let Some((name, rest)) = line.split_once(':') {
+            let name = name.trim();
+            if !is_identifier(name) {
+                return Err(syntax_error(name, format!("invalid label {name:?}")));
             }
+            label = Some(name);
+            line = rest.trim();
         }
-        result.unwrap();
-        let opcode = buf[0];
-
-        if let Some(opcode) = get_instruction(opcode) {
-            if let Ok(instruction) = opcode.read_parameters(r.by_ref()) {
-                instructions.push(instruction);
-            } else {
-                instructions.push(crate::opcode::Opcode::Invalid.iinsn());
+
+        if line.is_empty() {
+            if let Some(label) = label {
+                return Err(syntax_error(
+                    label,
+                    format!("label {label:?} without instruction"),
+                ));
             }
+            continue;
+        }
+
+        let (mnemonic, operand) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let operand = operand.trim();
+        let (mnemonic, parameter) = match mnemonic.to_ascii_lowercase().as_str() {
+            ".org" => (
+                PseudoInstruction::Org.into(),
+                evaluate(operand, &constants).map(Parameter::Absolute),
+            ),
+            ".byte" => (
+                PseudoInstruction::Byte.into(),
+                operand
+                    .split(',')
+                    .map(|byte| byte_value(byte, &constants))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Parameter::List),
+            ),
+            _ => {
+                let opcode = OPCODES
+                    .iter()
+                    .map(|meta| meta.opcode)
+                    .find(|opcode| format!("{opcode:?}").eq_ignore_ascii_case(mnemonic))
+                    .ok_or_else(|| {
+                        syntax_error(mnemonic, format!("unknown mnemonic {mnemonic:?}"))
+                    })?;
+                (opcode.into(), parse_operand(operand, &constants))
+            }
+        };
+        let parameter = parameter.map_err(|message| syntax_error(operand, message))?;
+        instructions.push(Instruction {
+            label: label.map(str::to_string),
+            mnemonic,
+            parameter,
+            location: Some(location(line)),
+        });
+    }
+    Ok(instructions)
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    if let Some(hex) = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = s.strip_prefix('%') {
+        u16::from_str_radix(bin, 2).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Evaluate a sum of numbers and constants like `VIA_BASE + $0D - 1`
+fn evaluate(expression: &str, constants: &HashMap<String, u16>) -> Result<u16, String> {
+    let mut result = 0u16;
+    let mut subtract = false;
+    let mut rest = expression;
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = rest[..end].trim();
+        let value = match constants.get(term) {
+            Some(&value) => value,
+            None if is_identifier(term) => return Err(format!("unknown constant {term:?}")),
+            None => parse_number(term).ok_or_else(|| format!("invalid number {term:?}"))?,
+        };
+        result = if subtract {
+            result.checked_sub(value)
         } else {
-            instructions.push(crate::opcode::Opcode::NOP.iinsn());
+            result.checked_add(value)
         }
-    }*/
+        .ok_or_else(|| format!("value of {expression:?} does not fit into 16 bits"))?;
+
+        let Some(operator) = rest[end..].chars().next() else {
+            return Ok(result);
+        };
+        subtract = operator == '-';
+        rest = &rest[end + 1..];
+    }
+}
+
+/// A `.byte` value as [`Parameter::Immediate`]
+fn byte_value(value: &str, constants: &HashMap<String, u16>) -> Result<Parameter, String> {
+    let number = evaluate(value.trim(), constants)?;
+    u8::try_from(number)
+        .map(Parameter::Immediate)
+        .map_err(|_| format!("value {number} does not fit into a byte"))
+}
+
+fn parse_operand(operand: &str, constants: &HashMap<String, u16>) -> Result<Parameter, String> {
+    let value = |s: &str| {
+        let s = s.trim();
+        if let Some(&number) = constants.get(s) {
+            Ok(Parameter::Absolute(number))
+        } else if is_identifier(s) {
+            Ok(Parameter::label(s))
+        } else {
+            evaluate(s, constants).map(Parameter::Absolute)
+        }
+    };
+    let index = |s: &str| match s.trim().to_ascii_uppercase().as_str() {
+        "X" => Some(Parameter::X),
+        "Y" => Some(Parameter::Y),
+        _ => None,
+    };
+
+    if operand.is_empty() {
+        return Ok(Parameter::None);
+    }
+    if operand.eq_ignore_ascii_case("A") {
+        return Ok(Parameter::A);
+    }
+    if let Some(immediate) = operand.strip_prefix('#') {
+        return match value(immediate)? {
+            Parameter::Absolute(number) => u8::try_from(number)
+                .map(Parameter::Immediate)
+                .map_err(|_| format!("immediate value {number} does not fit into a byte")),
+            _ => Err(format!(
+                "labels can't be used as immediate value: {immediate:?}"
+            )),
+        };
+    }
+    if let Some(inner) = operand.strip_prefix('(') {
+        // (a), (a,X) or (zp),Y
+        let (inner, rest) = inner
+            .split_once(')')
+            .ok_or_else(|| format!("missing ')' in {operand:?}"))?;
+        let rest = rest.trim();
+        let indirect = match inner.split_once(',') {
+            Some((address, register)) if index(register) == Some(Parameter::X) => {
+                Parameter::Indirect(Box::new(Parameter::list([value(address)?, Parameter::X])))
+            }
+            Some(_) => return Err(format!("invalid indirect operand {operand:?}")),
+            None => Parameter::Indirect(Box::new(value(inner)?)),
+        };
+        return match rest.strip_prefix(',') {
+            None if rest.is_empty() => Ok(indirect),
+            Some(register) if index(register) == Some(Parameter::Y) => {
+                Ok(Parameter::list([indirect, Parameter::Y]))
+            }
+            _ => Err(format!("invalid indirect operand {operand:?}")),
+        };
+    }
+    match operand.split_once(',') {
+        // a,X / a,Y or the zp,label form of BBR and BBS
+        Some((address, second)) => Ok(Parameter::list([
+            value(address)?,
+            match index(second) {
+                Some(register) => register,
+                None => value(second)?,
+            },
+        ])),
+        None => value(operand),
+    }
+}
+
+/// One decoded instruction of a disassembly listing
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DisassembledInstruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    /// `None` if the bytes are no valid instruction
+    pub instruction: Option<&'static InstructionMeta>,
+}
+
+impl DisassembledInstruction {
+    /// Format as `ADDR  BYTES  MNEMONIC OPERAND`, addresses found in `symbols` are replaced by their name
+    pub fn format(&self, symbols: &HashMap<u16, String>) -> String {
+        let bytes = self.bytes.iter().map(|b| format!("{b:02X}")).join(" ");
+        match self.source(symbols) {
+            Some(source) => format!("{:04X}  {bytes:<8}  {source}", self.address),
+            None => format!(
+                "{:04X}  {bytes:<8}  .byte ${}",
+                self.address,
+                bytes.replace(' ', ",$")
+            ),
+        }
+    }
+
+    /// Format as `MNEMONIC OPERAND` in the syntax of [`parse`], `None` if the bytes are no valid instruction.
+    ///
+    /// Assembling it gives the same bytes, except for absolute operands below 0x100 which get the zero page form if there is one.
+    pub fn source(&self, symbols: &HashMap<u16, String>) -> Option<String> {
+        let meta = self.instruction?;
+        let address = |value: u16, width: usize| {
+            symbols
+                .get(&value)
+                .cloned()
+                .unwrap_or_else(|| format!("${value:0width$X}"))
+        };
+        let operand = self
+            .operands()
+            .into_iter()
+            .map(|(mode, value)| match mode {
+                AddressingMode::None => String::new(),
+                AddressingMode::Accumulator => "A".to_string(),
+                AddressingMode::Immediate => format!("#${value:02X}"),
+                AddressingMode::Absolute | AddressingMode::ProgramCounterRelative => {
+                    address(value, 4)
+                }
+                AddressingMode::AbsoluteIndexedX => format!("{},X", address(value, 4)),
+                AddressingMode::AbsoluteIndexedY => format!("{},Y", address(value, 4)),
+                AddressingMode::AbsoluteIndirect => format!("({})", address(value, 4)),
+                AddressingMode::AbsoluteIndexedIndirectX => format!("({},X)", address(value, 4)),
+                AddressingMode::ZeroPage => address(value, 2),
+                AddressingMode::ZeroPageIndexedX => format!("{},X", address(value, 2)),
+                AddressingMode::ZeroPageIndexedY => format!("{},Y", address(value, 2)),
+                AddressingMode::ZeroPageIndirect => format!("({})", address(value, 2)),
+                AddressingMode::ZeroPageIndexedIndirectX => format!("({},X)", address(value, 2)),
+                AddressingMode::ZeroPageIndirectIndexedY => format!("({}),Y", address(value, 2)),
+            })
+            .filter(|o| !o.is_empty())
+            .join(",");
+        Some(
+            format!("{:?} {operand}", meta.opcode)
+                .trim_end()
+                .to_string(),
+        )
+    }
+
+    /// Addressing mode and value of the operands, branch offsets are resolved to the target address
+    pub fn operands(&self) -> Vec<(AddressingMode, u16)> {
+        let Some(meta) = self.instruction else {
+            return vec![];
+        };
+        let next = self.address.wrapping_add(meta.width());
+        let mut parameters = self.bytes[1..].iter().copied();
+        [meta.parameter_1, meta.parameter_2]
+            .into_iter()
+            .filter(|&mode| mode != AddressingMode::None)
+            .map(|mode| {
+                let mut value = 0u16;
+                for i in 0..mode.width() {
+                    value |= (parameters.next().unwrap() as u16) << (8 * i);
+                }
+                if mode == AddressingMode::ProgramCounterRelative {
+                    value = next.wrapping_add_signed(value as u8 as i8 as i16);
+                }
+                (mode, value)
+            })
+            .collect()
+    }
+
+    /// Whether assembling the [`source`](Self::source) would pick a zero page form instead of the absolute one
+    fn has_zero_page_form(&self) -> bool {
+        let Some(meta) = self.instruction else {
+            return false;
+        };
+        let zero_page_mode = match meta.parameter_1 {
+            AddressingMode::Absolute => AddressingMode::ZeroPage,
+            AddressingMode::AbsoluteIndexedX => AddressingMode::ZeroPageIndexedX,
+            AddressingMode::AbsoluteIndexedY => AddressingMode::ZeroPageIndexedY,
+            AddressingMode::AbsoluteIndirect => AddressingMode::ZeroPageIndirect,
+            AddressingMode::AbsoluteIndexedIndirectX => AddressingMode::ZeroPageIndexedIndirectX,
+            _ => return false,
+        };
+        self.operands()[0].1 <= u8::MAX as u16
+            && get_instructions(meta.opcode)
+                .iter()
+                .any(|candidate| candidate.parameter_1 == zero_page_mode)
+    }
+}
+
+/// Output of [`write_listing`]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum ListingFormat {
+    /// Address, bytes and instruction per line
+    #[default]
+    Plain,
+    /// Source for ca65 of the cc65 suite
+    Ca65,
+    /// Source for `asm`, see [`parse`]
+    Native,
+}
+
+/// Write `instructions` as listing or as source that assembles to the same bytes, addresses found in `symbols` are replaced by their name.
+///
+/// The sources label every referenced address that starts an instruction and define the other symbols they use.
+pub fn write_listing(
+    instructions: &[DisassembledInstruction],
+    symbols: &HashMap<u16, String>,
+    format: ListingFormat,
+    mut w: impl Write,
+) -> std::io::Result<()> {
+    if format == ListingFormat::Plain {
+        for instruction in instructions {
+            if let Some(name) = symbols.get(&instruction.address) {
+                writeln!(w, "{name}:")?;
+            }
+            writeln!(w, "{}", instruction.format(symbols))?;
+        }
+        return w.flush();
+    }
+
+    let referenced: HashSet<u16> = instructions
+        .iter()
+        .flat_map(DisassembledInstruction::operands)
+        .filter(|(mode, _)| mode.width() == 2 || *mode == AddressingMode::ProgramCounterRelative)
+        .map(|(_, address)| address)
+        .collect();
+    let labels: HashMap<u16, String> = instructions
+        .iter()
+        .map(|instruction| instruction.address)
+        .filter(|address| referenced.contains(address) || symbols.contains_key(address))
+        .map(|address| {
+            let name = symbols
+                .get(&address)
+                .cloned()
+                .unwrap_or_else(|| format!("L{address:04X}"));
+            (address, name)
+        })
+        .collect();
+    let definitions: BTreeMap<u16, &String> = instructions
+        .iter()
+        .flat_map(DisassembledInstruction::operands)
+        .filter(|(mode, _)| mode.width() > 0 && *mode != AddressingMode::Immediate)
+        .filter_map(|(_, address)| Some((address, symbols.get(&address)?)))
+        .filter(|(address, _)| !labels.contains_key(address))
+        .collect();
+    let mut names = symbols.clone();
+    names.extend(labels.clone());
+
+    if format == ListingFormat::Ca65 {
+        writeln!(w, ".setcpu \"W65C02\"")?;
+    }
+    for (address, name) in &definitions {
+        writeln!(w, "{name} = ${address:04X}")?;
+    }
+    if let Some(first) = instructions.first() {
+        writeln!(w, ".org ${:04X}", first.address)?;
+    }
+    for instruction in instructions {
+        let label = labels
+            .get(&instruction.address)
+            .map(|label| format!("{label}:"))
+            .unwrap_or_default();
+        let bytes = instruction
+            .bytes
+            .iter()
+            .map(|b| format!("${b:02X}"))
+            .join(",");
+        let mut comment = format!(
+            "{:04X}  {}",
+            instruction.address,
+            instruction
+                .bytes
+                .iter()
+                .map(|b| format!("{b:02X}"))
+                .join(" ")
+        );
+        let source = match instruction.source(&names) {
+            None => format!(".byte {bytes}"),
+            Some(source) if instruction.has_zero_page_form() => match format {
+                // force the absolute addressing mode
+                ListingFormat::Ca65 => source.replacen(' ', " a:", 1),
+                _ => {
+                    comment = format!("{comment}  {source}");
+                    format!(".byte {bytes}")
+                }
+            },
+            Some(source) => source,
+        };
+        writeln!(w, "{label:<7} {source:<24}; {comment}")?;
+    }
+    w.flush()
+}
+
+/// Decode `data` loaded at `address` into instructions, bytes that are no valid instruction are kept as data
+pub fn disassemble(data: &[u8], address: u16) -> Vec<DisassembledInstruction> {
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let instruction =
+            disassemble_instruction(&data[offset..], address.wrapping_add(offset as u16));
+        offset += instruction.bytes.len();
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+/// Decode the first instruction of the non-empty `data` loaded at `address`
+pub fn disassemble_instruction(data: &[u8], address: u16) -> DisassembledInstruction {
+    let instruction = get_instruction(data[0]).filter(|meta| meta.width() as usize <= data.len());
+    let width = instruction.map_or(1, |meta| meta.width() as usize);
+    DisassembledInstruction {
+        address,
+        bytes: data[..width].to_vec(),
+        instruction,
+    }
+}
+
+/// Entry points of `data` loaded at `address`: the NMI, reset and IRQ vectors if it contains them, else its start
+pub fn vector_entries(data: &[u8], address: u16) -> Vec<u16> {
+    let entries: Vec<_> = [NMI_VECTOR, RESET_VECTOR, IRQ_VECTOR]
+        .into_iter()
+        .filter_map(|vector| {
+            let offset = vector.wrapping_sub(address) as usize;
+            let bytes = data.get(offset..offset + 2)?;
+            Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+        })
+        .collect();
+    if entries.is_empty() {
+        vec![address]
+    } else {
+        entries
+    }
+}
+
+/// Mark the bytes of `data` loaded at `address` that belong to instructions reachable from `entries`.
+///
+/// Follows branches, jumps and subroutine calls, indirect jumps and returns end a path.
+/// Executed addresses from a trace make good additional entries, they cover indirect jumps.
+pub fn find_code(data: &[u8], address: u16, entries: impl IntoIterator<Item = u16>) -> Vec<bool> {
+    let mut code = vec![false; data.len()];
+    let mut visited = vec![false; data.len()];
+    let mut pending: Vec<u16> = entries.into_iter().collect();
+    while let Some(current) = pending.pop() {
+        let offset = current.wrapping_sub(address) as usize;
+        if offset >= data.len() || visited[offset] {
+            continue;
+        }
+        visited[offset] = true;
+        let instruction = disassemble_instruction(&data[offset..], current);
+        let Some(meta) = instruction.instruction else {
+            continue;
+        };
+        code[offset..offset + instruction.bytes.len()].fill(true);
+
+        let next = current.wrapping_add(meta.width());
+        let operand = |i: usize| instruction.bytes[i];
+        let absolute = || u16::from_le_bytes([operand(1), operand(2)]);
+        // the branch offset is always the last byte, also for BBR and BBS
+        let relative = || next.wrapping_add_signed(*instruction.bytes.last().unwrap() as i8 as i16);
+        match meta.opcode {
+            Opcode::JMP if meta.parameter_1 == AddressingMode::Absolute => pending.push(absolute()),
+            Opcode::JSR => pending.extend([next, absolute()]),
+            Opcode::BRA => pending.push(relative()),
+            Opcode::JMP | Opcode::RTS | Opcode::RTI | Opcode::BRK | Opcode::STP => {}
+            _ if meta.parameter_1 == AddressingMode::ProgramCounterRelative
+                || meta.parameter_2 == AddressingMode::ProgramCounterRelative =>
+            {
+                pending.extend([next, relative()]);
+            }
+            _ => pending.push(next),
+        }
+    }
+    code
+}
+
+/// Like [`disassemble`], but only bytes marked as `code`, see [`find_code`], are decoded, the others are kept as data
+pub fn disassemble_code(data: &[u8], address: u16, code: &[bool]) -> Vec<DisassembledInstruction> {
+    const DATA_PER_LINE: usize = 4;
+
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let current = address.wrapping_add(offset as u16);
+        let instruction = if code[offset] {
+            disassemble_instruction(&data[offset..], current)
+        } else {
+            let len = code[offset..]
+                .iter()
+                .take(DATA_PER_LINE)
+                .take_while(|&&code| !code)
+                .count();
+            DisassembledInstruction {
+                address: current,
+                bytes: data[offset..offset + len].to_vec(),
+                instruction: None,
+            }
+        };
+        offset += instruction.bytes.len();
+        instructions.push(instruction);
+    }
     instructions
 }
+
+/// Parse a symbol file with one `name = value` definition per line, `;` starts a comment
+pub fn parse_symbols(text: &str) -> Result<HashMap<u16, String>, AssemblerError> {
+    let mut symbols = HashMap::new();
+    for (i, source) in text.lines().enumerate() {
+        let syntax_error = |part: &str, message: String| AssemblerError::Syntax {
+            location: SourceLocation::of(i + 1, source, part),
+            message,
+        };
+        let line = source.split(';').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (name, value) = line
+            .split_once('=')
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .filter(|(name, _)| is_identifier(name))
+            .ok_or_else(|| syntax_error(line, format!("expected `name = value`, got {line:?}")))?;
+        let value = parse_number(value)
+            .ok_or_else(|| syntax_error(value, format!("invalid number {value:?}")))?;
+        symbols.insert(value, name.to_string());
+    }
+    Ok(symbols)
+}
@@ -0,0 +1,156 @@
+use crate::machine::Machine;
+use crate::opcode::get_instruction;
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+/// Why a benchmark run ended
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BenchEnd {
+    /// the cpu stopped, usually by executing STP at this address
+    Stopped(u16),
+    /// the program counter reached the target address
+    Reached(u16),
+    CycleLimit,
+}
+
+/// Executions of one opcode and the cycles they took
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct OpcodeStats {
+    pub count: u64,
+    pub cycles: u64,
+}
+
+/// Exact cycle and instruction counts of a run, cycles spent servicing interrupts count towards the interrupted instruction
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BenchReport {
+    pub end: BenchEnd,
+    pub cycles: u64,
+    pub instructions: u64,
+    /// statistics per opcode byte
+    pub opcodes: BTreeMap<u8, OpcodeStats>,
+}
+
+/// Run `machine` until the cpu stops, the program counter reaches `until_pc` or `max_cycles` elapsed
+pub fn run(machine: &mut Machine, until_pc: Option<u16>, max_cycles: u64) -> BenchReport {
+    let mut report = BenchReport {
+        end: BenchEnd::CycleLimit,
+        cycles: 0,
+        instructions: 0,
+        opcodes: BTreeMap::new(),
+    };
+    let mut pc = machine.cpu.pc;
+    report.end = loop {
+        if !machine.cpu.is_running() {
+            break BenchEnd::Stopped(pc);
+        }
+        if until_pc == Some(machine.cpu.pc) {
+            break BenchEnd::Reached(machine.cpu.pc);
+        }
+        if report.cycles >= max_cycles {
+            break BenchEnd::CycleLimit;
+        }
+
+        let cycles = machine.step_instruction() as u64;
+        report.cycles += cycles;
+        if let Some((address, byte)) = machine.cpu.last_instruction() {
+            pc = address;
+            report.instructions += 1;
+            let stats = report.opcodes.entry(byte).or_default();
+            stats.count += 1;
+            stats.cycles += cycles;
+        }
+    };
+    report
+}
+
+impl Display for BenchReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.end {
+            BenchEnd::Stopped(pc) => writeln!(f, "Stopped at 0x{pc:04X}")?,
+            BenchEnd::Reached(pc) => writeln!(f, "Reached 0x{pc:04X}")?,
+            BenchEnd::CycleLimit => writeln!(f, "Cycle limit reached")?,
+        }
+        writeln!(f, "cycles:       {}", self.cycles)?;
+        writeln!(f, "instructions: {}", self.instructions)?;
+        writeln!(f)?;
+        write!(
+            f,
+            "{:>12} {:>12} {:>7}  opcode",
+            "count", "cycles", "cycles%"
+        )?;
+        let opcodes = self
+            .opcodes
+            .iter()
+            .sorted_by_key(|(byte, stats)| (std::cmp::Reverse(stats.cycles), **byte));
+        for (&byte, stats) in opcodes {
+            let percent = 100.0 * stats.cycles as f64 / self.cycles.max(1) as f64;
+            let name = get_instruction(byte).map_or_else(
+                || "illegal".to_string(),
+                |meta| format!("{:?} {:?}", meta.opcode, meta.parameter_1),
+            );
+            write!(
+                f,
+                "\n{:>12} {:>12} {:>6.2}%  0x{byte:02X} {name}",
+                stats.count, stats.cycles, percent
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Image, Vectors};
+
+    fn machine() -> Machine {
+        // LDX #3, loop: DEX, BNE loop, STP
+        let program = vec![0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0xDB];
+        let image = Image::new(program, false, None).unwrap();
+        Machine::new(image, Vectors::default())
+    }
+
+    #[test]
+    fn test_bench() {
+        let report = run(&mut machine(), None, u64::MAX);
+        assert_eq!(report.end, BenchEnd::Stopped(0xE005));
+        assert_eq!(report.instructions, 8);
+        assert_eq!(
+            report.opcodes[&0xCA],
+            OpcodeStats {
+                count: 3,
+                cycles: 3 * 2
+            }
+        );
+        // taken branches take one more cycle
+        assert_eq!(
+            report.opcodes[&0xD0],
+            OpcodeStats {
+                count: 3,
+                cycles: 2 * 3 + 2
+            }
+        );
+        assert_eq!(
+            report.cycles,
+            report
+                .opcodes
+                .values()
+                .map(|stats| stats.cycles)
+                .sum::<u64>()
+        );
+        assert!(report.to_string().contains("0xCA DEX None"));
+    }
+
+    #[test]
+    fn test_bench_limits() {
+        let report = run(&mut machine(), Some(0xE003), u64::MAX);
+        assert_eq!(report.end, BenchEnd::Reached(0xE003));
+        assert_eq!(report.instructions, 2);
+        assert_eq!(report.cycles, 4);
+
+        let report = run(&mut machine(), None, 3);
+        assert_eq!(report.end, BenchEnd::CycleLimit);
+        assert_eq!(report.instructions, 2);
+    }
+}
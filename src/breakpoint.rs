@@ -0,0 +1,457 @@
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::watch::{Location, Register};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    /// bitwise not
+    Not,
+    /// logical not
+    LogicalNot,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    And,
+    Xor,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LogicalAnd,
+    LogicalOr,
+}
+
+/// Binary operators by their text, loosest binding first
+const BINARY_OPS: [&[(&str, BinaryOp)]; 7] = [
+    &[("||", BinaryOp::LogicalOr)],
+    &[("&&", BinaryOp::LogicalAnd)],
+    &[
+        ("==", BinaryOp::Eq),
+        ("!=", BinaryOp::Ne),
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("|", BinaryOp::Or)],
+    &[("^", BinaryOp::Xor)],
+    &[("&", BinaryOp::And)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+];
+
+/// Expression over the registers and memory, e.g. `A == 0x42 && mem[$D001] & 1`.
+///
+/// Values are 16 bit and wrap around, comparisons and logical operators give 1 or 0, anything but 0 is true.
+/// Operators bind like in Rust.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Expression {
+    Number(u16),
+    Register(Register),
+    /// byte at an address, `mem[ADDRESS]`
+    Memory(Box<Expression>),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Evaluate without side effects on devices
+    pub fn evaluate<M: Memory>(&self, cpu: &mut Cpu<M>) -> u16 {
+        match self {
+            Self::Number(value) => *value,
+            Self::Register(register) => Location::Register(*register).read(cpu),
+            Self::Memory(address) => Location::Byte(address.evaluate(cpu)).read(cpu),
+            Self::Unary(op, operand) => {
+                let value = operand.evaluate(cpu);
+                match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::Not => !value,
+                    UnaryOp::LogicalNot => (value == 0) as u16,
+                }
+            }
+            Self::Binary(BinaryOp::LogicalAnd, left, right) => {
+                (left.evaluate(cpu) != 0 && right.evaluate(cpu) != 0) as u16
+            }
+            Self::Binary(BinaryOp::LogicalOr, left, right) => {
+                (left.evaluate(cpu) != 0 || right.evaluate(cpu) != 0) as u16
+            }
+            Self::Binary(op, left, right) => {
+                let (left, right) = (left.evaluate(cpu), right.evaluate(cpu));
+                match op {
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Sub => left.wrapping_sub(right),
+                    BinaryOp::And => left & right,
+                    BinaryOp::Xor => left ^ right,
+                    BinaryOp::Or => left | right,
+                    BinaryOp::Eq => (left == right) as u16,
+                    BinaryOp::Ne => (left != right) as u16,
+                    BinaryOp::Lt => (left < right) as u16,
+                    BinaryOp::Le => (left <= right) as u16,
+                    BinaryOp::Gt => (left > right) as u16,
+                    BinaryOp::Ge => (left >= right) as u16,
+                    BinaryOp::LogicalAnd | BinaryOp::LogicalOr => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    /// Numbers are decimal or hex with a `$` or `0x` prefix, registers are `a`, `x`, `y`, `s`, `p` and `pc`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { rest: s };
+        let expression = parser.expression(0)?;
+        if !parser.rest.trim().is_empty() {
+            return Err(format!("unexpected {:?} in {s:?}", parser.rest.trim()));
+        }
+        Ok(expression)
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    /// Consume `token` if the remaining text starts with it
+    fn eat(&mut self, token: &str) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("expected {token:?} at {:?}", self.rest))
+        }
+    }
+
+    /// Binary operators of `level` and tighter binding ones
+    fn expression(&mut self, level: usize) -> Result<Expression, String> {
+        let Some(ops) = BINARY_OPS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.expression(level + 1)?;
+        'operators: loop {
+            for &(token, op) in *ops {
+                // `&` and `|` must not take the first half of `&&` and `||`
+                let doubled =
+                    token.len() == 1 && self.rest.trim_start().starts_with(&token.repeat(2));
+                if !doubled && self.eat(token) {
+                    let right = self.expression(level + 1)?;
+                    left = Expression::Binary(op, Box::new(left), Box::new(right));
+                    continue 'operators;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        for (token, op) in [
+            ("-", UnaryOp::Neg),
+            ("~", UnaryOp::Not),
+            ("!", UnaryOp::LogicalNot),
+        ] {
+            if self.eat(token) {
+                return Ok(Expression::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        if self.eat("(") {
+            let expression = self.expression(0)?;
+            self.expect(")")?;
+            return Ok(expression);
+        }
+
+        self.rest = self.rest.trim_start();
+        let end = self
+            .rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '$' && c != '_')
+            .unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(end);
+        if word.is_empty() {
+            return Err(format!("expected a value at {:?}", self.rest));
+        }
+        self.rest = rest;
+        let register = match word.to_ascii_lowercase().as_str() {
+            "a" => Register::A,
+            "x" => Register::X,
+            "y" => Register::Y,
+            "s" => Register::S,
+            "p" => Register::P,
+            "pc" => Register::Pc,
+            "mem" => {
+                self.expect("[")?;
+                let address = self.expression(0)?;
+                self.expect("]")?;
+                return Ok(Expression::Memory(Box::new(address)));
+            }
+            _ => {
+                return parse_number(word).map(Expression::Number).map_err(|_| {
+                    format!("expected a register, mem[ADDRESS] or a number, got {word:?}")
+                });
+            }
+        };
+        Ok(Expression::Register(register))
+    }
+}
+
+/// Decimal or hex with a `$` or `0x` prefix
+fn parse_number(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    let result = if let Some(hex) = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")) {
+        u16::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+    result.map_err(|_| format!("expected a 16 bit number, got {s:?}"))
+}
+
+/// Part of the message of a [`Action::Log`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LogPart {
+    Text(String),
+    /// `{EXPRESSION}`, formatted as hex
+    Value(Expression),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Action {
+    /// log a message at info level
+    Log(Vec<LogPart>),
+    /// log a hexdump of an inclusive range of bytes
+    Dump(u16, u16),
+    /// keep running instead of pausing
+    Continue,
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    /// `log MESSAGE` with `{EXPRESSION}` placeholders, `dump START-END` or `continue`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, argument) = s.split_once(' ').unwrap_or((s, ""));
+        match name {
+            "log" => {
+                let mut parts = vec![];
+                let mut rest = argument.trim();
+                while let Some((text, after)) = rest.split_once('{') {
+                    let (expression, after) = after
+                        .split_once('}')
+                        .ok_or_else(|| format!("missing '}}' in {s:?}"))?;
+                    if !text.is_empty() {
+                        parts.push(LogPart::Text(text.to_string()));
+                    }
+                    parts.push(LogPart::Value(expression.parse()?));
+                    rest = after;
+                }
+                if !rest.is_empty() {
+                    parts.push(LogPart::Text(rest.to_string()));
+                }
+                Ok(Self::Log(parts))
+            }
+            "dump" => {
+                let (start, end) = argument
+                    .split_once('-')
+                    .ok_or_else(|| format!("expected dump START-END, got {s:?}"))?;
+                let (start, end) = (parse_number(start)?, parse_number(end)?);
+                if start > end {
+                    return Err(format!("range {argument:?} ends before it starts"));
+                }
+                Ok(Self::Dump(start, end))
+            }
+            "continue" if argument.is_empty() => Ok(Self::Continue),
+            _ => Err(format!(
+                "expected log MESSAGE, dump START-END or continue, got {s:?}"
+            )),
+        }
+    }
+}
+
+/// Pauses the machine before executing the instruction at `address`, optionally only if a condition holds
+/// and after running actions that may keep it running.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub condition: Option<Expression>,
+    pub actions: Vec<Action>,
+    /// text the breakpoint was parsed from
+    text: String,
+}
+
+/// What a [`Breakpoint`] did when its condition held
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BreakpointHit {
+    /// output of the log and dump actions
+    pub messages: Vec<String>,
+    pub pause: bool,
+}
+
+impl Breakpoint {
+    /// Unconditional breakpoint without actions
+    pub fn at(address: u16) -> Self {
+        Self {
+            address,
+            condition: None,
+            actions: vec![],
+            text: format!("0x{address:04X}"),
+        }
+    }
+
+    /// Evaluate the condition and run the actions, `None` if the condition is false
+    pub fn check<M: Memory>(&self, cpu: &mut Cpu<M>) -> Option<BreakpointHit> {
+        if let Some(condition) = &self.condition
+            && condition.evaluate(cpu) == 0
+        {
+            return None;
+        }
+
+        let mut hit = BreakpointHit {
+            messages: vec![],
+            pause: true,
+        };
+        for action in &self.actions {
+            match action {
+                Action::Log(parts) => {
+                    let message = parts
+                        .iter()
+                        .map(|part| match part {
+                            LogPart::Text(text) => text.clone(),
+                            LogPart::Value(expression) => {
+                                format!("0x{:02X}", expression.evaluate(cpu))
+                            }
+                        })
+                        .collect();
+                    hit.messages.push(message);
+                }
+                &Action::Dump(start, end) => {
+                    for row in (start..=end).step_by(16) {
+                        let bytes: Vec<_> = (row..=end.min(row.saturating_add(15)))
+                            .map(|address| format!("{:02X}", cpu.memory.peek_u8(address)))
+                            .collect();
+                        hit.messages
+                            .push(format!("0x{row:04X}: {}", bytes.join(" ")));
+                    }
+                }
+                Action::Continue => hit.pause = false,
+            }
+        }
+        Some(hit)
+    }
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    /// `ADDRESS[ if CONDITION][; ACTION]...`, e.g. `0xE123 if a == 0x42; log a={a}; continue`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = s.trim();
+        let mut parts = text.split(';');
+        let head = parts.next().unwrap_or_default().trim();
+        let (address, condition) = match head.split_once(" if ") {
+            Some((address, condition)) => (address, Some(condition.parse()?)),
+            None => (head, None),
+        };
+        Ok(Self {
+            address: parse_number(address)?,
+            condition,
+            actions: parts.map(str::parse).collect::<Result<_, _>>()?,
+            text: text.to_string(),
+        })
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::contiguous::Contiguous;
+
+    fn cpu() -> Cpu<Contiguous> {
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.force_write_all(0xD001, &[0x03]);
+        memory.force_write_all(0x0010, b"Cody");
+        let mut cpu = Cpu::new(memory);
+        cpu.a = 0x42;
+        cpu.x = 0x10;
+        cpu
+    }
+
+    #[test]
+    fn test_expression() {
+        let mut cpu = cpu();
+        let mut evaluate = |s: &str| s.parse::<Expression>().unwrap().evaluate(&mut cpu);
+        assert_eq!(evaluate("A == 0x42 && mem[$D001] & 1"), 1);
+        assert_eq!(evaluate("a != 66 || x > 16"), 0);
+        assert_eq!(evaluate("mem[x + 1]"), b'o' as u16);
+        assert_eq!(evaluate("1 + 2 == 3"), 1);
+        assert_eq!(evaluate("mem[$D001] & 2 | 4"), 6);
+        assert_eq!(evaluate("-(1) + ~0 + !0"), 0xFFFF);
+        assert_eq!(evaluate("x - 0x11"), 0xFFFF);
+
+        assert!("a ==".parse::<Expression>().is_err());
+        assert!("mem[1".parse::<Expression>().is_err());
+        assert!("q + 1".parse::<Expression>().is_err());
+        assert!("1 2".parse::<Expression>().is_err());
+        assert!("$10000".parse::<Expression>().is_err());
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut cpu = cpu();
+        let breakpoint: Breakpoint =
+            "0xE123 if a == $42; log a={a} x={x}!; dump 0x10-0x13; continue"
+                .parse()
+                .unwrap();
+        assert_eq!(breakpoint.address, 0xE123);
+        assert_eq!(
+            breakpoint.check(&mut cpu),
+            Some(BreakpointHit {
+                messages: vec![
+                    "a=0x42 x=0x10!".to_string(),
+                    "0x0010: 43 6F 64 79".to_string()
+                ],
+                pause: false
+            })
+        );
+        cpu.a = 0;
+        assert_eq!(breakpoint.check(&mut cpu), None);
+
+        let breakpoint: Breakpoint = "$E000".parse().unwrap();
+        assert_eq!(breakpoint.condition, None);
+        assert_eq!(breakpoint.check(&mut cpu).map(|hit| hit.pause), Some(true));
+        assert_eq!(Breakpoint::at(0xE000).to_string(), "0xE000");
+
+        assert!("0xE000; jump".parse::<Breakpoint>().is_err());
+        assert!("0xE000; dump 0x20-0x10".parse::<Breakpoint>().is_err());
+        assert!("0xE000; log {a".parse::<Breakpoint>().is_err());
+        assert!("0xE000 if".parse::<Breakpoint>().is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::opcode::{Opcode, get_instruction};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// How a [`Frame`] was entered
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FrameKind {
+    Call,
+    Irq,
+    Nmi,
+}
+
+impl Display for FrameKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FrameKind::Call => "JSR",
+            FrameKind::Irq => "IRQ",
+            FrameKind::Nmi => "NMI",
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// entry address of the subroutine or interrupt handler
+    pub entry: u16,
+    /// address of the JSR or of the interrupted instruction
+    pub from: u16,
+    /// stack pointer after returning
+    return_s: u16,
+}
+
+/// Shadow call stack following JSR, RTS, interrupts and RTI, for backtraces and stepping out.
+///
+/// A frame is left when RTS, RTI or TXS bring the stack pointer back to where it was before the frame was entered,
+/// or when any other instruction shrinks the stack beyond that. This keeps subroutines that pull their return address
+/// and push it back, e.g. to read inline arguments, and drops frames of code that discards its return address and
+/// jumps elsewhere once its caller returns.
+#[derive(Debug, Clone)]
+pub struct CallStack {
+    /// the outermost frame is the code started by the last reset
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    /// Start with the code at `entry` as outermost function
+    pub fn new(entry: u16) -> Self {
+        let mut call_stack = Self { frames: vec![] };
+        call_stack.reset(entry);
+        call_stack
+    }
+
+    /// Forget all frames after a reset or restoring a snapshot
+    pub fn reset(&mut self, entry: u16) {
+        self.frames.clear();
+        self.frames.push(Frame {
+            kind: FrameKind::Call,
+            entry,
+            from: entry,
+            return_s: u16::MAX,
+        });
+    }
+
+    /// Number of frames including the outermost one
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Frames from the outermost to the innermost
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Follow one step of `cpu`, `interrupt` is the kind, program counter and stack pointer before it if an interrupt was serviced
+    pub fn record<M: Memory>(&mut self, cpu: &Cpu<M>, interrupt: Option<(FrameKind, u16, u8)>) {
+        if let (Some((kind, from, s)), Some((entry, _))) = (interrupt, cpu.last_instruction()) {
+            self.frames.push(Frame {
+                kind,
+                entry,
+                from,
+                return_s: s as u16,
+            });
+        }
+
+        let Some((address, byte)) = cpu.last_instruction() else {
+            return;
+        };
+        let s = cpu.s as u16;
+        match get_instruction(byte).map(|meta| meta.opcode) {
+            Some(Opcode::JSR) => self.frames.push(Frame {
+                kind: FrameKind::Call,
+                entry: cpu.pc,
+                from: address,
+                return_s: s + 2,
+            }),
+            Some(Opcode::RTS | Opcode::RTI | Opcode::TXS) => self.leave(|return_s| return_s <= s),
+            _ => self.leave(|return_s| return_s < s),
+        }
+    }
+
+    fn leave(&mut self, left: impl Fn(u16) -> bool) {
+        while self.frames.len() > 1 && left(self.frames.last().unwrap().return_s) {
+            self.frames.pop();
+        }
+    }
+
+    /// One line per frame from the innermost, which is executing at `pc`
+    pub fn backtrace(&self, pc: u16, symbols: &HashMap<u16, String>) -> Vec<String> {
+        let name = |address: u16| {
+            symbols
+                .get(&address)
+                .cloned()
+                .unwrap_or_else(|| format!("${address:04X}"))
+        };
+        let mut pc = pc;
+        let mut lines = vec![];
+        for (i, frame) in self.frames.iter().rev().enumerate() {
+            let mut line = format!("#{i} {pc:04X} in {}", name(frame.entry));
+            if frame.kind != FrameKind::Call {
+                line += &format!(" ({} handler)", frame.kind);
+            }
+            lines.push(line);
+            pc = frame.from;
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+    use crate::memory::contiguous::Contiguous;
+
+    fn run(program: &[u8], steps: usize) -> (Cpu<Contiguous>, CallStack) {
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.force_write_all(0x0200, program);
+        memory.write_u16(0xFFFC, 0x0200);
+        let mut cpu = Cpu::new(memory);
+        cpu.s = 0xFF;
+        let mut call_stack = CallStack::new(cpu.pc);
+        for _ in 0..steps {
+            cpu.step_instruction();
+            call_stack.record(&cpu, None);
+        }
+        (cpu, call_stack)
+    }
+
+    #[test]
+    fn test_call_stack() {
+        // main: JSR outer, STP; outer: JSR inner, RTS; inner: NOP, RTS
+        let program = [0x20, 0x04, 0x02, 0xDB, 0x20, 0x08, 0x02, 0x60, 0xEA, 0x60];
+        let (cpu, call_stack) = run(&program, 3);
+        assert_eq!(cpu.pc, 0x0209);
+        assert_eq!(call_stack.depth(), 3);
+        let symbols = HashMap::from([(0x0208, "inner".to_string())]);
+        assert_eq!(
+            call_stack.backtrace(cpu.pc, &symbols),
+            ["#0 0209 in inner", "#1 0204 in $0204", "#2 0200 in $0200"]
+        );
+
+        let (cpu, call_stack) = run(&program, 5);
+        assert_eq!(cpu.pc, 0x0203);
+        assert_eq!(call_stack.depth(), 1);
+    }
+
+    #[test]
+    fn test_manual_stack_manipulation() {
+        // main: JSR sub, STP; sub: PLA, PLA, PHA, PHA, RTS
+        let program = [0x20, 0x04, 0x02, 0xDB, 0x68, 0x68, 0x48, 0x48, 0x60];
+        let (_, call_stack) = run(&program, 3);
+        assert_eq!(call_stack.depth(), 2, "pulled return address");
+        let (cpu, call_stack) = run(&program, 6);
+        assert_eq!(cpu.pc, 0x0203);
+        assert_eq!(call_stack.depth(), 1);
+
+        // main: JSR sub, STP; sub: LDX #$FF, TXS
+        let program = [0x20, 0x04, 0x02, 0xDB, 0xA2, 0xFF, 0x9A];
+        let (_, call_stack) = run(&program, 3);
+        assert_eq!(call_stack.depth(), 1, "reset stack pointer");
+    }
+
+    #[test]
+    fn test_interrupt_frame() {
+        let mut call_stack = CallStack::new(0x0200);
+        call_stack.frames.push(Frame {
+            kind: FrameKind::Irq,
+            entry: 0xF000,
+            from: 0x0234,
+            return_s: 0xFF,
+        });
+        assert_eq!(
+            call_stack.backtrace(0xF002, &HashMap::new()),
+            ["#0 F002 in $F000 (IRQ handler)", "#1 0234 in $0200"]
+        );
+    }
+}
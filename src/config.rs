@@ -0,0 +1,345 @@
+use crate::device::blanking::VblankInterrupt;
+use crate::device::keyboard::{HostKey, InputMacro, KeyboardEmulation};
+use crate::device::uart::UartStdioMode;
+use crate::device::vid::{Border, Firmware, Palette};
+use crate::filter::{AspectRatio, DisplayFilter};
+use crate::memory::contiguous::{Contiguous, Ram, Rom};
+use crate::memory::mapped::{Banked, MappedMemory, PRIORITY_USER};
+use crate::timing::VideoStandard;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Configuration file that is loaded from the working directory if it exists
+pub const DEFAULT_PATH: &str = "cody.toml";
+
+/// Machine description read from a `cody.toml` file, command line options take precedence.
+///
+/// Relative paths are resolved against the directory containing the file.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub binary: BinaryConfig,
+    /// additional memory regions, mapped over the default memory map
+    pub memory: Vec<MemoryRegion>,
+    pub devices: DevicesConfig,
+    pub uart1: UartConfig,
+    pub display: DisplayConfig,
+    pub keymap: Option<KeyboardEmulation>,
+    pub joystick1: JoystickConfig,
+    pub joystick2: JoystickConfig,
+    /// host keys that run a sequence of cody key presses and releases
+    pub macros: Vec<InputMacro>,
+    /// start with autofire on the joystick fire buttons
+    pub autofire: bool,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BinaryConfig {
+    pub path: Option<PathBuf>,
+    pub cartridge: bool,
+    pub load_address: Option<u16>,
+    pub reset_vector: Option<u16>,
+    pub irq_vector: Option<u16>,
+    pub nmi_vector: Option<u16>,
+}
+
+/// RAM or ROM mapped at `start`, optionally filled with the contents of `file`
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MemoryRegion {
+    pub start: u16,
+    pub size: u16,
+    pub file: Option<PathBuf>,
+    #[serde(default)]
+    pub read_only: bool,
+    /// split the file into banks of `size` bytes, selected by writing to this address
+    pub bank_latch: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DevicesConfig {
+    pub vblank_interrupt: Option<VblankInterrupt>,
+    pub video_standard: Option<VideoStandard>,
+    pub firmware: Option<Firmware>,
+    /// cpu clock in Hz
+    pub cpu_frequency: Option<u32>,
+    pub mirror_via: bool,
+    pub keyboard_ghosting: bool,
+    pub open_bus: bool,
+    pub expansion_vias: Vec<u16>,
+    /// address of the host bridge's registers
+    pub host_bridge: Option<u16>,
+    /// address of the mouse's registers
+    pub mouse: Option<u16>,
+    /// address of the light pen's registers
+    pub light_pen: Option<u16>,
+    /// address of the raster line counter's registers
+    pub raster: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct UartConfig {
+    pub source: Option<PathBuf>,
+    pub sink: Option<PathBuf>,
+    pub stdio: Option<UartStdioMode>,
+    pub fix_newlines: bool,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DisplayConfig {
+    /// preset name or palette file
+    pub palette: Option<String>,
+    pub filter: Option<DisplayFilter>,
+    pub aspect_ratio: Option<AspectRatio>,
+    pub border: Option<Border>,
+    pub fullscreen: bool,
+    /// integer scale of the picture in the window
+    pub scale: Option<u32>,
+}
+
+/// Host keys of a joystick, directions that are not given keep their default keys
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct JoystickConfig {
+    pub up: Option<Vec<HostKey>>,
+    pub down: Option<Vec<HostKey>>,
+    pub left: Option<Vec<HostKey>>,
+    pub right: Option<Vec<HostKey>>,
+    pub fire: Option<Vec<HostKey>>,
+}
+
+impl JoystickConfig {
+    /// Keys in the order of [`crate::device::keyboard::JOYSTICK1`]
+    pub fn keys(&self) -> [Option<&Vec<HostKey>>; 5] {
+        [&self.up, &self.down, &self.left, &self.right, &self.fire].map(Option::as_ref)
+    }
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("error reading config {}: {e}", path.display()))?;
+        let mut config = Self::parse(&text)
+            .map_err(|e| format!("error parsing config {}: {e}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        for region in &config.memory {
+            if region.start as usize + region.size as usize > 0x10000 {
+                return Err(format!(
+                    "memory region at 0x{:04X} with size 0x{:04X} exceeds the address space",
+                    region.start, region.size
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    fn resolve_paths(&mut self, dir: &Path) {
+        let paths = [
+            self.binary.path.as_mut(),
+            self.uart1.source.as_mut(),
+            self.uart1.sink.as_mut(),
+        ]
+        .into_iter()
+        .chain(self.memory.iter_mut().map(|region| region.file.as_mut()));
+        for path in paths.flatten() {
+            // `-` is stdout for the UART sink
+            if path.as_os_str() != "-" {
+                *path = dir.join(&*path);
+            }
+        }
+        if let Some(palette) = &mut self.display.palette
+            && !Palette::PRESETS.contains(&palette.as_str())
+        {
+            *palette = dir.join(&*palette).to_string_lossy().into_owned();
+        }
+    }
+}
+
+impl MemoryRegion {
+    /// Map the region over the devices and memory, it must not overlap the other regions
+    pub fn add_to(&self, memory: &mut MappedMemory) -> Result<(), String> {
+        if let Some(other) = memory.overlapping(self.start, self.size, PRIORITY_USER) {
+            return Err(format!(
+                "memory region at 0x{:04X} overlaps the region at 0x{other:04X}",
+                self.start
+            ));
+        }
+        // the latches are mapped one priority higher, see `MappedMemory::add_banked`
+        if let Some(latch_address) = self.bank_latch
+            && let Some(other) = memory.overlapping(latch_address, 1, PRIORITY_USER + 1)
+        {
+            return Err(format!(
+                "bank latch of the memory region at 0x{:04X} overlaps the bank latch at 0x{other:04X}",
+                self.start
+            ));
+        }
+        let data = match &self.file {
+            Some(path) => {
+                std::fs::read(path).map_err(|e| format!("error reading {}: {e}", path.display()))?
+            }
+            None => vec![],
+        };
+        let size = self.size as usize;
+        if let Some(latch_address) = self.bank_latch {
+            let banked = if self.read_only {
+                Banked::from_bytes::<Rom>(self.size, &data)
+            } else {
+                Banked::from_bytes::<Ram>(self.size, &data)
+            };
+            memory.add_banked(self.start, self.size, PRIORITY_USER, banked, latch_address);
+        } else if self.read_only {
+            memory.add_rom(
+                self.start,
+                self.size,
+                PRIORITY_USER,
+                Rc::new(RefCell::new(Contiguous::from_bytes(size, &data))),
+            );
+        } else {
+            memory.add_ram(
+                self.start,
+                self.size,
+                PRIORITY_USER,
+                Rc::new(RefCell::new(Contiguous::from_bytes(size, &data))),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::keyboard::KeyCode;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r#"
+            keymap = "physical"
+            autofire = true
+
+            [binary]
+            path = "codybasic.bin"
+            load-address = 0xE000
+
+            [[memory]]
+            start = 0xA000
+            size = 0x1000
+            file = "extension.bin"
+            read-only = true
+
+            [[memory]]
+            start = 0x8000
+            size = 0x2000
+            file = "banked.bin"
+            bank-latch = 0x9E80
+
+            [devices]
+            vblank-interrupt = "via-ca1"
+            video-standard = "pal"
+            firmware = "rev1"
+            expansion-vias = [0x9E00]
+            host-bridge = 0x9E10
+            keyboard-ghosting = true
+
+            [uart1]
+            stdio = "line"
+
+            [joystick2]
+            up = ["KeyW"]
+            fire = ["Tab", "CapsLock"]
+
+            [display]
+            palette = "pepto"
+            filter = "crt"
+            aspect-ratio = "4:3"
+
+            [[macros]]
+            key = "Insert"
+            steps = "+Joystick1Fire 2 -Joystick1Fire"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keymap, Some(KeyboardEmulation::Physical));
+        assert_eq!(config.binary.load_address, Some(0xE000));
+        assert_eq!(config.memory[1].bank_latch, Some(0x9E80));
+        assert_eq!(
+            config.memory[..1],
+            [MemoryRegion {
+                start: 0xA000,
+                size: 0x1000,
+                file: Some("extension.bin".into()),
+                read_only: true,
+                bank_latch: None,
+            }]
+        );
+        assert_eq!(
+            config.devices.vblank_interrupt,
+            Some(VblankInterrupt::ViaCa1)
+        );
+        assert_eq!(config.devices.video_standard, Some(VideoStandard::Pal));
+        assert_eq!(config.devices.firmware, Some(Firmware::Rev1));
+        assert_eq!(config.devices.expansion_vias, [0x9E00]);
+        assert_eq!(config.devices.host_bridge, Some(0x9E10));
+        assert!(config.devices.keyboard_ghosting);
+        assert_eq!(config.uart1.stdio, Some(UartStdioMode::Line));
+        assert_eq!(config.joystick2.up, Some(vec![HostKey(KeyCode::KeyW)]));
+        assert_eq!(config.joystick2.down, None);
+        assert_eq!(config.joystick2.keys()[4].unwrap().len(), 2);
+        assert_eq!(config.display.filter, Some(DisplayFilter::Crt));
+        assert_eq!(config.display.aspect_ratio, Some(AspectRatio::Tv));
+        assert!(config.autofire);
+        assert_eq!(config.macros[0].key, HostKey(KeyCode::Insert));
+        assert_eq!(config.macros[0].steps.0.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Config::parse("unknown = 1").is_err());
+        assert!(Config::parse("[joystick1]\nup = [\"W\"]").is_err());
+        assert!(Config::parse("[[memory]]\nstart = 0xF000\nsize = 0x2000").is_err());
+        assert!(Config::parse("[[macros]]\nkey = \"Insert\"\nsteps = \"+KeyQ wait\"").is_err());
+    }
+
+    #[test]
+    fn test_add_to() {
+        let region = |start, bank_latch| MemoryRegion {
+            start,
+            size: 0x1000,
+            file: None,
+            read_only: false,
+            bank_latch,
+        };
+        let mut memory = MappedMemory::new();
+        region(0xA000, Some(0x9E80)).add_to(&mut memory).unwrap();
+        assert!(region(0xA800, None).add_to(&mut memory).is_err());
+        assert!(region(0xB000, Some(0x9E80)).add_to(&mut memory).is_err());
+        region(0xB000, Some(0x9E81)).add_to(&mut memory).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_paths() {
+        let mut config = Config::parse(
+            "[uart1]\nsource = \"in.bas\"\nsink = \"-\"\n[display]\npalette = \"cody\"",
+        )
+        .unwrap();
+        config.resolve_paths(Path::new("machines"));
+        assert_eq!(config.uart1.source, Some(PathBuf::from("machines/in.bas")));
+        assert_eq!(config.uart1.sink, Some(PathBuf::from("-")));
+        assert_eq!(config.display.palette.as_deref(), Some("cody"));
+    }
+}
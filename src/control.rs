@@ -0,0 +1,117 @@
+use log::{debug, info, warn};
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// Line based TCP endpoint that streams one [`FrameReport`] per frame to all connected clients
+#[derive(Debug)]
+pub struct ControlServer {
+    listener: TcpListener,
+    clients: Vec<TcpStream>,
+}
+
+impl ControlServer {
+    pub fn bind(address: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        info!("Control socket listening on {}", listener.local_addr()?);
+        Ok(Self {
+            listener,
+            clients: vec![],
+        })
+    }
+
+    fn accept_pending(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    // a client that stops reading must not stall the emulation
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        warn!("Control socket: rejecting client {address}: {e}");
+                        continue;
+                    }
+                    info!("Control socket: client {address} connected");
+                    self.clients.push(stream);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Control socket: error accepting client: {e}");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Send one line to all clients, dropping the ones that disconnected or fell behind so far that their socket
+    /// buffer is full
+    pub fn broadcast(&mut self, line: impl Display) {
+        self.accept_pending();
+        if self.clients.is_empty() {
+            return;
+        }
+
+        let line = format!("{line}\n");
+        self.clients
+            .retain_mut(|client| match client.write_all(line.as_bytes()) {
+                Ok(()) => true,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    warn!("Control socket: dropping client that does not read its reports");
+                    false
+                }
+                Err(e) => {
+                    debug!("Control socket: dropping client: {e}");
+                    false
+                }
+            });
+    }
+}
+
+/// Per-frame machine state, small enough to compare many instances without transferring framebuffers
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FrameReport {
+    pub frame: usize,
+    pub cycle: usize,
+    pub pc: u16,
+    pub running: bool,
+    pub hash: u64,
+}
+
+impl Display for FrameReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "frame={} cycle={} pc=0x{:04X} running={} hash=0x{:016X}",
+            self.frame, self.cycle, self.pc, self.running, self.hash
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_broadcast() {
+        let mut server = ControlServer::bind("127.0.0.1:0").unwrap();
+        let address = server.listener.local_addr().unwrap();
+        let reader = TcpStream::connect(address).unwrap();
+        let _stalled = TcpStream::connect(address).unwrap();
+
+        server.broadcast("hello");
+        assert_eq!(server.clients.len(), 2);
+        let mut line = String::new();
+        BufReader::new(&reader).read_line(&mut line).unwrap();
+        assert_eq!(line, "hello\n");
+
+        // neither client reads, the broadcast returns and drops them once their buffers are full
+        let long_line = "x".repeat(0x10000);
+        for _ in 0..0x1000 {
+            server.broadcast(&long_line);
+            if server.clients.is_empty() {
+                break;
+            }
+        }
+        assert!(server.clients.is_empty());
+    }
+}
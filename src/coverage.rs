@@ -0,0 +1,159 @@
+use crate::assembler::disassemble_instruction;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Addresses at which the cpu started executing an instruction
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    executed: Box<[bool]>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self {
+            executed: vec![false; 0x10000].into_boxed_slice(),
+        }
+    }
+}
+
+impl Coverage {
+    pub fn record(&mut self, pc: u16) {
+        self.executed[pc as usize] = true;
+    }
+
+    pub fn is_executed(&self, address: u16) -> bool {
+        self.executed[address as usize]
+    }
+
+    /// Write a listing of `data` loaded at `address` with every executed instruction marked by `*`.
+    ///
+    /// Decoding follows the executed instructions, so data between code does not hide them.
+    /// Addresses found in `symbols` are replaced by their name.
+    pub fn write_report(
+        &self,
+        data: &[u8],
+        address: u16,
+        symbols: &HashMap<u16, String>,
+        mut w: impl Write,
+    ) -> std::io::Result<()> {
+        let mut lines = vec![];
+        let (mut instructions, mut executed_instructions) = (0, 0);
+        let (mut executed_bytes, mut offset) = (0, 0);
+        while offset < data.len() {
+            let current = address.wrapping_add(offset as u16);
+            let mut instruction = disassemble_instruction(&data[offset..], current);
+            let executed = self.is_executed(current);
+            if !executed
+                && (1..instruction.bytes.len())
+                    .any(|i| self.is_executed(current.wrapping_add(i as u16)))
+            {
+                // an executed instruction starts inside, so this can't be code
+                instruction = disassemble_instruction(&data[offset..offset + 1], current);
+            }
+
+            if instruction.instruction.is_some() {
+                instructions += 1;
+            }
+            if executed {
+                executed_instructions += 1;
+                executed_bytes += instruction.bytes.len();
+            }
+            offset += instruction.bytes.len();
+            lines.push((executed, instruction));
+        }
+
+        writeln!(
+            w,
+            "; executed {executed_instructions} of {instructions} decoded instructions ({:.1}%), {executed_bytes} of {} bytes",
+            percent(executed_instructions, instructions),
+            data.len()
+        )?;
+        for (executed, instruction) in lines {
+            let marker = if executed { '*' } else { ' ' };
+            writeln!(w, "{marker} {}", instruction.format(symbols))?;
+        }
+        w.flush()
+    }
+}
+
+/// Executed addresses from a report written by [`Coverage::write_report`] or a trace with one hex address per line
+pub fn parse_executed(text: &str) -> Result<Vec<u16>, String> {
+    let mut addresses = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap();
+        let address = if let Some(executed) = line.strip_prefix('*') {
+            executed.split_whitespace().next()
+        } else {
+            // unmarked lines of a report have more than one column
+            let mut columns = line.split_whitespace();
+            match (columns.next(), columns.next()) {
+                (Some(address), None) => Some(address),
+                _ => None,
+            }
+        };
+        if let Some(address) = address {
+            let hex = address
+                .strip_prefix('$')
+                .or_else(|| address.strip_prefix("0x"))
+                .unwrap_or(address);
+            addresses.push(
+                u16::from_str_radix(hex, 16)
+                    .map_err(|_| format!("line {}: invalid address {address:?}", i + 1))?,
+            );
+        }
+    }
+    Ok(addresses)
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * part as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        // BRA +1, .byte $A9, LDA #$01, STP
+        let data = [0x80, 0x01, 0xA9, 0xA9, 0x01, 0xDB];
+        let mut coverage = Coverage::default();
+        for pc in [0xE000, 0xE003] {
+            coverage.record(pc);
+        }
+
+        let mut report = vec![];
+        coverage
+            .write_report(&data, 0xE000, &HashMap::new(), &mut report)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "; executed 2 of 3 decoded instructions (66.7%), 4 of 6 bytes
+* E000  80 01     BRA $E003
+  E002  A9        .byte $A9
+* E003  A9 01     LDA #$01
+  E005  DB        STP
+"
+        );
+    }
+
+    #[test]
+    fn test_parse_executed() {
+        let report = "; executed 2 of 3 decoded instructions (66.7%), 4 of 6 bytes
+* E000  80 01     BRA $E003
+  E002  A9        .byte $A9
+* E003  A9 01     LDA #$01
+  E005  DB        STP
+";
+        assert_eq!(parse_executed(report), Ok(vec![0xE000, 0xE003]));
+        assert_eq!(
+            parse_executed("E000\n$E003 ; loop"),
+            Ok(vec![0xE000, 0xE003])
+        );
+        assert!(parse_executed("E000\nloop").is_err());
+    }
+}
@@ -1,12 +1,18 @@
+use crate::decode_cache::{DecodeCache, Instruction};
+use crate::interrupt::InterruptController;
 use crate::memory::Memory;
 use crate::opcode::{AddressingMode, Opcode, get_instruction};
 use bitfields::bitfield;
-use log::trace;
+use log::{error, trace};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
 
 pub const INITIAL_STACK_POINTER: u8 = 0xFD;
 pub const NMI_VECTOR: u16 = 0xFFFA;
 pub const RESET_VECTOR: u16 = 0xFFFC;
 pub const IRQ_VECTOR: u16 = 0xFFFE;
+/// Cycles of the interrupt and reset sequences: two dummy reads, three stack accesses and the vector
+const INTERRUPT_CYCLES: u8 = 7;
 
 #[bitfield(u8)]
 #[derive(Eq, PartialEq)]
@@ -24,6 +30,60 @@ pub struct Status {
     negative: bool,
 }
 
+/// What to do when the cpu fetches an opcode that is undefined on the 65C02
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum IllegalOpcodePolicy {
+    /// Skip the opcode byte and continue with the next one
+    #[default]
+    Nop,
+    /// Halt the cpu with the program counter pointing at the offending opcode
+    Trap,
+    /// Execute the NMOS 6502 behavior where there is one, otherwise fall back to `nop`
+    Nmos,
+}
+
+/// How the cpu fetches instructions
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum Engine {
+    /// Fetch every instruction over the bus
+    #[default]
+    Interpreter,
+    /// Keep decoded instructions of plain RAM and ROM by address and skip their bus accesses, invalidated on writes
+    Cached,
+}
+
+/// What the cpu does after an instruction hook ran
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HookAction {
+    Continue,
+    /// add cycles to the step, e.g. for a peripheral that stalls the cpu
+    Delay(u8),
+    /// do not execute the instruction and take this many cycles instead, after the instruction the same as `Delay`
+    Skip(u8),
+    /// halt the cpu like STP
+    Stop,
+}
+
+/// Runs at an instruction boundary with full access to the registers and memory.
+///
+/// Writes through `cpu.memory` bypass [`Engine::Cached`], a hook that patches code has to [`Cpu::flush_decode_cache`].
+pub type InstructionHook<M> = fn(&mut Cpu<M>) -> HookAction;
+
+/// Registers and execution state of the cpu, without the memory and the cycle counter
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CpuState {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    pub pc: u16,
+    pub run: bool,
+    pub wai: bool,
+    /// an NMI edge was latched and not serviced yet
+    pub nmi_pending: bool,
+}
+
 #[derive(Debug, Default)]
 pub struct Cpu<M> {
     /// A register
@@ -46,6 +106,48 @@ pub struct Cpu<M> {
     wai: bool,
     /// cycles elapsed since turning on
     cycle: usize,
+    /// IRQ and NMI lines of the devices
+    interrupts: InterruptController,
+    /// behavior for undefined opcodes
+    illegal_opcode_policy: IllegalOpcodePolicy,
+    /// number of times each undefined opcode was fetched
+    illegal_opcodes: BTreeMap<u8, usize>,
+    /// number of serviced IRQs
+    irq_count: usize,
+    /// number of serviced NMIs
+    nmi_count: usize,
+    /// address and opcode byte of the instruction executed by the last step
+    last_instruction: Option<(u16, u8)>,
+    /// decoded instructions, only with [`Engine::Cached`]
+    decode_cache: Option<Box<DecodeCache>>,
+    /// cached bytes of the instruction being executed
+    fetched: Option<Instruction>,
+    /// runs before every instruction, after a pending interrupt was taken
+    pre_instruction_hook: Option<InstructionHook<M>>,
+    /// runs after every instruction
+    post_instruction_hook: Option<InstructionHook<M>>,
+}
+
+impl<M> Display for Cpu<M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let p = self.p.into_bits();
+        let flags: String = "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                if (p & (0x80 >> i)) != 0 {
+                    c
+                } else {
+                    c.to_ascii_lowercase()
+                }
+            })
+            .collect();
+        write!(
+            f,
+            "PC:0x{:04X} A:0x{:02X} X:0x{:02X} Y:0x{:02X} S:0x{:02X} P:0x{p:02X} ({flags}) cycles:{}",
+            self.pc, self.a, self.x, self.y, self.s, self.cycle
+        )
+    }
 }
 
 impl<M: Memory> Cpu<M> {
@@ -61,21 +163,141 @@ impl<M: Memory> Cpu<M> {
             run: false,
             wai: false,
             cycle: 0,
+            interrupts: InterruptController::default(),
+            illegal_opcode_policy: IllegalOpcodePolicy::default(),
+            illegal_opcodes: BTreeMap::new(),
+            irq_count: 0,
+            nmi_count: 0,
+            last_instruction: None,
+            decode_cache: None,
+            fetched: None,
+            pre_instruction_hook: None,
+            post_instruction_hook: None,
         };
         cpu.reset();
         cpu
     }
 
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_opcode_policy = policy;
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.decode_cache = match engine {
+            Engine::Interpreter => None,
+            Engine::Cached => Some(Box::default()),
+        };
+    }
+
+    /// Run `hook` before every instruction, it sees the program counter of the instruction about to be executed
+    pub fn set_pre_instruction_hook(&mut self, hook: InstructionHook<M>) {
+        self.pre_instruction_hook = Some(hook);
+    }
+
+    /// Run `hook` after every instruction, when the cycle counter already includes the instruction
+    pub fn set_post_instruction_hook(&mut self, hook: InstructionHook<M>) {
+        self.post_instruction_hook = Some(hook);
+    }
+
+    pub fn clear_instruction_hooks(&mut self) {
+        self.pre_instruction_hook = None;
+        self.post_instruction_hook = None;
+    }
+
+    /// Forget all decoded instructions, needed after memory was changed without the cpu
+    pub fn flush_decode_cache(&mut self) {
+        if let Some(decode_cache) = &mut self.decode_cache {
+            decode_cache.flush();
+        }
+    }
+
+    /// Number of times each undefined opcode was fetched, keyed by opcode byte
+    pub fn illegal_opcodes(&self) -> &BTreeMap<u8, usize> {
+        &self.illegal_opcodes
+    }
+
+    /// false after STP or when trapped on an illegal opcode, only a reset starts the cpu again
+    pub const fn is_running(&self) -> bool {
+        self.run
+    }
+
+    /// true after WAI until an interrupt line is asserted
+    pub const fn is_waiting(&self) -> bool {
+        self.wai
+    }
+
+    /// cycles elapsed since turning on, resets count like interrupts
+    pub const fn cycle(&self) -> usize {
+        self.cycle
+    }
+
+    /// number of IRQs serviced since turning on
+    pub const fn irq_count(&self) -> usize {
+        self.irq_count
+    }
+
+    /// number of NMIs serviced since turning on
+    pub const fn nmi_count(&self) -> usize {
+        self.nmi_count
+    }
+
+    /// Interrupt lines as sampled by the last step
+    pub const fn interrupts(&self) -> &InterruptController {
+        &self.interrupts
+    }
+
+    /// Address and opcode byte of the instruction executed by the last step, `None` if it only waited.
+    ///
+    /// Differs from the program counter before the step if an interrupt was serviced.
+    pub const fn last_instruction(&self) -> Option<(u16, u8)> {
+        self.last_instruction
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            s: self.s,
+            p: self.p.into_bits(),
+            pc: self.pc,
+            run: self.run,
+            wai: self.wai,
+            nmi_pending: self.interrupts.nmi_pending(),
+        }
+    }
+
+    /// Restore registers and execution state, the cycle counter keeps counting up so devices stay in sync
+    pub fn restore_state(&mut self, state: &CpuState) {
+        self.a = state.a;
+        self.x = state.x;
+        self.y = state.y;
+        self.s = state.s;
+        self.p = Status::from_bits(state.p);
+        self.pc = state.pc;
+        self.run = state.run;
+        self.wai = state.wai;
+        self.interrupts.set_nmi_pending(state.nmi_pending);
+    }
+
+    /// Run the reset sequence and jump through the reset vector, also starts a cpu stopped by STP.
+    ///
+    /// Like on the real chip A, X and Y are kept and the stack accesses of an interrupt become reads,
+    /// moving the stack pointer down by 3 without writing.
     pub fn reset(&mut self) {
-        self.run = true;
-        self.a = 0;
-        self.x = 0;
-        self.y = 0;
-        self.s = INITIAL_STACK_POINTER;
-        self.p = Status::default();
+        self.memory.read_u8(self.pc);
+        self.memory.read_u8(self.pc);
+        for _ in 0..3 {
+            self.memory.read_u8(0x0100 + self.s as u16);
+            self.s = self.s.wrapping_sub(1);
+        }
+        self.p.set_irqb_disable(true);
+        self.p.set_decimal_mode(false);
         self.pc = self.memory.read_u16(RESET_VECTOR);
+        self.run = true;
         self.wai = false;
-        self.cycle = 0;
+        self.interrupts = InterruptController::default();
+        self.cycle = self.cycle.wrapping_add(INTERRUPT_CYCLES as usize);
     }
 
     pub fn run(&mut self) {
@@ -86,29 +308,61 @@ impl<M: Memory> Cpu<M> {
 
     /// execute one instruction, returns the number of elapsed cycles
     pub fn step_instruction(&mut self) -> u8 {
+        self.last_instruction = None;
         if !self.run {
             return 0;
         }
 
-        let interrupt = self.memory.update(self.cycle);
-        if interrupt.is_nmi() || interrupt.is_irq() {
+        self.interrupts
+            .sample(self.memory.update(self.cycle), self.cycle);
+        let nmi = self.interrupts.nmi_pending();
+        let mut interrupt_cycles = 0;
+        if nmi || self.interrupts.irq() {
+            // an IRQ wakes up WAI even while disabled, execution then continues after the WAI
             self.wai = false;
-            if interrupt.is_nmi() || (interrupt.is_irq() && !self.p.irqb_disable()) {
+            if nmi || !self.p.irqb_disable() {
+                interrupt_cycles = INTERRUPT_CYCLES;
+                if nmi {
+                    self.interrupts.acknowledge_nmi();
+                    self.nmi_count += 1;
+                } else {
+                    self.irq_count += 1;
+                }
+                self.memory.read_u8(self.pc);
+                self.memory.read_u8(self.pc);
                 self.push_pc();
                 self.push_flags_no_brk();
                 self.p.set_irqb_disable(true);
                 self.p.set_decimal_mode(false);
-                self.pc = self.memory.read_u16(if interrupt.is_nmi() {
-                    NMI_VECTOR
-                } else {
-                    IRQ_VECTOR
-                });
+                self.pc = self
+                    .memory
+                    .read_u16(if nmi { NMI_VECTOR } else { IRQ_VECTOR });
             }
         }
 
         if !self.wai {
+            let mut hook_cycles = 0;
+            if let Some(hook) = self.pre_instruction_hook {
+                match hook(self) {
+                    HookAction::Continue => {}
+                    HookAction::Delay(cycles) => hook_cycles = cycles,
+                    HookAction::Skip(cycles) => {
+                        return self.finish_step(interrupt_cycles.saturating_add(cycles));
+                    }
+                    HookAction::Stop => {
+                        self.run = false;
+                        return self.finish_step(interrupt_cycles);
+                    }
+                }
+            }
+
             let pc = self.pc;
-            let opcode = get_instruction(self.read_u8_inc_pc());
+            if let Some(decode_cache) = &mut self.decode_cache {
+                self.fetched = decode_cache.fetch(&mut self.memory, pc);
+            }
+            let byte = self.read_u8_inc_pc();
+            self.last_instruction = Some((pc, byte));
+            let opcode = get_instruction(byte);
             let cycles = if let Some(opcode) = opcode {
                 trace!("Executing opcode 0x{pc:04X} {opcode:?}");
                 let mut extra_cycles = 0;
@@ -138,15 +392,13 @@ impl<M: Memory> Cpu<M> {
                             self.set_a(m << 1);
                             self.p.set_carry((m & 0x80) != 0);
                         } else {
-                            let (addr, page_cross) = self.read_address_operand(opcode.parameter_1);
-                            if page_cross {
-                                extra_cycles += 1;
-                            }
-                            let m = self.memory.read_u8(addr);
-                            let value = m << 1;
-                            self.memory.write_u8(addr, value);
-                            self.update_nz_flags(value);
-                            self.p.set_carry((m & 0x80) != 0);
+                            extra_cycles +=
+                                self.read_modify_write(opcode.parameter_1, false, |cpu, m| {
+                                    let value = m << 1;
+                                    cpu.update_nz_flags(value);
+                                    cpu.p.set_carry((m & 0x80) != 0);
+                                    value
+                                }) as u8;
                         }
                     }
                     Opcode::BBR0 => extra_cycles += self.bbr(0),
@@ -185,7 +437,9 @@ impl<M: Memory> Cpu<M> {
                     Opcode::BPL => extra_cycles += self.branch(!self.p.negative()),
                     Opcode::BRA => extra_cycles += self.branch(true),
                     Opcode::BRK => {
-                        self.pc = self.pc.wrapping_add(1); // skip unused 2nd instruction byte
+                        // skip the signature byte
+                        self.memory.read_u8(self.pc);
+                        self.pc = self.pc.wrapping_add(1);
                         // BRK logic
                         self.push_pc();
                         self.push_flags();
@@ -221,15 +475,11 @@ impl<M: Memory> Cpu<M> {
                         if opcode.parameter_1 == AddressingMode::Accumulator {
                             self.set_a(self.a.wrapping_sub(1));
                         } else {
-                            let (addr, page_cross) = self.read_address_operand(opcode.parameter_1);
-                            if page_cross && opcode.parameter_1 != AddressingMode::AbsoluteIndexedX
-                            {
-                                extra_cycles += 1;
-                            }
-                            let value = self.memory.read_u8(addr);
-                            let new_value = value.wrapping_sub(1);
-                            self.memory.write_u8(addr, new_value);
-                            self.update_nz_flags(new_value);
+                            self.read_modify_write(opcode.parameter_1, true, |cpu, m| {
+                                let value = m.wrapping_sub(1);
+                                cpu.update_nz_flags(value);
+                                value
+                            });
                         }
                     }
                     Opcode::DEX => self.set_x(self.x.wrapping_sub(1)),
@@ -245,15 +495,11 @@ impl<M: Memory> Cpu<M> {
                         if opcode.parameter_1 == AddressingMode::Accumulator {
                             self.set_a(self.a.wrapping_add(1));
                         } else {
-                            let (addr, page_cross) = self.read_address_operand(opcode.parameter_1);
-                            if page_cross && opcode.parameter_1 != AddressingMode::AbsoluteIndexedX
-                            {
-                                extra_cycles += 1;
-                            }
-                            let value = self.memory.read_u8(addr);
-                            let new_value = value.wrapping_add(1);
-                            self.memory.write_u8(addr, new_value);
-                            self.update_nz_flags(new_value);
+                            self.read_modify_write(opcode.parameter_1, true, |cpu, m| {
+                                let value = m.wrapping_add(1);
+                                cpu.update_nz_flags(value);
+                                value
+                            });
                         }
                     }
                     Opcode::INX => self.set_x(self.x.wrapping_add(1)),
@@ -301,15 +547,13 @@ impl<M: Memory> Cpu<M> {
                             self.set_a(m >> 1);
                             self.p.set_carry((m & 0b1) != 0);
                         } else {
-                            let (addr, page_cross) = self.read_address_operand(opcode.parameter_1);
-                            if page_cross {
-                                extra_cycles += 1;
-                            }
-                            let m = self.memory.read_u8(addr);
-                            let value = m >> 1;
-                            self.memory.write_u8(addr, value);
-                            self.update_nz_flags(value);
-                            self.p.set_carry((m & 0b1) != 0);
+                            extra_cycles +=
+                                self.read_modify_write(opcode.parameter_1, false, |cpu, m| {
+                                    let value = m >> 1;
+                                    cpu.update_nz_flags(value);
+                                    cpu.p.set_carry((m & 0b1) != 0);
+                                    value
+                                }) as u8;
                         }
                     }
                     Opcode::NOP => {}
@@ -353,15 +597,13 @@ impl<M: Memory> Cpu<M> {
                             self.set_a((m << 1) | self.p.carry() as u8);
                             self.p.set_carry((m & 0x80) != 0);
                         } else {
-                            let (addr, page_cross) = self.read_address_operand(opcode.parameter_1);
-                            if page_cross {
-                                extra_cycles += 1;
-                            }
-                            let m = self.memory.read_u8(addr);
-                            let value = (m << 1) | self.p.carry() as u8;
-                            self.memory.write_u8(addr, value);
-                            self.update_nz_flags(value);
-                            self.p.set_carry((m & 0x80) != 0);
+                            extra_cycles +=
+                                self.read_modify_write(opcode.parameter_1, false, |cpu, m| {
+                                    let value = (m << 1) | cpu.p.carry() as u8;
+                                    cpu.update_nz_flags(value);
+                                    cpu.p.set_carry((m & 0x80) != 0);
+                                    value
+                                }) as u8;
                         }
                     }
                     Opcode::ROR => {
@@ -370,15 +612,13 @@ impl<M: Memory> Cpu<M> {
                             self.set_a((m >> 1) | ((self.p.carry() as u8) << 7));
                             self.p.set_carry((m & 0b1) != 0);
                         } else {
-                            let (addr, page_cross) = self.read_address_operand(opcode.parameter_1);
-                            if page_cross {
-                                extra_cycles += 1;
-                            }
-                            let m = self.memory.read_u8(addr);
-                            let value = (m >> 1) | ((self.p.carry() as u8) << 7);
-                            self.memory.write_u8(addr, value);
-                            self.update_nz_flags(value);
-                            self.p.set_carry((m & 0b1) != 0);
+                            extra_cycles +=
+                                self.read_modify_write(opcode.parameter_1, false, |cpu, m| {
+                                    let value = (m >> 1) | ((cpu.p.carry() as u8) << 7);
+                                    cpu.update_nz_flags(value);
+                                    cpu.p.set_carry((m & 0b1) != 0);
+                                    value
+                                }) as u8;
                         }
                     }
                     Opcode::RTI => {
@@ -414,42 +654,34 @@ impl<M: Memory> Cpu<M> {
                     Opcode::SMB7 => self.smb(7),
                     Opcode::STA => {
                         let (addr, _) = self.read_address_operand(opcode.parameter_1);
-                        self.memory.write_u8(addr, self.a);
+                        self.write_u8(addr, self.a);
                     }
                     Opcode::STP => self.run = false,
                     Opcode::STX => {
                         let (addr, _) = self.read_address_operand(opcode.parameter_1);
-                        self.memory.write_u8(addr, self.x);
+                        self.write_u8(addr, self.x);
                     }
                     Opcode::STY => {
                         let (addr, _) = self.read_address_operand(opcode.parameter_1);
-                        self.memory.write_u8(addr, self.y);
+                        self.write_u8(addr, self.y);
                     }
                     Opcode::STZ => {
                         let (addr, _) = self.read_address_operand(opcode.parameter_1);
-                        self.memory.write_u8(addr, 0);
+                        self.write_u8(addr, 0);
                     }
                     Opcode::TAX => self.set_x(self.a),
                     Opcode::TAY => self.set_y(self.a),
                     Opcode::TRB => {
-                        let (addr, page_cross) = self.read_address_operand(opcode.parameter_1);
-                        if page_cross {
-                            extra_cycles += 1;
-                        }
-                        let a = self.a;
-                        let m = self.memory.read_u8(addr);
-                        self.memory.write_u8(addr, m & !a);
-                        self.p.set_zero((m & a) == 0);
+                        self.read_modify_write(opcode.parameter_1, false, |cpu, m| {
+                            cpu.p.set_zero((m & cpu.a) == 0);
+                            m & !cpu.a
+                        });
                     }
                     Opcode::TSB => {
-                        let (addr, page_cross) = self.read_address_operand(opcode.parameter_1);
-                        if page_cross {
-                            extra_cycles += 1;
-                        }
-                        let a = self.a;
-                        let m = self.memory.read_u8(addr);
-                        self.memory.write_u8(addr, m | a);
-                        self.p.set_zero((m & a) == 0);
+                        self.read_modify_write(opcode.parameter_1, false, |cpu, m| {
+                            cpu.p.set_zero((m & cpu.a) == 0);
+                            m | cpu.a
+                        });
                     }
                     Opcode::TSX => self.set_x(self.s),
                     Opcode::TXA => self.set_a(self.x),
@@ -460,29 +692,259 @@ impl<M: Memory> Cpu<M> {
 
                 opcode.cycles + extra_cycles
             } else {
-                // TODO: implement undocumented opcodes with correct cycle count
-                1
+                self.execute_illegal(pc, byte)
             };
+            self.fetched = None;
 
-            self.cycle = self.cycle.wrapping_add(cycles as usize);
-            return cycles;
+            let cycles = self.finish_step((cycles + interrupt_cycles).saturating_add(hook_cycles));
+            let Some(hook) = self.post_instruction_hook else {
+                return cycles;
+            };
+            return match hook(self) {
+                HookAction::Continue => cycles,
+                HookAction::Delay(delay) | HookAction::Skip(delay) => {
+                    cycles.saturating_add(self.finish_step(delay))
+                }
+                HookAction::Stop => {
+                    self.run = false;
+                    cycles
+                }
+            };
         }
 
-        // cycles for WAI check
-        // TODO: find exact value
+        // the clock keeps running while waiting, check the interrupt lines every cycle
+        self.cycle = self.cycle.wrapping_add(1);
         1
     }
 
+    /// Advance the cycle counter by the cycles of a step and return them
+    fn finish_step(&mut self, cycles: u8) -> u8 {
+        self.cycle = self.cycle.wrapping_add(cycles as usize);
+        cycles
+    }
+
+    fn execute_illegal(&mut self, pc: u16, byte: u8) -> u8 {
+        *self.illegal_opcodes.entry(byte).or_default() += 1;
+        match self.illegal_opcode_policy {
+            IllegalOpcodePolicy::Nop => {
+                trace!("Skipping illegal opcode 0x{pc:04X} 0x{byte:02X}");
+                // TODO: implement undocumented opcodes with correct cycle count
+                1
+            }
+            IllegalOpcodePolicy::Trap => {
+                error!("Trapped on illegal opcode 0x{byte:02X} at 0x{pc:04X}");
+                self.pc = pc;
+                self.run = false;
+                1
+            }
+            IllegalOpcodePolicy::Nmos => {
+                if let Some(cycles) = self.execute_nmos(byte) {
+                    trace!("Executing illegal opcode 0x{pc:04X} 0x{byte:02X} as NMOS");
+                    cycles
+                } else {
+                    trace!("Skipping illegal opcode 0x{pc:04X} 0x{byte:02X}");
+                    1
+                }
+            }
+        }
+    }
+
+    /// Execute the NMOS 6502 behavior of an opcode that is undefined on the 65C02.
+    ///
+    /// Only the stable combined instructions whose slot is still free on the 65C02 are emulated,
+    /// returns `None` without touching any state for all other opcodes.
+    fn execute_nmos(&mut self, byte: u8) -> Option<u8> {
+        let mode = match byte & 0x1F {
+            0x03 => AddressingMode::ZeroPageIndexedIndirectX,
+            0x13 => AddressingMode::ZeroPageIndirectIndexedY,
+            0x1B => AddressingMode::AbsoluteIndexedY,
+            0x0B => AddressingMode::Immediate,
+            _ => return None,
+        };
+        let rmw_cycles = if mode == AddressingMode::AbsoluteIndexedY {
+            7
+        } else {
+            8
+        };
+
+        let cycles = match byte {
+            // SLO: ASL + ORA
+            0x03 | 0x13 | 0x1B => {
+                self.nmos_rmw(mode, |cpu, m| {
+                    let value = m << 1;
+                    cpu.p.set_carry((m & 0x80) != 0);
+                    cpu.set_a(cpu.a | value);
+                    value
+                });
+                rmw_cycles
+            }
+            // RLA: ROL + AND
+            0x23 | 0x33 | 0x3B => {
+                self.nmos_rmw(mode, |cpu, m| {
+                    let value = (m << 1) | cpu.p.carry() as u8;
+                    cpu.p.set_carry((m & 0x80) != 0);
+                    cpu.set_a(cpu.a & value);
+                    value
+                });
+                rmw_cycles
+            }
+            // SRE: LSR + EOR
+            0x43 | 0x53 | 0x5B => {
+                self.nmos_rmw(mode, |cpu, m| {
+                    let value = m >> 1;
+                    cpu.p.set_carry((m & 0b1) != 0);
+                    cpu.set_a(cpu.a ^ value);
+                    value
+                });
+                rmw_cycles
+            }
+            // RRA: ROR + ADC
+            0x63 | 0x73 | 0x7B => {
+                self.nmos_rmw(mode, |cpu, m| {
+                    let value = (m >> 1) | ((cpu.p.carry() as u8) << 7);
+                    cpu.p.set_carry((m & 0b1) != 0);
+                    if cpu.p.decimal_mode() {
+                        cpu.do_addition_decimal(value);
+                    } else {
+                        cpu.do_addition(value);
+                    }
+                    value
+                });
+                rmw_cycles
+            }
+            // SAX: store A & X
+            0x83 => {
+                let (addr, _) = self.read_address_operand(mode);
+                self.write_u8(addr, self.a & self.x);
+                6
+            }
+            // LAX: LDA + LDX
+            0xA3 | 0xB3 => {
+                let (m, page_cross) = self.read_value_operand(mode);
+                self.set_a(m);
+                self.x = m;
+                if mode == AddressingMode::ZeroPageIndexedIndirectX {
+                    6
+                } else {
+                    5 + page_cross as u8
+                }
+            }
+            // DCP: DEC + CMP
+            0xC3 | 0xD3 => {
+                self.nmos_rmw(mode, |cpu, m| {
+                    let value = m.wrapping_sub(1);
+                    cpu.update_nz_flags(cpu.a.wrapping_sub(value));
+                    cpu.p.set_carry(cpu.a >= value);
+                    value
+                });
+                rmw_cycles
+            }
+            // ISC: INC + SBC
+            0xE3 | 0xF3 | 0xFB => {
+                self.nmos_rmw(mode, |cpu, m| {
+                    let value = m.wrapping_add(1);
+                    if cpu.p.decimal_mode() {
+                        cpu.do_subtraction_decimal(value);
+                    } else {
+                        cpu.do_subtraction(value);
+                    }
+                    value
+                });
+                rmw_cycles
+            }
+            // ANC: AND + copy N to C
+            0x0B | 0x2B => {
+                let (m, _) = self.read_value_operand(mode);
+                self.set_a(self.a & m);
+                self.p.set_carry(self.p.negative());
+                2
+            }
+            // ALR: AND + LSR A
+            0x4B => {
+                let (m, _) = self.read_value_operand(mode);
+                let value = self.a & m;
+                self.p.set_carry((value & 0b1) != 0);
+                self.set_a(value >> 1);
+                2
+            }
+            // USBC: same as SBC #
+            0xEB => {
+                let (m, _) = self.read_value_operand(mode);
+                if self.p.decimal_mode() {
+                    self.do_subtraction_decimal(m);
+                } else {
+                    self.do_subtraction(m);
+                }
+                2
+            }
+            _ => return None,
+        };
+        Some(cycles)
+    }
+
+    /// Read, modify and write memory with the bus accesses of the 65C02, returns if a page boundary was crossed.
+    ///
+    /// The value is read twice before the result is written. Adding the index re-reads the last operand byte,
+    /// for absolute X only on a page crossing unless `index_cycle_always`.
+    fn read_modify_write(
+        &mut self,
+        addressing_mode: AddressingMode,
+        index_cycle_always: bool,
+        op: impl FnOnce(&mut Self, u8) -> u8,
+    ) -> bool {
+        let (addr, page_cross) = match addressing_mode {
+            AddressingMode::ZeroPageIndexedX => {
+                let operand = self.read_u8_inc_pc();
+                self.memory.read_u8(self.pc.wrapping_sub(1));
+                (operand.wrapping_add(self.x) as u16, false)
+            }
+            AddressingMode::AbsoluteIndexedX => {
+                let (addr, page_cross) = self.read_address_operand(addressing_mode);
+                if page_cross || index_cycle_always {
+                    self.memory.read_u8(self.pc.wrapping_sub(1));
+                }
+                (addr, page_cross)
+            }
+            _ => self.read_address_operand(addressing_mode),
+        };
+        let m = self.memory.read_u8(addr);
+        self.memory.read_u8(addr);
+        let value = op(self, m);
+        self.write_u8(addr, value);
+        page_cross
+    }
+
+    fn nmos_rmw(&mut self, addressing_mode: AddressingMode, op: impl FnOnce(&mut Self, u8) -> u8) {
+        let (addr, _) = self.read_address_operand(addressing_mode);
+        let m = self.memory.read_u8(addr);
+        let value = op(self, m);
+        self.write_u8(addr, value);
+    }
+
+    /// Write over the bus, cached instructions overlapping `address` are forgotten
+    fn write_u8(&mut self, address: u16, value: u8) {
+        if let Some(decode_cache) = &mut self.decode_cache {
+            decode_cache.invalidate(address);
+            // the rest of the current instruction is fetched over the bus again
+            self.fetched = None;
+        }
+        self.memory.write_u8(address, value);
+    }
+
+    /// Fetch the next instruction byte, from the decode cache if the current instruction is cached
     fn read_u8_inc_pc(&mut self) -> u8 {
-        let result = self.memory.read_u8(self.pc);
+        let result = match self.fetched.and_then(|fetched| fetched.byte_at(self.pc)) {
+            Some(byte) => byte,
+            None => self.memory.read_u8(self.pc),
+        };
         self.pc += 1;
         result
     }
 
     fn read_u16_inc_pc(&mut self) -> u16 {
-        let result = self.memory.read_u16(self.pc);
-        self.pc += 2;
-        result
+        let l = self.read_u8_inc_pc();
+        let h = self.read_u8_inc_pc();
+        u16::from_le_bytes([l, h])
     }
 
     /// return value and if a page boundary was crossed
@@ -580,8 +1042,14 @@ impl<M: Memory> Cpu<M> {
         self.update_nz_flags(value);
     }
 
+    /// Continue after the JSR that called the current subroutine, like RTS without its cycles
+    pub fn return_from_subroutine(&mut self) {
+        self.pop_pc();
+        self.pc = self.pc.wrapping_add(1);
+    }
+
     fn push(&mut self, value: u8) {
-        self.memory.write_u8(0x0100 + self.s as u16, value);
+        self.write_u8(0x0100 + self.s as u16, value);
         self.s = self.s.wrapping_sub(1);
     }
 
@@ -627,62 +1095,49 @@ impl<M: Memory> Cpu<M> {
         self.p.set_overflow(((a ^ r) & (m ^ r) & 0x80) != 0);
     }
 
+    /// Decimal addition as on the 65C02, also defined for invalid BCD operands.
+    ///
+    /// N and Z reflect the decimal result, V is taken from the signed sum before the high digit is adjusted.
     fn do_addition_decimal(&mut self, m: u8) {
-        let a = self.a;
-        let cf = self.p.carry();
-
-        let mut c = cf as u8;
-        let mut lo = (a & 0xf).wrapping_add(m & 0xf).wrapping_add(c);
-        c = (lo > 9) as u8;
-        if c != 0 {
-            lo = (lo - 10) & 0xf;
+        let (a, m, c) = (self.a as u16, m as u16, self.p.carry() as u16);
+        let mut lo = (a & 0x0F) + (m & 0x0F) + c;
+        if lo >= 0x0A {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
         }
-
-        let mut hi = (a >> 4).wrapping_add(m >> 4).wrapping_add(c);
-        let overflow_flag_pre = (hi & 0x8) << 4;
-        c = (hi > 9) as u8;
-        if c != 0 {
-            hi = (hi - 10) & 0xf;
+        let signed = (a & 0xF0) as u8 as i8 as i16 + (m & 0xF0) as u8 as i8 as i16 + lo as i16;
+        let mut r = (a & 0xF0) + (m & 0xF0) + lo;
+        if r >= 0xA0 {
+            r += 0x60;
         }
 
-        let r = lo | (hi << 4);
-        self.set_a(r);
-        self.p.set_carry(c != 0);
-        self.p
-            .set_overflow(((a ^ overflow_flag_pre) & (m ^ overflow_flag_pre) & 0x80) != 0);
+        self.set_a(r as u8);
+        self.p.set_carry(r >= 0x100);
+        self.p.set_overflow(!(-128..=127).contains(&signed));
     }
 
     fn do_subtraction(&mut self, m: u8) {
         self.do_addition(!m);
     }
 
+    /// Decimal subtraction as on the 65C02, also defined for invalid BCD operands.
+    ///
+    /// N and Z reflect the decimal result, C and V are the same as for a binary subtraction.
     fn do_subtraction_decimal(&mut self, m: u8) {
-        let a = self.a;
-        let cf = self.p.carry();
-
-        let mut b = !cf as u8;
-        let mut lo = (a & 0xf).wrapping_sub(m & 0xf).wrapping_sub(b);
-        b = ((lo & 0x80) != 0) as u8;
-        if b != 0 {
-            lo = lo.wrapping_add(10);
+        let (a, m, c) = (self.a as i16, m as i16, self.p.carry() as i16);
+        let lo = (a & 0x0F) - (m & 0x0F) + c - 1;
+        let binary = a - m + c - 1;
+        let mut r = binary;
+        if r < 0 {
+            r -= 0x60;
         }
-        let negative_lo = (lo & 0x80) != 0;
-        lo &= 0xf;
-
-        let mut hi = (a >> 4).wrapping_sub(m >> 4).wrapping_sub(b);
-        let overflow_flag_pre = (hi & 0x8) << 4;
-        b = ((hi & 0x80) != 0) as u8;
-        if b != 0 {
-            hi = hi.wrapping_add(10);
+        if lo < 0 {
+            r -= 0x06;
         }
-        hi = hi.wrapping_sub(negative_lo as u8);
-        hi &= 0xf;
 
-        let r = lo | (hi << 4);
-        self.set_a(r);
-        self.p.set_carry(b == 0);
-        self.p
-            .set_overflow(((a ^ overflow_flag_pre) & (!m ^ overflow_flag_pre) & 0x80) != 0);
+        let overflow = ((a ^ m) & (a ^ binary) & 0x80) != 0;
+        self.set_a(r as u8);
+        self.p.set_carry(binary >= 0);
+        self.p.set_overflow(overflow);
     }
 
     fn bbr(&mut self, bit: u8) -> u8 {
@@ -728,14 +1183,10 @@ impl<M: Memory> Cpu<M> {
     }
 
     fn rmb(&mut self, bit: u8) {
-        let (addr, _) = self.read_address_operand(AddressingMode::ZeroPage);
-        let m = self.memory.read_u8(addr);
-        self.memory.write_u8(addr, m & !(1 << bit));
+        self.read_modify_write(AddressingMode::ZeroPage, false, |_, m| m & !(1 << bit));
     }
 
     fn smb(&mut self, bit: u8) {
-        let (addr, _) = self.read_address_operand(AddressingMode::ZeroPage);
-        let m = self.memory.read_u8(addr);
-        self.memory.write_u8(addr, m | (1 << bit));
+        self.read_modify_write(AddressingMode::ZeroPage, false, |_, m| m | (1 << bit));
     }
 }
@@ -0,0 +1,911 @@
+use crate::assembler::disassemble_instruction;
+use crate::breakpoint::Breakpoint;
+use crate::device::uart::{UART1_BASE, UART2_BASE, UartActivity, UartDirection};
+use crate::device::vid::{Palette, VideoView};
+use crate::machine::Machine;
+use crate::memory::Memory;
+use crate::symbols::{VIA_BASE, cody_addresses};
+use crate::watch::Watch;
+use egui::text::{LayoutJob, TextFormat};
+use egui::{
+    ClippedPrimitive, ColorImage, Context, FontId, FullOutput, RawInput, RichText, TextEdit,
+    TextureHandle, TextureOptions, TexturesDelta, ViewportId,
+};
+use egui_wgpu::{Renderer, RendererOptions, ScreenDescriptor};
+use itertools::Itertools;
+use log::error;
+use pixels::{Pixels, PixelsContext, wgpu};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc::Sender;
+use winit::event::WindowEvent;
+use winit::window::Window;
+
+const VIA_REGISTERS: [&str; 16] = [
+    "IORB", "IORA", "DDRB", "DDRA", "T1CL", "T1CH", "T1LL", "T1LH", "T2CL", "T2CH", "SR", "ACR",
+    "PCR", "IFR", "IER", "IORA2",
+];
+const UART_REGISTERS: [&str; 8] = ["CNTL", "CMND", "STAT", "-", "RXHD", "RXTL", "TXHD", "TXTL"];
+const DISASSEMBLY_LINES: usize = 16;
+/// Most recent device accesses shown below the registers
+const DEVICE_LOG_LINES: usize = 256;
+const VIDEO_VIEWS: [&str; 4] = ["Characters", "Screen", "Colors", "Sprites"];
+/// Frames a changed byte of the zero page or stack stays highlighted
+const CHANGE_FRAMES: u8 = 30;
+const JSR: u8 = 0x20;
+/// Most recent bytes shown in the serial console
+const SERIAL_CONSOLE_BYTES: usize = 4096;
+/// Bytes per line of the serial console's hex view
+const SERIAL_HEX_BYTES: usize = 16;
+
+/// Debugger panels drawn with egui on top of the emulated screen.
+///
+/// The panels themselves run next to the machine on the emulation thread, see [`Panels`].
+pub struct Debugger {
+    context: Context,
+    state: egui_winit::State,
+    renderer: Renderer,
+    screen: ScreenDescriptor,
+    paint_jobs: Vec<ClippedPrimitive>,
+    textures: TexturesDelta,
+    visible: bool,
+}
+
+impl Debugger {
+    pub fn new(window: &Window, pixels: &Pixels) -> Self {
+        let context = Context::default();
+        let max_texture_side = pixels.device().limits().max_texture_dimension_2d as usize;
+        let state = egui_winit::State::new(
+            context.clone(),
+            ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            Some(max_texture_side),
+        );
+        let size = window.inner_size();
+        Self {
+            context,
+            state,
+            renderer: Renderer::new(
+                pixels.device(),
+                pixels.render_texture_format(),
+                RendererOptions::default(),
+            ),
+            screen: ScreenDescriptor {
+                size_in_pixels: [size.width, size.height],
+                pixels_per_point: window.scale_factor() as f32,
+            },
+            paint_jobs: vec![],
+            textures: TexturesDelta::default(),
+            visible: false,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Keyboard input is meant for a text field and must not reach the emulated keyboard
+    pub fn wants_keyboard_input(&self) -> bool {
+        self.visible && self.context.wants_keyboard_input()
+    }
+
+    /// The pointer is over a panel or dragging one and must not reach the emulated mouse
+    pub fn wants_pointer_input(&self) -> bool {
+        self.visible && self.context.wants_pointer_input()
+    }
+
+    /// Pass a window event to the panels, returns true if they consumed it
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                self.screen.size_in_pixels = [size.width, size.height];
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.screen.pixels_per_point = *scale_factor as f32;
+            }
+            _ => {}
+        }
+        self.visible && self.state.on_window_event(window, event).consumed
+    }
+
+    /// Input for the next run of the panels, `None` while they are hidden
+    pub fn take_input(&mut self, window: &Window) -> Option<(Context, RawInput)> {
+        if !self.visible {
+            self.paint_jobs.clear();
+            return None;
+        }
+
+        Some((self.context.clone(), self.state.take_egui_input(window)))
+    }
+
+    /// Prepare drawing the output of a run of the panels
+    pub fn finish(&mut self, window: &Window, output: FullOutput) {
+        // textures are kept up to date even while hidden
+        self.textures.append(output.textures_delta);
+        if !self.visible {
+            return;
+        }
+
+        self.state
+            .handle_platform_output(window, output.platform_output);
+        self.screen.pixels_per_point = output.pixels_per_point;
+        self.paint_jobs = self
+            .context
+            .tessellate(output.shapes, output.pixels_per_point);
+    }
+
+    /// Draw the prepared panels over the already rendered screen
+    pub fn render(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_target: &wgpu::TextureView,
+        context: &PixelsContext,
+    ) {
+        for (id, image_delta) in &self.textures.set {
+            self.renderer
+                .update_texture(&context.device, &context.queue, *id, image_delta);
+        }
+        self.renderer.update_buffers(
+            &context.device,
+            &context.queue,
+            encoder,
+            &self.paint_jobs,
+            &self.screen,
+        );
+
+        let mut render_pass = encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("debugger"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: render_target,
+                    resolve_target: None,
+                    depth_slice: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            })
+            .forget_lifetime();
+        self.renderer
+            .render(&mut render_pass, &self.paint_jobs, &self.screen);
+        drop(render_pass);
+
+        for id in &self.textures.free {
+            self.renderer.free_texture(id);
+        }
+        self.textures.clear();
+    }
+}
+
+/// Debugger panels and the state of their text fields, run on the thread owning the machine
+#[derive(Default)]
+pub struct Panels {
+    /// address the memory view scrolls to in the next frame
+    scroll_to: Option<u16>,
+    goto: String,
+    search: String,
+    /// address and length of the last search result
+    found: Option<(u16, u16)>,
+    /// address and text of the byte being edited
+    edit: Option<(u16, String)>,
+    breakpoint: String,
+    watch: String,
+    /// index into [`VIDEO_VIEWS`]
+    video_view: usize,
+    video_texture: Option<TextureHandle>,
+    /// names of addresses in the disassembly
+    symbols: HashMap<u16, String>,
+    /// zero page and stack as of the last frame
+    previous: Vec<u8>,
+    /// frames left to highlight each byte of the zero page and stack
+    changes: Vec<u8>,
+    /// consoles of UART1 and UART2
+    serial: [SerialConsole; 2],
+    /// index into [`Self::serial`] of the console shown
+    serial_uart: usize,
+    serial_hex: bool,
+}
+
+/// Input and logging of the serial console of one UART
+#[derive(Default)]
+struct SerialConsole {
+    /// bytes sent here are received by the UART
+    input: Option<Sender<u8>>,
+    line: String,
+    log_path: String,
+    /// log file and the number of bytes of the UART's traffic already written to it
+    log: Option<(BufWriter<File>, usize)>,
+}
+
+impl Panels {
+    /// Panels that name the Cody's hardware registers and the given `symbols` in the disassembly
+    pub fn with_symbols(symbols: &HashMap<u16, String>) -> Self {
+        let mut addresses = cody_addresses();
+        addresses.extend(symbols.clone());
+        Self {
+            symbols: addresses,
+            ..Self::default()
+        }
+    }
+
+    /// Type the serial console's input into UART1 and UART2 through these streams
+    pub fn with_uart_inputs(mut self, uart1: Sender<u8>, uart2: Sender<u8>) -> Self {
+        self.serial[0].input = Some(uart1);
+        self.serial[1].input = Some(uart2);
+        self
+    }
+
+    /// Run the panels for the next frame, they may pause, step and modify the machine
+    pub fn run(
+        &mut self,
+        context: &Context,
+        input: RawInput,
+        machine: &mut Machine,
+        palette: &Palette,
+    ) -> FullOutput {
+        context.run(input, |ctx| self.show(ctx, machine, palette))
+    }
+
+    fn show(&mut self, ctx: &Context, machine: &mut Machine, palette: &Palette) {
+        egui::Window::new("CPU").show(ctx, |ui| {
+            ui.monospace(machine.cpu.to_string());
+            ui.horizontal(|ui| {
+                let label = if machine.is_paused() {
+                    "Resume"
+                } else {
+                    "Pause"
+                };
+                if ui.button(label).clicked() {
+                    machine.toggle_pause();
+                }
+                if ui
+                    .add_enabled(machine.is_paused(), egui::Button::new("Step"))
+                    .clicked()
+                {
+                    machine.single_step();
+                }
+                if ui
+                    .add_enabled(
+                        machine.call_stack().depth() > 1,
+                        egui::Button::new("Step out"),
+                    )
+                    .clicked()
+                {
+                    machine.step_out();
+                }
+            });
+        });
+
+        egui::Window::new("Call stack").show(ctx, |ui| {
+            for line in machine
+                .call_stack()
+                .backtrace(machine.cpu.pc, &self.symbols)
+            {
+                ui.monospace(line);
+            }
+        });
+
+        egui::Window::new("Disassembly").show(ctx, |ui| {
+            let mut address = machine.cpu.pc;
+            for _ in 0..DISASSEMBLY_LINES {
+                let bytes: Vec<u8> = (0..3)
+                    .map(|i| machine.cpu.memory.peek_u8(address.wrapping_add(i)))
+                    .collect();
+                let instruction = disassemble_instruction(&bytes, address);
+                let marker = if machine.breakpoints().contains_key(&address) {
+                    '*'
+                } else {
+                    ' '
+                };
+                let text = format!("{marker} {}", instruction.format(&self.symbols));
+                // clicking a line toggles its breakpoint
+                if ui
+                    .selectable_label(address == machine.cpu.pc, RichText::new(text).monospace())
+                    .clicked()
+                    && machine.breakpoints_mut().remove(&address).is_none()
+                {
+                    machine.add_breakpoint(Breakpoint::at(address));
+                }
+                address = address.wrapping_add(instruction.bytes.len() as u16);
+            }
+        });
+
+        egui::Window::new("Breakpoints").show(ctx, |ui| {
+            let mut removed = None;
+            for (&address, breakpoint) in machine.breakpoints() {
+                ui.horizontal(|ui| {
+                    ui.monospace(breakpoint.to_string());
+                    if ui.small_button("x").clicked() {
+                        removed = Some(address);
+                    }
+                });
+            }
+            if let Some(address) = removed {
+                machine.breakpoints_mut().remove(&address);
+            }
+            ui.horizontal(|ui| {
+                ui.add(
+                    TextEdit::singleline(&mut self.breakpoint)
+                        .hint_text("E000 or $E000 if a == 1; continue")
+                        .desired_width(160.0),
+                );
+                // a bare address is hex like everywhere else in the panels
+                if ui.button("Add").clicked()
+                    && let Some(breakpoint) = parse_address(&self.breakpoint)
+                        .map(Breakpoint::at)
+                        .or_else(|| self.breakpoint.parse().ok())
+                {
+                    machine.add_breakpoint(breakpoint);
+                    self.breakpoint.clear();
+                }
+            });
+        });
+
+        egui::Window::new("Watches").show(ctx, |ui| self.watches(ui, machine));
+
+        egui::Window::new("Memory").show(ctx, |ui| self.memory(ui, machine));
+
+        self.track_changes(machine);
+        egui::Window::new("Zero page & stack")
+            .show(ctx, |ui| self.zero_page_and_stack(ui, machine));
+
+        egui::Window::new("Devices").show(ctx, |ui| {
+            registers(ui, machine, "VIA", VIA_BASE, &VIA_REGISTERS);
+            registers(ui, machine, "UART1", UART1_BASE, &UART_REGISTERS);
+            registers(ui, machine, "UART2", UART2_BASE, &UART_REGISTERS);
+            if let Some(device_log) = machine.device_log() {
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .max_height(160.0)
+                    .show(ui, |ui| {
+                        for access in device_log.accesses().iter().rev().take(DEVICE_LOG_LINES) {
+                            ui.monospace(access.format(&self.symbols));
+                        }
+                    });
+            }
+        });
+
+        egui::Window::new("Serial console").show(ctx, |ui| self.serial_console(ui, machine));
+
+        egui::Window::new("Video").show(ctx, |ui| self.video(ui, machine, palette));
+    }
+
+    /// Dumb terminal on a UART, received bytes are colored
+    fn serial_console(&mut self, ui: &mut egui::Ui, machine: &mut Machine) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.serial_uart, 0, "UART1");
+            ui.selectable_value(&mut self.serial_uart, 1, "UART2");
+            ui.checkbox(&mut self.serial_hex, "Hex");
+        });
+        let devices = machine.devices();
+        let activities = [&devices.uart1_activity, &devices.uart2_activity];
+        for (console, activity) in self.serial.iter_mut().zip(activities) {
+            console.write_log(&activity.borrow());
+        }
+        let activity = activities[self.serial_uart].borrow();
+        let console = &mut self.serial[self.serial_uart];
+
+        ui.label(format!(
+            "{} bytes received, {} transmitted",
+            activity.rx_bytes, activity.tx_bytes
+        ));
+        let skip = activity
+            .traffic()
+            .len()
+            .saturating_sub(SERIAL_CONSOLE_BYTES);
+        let runs = traffic_runs(activity.traffic().iter().skip(skip).copied());
+        let font = FontId::monospace(12.0);
+        let mut job = LayoutJob::default();
+        for (direction, bytes) in runs {
+            let color = match direction {
+                UartDirection::Rx => ui.visuals().warn_fg_color,
+                UartDirection::Tx => ui.visuals().text_color(),
+            };
+            let format = TextFormat::simple(font.clone(), color);
+            if self.serial_hex {
+                for chunk in bytes.chunks(SERIAL_HEX_BYTES) {
+                    let hex = chunk.iter().map(|byte| format!("{byte:02X}")).join(" ");
+                    job.append(&format!("{hex}\n"), 0.0, format.clone());
+                }
+            } else {
+                job.append(&printable(&bytes), 0.0, format);
+            }
+        }
+        egui::ScrollArea::vertical()
+            .max_height(240.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| ui.label(job));
+
+        ui.horizontal(|ui| {
+            let response = ui.add_enabled(
+                console.input.is_some(),
+                TextEdit::singleline(&mut console.line).desired_width(240.0),
+            );
+            let entered =
+                response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+            if (ui.button("Send").clicked() || entered)
+                && let Some(input) = &console.input
+            {
+                // like --fix-newlines, lines end with a line feed
+                for byte in console.line.bytes().chain([b'\n']) {
+                    let _ = input.send(byte);
+                }
+                console.line.clear();
+                response.request_focus();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if console.log.is_some() {
+                ui.label(format!("Logging to {}", console.log_path));
+                if ui.button("Stop").clicked() {
+                    console.stop_log();
+                }
+            } else {
+                ui.add(
+                    TextEdit::singleline(&mut console.log_path)
+                        .hint_text("uart.log")
+                        .desired_width(160.0),
+                );
+                if ui.button("Log").clicked() {
+                    console.start_log(activity.total_bytes());
+                }
+            }
+        });
+    }
+
+    /// Show video memory decoded with the current video registers, scaled up 2x
+    fn video(&mut self, ui: &mut egui::Ui, machine: &mut Machine, palette: &Palette) {
+        ui.horizontal(|ui| {
+            for (i, name) in VIDEO_VIEWS.iter().enumerate() {
+                ui.selectable_value(&mut self.video_view, i, *name);
+            }
+        });
+
+        let memory = &mut machine.cpu.memory;
+        let view = match self.video_view {
+            0 => VideoView::character_set(memory, palette),
+            1 => VideoView::screen(memory, palette),
+            2 => VideoView::color_memory(memory, palette),
+            _ => VideoView::sprites(memory, palette),
+        };
+        let image = ColorImage::from_rgba_unmultiplied(
+            [view.width as usize, view.height as usize],
+            bytemuck::cast_slice(&view.pixels),
+        );
+        let texture = match &mut self.video_texture {
+            Some(texture) => {
+                texture.set(image, TextureOptions::NEAREST);
+                texture
+            }
+            None => self.video_texture.insert(ui.ctx().load_texture(
+                "video",
+                image,
+                TextureOptions::NEAREST,
+            )),
+        };
+        ui.image((texture.id(), texture.size_vec2() * 2.0));
+    }
+
+    /// Watched values and their recent changes, newest first
+    fn watches(&mut self, ui: &mut egui::Ui, machine: &mut Machine) {
+        let mut removed = None;
+        for (i, &watch) in machine.watches().watches().iter().enumerate() {
+            ui.horizontal(|ui| {
+                let text = match watch {
+                    Watch::Location(location) => {
+                        let value = machine.watches().value(location).unwrap_or_default();
+                        format!("{watch} = {}", location.format_value(value))
+                    }
+                    Watch::Range(..) => watch.to_string(),
+                };
+                ui.monospace(text);
+                if ui.small_button("x").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(index) = removed {
+            machine.unwatch(index);
+        }
+        ui.horizontal(|ui| {
+            ui.add(
+                TextEdit::singleline(&mut self.watch)
+                    .hint_text("a, 0x10.w or 0x10-0x1F")
+                    .desired_width(96.0),
+            );
+            if ui.button("Add").clicked()
+                && let Ok(watch) = self.watch.parse()
+            {
+                machine.watch(watch);
+                self.watch.clear();
+            }
+        });
+
+        ui.separator();
+        egui::ScrollArea::vertical()
+            .max_height(160.0)
+            .show(ui, |ui| {
+                for change in machine.watches().log().iter().rev() {
+                    ui.monospace(change.to_string());
+                }
+            });
+    }
+
+    /// Compare the zero page and stack with the last frame and restart highlighting changed bytes
+    fn track_changes(&mut self, machine: &mut Machine) {
+        let current: Vec<u8> = (0..0x200)
+            .map(|address| machine.cpu.memory.peek_u8(address))
+            .collect();
+        if self.previous.len() == current.len() {
+            for ((change, previous), value) in
+                self.changes.iter_mut().zip(&self.previous).zip(&current)
+            {
+                *change = if previous != value {
+                    CHANGE_FRAMES
+                } else {
+                    change.saturating_sub(1)
+                };
+            }
+        } else {
+            self.changes = vec![0; current.len()];
+        }
+        self.previous = current;
+    }
+
+    /// The zero page and the used part of the stack, return addresses name their caller
+    fn zero_page_and_stack(&mut self, ui: &mut egui::Ui, machine: &mut Machine) {
+        ui.label("Zero page");
+        for row in 0..16u16 {
+            ui.horizontal(|ui| {
+                ui.monospace(format!("{:04X}", row << 4));
+                for column in 0..16 {
+                    let address = row << 4 | column;
+                    let response = ui.label(self.changed_byte(ui, address));
+                    if let Some(name) = self.symbols.get(&address) {
+                        response.on_hover_text(name);
+                    }
+                }
+            });
+        }
+
+        ui.separator();
+        let s = machine.cpu.s;
+        ui.label(format!("Stack, S = {s:02X}"));
+        let returns = return_addresses(&mut machine.cpu.memory, s);
+        egui::ScrollArea::vertical()
+            .max_height(240.0)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.monospace(format!("{:04X}", 0x0100 | s as u16));
+                    ui.monospace("-- <- S");
+                });
+                for address in 0x0101 + s as u16..0x0200 {
+                    ui.horizontal(|ui| {
+                        ui.monospace(format!("{address:04X}"));
+                        ui.label(self.changed_byte(ui, address));
+                        if let Some(&(_, caller)) = returns.iter().find(|&&(low, _)| low == address)
+                        {
+                            let bytes: Vec<u8> = (0..3)
+                                .map(|i| machine.cpu.memory.peek_u8(caller.wrapping_add(i)))
+                                .collect();
+                            let call = disassemble_instruction(&bytes, caller)
+                                .source(&self.symbols)
+                                .unwrap_or_default();
+                            let text = format!(
+                                "return to {:04X}, {caller:04X} {call}",
+                                caller.wrapping_add(3)
+                            );
+                            ui.monospace(text);
+                        }
+                    });
+                }
+            });
+    }
+
+    /// The byte at `address` of the zero page or stack, highlighted if it recently changed
+    fn changed_byte(&self, ui: &egui::Ui, address: u16) -> RichText {
+        let text = RichText::new(format!("{:02X}", self.previous[address as usize])).monospace();
+        if self.changes[address as usize] > 0 {
+            text.color(ui.visuals().warn_fg_color)
+        } else {
+            text
+        }
+    }
+
+    fn memory(&mut self, ui: &mut egui::Ui, machine: &mut Machine) {
+        ui.horizontal(|ui| {
+            ui.add(TextEdit::singleline(&mut self.goto).desired_width(48.0));
+            if ui.button("Go to").clicked()
+                && let Some(address) = parse_address(&self.goto)
+            {
+                self.scroll_to = Some(address);
+            }
+            ui.add(
+                TextEdit::singleline(&mut self.search)
+                    .hint_text("A9 01 or \"text\"")
+                    .desired_width(96.0),
+            );
+            if ui.button("Find next").clicked()
+                && let Some(pattern) = parse_pattern(&self.search)
+            {
+                let from = self.found.map_or(0, |(address, _)| address.wrapping_add(1));
+                self.found = find(&mut machine.cpu.memory, &pattern, from)
+                    .map(|address| (address, pattern.len() as u16));
+                self.scroll_to = self.found.map(|(address, _)| address);
+            }
+        });
+        if !machine.is_paused() {
+            ui.label("Pause to edit memory");
+        }
+
+        let row_height =
+            ui.text_style_height(&egui::TextStyle::Monospace) + ui.spacing().item_spacing.y;
+        let mut scroll_area = egui::ScrollArea::vertical().auto_shrink(false);
+        if let Some(address) = self.scroll_to.take() {
+            scroll_area = scroll_area.vertical_scroll_offset((address >> 4) as f32 * row_height);
+        }
+        scroll_area.show_rows(ui, row_height, 0x1000, |ui, rows| {
+            for row in rows {
+                let row_address = (row as u16) << 4;
+                ui.horizontal(|ui| {
+                    ui.monospace(format!("{row_address:04X}"));
+                    let mut ascii = String::with_capacity(16);
+                    for column in 0..16 {
+                        let value = self.byte(ui, machine, row_address + column);
+                        ascii.push(if value.is_ascii_graphic() || value == b' ' {
+                            value as char
+                        } else {
+                            '.'
+                        });
+                    }
+                    ui.monospace(ascii);
+                });
+            }
+        });
+    }
+
+    /// Show the byte at `address`, clicking it while paused allows to enter a new value
+    fn byte(&mut self, ui: &mut egui::Ui, machine: &mut Machine, address: u16) -> u8 {
+        let value = machine.cpu.memory.peek_u8(address);
+        if let Some((edit_address, text)) = &mut self.edit
+            && *edit_address == address
+        {
+            let response = ui.add(TextEdit::singleline(text).desired_width(16.0));
+            if response.lost_focus() {
+                if ui.input(|i| i.key_pressed(egui::Key::Enter))
+                    && let Ok(value) = u8::from_str_radix(text.trim(), 16)
+                {
+                    machine.cpu.memory.write_u8(address, value);
+                    machine.cpu.flush_decode_cache();
+                }
+                self.edit = None;
+            } else if !response.has_focus() {
+                response.request_focus();
+            }
+            return value;
+        }
+
+        let mut text = RichText::new(format!("{value:02X}")).monospace();
+        if self
+            .found
+            .is_some_and(|(found, len)| address.wrapping_sub(found) < len)
+        {
+            text = text.color(ui.visuals().warn_fg_color);
+        }
+        if ui
+            .add(egui::Label::new(text).sense(egui::Sense::click()))
+            .clicked()
+            && machine.is_paused()
+        {
+            self.edit = Some((address, format!("{value:02X}")));
+        }
+        value
+    }
+}
+
+impl SerialConsole {
+    /// Log all bytes from now on, `total_bytes` is the traffic of the UART so far
+    fn start_log(&mut self, total_bytes: usize) {
+        match File::create(&self.log_path) {
+            Ok(file) => self.log = Some((BufWriter::new(file), total_bytes)),
+            Err(e) => error!("Cannot log UART traffic to {}: {e}", self.log_path),
+        }
+    }
+
+    fn stop_log(&mut self) {
+        if let Some((mut file, _)) = self.log.take()
+            && let Err(e) = file.flush()
+        {
+            error!("io error logging UART traffic: {e}");
+        }
+    }
+
+    /// Append the bytes the UART received and transmitted since the last call, while the panels were hidden only the
+    /// most recent ones are still known
+    fn write_log(&mut self, activity: &UartActivity) {
+        let Some((file, written)) = &mut self.log else {
+            return;
+        };
+        let new = activity.total_bytes() - *written;
+        let traffic = activity.traffic();
+        let bytes: Vec<u8> = traffic
+            .iter()
+            .skip(traffic.len().saturating_sub(new))
+            .map(|&(_, byte)| byte)
+            .collect();
+        *written = activity.total_bytes();
+        if let Err(e) = file.write_all(&bytes) {
+            error!("io error logging UART traffic: {e}");
+            self.log = None;
+        }
+    }
+}
+
+/// Consecutive bytes that went the same way
+fn traffic_runs(
+    traffic: impl Iterator<Item = (UartDirection, u8)>,
+) -> Vec<(UartDirection, Vec<u8>)> {
+    let mut runs: Vec<(UartDirection, Vec<u8>)> = vec![];
+    for (direction, byte) in traffic {
+        match runs.last_mut() {
+            Some((last, bytes)) if *last == direction => bytes.push(byte),
+            _ => runs.push((direction, vec![byte])),
+        }
+    }
+    runs
+}
+
+/// Bytes as terminal text, carriage returns are dropped and other control characters shown as `.`
+fn printable(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .filter(|&&byte| byte != b'\r')
+        .map(|&byte| match byte as char {
+            c if c == '\n' || c == ' ' || c.is_ascii_graphic() => c,
+            _ => '.',
+        })
+        .collect()
+}
+
+fn registers(ui: &mut egui::Ui, machine: &mut Machine, name: &str, base: u16, names: &[&str]) {
+    ui.label(format!("{name} at {base:04X}"));
+    egui::Grid::new(name).show(ui, |ui| {
+        for (i, register) in names.iter().enumerate() {
+            let value = machine.cpu.memory.peek_u8(base + i as u16);
+            ui.monospace(format!("{register:>5} {value:02X}"));
+            if i % 4 == 3 {
+                ui.end_row();
+            }
+        }
+    });
+}
+
+/// Parse a hexadecimal address with an optional `$` or `0x` prefix
+fn parse_address(s: &str) -> Option<u16> {
+    let s = s.trim();
+    let s = s
+        .strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s);
+    u16::from_str_radix(s, 16).ok()
+}
+
+/// Parse whitespace separated hex bytes or text in double quotes
+fn parse_pattern(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    let pattern = if let Some(text) = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        text.as_bytes().to_vec()
+    } else {
+        s.split_whitespace()
+            .map(|byte| u8::from_str_radix(byte.strip_prefix('$').unwrap_or(byte), 16).ok())
+            .collect::<Option<_>>()?
+    };
+    (!pattern.is_empty()).then_some(pattern)
+}
+
+/// Return addresses on the stack above `s`, as the address of their low byte and of their JSR.
+///
+/// A pair of bytes counts as a return address if the instruction before the address it points to
+/// is a JSR, so data pushed by the program is rarely mistaken for one.
+fn return_addresses(memory: &mut impl Memory, s: u8) -> Vec<(u16, u16)> {
+    let mut returns = vec![];
+    let mut slot = s as u16 + 1;
+    while slot < 0xFF {
+        let address = 0x0100 | slot;
+        let value = u16::from_le_bytes([memory.peek_u8(address), memory.peek_u8(address + 1)]);
+        // JSR pushes the address of its last byte
+        let caller = value.wrapping_sub(2);
+        if memory.peek_u8(caller) == JSR {
+            returns.push((address, caller));
+            slot += 2;
+        } else {
+            slot += 1;
+        }
+    }
+    returns
+}
+
+/// Search the whole address space for `pattern` starting at `from`, wrapping around at the end
+fn find(memory: &mut impl Memory, pattern: &[u8], from: u16) -> Option<u16> {
+    (0..=u16::MAX)
+        .map(|offset| from.wrapping_add(offset))
+        .find(|&address| {
+            pattern
+                .iter()
+                .enumerate()
+                .all(|(i, &byte)| memory.peek_u8(address.wrapping_add(i as u16)) == byte)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::contiguous::Contiguous;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("E000"), Some(0xE000));
+        assert_eq!(parse_address("$9f00"), Some(0x9F00));
+        assert_eq!(parse_address(" 0x10 "), Some(0x10));
+        assert_eq!(parse_address("xyz"), None);
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(parse_pattern("A9 $01"), Some(vec![0xA9, 0x01]));
+        assert_eq!(parse_pattern("\"OK\""), Some(b"OK".to_vec()));
+        assert_eq!(parse_pattern("A9 zz"), None);
+        assert_eq!(parse_pattern(""), None);
+
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.force_write_all(0x1000, b"OK");
+        memory.force_write_all(0x8000, b"OK");
+        assert_eq!(find(&mut memory, b"OK", 0), Some(0x1000));
+        assert_eq!(find(&mut memory, b"OK", 0x1001), Some(0x8000));
+        assert_eq!(find(&mut memory, b"OK", 0x8001), Some(0x1000));
+        assert_eq!(find(&mut memory, b"NO", 0), None);
+    }
+
+    #[test]
+    fn test_return_addresses() {
+        let mut memory = Contiguous::new_ram(0x10000);
+        // JSR $E100 at $E000 and JSR $E200 at $E100
+        memory.force_write_all(0xE000, &[JSR, 0x00, 0xE1]);
+        memory.force_write_all(0xE100, &[JSR, 0x00, 0xE2]);
+        // return to $E103 below a pushed byte, return to $E003 at the top
+        memory.force_write_all(0x01FA, &[0x42, 0x02, 0xE1, 0x02, 0xE0]);
+        assert_eq!(
+            return_addresses(&mut memory, 0xF9),
+            [(0x01FB, 0xE100), (0x01FD, 0xE000)]
+        );
+        assert_eq!(return_addresses(&mut memory, 0xFC), [(0x01FD, 0xE000)]);
+        assert_eq!(return_addresses(&mut memory, 0xFD), []);
+    }
+
+    #[test]
+    fn test_traffic_runs() {
+        let traffic = [
+            (UartDirection::Tx, b'O'),
+            (UartDirection::Tx, b'K'),
+            (UartDirection::Rx, b'A'),
+            (UartDirection::Tx, b'\r'),
+            (UartDirection::Tx, b'\n'),
+        ];
+        let runs = traffic_runs(traffic.into_iter());
+        assert_eq!(
+            runs,
+            [
+                (UartDirection::Tx, b"OK".to_vec()),
+                (UartDirection::Rx, b"A".to_vec()),
+                (UartDirection::Tx, b"\r\n".to_vec()),
+            ]
+        );
+        assert_eq!(printable(b"OK\r\n\x07"), "OK\n.");
+    }
+}
@@ -0,0 +1,147 @@
+use crate::memory::Memory;
+use crate::opcode::get_instruction;
+
+/// Longest instruction, opcode and two operand bytes
+pub const MAX_INSTRUCTION_LEN: usize = 3;
+
+/// Bytes of the instruction at one address as of its first fetch
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+enum Entry {
+    #[default]
+    Unknown,
+    /// some byte of the instruction is not plain memory, it is always fetched over the bus
+    Uncacheable,
+    Decoded(Instruction),
+}
+
+/// Opcode and operand bytes of a cached instruction
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: [u8; MAX_INSTRUCTION_LEN],
+    pub len: u8,
+}
+
+impl Instruction {
+    /// Byte of the instruction at `address`, `None` if it lies outside of it
+    pub fn byte_at(&self, address: u16) -> Option<u8> {
+        let offset = address.wrapping_sub(self.address);
+        (offset < self.len as u16).then(|| self.bytes[offset as usize])
+    }
+}
+
+/// Decoded instructions by address, used by [`crate::cpu::Engine::Cached`].
+///
+/// Only instructions in memory that reports itself as cacheable are kept, writes of the cpu
+/// invalidate the instructions they overlap and everything else has to [`DecodeCache::flush`].
+#[derive(Debug, Clone)]
+pub struct DecodeCache {
+    entries: Box<[Entry]>,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self {
+            entries: vec![Entry::Unknown; 0x10000].into_boxed_slice(),
+        }
+    }
+}
+
+impl DecodeCache {
+    /// Instruction at `address`, decoded on its first fetch, `None` if it has to be fetched over the bus
+    pub fn fetch(&mut self, memory: &mut impl Memory, address: u16) -> Option<Instruction> {
+        let entry = &mut self.entries[address as usize];
+        if *entry == Entry::Unknown {
+            *entry = Self::decode(memory, address);
+        }
+        match *entry {
+            Entry::Decoded(instruction) => Some(instruction),
+            _ => None,
+        }
+    }
+
+    fn decode(memory: &mut impl Memory, address: u16) -> Entry {
+        if !memory.is_cacheable(address) {
+            return Entry::Uncacheable;
+        }
+        let opcode = memory.peek_u8(address);
+        // undefined opcodes are cached as a single byte, their operands come from the bus
+        let len = get_instruction(opcode).map_or(1, |meta| meta.width() as u8);
+        let mut bytes = [opcode, 0, 0];
+        for offset in 1..len as u16 {
+            let operand_address = address.wrapping_add(offset);
+            if !memory.is_cacheable(operand_address) {
+                return Entry::Uncacheable;
+            }
+            bytes[offset as usize] = memory.peek_u8(operand_address);
+        }
+        Entry::Decoded(Instruction {
+            address,
+            bytes,
+            len,
+        })
+    }
+
+    /// Forget the instructions overlapping `address` after the cpu wrote to it
+    pub fn invalidate(&mut self, address: u16) {
+        for offset in 0..MAX_INSTRUCTION_LEN as u16 {
+            let entry = &mut self.entries[address.wrapping_sub(offset) as usize];
+            if matches!(entry, Entry::Decoded(_)) {
+                *entry = Entry::Unknown;
+            }
+        }
+    }
+
+    /// Forget all instructions, needed after memory changed without the cpu writing to it
+    pub fn flush(&mut self) {
+        self.entries.fill(Entry::Unknown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Cpu, Engine};
+    use crate::memory::contiguous::Contiguous;
+
+    fn run(program: &[u8], engine: Engine) -> Cpu<Contiguous> {
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.force_write_all(0x0200, program);
+        memory.write_u16(0xFFFC, 0x0200);
+        let mut cpu = Cpu::new(memory);
+        cpu.set_engine(engine);
+        cpu.run();
+        cpu
+    }
+
+    #[test]
+    fn test_fetch_and_invalidate() {
+        let mut memory = Contiguous::new_ram(0x10000);
+        // LDA $1234
+        memory.force_write_all(0x0200, &[0xAD, 0x34, 0x12]);
+        let mut cache = DecodeCache::default();
+        let instruction = cache.fetch(&mut memory, 0x0200).unwrap();
+        assert_eq!(instruction.bytes, [0xAD, 0x34, 0x12]);
+        assert_eq!(instruction.byte_at(0x0202), Some(0x12));
+        assert_eq!(instruction.byte_at(0x0203), None);
+
+        // stale until invalidated
+        memory.write_u8(0x0202, 0x56);
+        assert_eq!(cache.fetch(&mut memory, 0x0200).unwrap().bytes[2], 0x12);
+        cache.invalidate(0x0202);
+        assert_eq!(cache.fetch(&mut memory, 0x0200).unwrap().bytes[2], 0x56);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // loop: LDA #$00, INC loop+1, LDX loop+1, CPX #3, BNE loop, STP
+        let program = [
+            0xA9, 0x00, 0xEE, 0x01, 0x02, 0xAE, 0x01, 0x02, 0xE0, 0x03, 0xD0, 0xF4, 0xDB,
+        ];
+        let interpreted = run(&program, Engine::Interpreter);
+        let cached = run(&program, Engine::Cached);
+        assert_eq!(cached.a, 2);
+        assert_eq!(cached.state(), interpreted.state());
+        assert_eq!(cached.cycle(), interpreted.cycle());
+    }
+}
@@ -1,9 +1,52 @@
 use crate::interrupt::Interrupt;
 use crate::memory::Memory;
+use crate::timing::TimingProfile;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Interrupt raised at the start of vertical blanking
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VblankInterrupt {
+    /// Only the polled blanking register is available
+    #[default]
+    None,
+    /// Raise an NMI
+    Nmi,
+    /// Pulse the CA1 line of the VIA, which raises an IRQ if enabled in the VIA
+    ViaCa1,
+}
 
 #[derive(Debug, Clone, Default)]
 pub struct BlankingRegister {
     in_blanking_interval: bool,
+    /// the NMI line is held for the update at the start of blanking
+    nmi: bool,
+    interrupt: VblankInterrupt,
+    ca1: Option<Rc<RefCell<bool>>>,
+    timing: TimingProfile,
+}
+
+impl BlankingRegister {
+    pub fn with_nmi() -> Self {
+        Self {
+            interrupt: VblankInterrupt::Nmi,
+            ..Self::default()
+        }
+    }
+
+    /// Drive `ca1` high during vertical blanking
+    pub fn with_ca1(ca1: Rc<RefCell<bool>>) -> Self {
+        Self {
+            interrupt: VblankInterrupt::ViaCa1,
+            ca1: Some(ca1),
+            ..Self::default()
+        }
+    }
+
+    pub fn set_timing(&mut self, timing: TimingProfile) {
+        self.timing = timing;
+    }
 }
 
 impl Memory for BlankingRegister {
@@ -14,23 +57,85 @@ impl Memory for BlankingRegister {
     fn write_u8(&mut self, _address: u16, _value: u8) {}
 
     fn update(&mut self, cycle: usize) -> Interrupt {
-        // one (half-)frame is rendered roughly every 60 Hz
-        const FPS: f64 = 60.0 / 1.001;
-        // 262 lines in a (half-)frame
-        // 9 lines for VSYNC
-        // 12 blank lines
-        // 220 lines (20 lines top border + 200 (25x8) screen area) | VBLANK=0
-        // 21 lines bottom border
-        const VBLANK1_RATIO: f64 = (9.0 + 12.0 + 21.0) / 262.0;
-        const FRAME_TIME: f64 = 1.0 / FPS;
-        const VBLANK1_TIME: f64 = VBLANK1_RATIO * FRAME_TIME;
-        // Propeller runs at 80 MHz and the WD65C02 runs at 1MHz
-        const CYCLE_FREQUENCY: f64 = 1000000.0;
-        const FRAME_CYCLES: usize = (FRAME_TIME * CYCLE_FREQUENCY) as usize;
-        const VBLANK1_CYCLES: usize = (VBLANK1_TIME * CYCLE_FREQUENCY) as usize;
-
-        let frame_cycle = cycle % FRAME_CYCLES;
-        self.in_blanking_interval = frame_cycle < VBLANK1_CYCLES;
-        Interrupt::none()
+        let frame_cycle = cycle % self.timing.frame_cycles();
+        let was_blanking = self.in_blanking_interval;
+        self.in_blanking_interval = frame_cycle < self.timing.blanking_cycles();
+        if let Some(ca1) = &self.ca1 {
+            *ca1.borrow_mut() = self.in_blanking_interval;
+        }
+
+        self.nmi =
+            self.interrupt == VblankInterrupt::Nmi && self.in_blanking_interval && !was_blanking;
+        if self.nmi {
+            Interrupt::nmi()
+        } else {
+            Interrupt::none()
+        }
+    }
+
+    fn next_event(&mut self, cycle: usize) -> Option<usize> {
+        if self.nmi {
+            return Some(cycle + 1);
+        }
+        let frame_start = cycle - cycle % self.timing.frame_cycles();
+        Some(if self.in_blanking_interval {
+            frame_start + self.timing.blanking_cycles()
+        } else {
+            frame_start + self.timing.frame_cycles()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nmi_once_per_frame() {
+        let mut blanking = BlankingRegister::with_nmi();
+        let nmis = (0..100000)
+            .step_by(3)
+            .filter(|&cycle| blanking.update(cycle).is_nmi())
+            .count();
+        // 100ms at 59.94 fps, the first frame starts blanked
+        assert_eq!(nmis, 6);
+    }
+
+    #[test]
+    fn test_ca1_follows_blanking() {
+        let ca1 = Rc::new(RefCell::new(false));
+        let mut blanking = BlankingRegister::with_ca1(Rc::clone(&ca1));
+        assert_eq!(blanking.update(0), Interrupt::none());
+        assert!(*ca1.borrow());
+        blanking.update(10000);
+        assert!(!*ca1.borrow());
+    }
+
+    #[test]
+    fn test_pal_timing() {
+        let mut blanking = BlankingRegister::with_nmi();
+        blanking.set_timing(TimingProfile::PAL);
+        let nmis = (0..100000)
+            .step_by(3)
+            .filter(|&cycle| blanking.update(cycle).is_nmi())
+            .count();
+        // 100ms at 50 fps
+        assert_eq!(nmis, 5);
+    }
+
+    #[test]
+    fn test_next_event() {
+        let mut blanking = BlankingRegister::with_nmi();
+        let timing = TimingProfile::default();
+        assert!(blanking.update(0).is_nmi());
+        // the NMI line is released by the next update
+        assert_eq!(blanking.next_event(0), Some(1));
+        blanking.update(5);
+        assert_eq!(blanking.next_event(5), Some(timing.blanking_cycles()));
+        blanking.update(timing.blanking_cycles());
+        assert_eq!(
+            blanking.next_event(timing.blanking_cycles()),
+            Some(timing.frame_cycles())
+        );
     }
 }
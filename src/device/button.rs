@@ -0,0 +1,33 @@
+use crate::interrupt::Interrupt;
+use crate::memory::Memory;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The NMI button, pressing it asserts the NMI line for one instruction
+#[derive(Debug, Clone, Default)]
+pub struct NmiButton {
+    pressed: Rc<RefCell<bool>>,
+}
+
+impl NmiButton {
+    /// Set to true to press the button, it is released by the next update
+    pub const fn get_pressed(&self) -> &Rc<RefCell<bool>> {
+        &self.pressed
+    }
+}
+
+impl Memory for NmiButton {
+    fn read_u8(&mut self, _address: u16) -> u8 {
+        0
+    }
+
+    fn write_u8(&mut self, _address: u16, _value: u8) {}
+
+    fn update(&mut self, _cycle: usize) -> Interrupt {
+        if self.pressed.replace(false) {
+            Interrupt::nmi()
+        } else {
+            Interrupt::none()
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::device::uart::{UartSink, UartSource};
+use crate::interrupt::Interrupt;
+use crate::memory::Memory;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Write a byte to the host
+pub const PUTCHAR: u16 = 0x0;
+/// Read the next byte from the host, 0 if there is none
+pub const GETCHAR: u16 = 0x1;
+/// Bit 0 is set while a byte from the host is available
+pub const STATUS: u16 = 0x2;
+/// Write the exit code to stop the emulation
+pub const EXIT: u16 = 0x3;
+/// Cpu cycle as little endian u32, reading the lowest byte latches all four
+pub const CYCLE: u16 = 0x4;
+pub const HOST_BRIDGE_SIZE: u16 = 0x8;
+
+const STATUS_INPUT_AVAILABLE: u8 = 0x01;
+
+/// Magic registers that let bare-metal test programs talk to the host, like semihosting on ARM.
+///
+/// Not part of the Cody, it only exists when mapped with [`crate::machine::MachineBuilder::host_bridge`].
+#[derive(Debug)]
+pub struct HostBridge {
+    source: UartSource,
+    sink: UartSink,
+    /// byte read from the source but not yet by the program, needed to report it in the status register
+    pending: Option<u8>,
+    exit: Rc<RefCell<Option<u8>>>,
+    cycle: usize,
+    latched_cycle: [u8; 4],
+}
+
+impl HostBridge {
+    pub fn new(source: UartSource, sink: UartSink) -> Self {
+        Self {
+            source,
+            sink,
+            pending: None,
+            exit: Rc::default(),
+            cycle: 0,
+            latched_cycle: [0; 4],
+        }
+    }
+
+    /// Exit code written by the program, the machine stops once it is set
+    pub const fn get_exit(&self) -> &Rc<RefCell<Option<u8>>> {
+        &self.exit
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        if self.pending.is_none() {
+            self.pending = self.source.read();
+        }
+        self.pending
+    }
+}
+
+impl Memory for HostBridge {
+    fn read_u8(&mut self, address: u16) -> u8 {
+        match address {
+            GETCHAR => {
+                self.poll();
+                self.pending.take().unwrap_or(0)
+            }
+            CYCLE => {
+                // the counter wraps, programs only measure differences
+                self.latched_cycle = (self.cycle as u32).to_le_bytes();
+                self.latched_cycle[0]
+            }
+            _ => self.peek_u8(address),
+        }
+    }
+
+    fn peek_u8(&mut self, address: u16) -> u8 {
+        match address {
+            GETCHAR => self.pending.unwrap_or(0),
+            STATUS if self.poll().is_some() => STATUS_INPUT_AVAILABLE,
+            EXIT => self.exit.borrow().unwrap_or(0),
+            CYCLE..HOST_BRIDGE_SIZE => self.latched_cycle[(address - CYCLE) as usize],
+            _ => 0,
+        }
+    }
+
+    fn write_u8(&mut self, address: u16, value: u8) {
+        match address {
+            PUTCHAR => {
+                self.sink.write(value);
+                self.sink.flush();
+            }
+            EXIT => *self.exit.borrow_mut() = Some(value),
+            _ => {}
+        }
+    }
+
+    fn update(&mut self, cycle: usize) -> Interrupt {
+        self.cycle = cycle;
+        self.source.poll(cycle);
+        Interrupt::none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_putchar_and_getchar() {
+        let output = Shared::default();
+        let mut bridge = HostBridge::new(UartSource::new(*b"hi"), UartSink::new(output.clone()));
+        for &b in b"ok" {
+            bridge.write_u8(PUTCHAR, b);
+        }
+        assert_eq!(output.0.borrow()[..], *b"ok");
+
+        assert_eq!(bridge.read_u8(STATUS), STATUS_INPUT_AVAILABLE);
+        assert_eq!(bridge.peek_u8(GETCHAR), b'h');
+        assert_eq!(bridge.read_u8(GETCHAR), b'h');
+        assert_eq!(bridge.read_u8(GETCHAR), b'i');
+        assert_eq!(bridge.read_u8(STATUS), 0);
+        assert_eq!(bridge.read_u8(GETCHAR), 0);
+    }
+
+    #[test]
+    fn test_exit_and_cycle() {
+        let mut bridge = HostBridge::new(UartSource::empty(), UartSink::discard());
+        assert_eq!(*bridge.get_exit().borrow(), None);
+        bridge.write_u8(EXIT, 3);
+        assert_eq!(*bridge.get_exit().borrow(), Some(3));
+
+        bridge.update(0x1234_5678);
+        assert_eq!(bridge.read_u8(CYCLE + 1), 0);
+        assert_eq!(bridge.read_u8(CYCLE), 0x78);
+        bridge.update(0x1234_5679);
+        // the upper bytes stay latched
+        assert_eq!(bridge.read_u8(CYCLE + 1), 0x56);
+        assert_eq!(bridge.read_u8(CYCLE + 3), 0x12);
+    }
+}
@@ -1,39 +1,43 @@
 use crate::device::via::{CodyKeyCode, CodyModifier, KeyState};
+use log::warn;
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::rc::Rc;
+use std::str::FromStr;
 use strum::EnumCount;
 use winit::keyboard::{Key, KeyCode, NamedKey};
 use winit_input_helper::WinitInputHelper;
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum KeyboardEmulation {
     Physical,
     Logical,
 }
 
-#[derive(Debug, Clone)]
-pub struct Keyboard {
-    pub keyboard_emulation: KeyboardEmulation,
-    pub key_state: Rc<RefCell<KeyState>>,
-}
-
-impl Keyboard {
-    pub fn new(keyboard_emulation: KeyboardEmulation, key_state: Rc<RefCell<KeyState>>) -> Self {
-        Self {
-            keyboard_emulation,
-            key_state,
-        }
-    }
-
-    pub fn update(&mut self, input: &WinitInputHelper) {
-        match self.keyboard_emulation {
-            KeyboardEmulation::Physical => self.update_physical(input),
-            KeyboardEmulation::Logical => self.update_logical(input),
+impl KeyboardEmulation {
+    /// Cody keys held according to the host keys held in `input`, the joysticks are always mapped by physical key
+    pub fn held(
+        self,
+        input: &WinitInputHelper,
+        joysticks: &JoystickBindings,
+    ) -> [bool; CodyKeyCode::COUNT] {
+        let mut state = match self {
+            Self::Physical => Self::held_physical(input, joysticks),
+            Self::Logical => Self::held_logical(input),
+        };
+        for &(keycode, code) in &joysticks.0 {
+            state[code as usize] |= input.key_held(keycode);
         }
+        state
     }
 
-    fn update_physical(&self, input: &WinitInputHelper) {
-        const MAPPING: [(KeyCode, CodyKeyCode); 38] = [
+    /// Keys bound to a joystick do not type
+    fn held_physical(
+        input: &WinitInputHelper,
+        joysticks: &JoystickBindings,
+    ) -> [bool; CodyKeyCode::COUNT] {
+        const MAPPING: [(KeyCode, CodyKeyCode); 32] = [
             (KeyCode::KeyQ, CodyKeyCode::KeyQ),
             (KeyCode::KeyE, CodyKeyCode::KeyE),
             (KeyCode::KeyT, CodyKeyCode::KeyT),
@@ -66,280 +70,21 @@ impl Keyboard {
             (KeyCode::KeyY, CodyKeyCode::KeyY),
             (KeyCode::KeyI, CodyKeyCode::KeyI),
             (KeyCode::KeyP, CodyKeyCode::KeyP),
-            // joystick emulation
-            (KeyCode::ArrowUp, CodyKeyCode::Joystick1Up), // up
-            (KeyCode::ArrowDown, CodyKeyCode::Joystick1Down), // down
-            (KeyCode::ArrowLeft, CodyKeyCode::Joystick1Left), // left
-            (KeyCode::ArrowRight, CodyKeyCode::Joystick1Right), // right
-            (KeyCode::ShiftLeft, CodyKeyCode::Joystick1Fire), // fire button
-            (KeyCode::ShiftRight, CodyKeyCode::Joystick1Fire), // fire button
         ];
 
         let mut state = [false; CodyKeyCode::COUNT];
         for (keycode, code) in MAPPING {
-            state[code as usize] |= input.key_held(keycode);
+            if !joysticks.is_bound(keycode) {
+                state[code as usize] |= input.key_held(keycode);
+            }
         }
 
-        let mut key_state = self.key_state.borrow_mut();
-        for (code, pressed) in state.into_iter().enumerate() {
-            key_state.set_pressed((code as u8).try_into().unwrap(), pressed);
-        }
+        state
     }
 
-    fn update_logical(&mut self, input: &WinitInputHelper) {
-        const MAPPING: [(Key<&'static str>, CodyKeyCode, Option<CodyModifier>); 72] = [
-            (Key::Character("q"), CodyKeyCode::KeyQ, None),
-            (Key::Character("e"), CodyKeyCode::KeyE, None),
-            (Key::Character("t"), CodyKeyCode::KeyT, None),
-            (Key::Character("u"), CodyKeyCode::KeyU, None),
-            (Key::Character("o"), CodyKeyCode::KeyO, None),
-            (Key::Character("a"), CodyKeyCode::KeyA, None),
-            (Key::Character("d"), CodyKeyCode::KeyD, None),
-            (Key::Character("g"), CodyKeyCode::KeyG, None),
-            (Key::Character("j"), CodyKeyCode::KeyJ, None),
-            (Key::Character("l"), CodyKeyCode::KeyL, None),
-            (
-                Key::Named(NamedKey::Control),
-                CodyKeyCode::Cody,
-                Some(CodyModifier::Cody),
-            ),
-            (Key::Character("x"), CodyKeyCode::KeyX, None),
-            (Key::Character("v"), CodyKeyCode::KeyV, None),
-            (Key::Character("n"), CodyKeyCode::KeyN, None),
-            (
-                Key::Named(NamedKey::Alt),
-                CodyKeyCode::Meta,
-                Some(CodyModifier::Meta),
-            ),
-            (Key::Character("z"), CodyKeyCode::KeyZ, None),
-            (Key::Character("c"), CodyKeyCode::KeyC, None),
-            (Key::Character("b"), CodyKeyCode::KeyB, None),
-            (Key::Character("m"), CodyKeyCode::KeyM, None),
-            (Key::Named(NamedKey::Enter), CodyKeyCode::Enter, None),
-            (Key::Character("s"), CodyKeyCode::KeyS, None),
-            (Key::Character("f"), CodyKeyCode::KeyF, None),
-            (Key::Character("h"), CodyKeyCode::KeyH, None),
-            (Key::Character("k"), CodyKeyCode::KeyK, None),
-            (Key::Named(NamedKey::Space), CodyKeyCode::Space, None),
-            (Key::Character("w"), CodyKeyCode::KeyW, None),
-            (Key::Character("r"), CodyKeyCode::KeyR, None),
-            (Key::Character("y"), CodyKeyCode::KeyY, None),
-            (Key::Character("i"), CodyKeyCode::KeyI, None),
-            (Key::Character("p"), CodyKeyCode::KeyP, None),
-            (
-                Key::Named(NamedKey::ArrowUp),
-                CodyKeyCode::Joystick1Up,
-                None,
-            ),
-            (
-                Key::Named(NamedKey::ArrowDown),
-                CodyKeyCode::Joystick1Down,
-                None,
-            ),
-            (
-                Key::Named(NamedKey::ArrowLeft),
-                CodyKeyCode::Joystick1Left,
-                None,
-            ),
-            (
-                Key::Named(NamedKey::ArrowRight),
-                CodyKeyCode::Joystick1Right,
-                None,
-            ),
-            (
-                Key::Named(NamedKey::Shift),
-                CodyKeyCode::Joystick1Fire,
-                None,
-            ),
-            (
-                Key::Character("1"),
-                CodyKeyCode::KeyQ,
-                Some(CodyModifier::Cody),
-            ),
-            (
-                Key::Character("2"),
-                CodyKeyCode::KeyW,
-                Some(CodyModifier::Cody),
-            ),
-            (
-                Key::Character("3"),
-                CodyKeyCode::KeyE,
-                Some(CodyModifier::Cody),
-            ),
-            (
-                Key::Character("4"),
-                CodyKeyCode::KeyR,
-                Some(CodyModifier::Cody),
-            ),
-            (
-                Key::Character("5"),
-                CodyKeyCode::KeyT,
-                Some(CodyModifier::Cody),
-            ),
-            (
-                Key::Character("6"),
-                CodyKeyCode::KeyY,
-                Some(CodyModifier::Cody),
-            ),
-            (
-                Key::Character("7"),
-                CodyKeyCode::KeyU,
-                Some(CodyModifier::Cody),
-            ),
-            (
-                Key::Character("8"),
-                CodyKeyCode::KeyI,
-                Some(CodyModifier::Cody),
-            ),
-            (
-                Key::Character("9"),
-                CodyKeyCode::KeyO,
-                Some(CodyModifier::Cody),
-            ),
-            (
-                Key::Character("0"),
-                CodyKeyCode::KeyP,
-                Some(CodyModifier::Cody),
-            ),
-            (
-                Key::Named(NamedKey::Backspace),
-                CodyKeyCode::Enter,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("!"),
-                CodyKeyCode::KeyQ,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("\""),
-                CodyKeyCode::KeyW,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("#"),
-                CodyKeyCode::KeyE,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("$"),
-                CodyKeyCode::KeyR,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("%"),
-                CodyKeyCode::KeyT,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("^"),
-                CodyKeyCode::KeyY,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("&"),
-                CodyKeyCode::KeyU,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("*"),
-                CodyKeyCode::KeyI,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("("),
-                CodyKeyCode::KeyO,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character(")"),
-                CodyKeyCode::KeyP,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("@"),
-                CodyKeyCode::KeyA,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("="),
-                CodyKeyCode::KeyS,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("-"),
-                CodyKeyCode::KeyD,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("+"),
-                CodyKeyCode::KeyF,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character(":"),
-                CodyKeyCode::KeyG,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character(";"),
-                CodyKeyCode::KeyH,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("'"),
-                CodyKeyCode::KeyJ,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("["),
-                CodyKeyCode::KeyK,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("]"),
-                CodyKeyCode::KeyL,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("\\"),
-                CodyKeyCode::KeyZ,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("<"),
-                CodyKeyCode::KeyX,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character(">"),
-                CodyKeyCode::KeyC,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character(","),
-                CodyKeyCode::KeyV,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("."),
-                CodyKeyCode::KeyB,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("?"),
-                CodyKeyCode::KeyN,
-                Some(CodyModifier::Meta),
-            ),
-            (
-                Key::Character("/"),
-                CodyKeyCode::KeyM,
-                Some(CodyModifier::Meta),
-            ),
-        ];
-
+    fn held_logical(input: &WinitInputHelper) -> [bool; CodyKeyCode::COUNT] {
         let mut state = [false; CodyKeyCode::COUNT];
-        for (key, code, modifier) in MAPPING {
+        for (key, code, modifier) in LOGICAL_MAPPING {
             if input.key_held_logical(key) {
                 match modifier {
                     Some(CodyModifier::Cody) => state[CodyKeyCode::Cody as usize] |= true,
@@ -351,9 +96,880 @@ impl Keyboard {
             }
         }
 
-        let mut key_state = self.key_state.borrow_mut();
-        for (code, pressed) in state.into_iter().enumerate() {
-            key_state.set_pressed((code as u8).try_into().unwrap(), pressed);
+        state
+    }
+}
+
+/// Host keys and the Cody keys and modifier that produce the same character
+const LOGICAL_MAPPING: [(Key<&'static str>, CodyKeyCode, Option<CodyModifier>); 67] = [
+    (Key::Character("q"), CodyKeyCode::KeyQ, None),
+    (Key::Character("e"), CodyKeyCode::KeyE, None),
+    (Key::Character("t"), CodyKeyCode::KeyT, None),
+    (Key::Character("u"), CodyKeyCode::KeyU, None),
+    (Key::Character("o"), CodyKeyCode::KeyO, None),
+    (Key::Character("a"), CodyKeyCode::KeyA, None),
+    (Key::Character("d"), CodyKeyCode::KeyD, None),
+    (Key::Character("g"), CodyKeyCode::KeyG, None),
+    (Key::Character("j"), CodyKeyCode::KeyJ, None),
+    (Key::Character("l"), CodyKeyCode::KeyL, None),
+    (
+        Key::Named(NamedKey::Control),
+        CodyKeyCode::Cody,
+        Some(CodyModifier::Cody),
+    ),
+    (Key::Character("x"), CodyKeyCode::KeyX, None),
+    (Key::Character("v"), CodyKeyCode::KeyV, None),
+    (Key::Character("n"), CodyKeyCode::KeyN, None),
+    (
+        Key::Named(NamedKey::Alt),
+        CodyKeyCode::Meta,
+        Some(CodyModifier::Meta),
+    ),
+    (Key::Character("z"), CodyKeyCode::KeyZ, None),
+    (Key::Character("c"), CodyKeyCode::KeyC, None),
+    (Key::Character("b"), CodyKeyCode::KeyB, None),
+    (Key::Character("m"), CodyKeyCode::KeyM, None),
+    (Key::Named(NamedKey::Enter), CodyKeyCode::Enter, None),
+    (Key::Character("s"), CodyKeyCode::KeyS, None),
+    (Key::Character("f"), CodyKeyCode::KeyF, None),
+    (Key::Character("h"), CodyKeyCode::KeyH, None),
+    (Key::Character("k"), CodyKeyCode::KeyK, None),
+    (Key::Named(NamedKey::Space), CodyKeyCode::Space, None),
+    (Key::Character("w"), CodyKeyCode::KeyW, None),
+    (Key::Character("r"), CodyKeyCode::KeyR, None),
+    (Key::Character("y"), CodyKeyCode::KeyY, None),
+    (Key::Character("i"), CodyKeyCode::KeyI, None),
+    (Key::Character("p"), CodyKeyCode::KeyP, None),
+    (
+        Key::Character("1"),
+        CodyKeyCode::KeyQ,
+        Some(CodyModifier::Cody),
+    ),
+    (
+        Key::Character("2"),
+        CodyKeyCode::KeyW,
+        Some(CodyModifier::Cody),
+    ),
+    (
+        Key::Character("3"),
+        CodyKeyCode::KeyE,
+        Some(CodyModifier::Cody),
+    ),
+    (
+        Key::Character("4"),
+        CodyKeyCode::KeyR,
+        Some(CodyModifier::Cody),
+    ),
+    (
+        Key::Character("5"),
+        CodyKeyCode::KeyT,
+        Some(CodyModifier::Cody),
+    ),
+    (
+        Key::Character("6"),
+        CodyKeyCode::KeyY,
+        Some(CodyModifier::Cody),
+    ),
+    (
+        Key::Character("7"),
+        CodyKeyCode::KeyU,
+        Some(CodyModifier::Cody),
+    ),
+    (
+        Key::Character("8"),
+        CodyKeyCode::KeyI,
+        Some(CodyModifier::Cody),
+    ),
+    (
+        Key::Character("9"),
+        CodyKeyCode::KeyO,
+        Some(CodyModifier::Cody),
+    ),
+    (
+        Key::Character("0"),
+        CodyKeyCode::KeyP,
+        Some(CodyModifier::Cody),
+    ),
+    (
+        Key::Named(NamedKey::Backspace),
+        CodyKeyCode::Enter,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("!"),
+        CodyKeyCode::KeyQ,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("\""),
+        CodyKeyCode::KeyW,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("#"),
+        CodyKeyCode::KeyE,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("$"),
+        CodyKeyCode::KeyR,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("%"),
+        CodyKeyCode::KeyT,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("^"),
+        CodyKeyCode::KeyY,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("&"),
+        CodyKeyCode::KeyU,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("*"),
+        CodyKeyCode::KeyI,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("("),
+        CodyKeyCode::KeyO,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character(")"),
+        CodyKeyCode::KeyP,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("@"),
+        CodyKeyCode::KeyA,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("="),
+        CodyKeyCode::KeyS,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("-"),
+        CodyKeyCode::KeyD,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("+"),
+        CodyKeyCode::KeyF,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character(":"),
+        CodyKeyCode::KeyG,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character(";"),
+        CodyKeyCode::KeyH,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("'"),
+        CodyKeyCode::KeyJ,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("["),
+        CodyKeyCode::KeyK,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("]"),
+        CodyKeyCode::KeyL,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("\\"),
+        CodyKeyCode::KeyZ,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("<"),
+        CodyKeyCode::KeyX,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character(">"),
+        CodyKeyCode::KeyC,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character(","),
+        CodyKeyCode::KeyV,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("."),
+        CodyKeyCode::KeyB,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("?"),
+        CodyKeyCode::KeyN,
+        Some(CodyModifier::Meta),
+    ),
+    (
+        Key::Character("/"),
+        CodyKeyCode::KeyM,
+        Some(CodyModifier::Meta),
+    ),
+];
+
+/// Directions and fire button of the first joystick
+pub const JOYSTICK1: [CodyKeyCode; 5] = [
+    CodyKeyCode::Joystick1Up,
+    CodyKeyCode::Joystick1Down,
+    CodyKeyCode::Joystick1Left,
+    CodyKeyCode::Joystick1Right,
+    CodyKeyCode::Joystick1Fire,
+];
+/// Directions and fire button of the second joystick
+pub const JOYSTICK2: [CodyKeyCode; 5] = [
+    CodyKeyCode::Joystick2Up,
+    CodyKeyCode::Joystick2Down,
+    CodyKeyCode::Joystick2Left,
+    CodyKeyCode::Joystick2Right,
+    CodyKeyCode::Joystick2Fire,
+];
+
+/// Host keys that move the joysticks, both can be used at the same time for two players on one keyboard
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct JoystickBindings(pub Vec<(KeyCode, CodyKeyCode)>);
+
+impl Default for JoystickBindings {
+    /// Arrow keys and shift for the first joystick, the numeric keypad for the second
+    fn default() -> Self {
+        Self(vec![
+            (KeyCode::ArrowUp, CodyKeyCode::Joystick1Up),
+            (KeyCode::ArrowDown, CodyKeyCode::Joystick1Down),
+            (KeyCode::ArrowLeft, CodyKeyCode::Joystick1Left),
+            (KeyCode::ArrowRight, CodyKeyCode::Joystick1Right),
+            (KeyCode::ShiftLeft, CodyKeyCode::Joystick1Fire),
+            (KeyCode::ShiftRight, CodyKeyCode::Joystick1Fire),
+            (KeyCode::Numpad8, CodyKeyCode::Joystick2Up),
+            (KeyCode::Numpad2, CodyKeyCode::Joystick2Down),
+            (KeyCode::Numpad4, CodyKeyCode::Joystick2Left),
+            (KeyCode::Numpad6, CodyKeyCode::Joystick2Right),
+            (KeyCode::Numpad0, CodyKeyCode::Joystick2Fire),
+            (KeyCode::Numpad5, CodyKeyCode::Joystick2Fire),
+        ])
+    }
+}
+
+impl JoystickBindings {
+    /// Replace the host keys of `code`, e.g. [`CodyKeyCode::Joystick2Up`]
+    pub fn bind(&mut self, code: CodyKeyCode, keys: impl IntoIterator<Item = KeyCode>) {
+        self.0.retain(|&(_, bound)| bound != code);
+        self.0.extend(keys.into_iter().map(|key| (key, code)));
+    }
+
+    pub fn is_bound(&self, key: KeyCode) -> bool {
+        self.0.iter().any(|&(bound, _)| bound == key)
+    }
+}
+
+/// Host key by its physical position, named like winit's [`KeyCode`], e.g. `KeyW`, `Digit1`, `Numpad8` or `ShiftLeft`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct HostKey(pub KeyCode);
+
+impl FromStr for HostKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const LETTERS: [KeyCode; 26] = [
+            KeyCode::KeyA,
+            KeyCode::KeyB,
+            KeyCode::KeyC,
+            KeyCode::KeyD,
+            KeyCode::KeyE,
+            KeyCode::KeyF,
+            KeyCode::KeyG,
+            KeyCode::KeyH,
+            KeyCode::KeyI,
+            KeyCode::KeyJ,
+            KeyCode::KeyK,
+            KeyCode::KeyL,
+            KeyCode::KeyM,
+            KeyCode::KeyN,
+            KeyCode::KeyO,
+            KeyCode::KeyP,
+            KeyCode::KeyQ,
+            KeyCode::KeyR,
+            KeyCode::KeyS,
+            KeyCode::KeyT,
+            KeyCode::KeyU,
+            KeyCode::KeyV,
+            KeyCode::KeyW,
+            KeyCode::KeyX,
+            KeyCode::KeyY,
+            KeyCode::KeyZ,
+        ];
+        const DIGITS: [KeyCode; 10] = [
+            KeyCode::Digit0,
+            KeyCode::Digit1,
+            KeyCode::Digit2,
+            KeyCode::Digit3,
+            KeyCode::Digit4,
+            KeyCode::Digit5,
+            KeyCode::Digit6,
+            KeyCode::Digit7,
+            KeyCode::Digit8,
+            KeyCode::Digit9,
+        ];
+        const NUMPAD: [KeyCode; 10] = [
+            KeyCode::Numpad0,
+            KeyCode::Numpad1,
+            KeyCode::Numpad2,
+            KeyCode::Numpad3,
+            KeyCode::Numpad4,
+            KeyCode::Numpad5,
+            KeyCode::Numpad6,
+            KeyCode::Numpad7,
+            KeyCode::Numpad8,
+            KeyCode::Numpad9,
+        ];
+        const OTHERS: [(&str, KeyCode); 35] = [
+            ("ArrowUp", KeyCode::ArrowUp),
+            ("ArrowDown", KeyCode::ArrowDown),
+            ("ArrowLeft", KeyCode::ArrowLeft),
+            ("ArrowRight", KeyCode::ArrowRight),
+            ("ShiftLeft", KeyCode::ShiftLeft),
+            ("ShiftRight", KeyCode::ShiftRight),
+            ("ControlLeft", KeyCode::ControlLeft),
+            ("ControlRight", KeyCode::ControlRight),
+            ("AltLeft", KeyCode::AltLeft),
+            ("AltRight", KeyCode::AltRight),
+            ("Space", KeyCode::Space),
+            ("Enter", KeyCode::Enter),
+            ("Tab", KeyCode::Tab),
+            ("Backspace", KeyCode::Backspace),
+            ("CapsLock", KeyCode::CapsLock),
+            ("Insert", KeyCode::Insert),
+            ("Delete", KeyCode::Delete),
+            ("Home", KeyCode::Home),
+            ("End", KeyCode::End),
+            ("PageUp", KeyCode::PageUp),
+            ("PageDown", KeyCode::PageDown),
+            ("Backquote", KeyCode::Backquote),
+            ("Minus", KeyCode::Minus),
+            ("Equal", KeyCode::Equal),
+            ("BracketLeft", KeyCode::BracketLeft),
+            ("BracketRight", KeyCode::BracketRight),
+            ("Backslash", KeyCode::Backslash),
+            ("Semicolon", KeyCode::Semicolon),
+            ("Quote", KeyCode::Quote),
+            ("Comma", KeyCode::Comma),
+            ("Period", KeyCode::Period),
+            ("Slash", KeyCode::Slash),
+            ("NumpadEnter", KeyCode::NumpadEnter),
+            ("NumpadAdd", KeyCode::NumpadAdd),
+            ("NumpadSubtract", KeyCode::NumpadSubtract),
+        ];
+
+        let indexed = |prefix: &str, keys: &[KeyCode]| {
+            let rest = s.strip_prefix(prefix)?;
+            let mut chars = rest.chars();
+            let index = match (chars.next(), chars.next()) {
+                (Some(c), None) if prefix == "Key" => (c as usize).checked_sub('A' as usize)?,
+                (Some(c), None) => c.to_digit(10)? as usize,
+                _ => return None,
+            };
+            keys.get(index).copied()
+        };
+        indexed("Key", &LETTERS)
+            .or_else(|| indexed("Digit", &DIGITS))
+            .or_else(|| indexed("Numpad", &NUMPAD))
+            .or_else(|| {
+                OTHERS
+                    .iter()
+                    .find(|&&(name, _)| name == s)
+                    .map(|&(_, key)| key)
+            })
+            .map(Self)
+            .ok_or_else(|| {
+                format!("unknown key {s:?}, expected e.g. KeyW, Digit1, Numpad8 or ShiftLeft")
+            })
+    }
+}
+
+impl TryFrom<String> for HostKey {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl FromStr for CodyKeyCode {
+    type Err = String;
+
+    /// Name of the key like its variant, e.g. `KeyA`, `Cody`, `Enter` or `Joystick1Fire`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        (0..CodyKeyCode::COUNT as u8)
+            .map(|code| CodyKeyCode::try_from(code).unwrap())
+            .find(|&code| <&str>::from(code) == s)
+            .ok_or_else(|| {
+                format!("unknown cody key {s:?}, expected e.g. KeyA, Meta or Joystick1Fire")
+            })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MacroStep {
+    Press(CodyKeyCode),
+    Release(CodyKeyCode),
+    /// run the next steps this many frames later
+    Wait(usize),
+}
+
+/// Steps of an input macro, written as e.g. `+Meta +KeyQ 2 -KeyQ -Meta`: `+` presses a key, `-` releases it and a
+/// number waits that many frames
+#[derive(Debug, Clone, Eq, PartialEq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct MacroScript(pub Vec<MacroStep>);
+
+impl FromStr for MacroScript {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_whitespace()
+            .map(|step| {
+                if let Some(key) = step.strip_prefix('+') {
+                    key.parse().map(MacroStep::Press)
+                } else if let Some(key) = step.strip_prefix('-') {
+                    key.parse().map(MacroStep::Release)
+                } else {
+                    step.parse().map(MacroStep::Wait).map_err(|_| {
+                        format!(
+                            "invalid macro step {step:?}, expected +KEY, -KEY or a number of frames"
+                        )
+                    })
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl TryFrom<String> for MacroScript {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Host key that runs a macro when pressed
+#[derive(Debug, Clone, Eq, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputMacro {
+    pub key: HostKey,
+    pub steps: MacroScript,
+}
+
+/// Frames a fire button stays pressed and then released while autofire repeats it
+pub const AUTOFIRE_FRAMES: usize = 3;
+
+/// Macro that is being run, with the frames left until its next step
+#[derive(Debug, Clone)]
+struct RunningMacro {
+    steps: VecDeque<MacroStep>,
+    wait: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Keyboard {
+    pub key_state: Rc<RefCell<KeyState>>,
+    /// host keys as of the last update
+    held: [bool; CodyKeyCode::COUNT],
+    queue: KeyQueue,
+    macros: Vec<MacroScript>,
+    running: Vec<RunningMacro>,
+    autofire: bool,
+    /// updates each fire button has been held for on the host
+    fire_held: [usize; 2],
+}
+
+impl Keyboard {
+    /// Releases all keys, later only changes of the host keys are applied
+    pub fn new(key_state: Rc<RefCell<KeyState>>) -> Self {
+        key_state.borrow_mut().set_rows([0xFF; 8]);
+        Self {
+            key_state,
+            held: [false; CodyKeyCode::COUNT],
+            queue: KeyQueue::new(MIN_HOLD_SCANS),
+            macros: vec![],
+            running: vec![],
+            autofire: false,
+            fire_held: [0; 2],
+        }
+    }
+
+    /// Macros that [`Self::run_macro`] starts by their index
+    pub fn set_macros(&mut self, macros: Vec<MacroScript>) {
+        self.macros = macros;
+    }
+
+    /// Start the macro at `index`, it runs alongside the host keys and other macros
+    pub fn run_macro(&mut self, index: usize) {
+        if let Some(script) = self.macros.get(index) {
+            self.running.push(RunningMacro {
+                steps: script.0.iter().copied().collect(),
+                wait: 0,
+            });
         }
     }
+
+    pub const fn autofire(&self) -> bool {
+        self.autofire
+    }
+
+    /// Held fire buttons are pressed and released every [`AUTOFIRE_FRAMES`] updates
+    pub fn set_autofire(&mut self, autofire: bool) {
+        self.autofire = autofire;
+    }
+
+    /// Queue the keys that changed on the host since the last update, a short tap is held until the cody saw it.
+    ///
+    /// Called once per frame, which is the time base of autofire and the macros.
+    pub fn update(&mut self, mut held: [bool; CodyKeyCode::COUNT]) {
+        for (fire, frames) in [CodyKeyCode::Joystick1Fire, CodyKeyCode::Joystick2Fire]
+            .into_iter()
+            .zip(&mut self.fire_held)
+        {
+            let pressed = &mut held[fire as usize];
+            *frames = if *pressed { *frames + 1 } else { 0 };
+            if self.autofire && *pressed {
+                // starts pressed, so a tap still fires
+                *pressed = ((*frames - 1) / AUTOFIRE_FRAMES).is_multiple_of(2);
+            }
+        }
+
+        for (code, (&pressed, was_pressed)) in held.iter().zip(self.held).enumerate() {
+            if pressed != was_pressed {
+                self.queue.push((code as u8).try_into().unwrap(), pressed);
+            }
+        }
+        self.held = held;
+        self.run_macros();
+        self.queue.apply(&mut self.key_state.borrow_mut());
+    }
+
+    /// Queue the macro steps that are due in this frame
+    fn run_macros(&mut self) {
+        for running in &mut self.running {
+            if running.wait > 0 {
+                running.wait -= 1;
+                if running.wait > 0 {
+                    continue;
+                }
+            }
+            while let Some(step) = running.steps.pop_front() {
+                match step {
+                    MacroStep::Press(code) => self.queue.push(code, true),
+                    MacroStep::Release(code) => self.queue.push(code, false),
+                    MacroStep::Wait(0) => {}
+                    MacroStep::Wait(frames) => {
+                        running.wait = frames;
+                        break;
+                    }
+                }
+            }
+        }
+        self.running
+            .retain(|running| running.wait > 0 || !running.steps.is_empty());
+    }
+}
+
+/// Cody key and modifier that type `c`, letters are typed without regard to case
+pub fn key_for_char(c: char) -> Option<(CodyKeyCode, Option<CodyModifier>)> {
+    let c = c.to_ascii_lowercase();
+    let named = match c {
+        '\n' => Some(NamedKey::Enter),
+        ' ' => Some(NamedKey::Space),
+        _ => None,
+    };
+    LOGICAL_MAPPING
+        .iter()
+        .find(|(key, _, _)| match (key, named) {
+            (Key::Named(key), Some(named)) => *key == named,
+            (Key::Character(text), None) => text.chars().eq([c]),
+            _ => false,
+        })
+        .map(|&(_, code, modifier)| (code, modifier))
+}
+
+/// Keyboard scans a key press or release stays visible for, shorter taps would be missed by the cody
+pub const MIN_HOLD_SCANS: usize = 2;
+
+/// Key presses and releases that are applied in order, each one only after the previous change of
+/// the same key was visible for a minimum number of keyboard scans
+#[derive(Debug, Clone)]
+pub struct KeyQueue {
+    events: VecDeque<(CodyKeyCode, bool)>,
+    /// scan count at the last change of each key
+    changed_at: [Option<usize>; CodyKeyCode::COUNT],
+    min_scans: usize,
+}
+
+impl KeyQueue {
+    pub fn new(min_scans: usize) -> Self {
+        Self {
+            events: VecDeque::new(),
+            changed_at: [None; CodyKeyCode::COUNT],
+            min_scans,
+        }
+    }
+
+    pub fn push(&mut self, code: CodyKeyCode, pressed: bool) {
+        self.events.push_back((code, pressed));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Apply all events that are due, the first one that is not yet due holds back the ones after it
+    pub fn apply(&mut self, key_state: &mut KeyState) {
+        let scans = key_state.scans();
+        while let Some(&(code, pressed)) = self.events.front() {
+            let changed_at = &mut self.changed_at[code as usize];
+            if changed_at.is_some_and(|changed_at| scans - changed_at < self.min_scans) {
+                break;
+            }
+            key_state.set_pressed(code, pressed);
+            *changed_at = Some(scans);
+            self.events.pop_front();
+        }
+    }
+}
+
+/// Types text on the emulated keyboard by pressing and releasing one key after another as fast as
+/// the keyboard scan allows
+#[derive(Debug, Clone)]
+pub struct Typist {
+    queue: KeyQueue,
+    start: usize,
+}
+
+impl Typist {
+    /// Start typing `text` at `cycle`, characters without a key on the Cody keyboard are skipped
+    pub fn new(text: &str, cycle: usize) -> Self {
+        let mut queue = KeyQueue::new(MIN_HOLD_SCANS);
+        for c in text.chars().filter(|&c| c != '\r') {
+            let Some((code, modifier)) = key_for_char(c) else {
+                warn!("Cannot type {c:?} on the Cody keyboard, skipping it");
+                continue;
+            };
+            let modifier = modifier.map(CodyModifier::key_code);
+            modifier.inspect(|&modifier| queue.push(modifier, true));
+            queue.push(code, true);
+            queue.push(code, false);
+            modifier.inspect(|&modifier| queue.push(modifier, false));
+        }
+        Self {
+            queue,
+            start: cycle,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Press or release keys that are due at `cycle`, call before executing the instruction at `cycle`
+    pub fn apply(&mut self, cycle: usize, key_state: &mut KeyState) {
+        if cycle >= self.start {
+            self.queue.apply(key_state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_for_char() {
+        assert_eq!(key_for_char('a'), Some((CodyKeyCode::KeyA, None)));
+        assert_eq!(key_for_char('A'), Some((CodyKeyCode::KeyA, None)));
+        assert_eq!(
+            key_for_char('1'),
+            Some((CodyKeyCode::KeyQ, Some(CodyModifier::Cody)))
+        );
+        assert_eq!(key_for_char('\n'), Some((CodyKeyCode::Enter, None)));
+        assert_eq!(key_for_char('~'), None);
+    }
+
+    fn scan(key_state: &mut KeyState) {
+        for row in 0..8 {
+            key_state.record_scan(row);
+        }
+    }
+
+    #[test]
+    fn test_key_queue() {
+        let mut key_state = KeyState::default();
+        key_state.set_rows([0xFF; 8]);
+        let mut queue = KeyQueue::new(2);
+        // a tap shorter than a scan
+        queue.push(CodyKeyCode::KeyA, true);
+        queue.push(CodyKeyCode::KeyA, false);
+        queue.push(CodyKeyCode::KeyB, true);
+
+        queue.apply(&mut key_state);
+        let mut pressed = KeyState::default();
+        pressed.set_rows([0xFF; 8]);
+        pressed.set_pressed(CodyKeyCode::KeyA, true);
+        assert_eq!(key_state.rows(), pressed.rows());
+
+        scan(&mut key_state);
+        queue.apply(&mut key_state);
+        assert_eq!(key_state.rows(), pressed.rows());
+
+        scan(&mut key_state);
+        queue.apply(&mut key_state);
+        pressed.set_pressed(CodyKeyCode::KeyA, false);
+        pressed.set_pressed(CodyKeyCode::KeyB, true);
+        assert_eq!(key_state.rows(), pressed.rows());
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_typist() {
+        let mut key_state = KeyState::default();
+        key_state.set_rows([0xFF; 8]);
+        let mut typist = Typist::new("1", 100);
+        typist.apply(0, &mut key_state);
+        assert_eq!(key_state.rows(), [0xFF; 8]);
+
+        typist.apply(100, &mut key_state);
+        let mut expected = KeyState::default();
+        expected.set_rows([0xFF; 8]);
+        expected.set_pressed(CodyKeyCode::KeyQ, true);
+        expected.set_pressed(CodyKeyCode::Cody, true);
+        assert_eq!(key_state.rows(), expected.rows());
+        assert!(!typist.is_finished());
+
+        for _ in 0..MIN_HOLD_SCANS {
+            scan(&mut key_state);
+        }
+        typist.apply(101, &mut key_state);
+        assert_eq!(key_state.rows(), [0xFF; 8]);
+        assert!(typist.is_finished());
+    }
+
+    #[test]
+    fn test_host_key() {
+        assert_eq!("KeyW".parse(), Ok(HostKey(KeyCode::KeyW)));
+        assert_eq!("Digit0".parse(), Ok(HostKey(KeyCode::Digit0)));
+        assert_eq!("Numpad8".parse(), Ok(HostKey(KeyCode::Numpad8)));
+        assert_eq!("NumpadEnter".parse(), Ok(HostKey(KeyCode::NumpadEnter)));
+        assert_eq!("Tab".parse(), Ok(HostKey(KeyCode::Tab)));
+        assert!("Keyw".parse::<HostKey>().is_err());
+        assert!("KeyWW".parse::<HostKey>().is_err());
+        assert!("F1".parse::<HostKey>().is_err());
+    }
+
+    #[test]
+    fn test_macro_script() {
+        assert_eq!(
+            "+Meta +KeyQ 2 -KeyQ -Meta".parse(),
+            Ok(MacroScript(vec![
+                MacroStep::Press(CodyKeyCode::Meta),
+                MacroStep::Press(CodyKeyCode::KeyQ),
+                MacroStep::Wait(2),
+                MacroStep::Release(CodyKeyCode::KeyQ),
+                MacroStep::Release(CodyKeyCode::Meta),
+            ]))
+        );
+        assert_eq!("".parse(), Ok(MacroScript(vec![])));
+        assert!("+KeyQ -Shift".parse::<MacroScript>().is_err());
+        assert!("KeyQ".parse::<MacroScript>().is_err());
+    }
+
+    /// Run a frame of `keyboard` with the host keys `held`, the cody scans the keyboard twice per frame
+    fn frame(keyboard: &mut Keyboard, held: &[CodyKeyCode]) -> KeyState {
+        let mut host = [false; CodyKeyCode::COUNT];
+        for &code in held {
+            host[code as usize] = true;
+        }
+        keyboard.update(host);
+        for _ in 0..MIN_HOLD_SCANS {
+            scan(&mut keyboard.key_state.borrow_mut());
+        }
+        *keyboard.key_state.borrow()
+    }
+
+    fn pressed(codes: &[CodyKeyCode]) -> [u8; 8] {
+        let mut key_state = KeyState::default();
+        key_state.set_rows([0xFF; 8]);
+        for &code in codes {
+            key_state.set_pressed(code, true);
+        }
+        key_state.rows()
+    }
+
+    #[test]
+    fn test_run_macro() {
+        use CodyKeyCode::*;
+        let mut keyboard = Keyboard::new(Rc::new(RefCell::new(KeyState::default())));
+        keyboard.set_macros(vec!["+Meta +KeyQ 2 -KeyQ 0 -Meta".parse().unwrap()]);
+        keyboard.run_macro(1);
+        keyboard.run_macro(0);
+
+        // on top of the host keys
+        assert_eq!(
+            frame(&mut keyboard, &[KeyA]).rows(),
+            pressed(&[KeyA, Meta, KeyQ])
+        );
+        assert_eq!(frame(&mut keyboard, &[]).rows(), pressed(&[Meta, KeyQ]));
+        // 2 frames after the press
+        assert_eq!(frame(&mut keyboard, &[]).rows(), pressed(&[]));
+        assert!(keyboard.running.is_empty());
+    }
+
+    #[test]
+    fn test_autofire() {
+        use CodyKeyCode::*;
+        let mut keyboard = Keyboard::new(Rc::new(RefCell::new(KeyState::default())));
+        let fire = |keyboard: &mut Keyboard, held: &[CodyKeyCode]| {
+            frame(keyboard, held).rows() == pressed(&[Joystick1Fire])
+        };
+        assert!((0..10).all(|_| fire(&mut keyboard, &[Joystick1Fire])));
+        frame(&mut keyboard, &[]);
+
+        keyboard.set_autofire(true);
+        let fired: Vec<_> = (0..8)
+            .map(|_| fire(&mut keyboard, &[Joystick1Fire]))
+            .collect();
+        assert_eq!(fired, [true, true, true, false, false, false, true, true]);
+        // a new press starts with the button pressed
+        frame(&mut keyboard, &[]);
+        assert!(fire(&mut keyboard, &[Joystick1Fire]));
+    }
+
+    #[test]
+    fn test_bind_joystick() {
+        let mut joysticks = JoystickBindings::default();
+        assert!(joysticks.is_bound(KeyCode::Numpad8));
+        joysticks.bind(CodyKeyCode::Joystick2Up, [KeyCode::KeyW]);
+        assert!(!joysticks.is_bound(KeyCode::Numpad8));
+        assert!(joysticks.is_bound(KeyCode::KeyW));
+        joysticks.bind(CodyKeyCode::Joystick1Fire, []);
+        assert!(!joysticks.is_bound(KeyCode::ShiftLeft));
+    }
 }
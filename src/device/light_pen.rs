@@ -0,0 +1,159 @@
+use crate::device::vid::{Border, CONTENT_HEIGHT, CONTENT_WIDTH, first_line};
+use crate::interrupt::Interrupt;
+use crate::memory::Memory;
+use crate::timing::TimingProfile;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Horizontal beam position latched when the pen saw the beam, in multicolor pixels
+pub const LIGHT_PEN_X: u16 = 0x0;
+/// Vertical beam position latched when the pen saw the beam, in pixels
+pub const LIGHT_PEN_Y: u16 = 0x1;
+/// Bit 0: a position was latched since the last read of the status, bit 1: pen button held
+pub const LIGHT_PEN_STATUS: u16 = 0x2;
+pub const LIGHT_PEN_SIZE: u16 = 0x4;
+
+const STATUS_LATCHED: u8 = 0x01;
+const STATUS_BUTTON: u8 = 0x02;
+
+/// Where the host points the pen
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct PenState {
+    /// pixel of the screen content under the pen, `None` if it points elsewhere
+    pub position: Option<(u8, u8)>,
+    pub button: bool,
+}
+
+impl PenState {
+    /// Pen over the pixel at `x`, `y` of the screen content, positions outside of it do not see the beam
+    pub fn at(x: i32, y: i32, button: bool) -> Self {
+        let on_screen =
+            (0..CONTENT_WIDTH as i32).contains(&x) && (0..CONTENT_HEIGHT as i32).contains(&y);
+        Self {
+            position: on_screen.then_some((x as u8, y as u8)),
+            button,
+        }
+    }
+}
+
+/// Light pen that latches the beam position when the beam passes under it.
+///
+/// The beam sweeps each line at a constant speed without horizontal blanking, so the latched position is only as
+/// precise as the instruction that was running when the beam passed. Not part of the Cody, whose VID has no latch
+/// registers, it only exists when mapped with [`crate::machine::MachineBuilder::light_pen`].
+#[derive(Debug, Default)]
+pub struct LightPen {
+    state: Rc<RefCell<PenState>>,
+    timing: TimingProfile,
+    /// beam position at the last update, in pixels since the start of the frame
+    last_dot: usize,
+    latched: (u8, u8),
+    status: u8,
+}
+
+impl LightPen {
+    pub const fn get_state(&self) -> &Rc<RefCell<PenState>> {
+        &self.state
+    }
+
+    pub fn set_timing(&mut self, timing: TimingProfile) {
+        self.timing = timing;
+    }
+
+    /// Pixels since the start of the frame the beam passed at `cycle`
+    fn dot(&self, cycle: usize) -> usize {
+        let frame_cycles = self.timing.frame_cycles();
+        let lines = self.timing.video.lines_per_frame();
+        (cycle % frame_cycles) * lines * CONTENT_WIDTH as usize / frame_cycles
+    }
+}
+
+impl Memory for LightPen {
+    fn read_u8(&mut self, address: u16) -> u8 {
+        let value = self.peek_u8(address);
+        if address == LIGHT_PEN_STATUS {
+            self.status &= !STATUS_LATCHED;
+        }
+        value
+    }
+
+    fn peek_u8(&mut self, address: u16) -> u8 {
+        match address {
+            LIGHT_PEN_X => self.latched.0,
+            LIGHT_PEN_Y => self.latched.1,
+            LIGHT_PEN_STATUS => {
+                if self.state.borrow().button {
+                    self.status | STATUS_BUTTON
+                } else {
+                    self.status
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_u8(&mut self, _address: u16, _value: u8) {}
+
+    fn update(&mut self, cycle: usize) -> Interrupt {
+        let dot = self.dot(cycle);
+        let last_dot = std::mem::replace(&mut self.last_dot, dot);
+        let Some((x, y)) = self.state.borrow().position else {
+            return Interrupt::none();
+        };
+        let first_line = first_line(&self.timing, Border::None);
+        let target = (first_line + y as usize) * CONTENT_WIDTH as usize + x as usize;
+        let passed = if dot >= last_dot {
+            (last_dot + 1..=dot).contains(&target)
+        } else {
+            // a new frame started
+            target > last_dot || target <= dot
+        };
+        if passed {
+            let line = dot / CONTENT_WIDTH as usize;
+            self.latched = (
+                (dot % CONTENT_WIDTH as usize) as u8,
+                line.saturating_sub(first_line)
+                    .min(CONTENT_HEIGHT as usize - 1) as u8,
+            );
+            self.status |= STATUS_LATCHED;
+        }
+        Interrupt::none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latch() {
+        let mut pen = LightPen::default();
+        *pen.get_state().borrow_mut() = PenState::at(80, 100, true);
+        let timing = TimingProfile::default();
+        let line_start = |row: usize| timing.line_start(first_line(&timing, Border::None) + row);
+
+        // in steps of 4 cycles, like a sequence of short instructions
+        for cycle in (0..line_start(99)).step_by(4) {
+            pen.update(cycle);
+        }
+        assert_eq!(pen.read_u8(LIGHT_PEN_STATUS), STATUS_BUTTON);
+        for cycle in (line_start(99)..line_start(101)).step_by(4) {
+            pen.update(cycle);
+        }
+        assert_eq!(
+            pen.read_u8(LIGHT_PEN_STATUS),
+            STATUS_LATCHED | STATUS_BUTTON
+        );
+        assert_eq!(pen.read_u8(LIGHT_PEN_STATUS), STATUS_BUTTON);
+        assert_eq!(pen.read_u8(LIGHT_PEN_Y), 100);
+        // 4 cycles are 10 multicolor pixels
+        assert!(pen.read_u8(LIGHT_PEN_X).abs_diff(80) <= 10);
+    }
+
+    #[test]
+    fn test_off_screen() {
+        assert_eq!(PenState::at(-1, 10, false).position, None);
+        assert_eq!(PenState::at(160, 10, false).position, None);
+        assert_eq!(PenState::at(159, 199, false).position, Some((159, 199)));
+    }
+}
@@ -1,5 +1,11 @@
 pub mod blanking;
+pub mod button;
+pub mod host_bridge;
 pub mod keyboard;
+pub mod light_pen;
+pub mod mouse;
+pub mod raster;
 pub mod uart;
 pub mod via;
 pub mod vid;
+pub mod xmodem;
@@ -0,0 +1,87 @@
+use crate::device::vid::{CONTENT_HEIGHT, CONTENT_WIDTH};
+use crate::interrupt::Interrupt;
+use crate::memory::Memory;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Horizontal pointer position in multicolor pixels, 0 to 159
+pub const MOUSE_X: u16 = 0x0;
+/// Vertical pointer position in pixels, 0 to 199
+pub const MOUSE_Y: u16 = 0x1;
+/// Bit 0: left, bit 1: right, bit 2: middle button held
+pub const MOUSE_BUTTONS: u16 = 0x2;
+pub const MOUSE_SIZE: u16 = 0x4;
+
+pub const BUTTON_LEFT: u8 = 0x01;
+pub const BUTTON_RIGHT: u8 = 0x02;
+pub const BUTTON_MIDDLE: u8 = 0x04;
+
+/// Pointer position and buttons as set by the host
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct MouseState {
+    pub x: u8,
+    pub y: u8,
+    pub buttons: u8,
+}
+
+impl MouseState {
+    /// Pointer over the pixel at `x`, `y` of the screen content, positions outside of it are clamped to its edges
+    pub fn at(x: i32, y: i32, buttons: u8) -> Self {
+        Self {
+            x: x.clamp(0, CONTENT_WIDTH as i32 - 1) as u8,
+            y: y.clamp(0, CONTENT_HEIGHT as i32 - 1) as u8,
+            buttons,
+        }
+    }
+}
+
+/// Absolute pointing device, usable as mouse or as a pair of paddles with fire buttons.
+///
+/// Not part of the Cody, it only exists when mapped with [`crate::machine::MachineBuilder::mouse`].
+#[derive(Debug, Default)]
+pub struct Mouse {
+    state: Rc<RefCell<MouseState>>,
+}
+
+impl Mouse {
+    pub const fn get_state(&self) -> &Rc<RefCell<MouseState>> {
+        &self.state
+    }
+}
+
+impl Memory for Mouse {
+    fn read_u8(&mut self, address: u16) -> u8 {
+        let state = self.state.borrow();
+        match address {
+            MOUSE_X => state.x,
+            MOUSE_Y => state.y,
+            MOUSE_BUTTONS => state.buttons,
+            _ => 0,
+        }
+    }
+
+    fn write_u8(&mut self, _address: u16, _value: u8) {}
+
+    fn update(&mut self, _cycle: usize) -> Interrupt {
+        Interrupt::none()
+    }
+
+    fn next_event(&mut self, _cycle: usize) -> Option<usize> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        let mut mouse = Mouse::default();
+        *mouse.get_state().borrow_mut() = MouseState::at(200, -5, BUTTON_LEFT | BUTTON_MIDDLE);
+        assert_eq!(mouse.read_u8(MOUSE_X), 159);
+        assert_eq!(mouse.read_u8(MOUSE_Y), 0);
+        assert_eq!(mouse.read_u8(MOUSE_BUTTONS), 0x05);
+        assert_eq!(mouse.read_u8(0x3), 0);
+    }
+}
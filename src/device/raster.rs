@@ -0,0 +1,172 @@
+use crate::interrupt::Interrupt;
+use crate::memory::Memory;
+use crate::timing::TimingProfile;
+
+/// Low byte of the line the beam is on, counted from the start of vertical blanking
+pub const RASTER_LINE: u16 = 0x0;
+/// Bit 0: bit 8 of the line
+pub const RASTER_LINE_HIGH: u16 = 0x1;
+/// Low byte of the line that raises the raster interrupt
+pub const RASTER_COMPARE: u16 = 0x2;
+/// Bit 0: bit 8 of the compare line
+pub const RASTER_COMPARE_HIGH: u16 = 0x3;
+/// Bit 0: the beam reached the compare line since the last acknowledge, written as 1 to acknowledge,
+/// bit 7: hold IRQ while bit 0 is set
+pub const RASTER_CONTROL: u16 = 0x4;
+pub const RASTER_SIZE: u16 = 0x8;
+
+const CONTROL_MATCHED: u8 = 0x01;
+const CONTROL_IRQ_ENABLE: u8 = 0x80;
+
+/// Line counter with a raster compare interrupt, driven by the cycle counter like the blanking register.
+///
+/// Line 0 is the first line of vertical blanking, the first line of the content area is
+/// [`crate::device::vid::first_line`] without border. Not part of the Cody, whose VID only has the blanking register,
+/// it only exists when mapped with [`crate::machine::MachineBuilder::raster`].
+#[derive(Debug, Default)]
+pub struct RasterRegister {
+    timing: TimingProfile,
+    /// line at the last update
+    line: u16,
+    compare: u16,
+    control: u8,
+}
+
+impl RasterRegister {
+    pub fn set_timing(&mut self, timing: TimingProfile) {
+        self.timing = timing;
+    }
+}
+
+impl Memory for RasterRegister {
+    fn read_u8(&mut self, address: u16) -> u8 {
+        match address {
+            RASTER_LINE => self.line as u8,
+            RASTER_LINE_HIGH => (self.line >> 8) as u8,
+            RASTER_COMPARE => self.compare as u8,
+            RASTER_COMPARE_HIGH => (self.compare >> 8) as u8,
+            RASTER_CONTROL => self.control,
+            _ => 0,
+        }
+    }
+
+    fn write_u8(&mut self, address: u16, value: u8) {
+        match address {
+            RASTER_COMPARE => self.compare = (self.compare & 0x100) | value as u16,
+            RASTER_COMPARE_HIGH => {
+                self.compare = (self.compare & 0xFF) | ((value as u16 & 0x1) << 8)
+            }
+            RASTER_CONTROL => {
+                self.control =
+                    (self.control & !value & CONTROL_MATCHED) | (value & CONTROL_IRQ_ENABLE)
+            }
+            _ => {}
+        }
+    }
+
+    fn update(&mut self, cycle: usize) -> Interrupt {
+        let line = self.timing.line(cycle) as u16;
+        let last_line = std::mem::replace(&mut self.line, line);
+        let passed = if line >= last_line {
+            (last_line + 1..=line).contains(&self.compare)
+        } else {
+            // a new frame started
+            let lines = self.timing.video.lines_per_frame() as u16;
+            (last_line + 1..lines).contains(&self.compare) || self.compare <= line
+        };
+        if passed {
+            self.control |= CONTROL_MATCHED;
+        }
+
+        if self.control & (CONTROL_MATCHED | CONTROL_IRQ_ENABLE)
+            == CONTROL_MATCHED | CONTROL_IRQ_ENABLE
+        {
+            Interrupt::irq()
+        } else {
+            Interrupt::none()
+        }
+    }
+
+    fn next_event(&mut self, cycle: usize) -> Option<usize> {
+        let frame_start = cycle - cycle % self.timing.frame_cycles();
+        Some(frame_start + self.timing.line_start(self.line as usize + 1))
+    }
+
+    /// Compare line and control register, the line follows the cycle counter
+    fn save_state(&self) -> Vec<u8> {
+        let [lo, hi] = self.compare.to_le_bytes();
+        vec![lo, hi, self.control]
+    }
+
+    fn restore_state(&mut self, state: &[u8], cycle: usize) {
+        if let &[lo, hi, control] = state {
+            self.compare = u16::from_le_bytes([lo, hi]);
+            self.control = control;
+            self.line = self.timing.line(cycle) as u16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        let mut raster = RasterRegister::default();
+        let timing = TimingProfile::default();
+        let line_start = |line: usize| timing.line_start(line);
+
+        raster.update(line_start(1));
+        assert_eq!(raster.read_u8(RASTER_LINE), 1);
+        raster.update(line_start(261) + 10);
+        assert_eq!(raster.read_u8(RASTER_LINE), (261 - 256) as u8);
+        assert_eq!(raster.read_u8(RASTER_LINE_HIGH), 1);
+        raster.update(timing.frame_cycles() + line_start(3));
+        assert_eq!(raster.read_u8(RASTER_LINE), 3);
+        assert_eq!(raster.read_u8(RASTER_LINE_HIGH), 0);
+    }
+
+    #[test]
+    fn test_compare_interrupt() {
+        let mut raster = RasterRegister::default();
+        raster.write_u8(RASTER_COMPARE, 0x04);
+        raster.write_u8(RASTER_COMPARE_HIGH, 0x01);
+        raster.write_u8(RASTER_CONTROL, CONTROL_IRQ_ENABLE);
+        let timing = TimingProfile::default();
+        let compare_start = timing.line_start(260);
+
+        // in steps of 4 cycles, like a sequence of short instructions
+        let irq_at = (0..timing.frame_cycles())
+            .step_by(4)
+            .find(|&cycle| raster.update(cycle).is_irq());
+        assert!(irq_at.is_some_and(|cycle| cycle.abs_diff(compare_start) < 4));
+        assert_eq!(raster.read_u8(RASTER_CONTROL), 0x81);
+        // held until acknowledged
+        assert!(raster.update(timing.frame_cycles() + 10).is_irq());
+        raster.write_u8(RASTER_CONTROL, CONTROL_IRQ_ENABLE | CONTROL_MATCHED);
+        assert_eq!(raster.read_u8(RASTER_CONTROL), 0x80);
+        assert_eq!(raster.update(timing.frame_cycles() + 20), Interrupt::none());
+    }
+
+    #[test]
+    fn test_polled() {
+        let mut raster = RasterRegister::default();
+        raster.write_u8(RASTER_COMPARE, 100);
+        let timing = TimingProfile::PAL;
+        raster.set_timing(timing);
+
+        for cycle in (0..timing.frame_cycles()).step_by(3) {
+            assert_eq!(raster.update(cycle), Interrupt::none());
+        }
+        assert_eq!(raster.read_u8(RASTER_CONTROL), CONTROL_MATCHED);
+        // a line past the end of the frame never matches
+        raster.write_u8(RASTER_CONTROL, CONTROL_MATCHED);
+        raster.write_u8(RASTER_COMPARE_HIGH, 0x01);
+        raster.write_u8(RASTER_COMPARE, 0x40);
+        for cycle in (timing.frame_cycles()..2 * timing.frame_cycles()).step_by(3) {
+            raster.update(cycle);
+        }
+        assert_eq!(raster.read_u8(RASTER_CONTROL), 0);
+    }
+}
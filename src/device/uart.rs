@@ -1,34 +1,63 @@
 use crate::interrupt::Interrupt;
 use crate::memory::Memory;
-use log::debug;
+use crate::timing::TimingProfile;
+use log::{debug, error};
 use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::io::{BufRead, Read, Write};
 use std::rc::Rc;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 
 pub const UART1_BASE: u16 = 0xD480;
 pub const UART2_BASE: u16 = 0xD4A0;
 
 /// Control register
-const UART_CNTL: u16 = 0;
+pub const UART_CNTL: u16 = 0;
 /// Command register
-const UART_CMND: u16 = 1;
+pub const UART_CMND: u16 = 1;
 /// Status register
-const UART_STAT: u16 = 2;
+pub const UART_STAT: u16 = 2;
 /// Receive ring buffer head register
-const UART_RXHD: u16 = 4;
+pub const UART_RXHD: u16 = 4;
 /// Receive ring buffer tail register
-const UART_RXTL: u16 = 5;
+pub const UART_RXTL: u16 = 5;
 /// Transmit ring buffer head register
-const UART_TXHD: u16 = 6;
+pub const UART_TXHD: u16 = 6;
 /// Transmit ring buffer tail register
-const UART_TXTL: u16 = 7;
+pub const UART_TXTL: u16 = 7;
 /// Ring buffer size
 const UART_BUFFER_SIZE: u16 = 8;
 /// Receive ring buffer (8 bytes)
-const UART_RXBF: u16 = 8;
+pub const UART_RXBF: u16 = 8;
 /// Transmit ring buffer (8 bytes)
-const UART_TXBF: u16 = UART_RXBF + UART_BUFFER_SIZE;
-/// End location
-pub const UART_END: u16 = UART_TXBF + UART_BUFFER_SIZE;
+pub const UART_TXBF: u16 = UART_RXBF + UART_BUFFER_SIZE;
+/// Size of the register block, the registers above are offsets from [`UART1_BASE`] or [`UART2_BASE`]
+pub const UART_SIZE: u16 = UART_TXBF + UART_BUFFER_SIZE;
+
+/// Command register: enable the UART
+const CMND_ENABLE: u8 = 0x01;
+/// Command register: raise an interrupt after each received byte
+const CMND_RX_IRQ: u8 = 0x02;
+/// Command register: raise an interrupt after each transmitted byte
+const CMND_TX_IRQ: u8 = 0x04;
+
+/// Status register: a byte was received since the last status read
+const STAT_RX: u8 = 0x08;
+/// Status register: a byte was transmitted since the last status read
+const STAT_TX: u8 = 0x10;
+/// Status register: UART is enabled
+const STAT_ENABLED: u8 = 0x40;
+/// Status register: an interrupt is pending, cleared by reading the status
+pub const STAT_IRQ: u8 = 0x80;
+
+/// Baud rates selected by the low nibble of the control register, 0 disables pacing
+const BAUD_RATES: [u32; 16] = [
+    0, 50, 75, 110, 135, 150, 300, 600, 1200, 1800, 2400, 3600, 4800, 7200, 9600, 19200,
+];
+/// start bit, 8 data bits, stop bit
+const BITS_PER_BYTE: u32 = 10;
 
 #[derive(Debug, Clone)]
 pub struct Uart {
@@ -38,10 +67,19 @@ pub struct Uart {
     receive_buffer: Rc<RefCell<RingBuf>>,
     transmit_buffer: Rc<RefCell<RingBuf>>,
     source: UartSource,
+    sink: UartSink,
+    activity: Rc<RefCell<UartActivity>>,
+    last_update: usize,
+    /// cycles spent on the byte currently being transmitted
+    tx_progress: usize,
+    /// cycles spent on the byte currently being received
+    rx_progress: usize,
+    /// cpu cycles per second, the baud rates are paced against it
+    cpu_frequency: u32,
 }
 
 impl Uart {
-    pub fn new(source: UartSource) -> Self {
+    pub fn new(source: UartSource, sink: UartSink) -> Self {
         Self {
             control: 0,
             command: 0,
@@ -49,18 +87,33 @@ impl Uart {
             receive_buffer: Default::default(),
             transmit_buffer: Default::default(),
             source,
+            sink,
+            activity: Default::default(),
+            last_update: 0,
+            tx_progress: 0,
+            rx_progress: 0,
+            cpu_frequency: TimingProfile::CODY_FREQUENCY,
         }
     }
 
+    pub fn set_cpu_frequency(&mut self, cpu_frequency: u32) {
+        self.cpu_frequency = cpu_frequency;
+    }
+
     pub const fn is_enabled(&self) -> bool {
-        self.command & 0x1 != 0
+        self.command & CMND_ENABLE != 0
     }
 
-    pub fn update_state(&mut self) {
-        // set enable/disable status bit
-        if self.command & 0x1 != 0 {
-            // discard all errors and transmit/receive status
-            self.status = 0x40;
+    fn set_command(&mut self, command: u8) {
+        let was_enabled = self.is_enabled();
+        self.command = command;
+        if self.is_enabled() {
+            if !was_enabled {
+                // discard all errors and transmit/receive status
+                self.status = STAT_ENABLED;
+                self.tx_progress = 0;
+                self.rx_progress = 0;
+            }
         } else {
             self.status = 0x0;
             self.receive_buffer.borrow_mut().set_head(0);
@@ -68,6 +121,13 @@ impl Uart {
         }
     }
 
+    /// cpu cycles needed to shift one byte at the configured baud rate, `None` if not paced
+    pub fn byte_cycles(&self) -> Option<usize> {
+        let baud = BAUD_RATES[(self.control & 0xF) as usize];
+        (baud != 0)
+            .then(|| (self.cpu_frequency as u64 * BITS_PER_BYTE as u64 / baud as u64) as usize)
+    }
+
     pub const fn get_receive_buffer(&self) -> &Rc<RefCell<RingBuf>> {
         &self.receive_buffer
     }
@@ -75,6 +135,49 @@ impl Uart {
     pub const fn get_transmit_buffer(&self) -> &Rc<RefCell<RingBuf>> {
         &self.transmit_buffer
     }
+
+    pub const fn get_activity(&self) -> &Rc<RefCell<UartActivity>> {
+        &self.activity
+    }
+
+    fn flag(&mut self, status: u8, irq_enable: u8) {
+        self.status |= status;
+        if (self.command & irq_enable) != 0 {
+            self.status |= STAT_IRQ;
+        }
+    }
+
+    /// returns false if there was nothing to transmit
+    fn transmit_byte(&mut self) -> bool {
+        let Some(c) = self.transmit_buffer.borrow_mut().pop() else {
+            return false;
+        };
+        debug!("UART tx: {:?} ({c})", c as char);
+        self.sink.write(c);
+        self.activity.borrow_mut().record_tx(c);
+        self.flag(STAT_TX, CMND_TX_IRQ);
+        true
+    }
+
+    /// returns false if the receive buffer is full or there was nothing to receive
+    fn receive_byte(&mut self) -> bool {
+        if self.receive_buffer.borrow().is_full() {
+            return false;
+        }
+        let Some(value) = self.source.read() else {
+            return false;
+        };
+        self.receive_buffer.borrow_mut().push(value);
+        self.activity.borrow_mut().record_rx(value);
+        debug!(
+            "UART rx: push byte {:?} ({value}), remaining {}/{}",
+            value as char,
+            self.source.pos(),
+            self.source.len(),
+        );
+        self.flag(STAT_RX, CMND_RX_IRQ);
+        true
+    }
 }
 
 impl Memory for Uart {
@@ -82,7 +185,11 @@ impl Memory for Uart {
         match address {
             UART_CNTL => self.control,
             UART_CMND => self.command,
-            UART_STAT => self.status,
+            UART_STAT => {
+                let status = self.status;
+                self.status &= !(STAT_RX | STAT_TX | STAT_IRQ);
+                status
+            }
             UART_RXHD => self.receive_buffer.borrow().head(),
             UART_RXTL => self.receive_buffer.borrow().tail(),
             UART_TXHD => self.transmit_buffer.borrow().head(),
@@ -91,7 +198,7 @@ impl Memory for Uart {
                 .receive_buffer
                 .borrow()
                 .get((address - UART_RXBF) as u8),
-            UART_TXBF..UART_END => self
+            UART_TXBF..UART_SIZE => self
                 .transmit_buffer
                 .borrow()
                 .get((address - UART_TXBF) as u8),
@@ -99,12 +206,17 @@ impl Memory for Uart {
         }
     }
 
+    fn peek_u8(&mut self, address: u16) -> u8 {
+        match address {
+            UART_STAT => self.status,
+            _ => self.read_u8(address),
+        }
+    }
+
     fn write_u8(&mut self, address: u16, value: u8) {
         match address {
             UART_CNTL => self.control = value,
-            UART_CMND => {
-                self.command = value;
-            }
+            UART_CMND => self.set_command(value),
             UART_STAT => {
                 // no-op
             }
@@ -116,7 +228,7 @@ impl Memory for Uart {
                 .receive_buffer
                 .borrow_mut()
                 .set((address - UART_RXBF) as u8, value),
-            UART_TXBF..UART_END => self
+            UART_TXBF..UART_SIZE => self
                 .transmit_buffer
                 .borrow_mut()
                 .set((address - UART_TXBF) as u8, value),
@@ -124,39 +236,167 @@ impl Memory for Uart {
         }
     }
 
-    fn update(&mut self, _cycle: usize) -> Interrupt {
-        // TODO: this is kinda hacky
-        self.update_state();
+    fn update(&mut self, cycle: usize) -> Interrupt {
+        let cycles_elapsed = cycle.wrapping_sub(self.last_update);
+        self.last_update = cycle;
+        self.source.poll(cycle);
+
         if self.is_enabled() {
-            // transmit
-            {
-                let mut tx = self.transmit_buffer.borrow_mut();
-                while let Some(c) = tx.pop() {
-                    // discard
-                    debug!("UART tx: {:?} ({c})", c as char);
+            let mut transmitted = false;
+            if let Some(byte_cycles) = self.byte_cycles() {
+                // an idle line does not bank time for the next byte
+                self.tx_progress += cycles_elapsed;
+                while self.tx_progress >= byte_cycles {
+                    self.tx_progress -= byte_cycles;
+                    if !self.transmit_byte() {
+                        self.tx_progress = 0;
+                        break;
+                    }
+                    transmitted = true;
                 }
-            }
 
-            // receive
-            {
-                let mut rx = self.receive_buffer.borrow_mut();
-                while !rx.is_full() {
-                    if let Some(value) = self.source.read() {
-                        rx.push(value);
-                        debug!(
-                            "UART rx: push byte {:?} ({value}), remaining {}/{}",
-                            value as char,
-                            self.source.pos(),
-                            self.source.len(),
-                        )
-                    } else {
+                self.rx_progress += cycles_elapsed;
+                while self.rx_progress >= byte_cycles {
+                    self.rx_progress -= byte_cycles;
+                    if !self.receive_byte() {
+                        self.rx_progress = 0;
                         break;
                     }
                 }
+            } else {
+                while self.transmit_byte() {
+                    transmitted = true;
+                }
+                while self.receive_byte() {}
+            }
+
+            if transmitted {
+                self.sink.flush();
             }
         }
 
-        Interrupt::none()
+        if (self.status & STAT_IRQ) != 0 {
+            Interrupt::irq()
+        } else {
+            Interrupt::none()
+        }
+    }
+
+    /// Registers, ring buffers and the progress of the bytes on the line, the bytes already taken from the source
+    /// are not given back
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.control, self.command, self.status];
+        for buffer in [&self.receive_buffer, &self.transmit_buffer] {
+            let buffer = buffer.borrow();
+            state.extend(buffer.buf);
+            state.extend([buffer.head, buffer.tail]);
+        }
+        state.extend((self.tx_progress as u64).to_le_bytes());
+        state.extend((self.rx_progress as u64).to_le_bytes());
+        state
+    }
+
+    fn restore_state(&mut self, state: &[u8], cycle: usize) {
+        const BUFFER_STATE: usize = UART_BUFFER_SIZE as usize + 2;
+        if state.len() != 3 + 2 * BUFFER_STATE + 16 {
+            return;
+        }
+        let (registers, rest) = state.split_at(3);
+        let (buffers, progress) = rest.split_at(2 * BUFFER_STATE);
+        self.control = registers[0];
+        self.command = registers[1];
+        self.status = registers[2];
+        for (buffer, state) in [&self.receive_buffer, &self.transmit_buffer]
+            .into_iter()
+            .zip(buffers.chunks(BUFFER_STATE))
+        {
+            let mut buffer = buffer.borrow_mut();
+            let (contents, pointers) = state.split_at(UART_BUFFER_SIZE as usize);
+            buffer.buf.copy_from_slice(contents);
+            buffer.set_head(pointers[0]);
+            buffer.set_tail(pointers[1]);
+        }
+        let (tx_progress, rx_progress) = progress.split_at(8);
+        self.tx_progress = u64::from_le_bytes(tx_progress.try_into().unwrap()) as usize;
+        self.rx_progress = u64::from_le_bytes(rx_progress.try_into().unwrap()) as usize;
+        self.last_update = cycle;
+    }
+}
+
+/// Which way a byte went over a UART
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UartDirection {
+    Rx,
+    Tx,
+}
+
+/// Counters and recent traffic of a UART, for monitoring
+#[derive(Debug, Clone, Default)]
+pub struct UartActivity {
+    pub rx_bytes: usize,
+    pub tx_bytes: usize,
+    /// the byte transmitted last
+    pub last_tx: Option<u8>,
+    tx_lines: VecDeque<String>,
+    traffic: VecDeque<(UartDirection, u8)>,
+}
+
+impl UartActivity {
+    const MAX_LINES: usize = 32;
+    const MAX_TRAFFIC: usize = 0x4000;
+
+    fn record(&mut self, direction: UartDirection, value: u8) {
+        if self.traffic.len() == Self::MAX_TRAFFIC {
+            self.traffic.pop_front();
+        }
+        self.traffic.push_back((direction, value));
+    }
+
+    fn record_rx(&mut self, value: u8) {
+        self.rx_bytes += 1;
+        self.record(UartDirection::Rx, value);
+    }
+
+    fn record_tx(&mut self, value: u8) {
+        self.tx_bytes += 1;
+        self.last_tx = Some(value);
+        self.record(UartDirection::Tx, value);
+        match value {
+            b'\r' => {}
+            b'\n' => {
+                self.tx_lines.push_back(String::new());
+                while self.tx_lines.len() > Self::MAX_LINES {
+                    self.tx_lines.pop_front();
+                }
+            }
+            _ => {
+                if self.tx_lines.is_empty() {
+                    self.tx_lines.push_back(String::new());
+                }
+                let c = value as char;
+                let line = self.tx_lines.back_mut().unwrap();
+                line.push(if c.is_ascii_graphic() || c == ' ' {
+                    c
+                } else {
+                    '.'
+                });
+            }
+        }
+    }
+
+    /// Most recent transmitted lines, oldest first, the last one may be incomplete
+    pub fn tx_lines(&self) -> impl Iterator<Item = &str> {
+        self.tx_lines.iter().map(String::as_str)
+    }
+
+    /// Most recent bytes received and transmitted, oldest first
+    pub fn traffic(&self) -> &VecDeque<(UartDirection, u8)> {
+        &self.traffic
+    }
+
+    /// Bytes received and transmitted since the UART was attached, including those no longer in [`Self::traffic`]
+    pub const fn total_bytes(&self) -> usize {
+        self.rx_bytes + self.tx_bytes
     }
 }
 
@@ -249,6 +489,14 @@ impl Default for RingBuf {
 pub struct UartSource {
     source: Vec<u8>,
     pos: usize,
+    /// bytes arriving while running, read after the fixed source is exhausted
+    streams: Vec<Rc<Receiver<u8>>>,
+    /// cycles between a byte arriving on a stream and it being received
+    latency: usize,
+    /// stream bytes waiting for the latency to pass together with the cycle they are due
+    delayed: VecDeque<(usize, u8)>,
+    /// cycle of the last [`Self::poll`]
+    cycle: usize,
 }
 
 impl UartSource {
@@ -256,6 +504,10 @@ impl UartSource {
         Self {
             source: vec![],
             pos: 0,
+            streams: vec![],
+            latency: 0,
+            delayed: VecDeque::new(),
+            cycle: 0,
         }
     }
 
@@ -263,9 +515,70 @@ impl UartSource {
         Self {
             source: source.into(),
             pos: 0,
+            streams: vec![],
+            latency: 0,
+            delayed: VecDeque::new(),
+            cycle: 0,
         }
     }
 
+    /// Continue with the bytes arriving on `stream` after the fixed source is exhausted,
+    /// earlier streams take precedence
+    pub fn with_stream(mut self, stream: Receiver<u8>) -> Self {
+        self.streams.push(Rc::new(stream));
+        self
+    }
+
+    /// Receive bytes from the streams `latency` cycles after they arrived
+    pub const fn with_latency(mut self, latency: usize) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Continue with the host's stdin after the fixed source is exhausted
+    pub fn with_stdin(self, mode: UartStdioMode) -> Self {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let stdin = std::io::stdin();
+            match mode {
+                UartStdioMode::Raw => {
+                    let mut buf = [0; 256];
+                    loop {
+                        match stdin.lock().read(&mut buf) {
+                            Ok(0) => break,
+                            Ok(n) => {
+                                if buf[..n].iter().any(|&b| tx.send(b).is_err()) {
+                                    break;
+                                }
+                            }
+                            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                            Err(e) => {
+                                error!("UART stdin: error reading: {e}");
+                                break;
+                            }
+                        }
+                    }
+                }
+                UartStdioMode::Line => {
+                    for line in stdin.lock().lines() {
+                        let line = match line {
+                            Ok(line) => line,
+                            Err(e) => {
+                                error!("UART stdin: error reading: {e}");
+                                break;
+                            }
+                        };
+                        if line.bytes().chain([b'\n']).any(|b| tx.send(b).is_err()) {
+                            break;
+                        }
+                    }
+                }
+            }
+            debug!("UART stdin: closed");
+        });
+        self.with_stream(rx)
+    }
+
     pub const fn pos(&self) -> usize {
         self.pos
     }
@@ -287,8 +600,42 @@ impl UartSource {
             let value = self.source[self.pos];
             self.pos += 1;
             Some(value)
+        } else if self.latency > 0 {
+            let &(due, value) = self.delayed.front()?;
+            (due <= self.cycle).then(|| {
+                self.delayed.pop_front();
+                value
+            })
         } else {
-            None
+            self.read_stream()
+        }
+    }
+
+    fn read_stream(&mut self) -> Option<u8> {
+        let mut value = None;
+        self.streams.retain(|stream| {
+            if value.is_some() {
+                return true;
+            }
+            match stream.try_recv() {
+                Ok(v) => {
+                    value = Some(v);
+                    true
+                }
+                Err(TryRecvError::Empty) => true,
+                Err(TryRecvError::Disconnected) => false,
+            }
+        });
+        value
+    }
+
+    /// Advance to `cycle`, bytes that arrived on the streams since the last poll are delayed by the latency
+    pub fn poll(&mut self, cycle: usize) {
+        self.cycle = cycle;
+        if self.latency > 0 {
+            while let Some(value) = self.read_stream() {
+                self.delayed.push_back((cycle + self.latency, value));
+            }
         }
     }
 
@@ -297,10 +644,187 @@ impl UartSource {
     }
 }
 
+/// How the host's stdin and stdout are bridged to a UART
+#[derive(Debug, Copy, Clone, Eq, PartialEq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UartStdioMode {
+    /// Forward every byte as soon as it is available
+    Raw,
+    /// Forward complete lines only, normalizing line endings to `\n`
+    Line,
+}
+
+/// Destination for transmitted bytes, discards everything by default
+#[derive(Clone, Default)]
+pub struct UartSink {
+    sink: Option<Rc<RefCell<dyn Write>>>,
+    line_buffered: bool,
+}
+
+impl UartSink {
+    pub const fn discard() -> Self {
+        Self {
+            sink: None,
+            line_buffered: false,
+        }
+    }
+
+    /// Flush after every update that transmitted data
+    pub fn new(sink: impl Write + 'static) -> Self {
+        Self {
+            sink: Some(Rc::new(RefCell::new(sink))),
+            line_buffered: false,
+        }
+    }
+
+    /// Like [`Self::new`] for a writer that is also used outside of the UART
+    pub fn shared(sink: Rc<RefCell<dyn Write>>) -> Self {
+        Self {
+            sink: Some(sink),
+            line_buffered: false,
+        }
+    }
+
+    /// Never flush explicitly, leaving it to the writer (e.g. [`std::io::Stdout`] flushes on newlines)
+    pub fn line_buffered(sink: impl Write + 'static) -> Self {
+        Self {
+            sink: Some(Rc::new(RefCell::new(sink))),
+            line_buffered: true,
+        }
+    }
+
+    pub fn stdout(mode: UartStdioMode) -> Self {
+        match mode {
+            UartStdioMode::Raw => Self::new(std::io::stdout()),
+            UartStdioMode::Line => Self::line_buffered(std::io::stdout()),
+        }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        if let Some(sink) = &self.sink
+            && let Err(e) = sink.borrow_mut().write_all(&[value])
+        {
+            error!("UART sink: error writing byte: {e}");
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if !self.line_buffered
+            && let Some(sink) = &self.sink
+            && let Err(e) = sink.borrow_mut().flush()
+        {
+            error!("UART sink: error flushing: {e}");
+        }
+    }
+}
+
+impl Debug for UartSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UartSink")
+            .field("discard", &self.sink.is_none())
+            .field("line_buffered", &self.line_buffered)
+            .finish()
+    }
+}
+
+/// Forwards written bytes to the [`UartSource`] of a [`UartBackend`]
+struct ChannelWriter(Sender<u8>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &b in buf {
+            self.0
+                .send(b)
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Source and sink of a UART that is connected to a UART instead of the host
+#[derive(Debug)]
+pub enum UartBackend {
+    /// transmitted bytes are received again by the same UART
+    Loopback,
+    /// one end of a null modem cable, created in pairs by [`UartBackend::link`]
+    Link(UartSource, UartSink),
+}
+
+impl UartBackend {
+    /// Both ends of a null modem cable, bytes transmitted by one end are received by the other
+    /// `latency` cycles later.
+    ///
+    /// Used to link the UARTs of two machines running in the same process, see [`crate::machine::run_together`].
+    pub fn link(latency: usize) -> [Self; 2] {
+        let (tx_a, rx_b) = mpsc::channel();
+        let (tx_b, rx_a) = mpsc::channel();
+        [
+            Self::Link(
+                UartSource::empty().with_stream(rx_a).with_latency(latency),
+                UartSink::new(ChannelWriter(tx_a)),
+            ),
+            Self::Link(
+                UartSource::empty().with_stream(rx_b).with_latency(latency),
+                UartSink::new(ChannelWriter(tx_b)),
+            ),
+        ]
+    }
+
+    /// Source and sink to pass to [`crate::machine::MachineBuilder::uart1`] or [`crate::machine::MachineBuilder::uart2`]
+    pub fn into_parts(self) -> (UartSource, UartSink) {
+        match self {
+            Self::Loopback => {
+                let (tx, rx) = mpsc::channel();
+                (
+                    UartSource::empty().with_stream(rx),
+                    UartSink::new(ChannelWriter(tx)),
+                )
+            }
+            Self::Link(source, sink) => (source, sink),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_link() {
+        let [a, b] = UartBackend::link(0);
+        let ((mut source_a, mut sink_a), (mut source_b, mut sink_b)) =
+            (a.into_parts(), b.into_parts());
+        sink_a.write(1);
+        sink_a.write(2);
+        sink_b.write(3);
+        assert_eq!(source_b.read(), Some(1));
+        assert_eq!(source_b.read(), Some(2));
+        assert_eq!(source_b.read(), None);
+        assert_eq!(source_a.read(), Some(3));
+        assert_eq!(source_a.read(), None);
+
+        let (mut source, mut sink) = UartBackend::Loopback.into_parts();
+        sink.write(4);
+        assert_eq!(source.read(), Some(4));
+    }
+
+    #[test]
+    fn test_link_latency() {
+        let [a, b] = UartBackend::link(100);
+        let ((_, mut sink), (mut source, _)) = (a.into_parts(), b.into_parts());
+        sink.write(1);
+        source.poll(1000);
+        assert_eq!(source.read(), None);
+        source.poll(1099);
+        assert_eq!(source.read(), None);
+        source.poll(1100);
+        assert_eq!(source.read(), Some(1));
+    }
+
     #[test]
     fn test_push_to_capacity() {
         let mut buf = RingBuf::new();
@@ -392,6 +916,83 @@ mod tests {
         assert_eq!(buf.len(), 0);
     }
 
+    fn enabled_uart(control: u8, command: u8, source: &[u8]) -> Uart {
+        let mut uart = Uart::new(UartSource::new(source), UartSink::discard());
+        uart.write_u8(UART_CNTL, control);
+        uart.write_u8(UART_CMND, command);
+        uart
+    }
+
+    #[test]
+    fn test_unpaced_receive() {
+        let mut uart = enabled_uart(0x00, CMND_ENABLE, b"abc");
+        uart.update(1);
+        assert_eq!(uart.get_receive_buffer().borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_paced_receive() {
+        // 19200 baud
+        let mut uart = enabled_uart(0x0F, CMND_ENABLE, b"abc");
+        let byte_cycles = uart.byte_cycles().unwrap();
+        assert_eq!(byte_cycles, 520);
+
+        uart.update(byte_cycles - 1);
+        assert_eq!(uart.get_receive_buffer().borrow().len(), 0);
+        uart.update(byte_cycles);
+        assert_eq!(uart.get_receive_buffer().borrow().len(), 1);
+        uart.update(3 * byte_cycles);
+        assert_eq!(uart.get_receive_buffer().borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_byte_cycles_fast_clock() {
+        let mut uart = enabled_uart(0x0F, CMND_ENABLE, b"");
+        uart.set_cpu_frequency(TimingProfile::MAX_FREQUENCY);
+        assert_eq!(uart.byte_cycles(), Some(520833));
+    }
+
+    #[test]
+    fn test_receive_interrupt() {
+        let mut uart = enabled_uart(0x0F, CMND_ENABLE, b"a");
+        assert!(!uart.update(1000).is_irq());
+        assert_ne!(uart.read_u8(UART_STAT) & STAT_RX, 0);
+
+        let mut uart = enabled_uart(0x0F, CMND_ENABLE | CMND_RX_IRQ, b"a");
+        assert!(uart.update(1000).is_irq());
+        let status = uart.read_u8(UART_STAT);
+        assert_eq!(status, STAT_ENABLED | STAT_RX | STAT_IRQ);
+        assert_eq!(uart.read_u8(UART_STAT), STAT_ENABLED);
+        assert!(!uart.update(2000).is_irq());
+    }
+
+    #[test]
+    fn test_paced_transmit() {
+        let mut uart = enabled_uart(0x0F, CMND_ENABLE | CMND_TX_IRQ, b"");
+        {
+            let mut tx = uart.get_transmit_buffer().borrow_mut();
+            tx.push(1);
+            tx.push(2);
+        }
+        assert!(uart.update(520).is_irq());
+        assert_eq!(uart.get_transmit_buffer().borrow().len(), 1);
+        uart.update(1040);
+        assert!(uart.get_transmit_buffer().borrow().is_empty());
+    }
+
+    #[test]
+    fn test_traffic() {
+        let mut uart = enabled_uart(0x00, CMND_ENABLE, b"a");
+        uart.get_transmit_buffer().borrow_mut().push(b'b');
+        uart.update(1);
+        let activity = uart.get_activity().borrow();
+        assert_eq!(
+            activity.traffic(),
+            &[(UartDirection::Tx, b'b'), (UartDirection::Rx, b'a')]
+        );
+        assert_eq!(activity.total_bytes(), 2);
+    }
+
     #[test]
     fn test_status() {
         let mut buf = RingBuf::new();
@@ -2,6 +2,7 @@ use crate::interrupt::Interrupt;
 use crate::memory::Memory;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use std::cell::RefCell;
+use std::fmt::Debug;
 use std::rc::Rc;
 use strum::{EnumCount, IntoStaticStr};
 
@@ -22,40 +23,212 @@ pub const VIA_IFR: u16 = 0xD;
 pub const VIA_IER: u16 = 0xE;
 pub const VIA_IORA_NO_HANDSHAKE: u16 = 0xF;
 
+/// Something attached to the 8 lines of a VIA port, e.g. a user-port peripheral
+pub trait PortDevice: Debug {
+    /// Level of the port lines, only the bits not set in `ddr` are used
+    fn read(&mut self, output: u8, ddr: u8) -> u8;
+
+    /// Called whenever the VIA changes its output register or data direction
+    fn write(&mut self, output: u8, ddr: u8) {
+        let _ = (output, ddr);
+    }
+}
+
+/// Port lines that can be driven and observed by the host
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PortLines {
+    /// level of the lines that the VIA configured as inputs
+    pub input: u8,
+    /// output register of the VIA
+    pub output: u8,
+    /// data direction of the VIA, set bits are outputs
+    pub ddr: u8,
+}
+
+impl Default for PortLines {
+    fn default() -> Self {
+        Self {
+            // undriven lines are pulled high
+            input: 0xFF,
+            output: 0,
+            ddr: 0,
+        }
+    }
+}
+
+impl PortLines {
+    /// Level of all lines as seen from outside the VIA
+    pub const fn lines(&self) -> u8 {
+        (self.output & self.ddr) | (self.input & !self.ddr)
+    }
+}
+
+impl PortDevice for PortLines {
+    fn read(&mut self, _output: u8, _ddr: u8) -> u8 {
+        self.input
+    }
+
+    fn write(&mut self, output: u8, ddr: u8) {
+        self.output = output;
+        self.ddr = ddr;
+    }
+}
+
+/// W65C22 with the keyboard on port A, or other devices on its ports.
+///
+/// The timers count every cycle and time out when they pass 0 to 0xFFFF, N + 1.5 cycles after the write that started
+/// them, which is seen as N + 2. A free-running timer 1 then takes a cycle to reload its latch, a period of N + 2
+/// cycles, a one-shot timer keeps counting down without interrupts until it is started again. Register accesses take
+/// effect at the cycle of the last update, the start of the instruction, so the cycles between two absolute
+/// accesses are the same as on the hardware.
+///
+/// Reading a port returns the input register: the port A lines, or the port B output register for outputs and the
+/// lines for inputs. With latching enabled in ACR the lines are the ones at the last active CA1 or CB1 edge. IORA
+/// and IORB acknowledge the CA1/CA2 or CB1/CB2 flags, IORA at offset 0xF does not. The CA2 and CB2 lines are not
+/// emulated.
 #[derive(Debug, Clone, Default)]
 pub struct Via {
     registers: [u8; 16],
     key_state: Rc<RefCell<KeyState>>,
+    port_a: Option<Rc<RefCell<dyn PortDevice>>>,
+    port_b: Option<Rc<RefCell<dyn PortDevice>>>,
+    ca1: Rc<RefCell<bool>>,
+    ca1_level: bool,
+    cb1: Rc<RefCell<bool>>,
+    cb1_level: bool,
+    /// port A lines at the last active CA1 edge, read instead of the lines when ACR bit 0 is set
+    ira_latch: u8,
+    /// port B lines at the last active CB1 edge, read instead of the lines when ACR bit 1 is set
+    irb_latch: u8,
     last_update: usize,
     t1_latch_lo: u8,
     t1_latch_hi: u8,
     t1_counter: u16,
+    /// the next time out sets the interrupt flag
     t1_enabled: bool,
+    /// the latch is loaded into the counter at the next cycle
+    t1_reload: bool,
     t2_latch_lo: u8,
     t2_latch_hi: u8,
     t2_counter: u16,
     t2_enabled: bool,
+    t2_reload: bool,
     ifr: u8,
     ier: u8,
 }
 
 impl Via {
-    fn read_iora(&mut self) -> u8 {
+    /// VIA without a keyboard, with the given devices attached to its ports
+    pub fn with_ports(
+        port_a: Rc<RefCell<dyn PortDevice>>,
+        port_b: Rc<RefCell<dyn PortDevice>>,
+    ) -> Self {
+        Self {
+            port_a: Some(port_a),
+            port_b: Some(port_b),
+            ..Self::default()
+        }
+    }
+
+    /// Level of the port A lines
+    fn port_a_lines(&mut self) -> u8 {
         let ddr = self.registers[VIA_DDRA as usize];
         let ior = self.registers[VIA_IORA as usize];
-        // TODO: only works for cody right now
-        assert_eq!(
-            ddr, 0x7,
-            "when reading IORA only DDRA = 0x7 is supported, but was {ddr:#x}"
-        );
-        let output = ior & ddr;
-        self.key_state.borrow().state[output as usize] | output
+        if let Some(port) = &self.port_a {
+            return (ior & ddr) | (port.borrow_mut().read(ior, ddr) & !ddr);
+        }
+
+        // PA0-PA2 select the keyboard row, undriven lines are pulled high
+        let row = (ior | !ddr) & 0x7;
+        // PA3-PA7 read the selected row's columns (active low)
+        let pins = (self.key_state.borrow().read_row(row) & !0x7) | row;
+        // output pins read back the output register
+        (ior & ddr) | (pins & !ddr)
+    }
+
+    /// Level of the port B lines that are inputs, undriven lines are pulled high
+    fn port_b_lines(&mut self) -> u8 {
+        let ddr = self.registers[VIA_DDRB as usize];
+        let ior = self.registers[VIA_IORB as usize];
+        match &self.port_b {
+            Some(port) => port.borrow_mut().read(ior, ddr),
+            None => 0xFF,
+        }
+    }
+
+    fn read_iora(&mut self) -> u8 {
+        if (self.registers[VIA_ACR as usize] & 0x01) != 0 {
+            self.ira_latch
+        } else {
+            self.port_a_lines()
+        }
+    }
+
+    /// Read port A like the cpu does, which counts keyboard scans
+    fn scan_keyboard(&mut self) -> u8 {
+        let value = self.read_iora();
+        if self.port_a.is_none() {
+            let row = value & 0x7;
+            self.key_state.borrow_mut().record_scan(row);
+        }
+        value
+    }
+
+    fn read_iorb(&mut self) -> u8 {
+        let ddr = self.registers[VIA_DDRB as usize];
+        let ior = self.registers[VIA_IORB as usize];
+        let lines = if (self.registers[VIA_ACR as usize] & 0x02) != 0 {
+            self.irb_latch
+        } else {
+            self.port_b_lines()
+        };
+        // output pins read back the output register
+        (ior & ddr) | (lines & !ddr)
+    }
+
+    /// Clear the CA1 flag and the CA2 flag unless CA2 is an independent interrupt input
+    fn acknowledge_port_a(&mut self) {
+        let independent = (self.registers[VIA_PCR as usize] & 0x0A) == 0x02;
+        let flags = if independent { 0x02 } else { 0x03 };
+        self.set_ifr(self.ifr & !flags);
+    }
+
+    /// Clear the CB1 flag and the CB2 flag unless CB2 is an independent interrupt input
+    fn acknowledge_port_b(&mut self) {
+        let independent = (self.registers[VIA_PCR as usize] & 0xA0) == 0x20;
+        let flags = if independent { 0x10 } else { 0x18 };
+        self.set_ifr(self.ifr & !flags);
+    }
+
+    fn notify_ports(&self) {
+        if let Some(port) = &self.port_a {
+            port.borrow_mut().write(
+                self.registers[VIA_IORA as usize],
+                self.registers[VIA_DDRA as usize],
+            );
+        }
+        if let Some(port) = &self.port_b {
+            port.borrow_mut().write(
+                self.registers[VIA_IORB as usize],
+                self.registers[VIA_DDRB as usize],
+            );
+        }
     }
 
     pub fn get_key_state(&self) -> &Rc<RefCell<KeyState>> {
         &self.key_state
     }
 
+    /// Level of the CA1 input line
+    pub fn get_ca1(&self) -> &Rc<RefCell<bool>> {
+        &self.ca1
+    }
+
+    /// Level of the CB1 input line
+    pub fn get_cb1(&self) -> &Rc<RefCell<bool>> {
+        &self.cb1
+    }
+
     fn set_ifr(&mut self, ifr: u8) {
         let mut ifr = ifr & 0x7F;
         if (ifr & self.ier) != 0 {
@@ -64,6 +237,46 @@ impl Via {
         self.ifr = ifr;
     }
 
+    /// Advance timer 1 by a cycle
+    fn tick_t1(&mut self, acr: u8) {
+        if self.t1_reload {
+            self.t1_reload = false;
+            self.t1_counter = self.t1_latch_lo as u16 | (self.t1_latch_hi as u16) << 8;
+            return;
+        }
+        self.t1_counter = self.t1_counter.wrapping_sub(1);
+        if self.t1_counter == 0xFFFF {
+            let continuous = (acr & 0x40) != 0;
+            if self.t1_enabled {
+                self.set_ifr(self.ifr | 0x40);
+                self.t1_enabled = continuous;
+            }
+            self.t1_reload = continuous;
+
+            if (acr & 0x80) != 0 {
+                // TODO: flip PB7
+            }
+        }
+    }
+
+    /// Advance timer 2 by a cycle, it is always a one-shot timer
+    fn tick_t2(&mut self, acr: u8) {
+        if self.t2_reload {
+            self.t2_reload = false;
+            self.t2_counter = self.t2_latch_lo as u16 | (self.t2_latch_hi as u16) << 8;
+            return;
+        }
+        if (acr & 0x20) != 0 {
+            // TODO: count PB6 pulses
+            return;
+        }
+        self.t2_counter = self.t2_counter.wrapping_sub(1);
+        if self.t2_counter == 0xFFFF && self.t2_enabled {
+            self.set_ifr(self.ifr | 0x20);
+            self.t2_enabled = false;
+        }
+    }
+
     fn set_ier(&mut self, ier: u8) {
         if (ier & 0x80) != 0 {
             self.ier |= ier;
@@ -79,7 +292,15 @@ impl Via {
 impl Memory for Via {
     fn read_u8(&mut self, address: u16) -> u8 {
         match address {
-            VIA_IORA => self.read_iora(),
+            VIA_IORA => {
+                self.acknowledge_port_a();
+                self.scan_keyboard()
+            }
+            VIA_IORA_NO_HANDSHAKE => self.scan_keyboard(),
+            VIA_IORB => {
+                self.acknowledge_port_b();
+                self.read_iorb()
+            }
             VIA_T1CL => {
                 self.set_ifr(self.ifr & !0x40);
                 (self.t1_counter & 0xFF) as u8
@@ -94,18 +315,28 @@ impl Memory for Via {
             VIA_T2CH => (self.t2_counter >> 8) as u8,
             VIA_IFR => self.ifr,
             VIA_IER => self.ier | 0x80,
-            0x0..=0xF => self.registers[address as usize],
+            VIA_DDRB | VIA_DDRA | VIA_SR | VIA_ACR | VIA_PCR => self.registers[address as usize],
             _ => 0,
         }
     }
 
+    fn peek_u8(&mut self, address: u16) -> u8 {
+        match address {
+            VIA_IORA | VIA_IORA_NO_HANDSHAKE => self.read_iora(),
+            VIA_IORB => self.read_iorb(),
+            VIA_T1CL => (self.t1_counter & 0xFF) as u8,
+            VIA_T2CL => (self.t2_counter & 0xFF) as u8,
+            _ => self.read_u8(address),
+        }
+    }
+
     fn write_u8(&mut self, address: u16, value: u8) {
         match address {
             VIA_T1CL => self.t1_latch_lo = value,
             VIA_T1CH => {
                 self.t1_latch_hi = value;
                 self.set_ifr(self.ifr & !0x40);
-                self.t1_counter = self.t1_latch_lo as u16 | (self.t1_latch_hi as u16) << 8;
+                self.t1_reload = true;
                 self.t1_enabled = true;
             }
             VIA_T1LL => self.t1_latch_lo = value,
@@ -117,12 +348,29 @@ impl Memory for Via {
             VIA_T2CH => {
                 self.t2_latch_hi = value;
                 self.set_ifr(self.ifr & !0x20);
-                self.t2_counter = self.t2_latch_lo as u16 | (self.t2_latch_hi as u16) << 8;
+                self.t2_reload = true;
                 self.t2_enabled = true;
             }
-            VIA_IFR => self.set_ifr(value),
+            // set bits clear the flags
+            VIA_IFR => self.set_ifr(self.ifr & !value),
             VIA_IER => self.set_ier(value),
-            0x0..=0xF => {
+            VIA_IORA | VIA_IORA_NO_HANDSHAKE => {
+                if address == VIA_IORA {
+                    self.acknowledge_port_a();
+                }
+                self.registers[VIA_IORA as usize] = value;
+                self.notify_ports();
+            }
+            VIA_IORB => {
+                self.acknowledge_port_b();
+                self.registers[VIA_IORB as usize] = value;
+                self.notify_ports();
+            }
+            VIA_DDRB | VIA_DDRA => {
+                self.registers[address as usize] = value;
+                self.notify_ports();
+            }
+            VIA_SR | VIA_ACR | VIA_PCR => {
                 self.registers[address as usize] = value;
             }
             _ => {}
@@ -136,34 +384,33 @@ impl Memory for Via {
         let acr = self.registers[VIA_ACR as usize];
 
         for _ in 0..cycles_elapsed {
-            self.t1_counter = self.t1_counter.wrapping_sub(1);
-            if self.t1_counter == 0 {
-                if self.t1_enabled {
-                    self.set_ifr(self.ifr | 0x40);
-
-                    // if not in continuous mode we stop the interrupt trigger
-                    if (acr & 0x40) == 0 {
-                        self.t1_enabled = false;
-                    }
-                }
-
-                // reset counter to latched value
-                self.t1_counter = self.t1_latch_lo as u16 | (self.t1_latch_hi as u16) << 8;
+            self.tick_t1(acr);
+            self.tick_t2(acr);
+        }
 
-                if (acr & 0x80) != 0 {
-                    // TODO: flip PB7
+        // PCR bit 0 selects the active CA1 edge, 0 = negative and 1 = positive
+        let pcr = self.registers[VIA_PCR as usize];
+        let ca1_level = *self.ca1.borrow();
+        if ca1_level != self.ca1_level {
+            self.ca1_level = ca1_level;
+            let positive_edge = (pcr & 0x01) != 0;
+            if ca1_level == positive_edge {
+                if (acr & 0x01) != 0 {
+                    self.ira_latch = self.port_a_lines();
                 }
+                self.set_ifr(self.ifr | 0x02);
             }
-
-            if (acr & 0x20) != 0 {
-                // TODO: count PB6 pulses
-            } else {
-                self.t2_counter = self.t2_counter.wrapping_sub(1);
-            }
-
-            if self.t2_counter == 0 && self.t2_enabled {
-                self.set_ifr(self.ifr | 0x20);
-                self.t2_enabled = false;
+        }
+        // PCR bit 4 selects the active CB1 edge
+        let cb1_level = *self.cb1.borrow();
+        if cb1_level != self.cb1_level {
+            self.cb1_level = cb1_level;
+            let positive_edge = (pcr & 0x10) != 0;
+            if cb1_level == positive_edge {
+                if (acr & 0x02) != 0 {
+                    self.irb_latch = self.port_b_lines();
+                }
+                self.set_ifr(self.ifr | 0x10);
             }
         }
 
@@ -173,6 +420,83 @@ impl Memory for Via {
             Interrupt::none()
         }
     }
+
+    /// Registers, timers, interrupt flags and input latches, the attached devices are not included
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.registers.to_vec();
+        state.extend([
+            self.ca1_level as u8,
+            self.cb1_level as u8,
+            self.ira_latch,
+            self.irb_latch,
+            self.t1_latch_lo,
+            self.t1_latch_hi,
+        ]);
+        state.extend(self.t1_counter.to_le_bytes());
+        state.extend([
+            self.t1_enabled as u8,
+            self.t1_reload as u8,
+            self.t2_latch_lo,
+            self.t2_latch_hi,
+        ]);
+        state.extend(self.t2_counter.to_le_bytes());
+        state.extend([
+            self.t2_enabled as u8,
+            self.t2_reload as u8,
+            self.ifr,
+            self.ier,
+        ]);
+        state
+    }
+
+    fn restore_state(&mut self, state: &[u8], cycle: usize) {
+        let Ok(
+            [
+                registers @ ..,
+                ca1_level,
+                cb1_level,
+                ira_latch,
+                irb_latch,
+                t1_latch_lo,
+                t1_latch_hi,
+                t1_counter_lo,
+                t1_counter_hi,
+                t1_enabled,
+                t1_reload,
+                t2_latch_lo,
+                t2_latch_hi,
+                t2_counter_lo,
+                t2_counter_hi,
+                t2_enabled,
+                t2_reload,
+                ifr,
+                ier,
+            ],
+        ) = <&[u8; 34]>::try_from(state)
+        else {
+            return;
+        };
+        self.registers = *registers;
+        self.ca1_level = *ca1_level != 0;
+        self.cb1_level = *cb1_level != 0;
+        self.ira_latch = *ira_latch;
+        self.irb_latch = *irb_latch;
+        self.t1_latch_lo = *t1_latch_lo;
+        self.t1_latch_hi = *t1_latch_hi;
+        self.t1_counter = u16::from_le_bytes([*t1_counter_lo, *t1_counter_hi]);
+        self.t1_enabled = *t1_enabled != 0;
+        self.t1_reload = *t1_reload != 0;
+        self.t2_latch_lo = *t2_latch_lo;
+        self.t2_latch_hi = *t2_latch_hi;
+        self.t2_counter = u16::from_le_bytes([*t2_counter_lo, *t2_counter_hi]);
+        self.t2_enabled = *t2_enabled != 0;
+        self.t2_reload = *t2_reload != 0;
+        self.ifr = *ifr;
+        self.ier = *ier;
+        // the timers continue from the current cycle
+        self.last_update = cycle;
+        self.notify_ports();
+    }
 }
 
 #[repr(u8)]
@@ -233,12 +557,70 @@ pub enum CodyKeyCode {
     Joystick2Fire = 39,
 }
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
 pub struct KeyState {
     state: [u8; 8],
+    /// completed keyboard scans, counted whenever the last row is read
+    scans: usize,
+    /// keys held in other rows show up like the diodeless matrix, see [`Self::read_row`]
+    ghosting: bool,
 }
 
 impl KeyState {
+    /// Column bits of each keyboard row as read through the VIA, pressed keys are 0
+    pub const fn rows(&self) -> [u8; 8] {
+        self.state
+    }
+
+    pub fn set_rows(&mut self, rows: [u8; 8]) {
+        self.state = rows;
+    }
+
+    pub const fn ghosting(&self) -> bool {
+        self.ghosting
+    }
+
+    pub fn set_ghosting(&mut self, ghosting: bool) {
+        self.ghosting = ghosting;
+    }
+
+    /// Column bits read while `row` is selected, pressed keys are 0.
+    ///
+    /// With ghosting, the columns of held keys are connected through the rows without diodes, so a column reads as
+    /// pressed when it is connected to the selected row by any chain of held keys: three keys held in a rectangle
+    /// make the fourth corner appear held. Only one row is selected at a time, software looking for any key
+    /// scans all rows and sees the ghost keys as well.
+    pub fn read_row(&self, row: u8) -> u8 {
+        let row = row as usize;
+        if !self.ghosting {
+            return self.state[row];
+        }
+
+        let held = self.state.map(|columns| !columns & 0xF8);
+        let mut connected = held[row];
+        loop {
+            let reachable = held
+                .iter()
+                .filter(|&&columns| columns & connected != 0)
+                .fold(connected, |connected, &columns| connected | columns);
+            if reachable == connected {
+                break;
+            }
+            connected = reachable;
+        }
+        self.state[row] & !connected
+    }
+
+    pub const fn scans(&self) -> usize {
+        self.scans
+    }
+
+    pub fn record_scan(&mut self, row: u8) {
+        if row == 7 {
+            self.scans += 1;
+        }
+    }
+
     pub fn set_pressed(&mut self, code: CodyKeyCode, pressed: bool) {
         let code = code as u8;
         let bit = (code % 5) + 3;
@@ -257,3 +639,403 @@ pub enum CodyModifier {
     Cody,
     Meta,
 }
+
+impl CodyModifier {
+    pub const fn key_code(self) -> CodyKeyCode {
+        match self {
+            Self::Cody => CodyKeyCode::Cody,
+            Self::Meta => CodyKeyCode::Meta,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn via_with_key(code: CodyKeyCode) -> Via {
+        let via = Via::default();
+        {
+            let mut key_state = via.get_key_state().borrow_mut();
+            for code in 0..CodyKeyCode::COUNT as u8 {
+                key_state.set_pressed(CodyKeyCode::try_from(code).unwrap(), false);
+            }
+            key_state.set_pressed(code, true);
+        }
+        via
+    }
+
+    /// Keyboard matrix of the Cody, rows are selected by PA0-PA2 and keys are read on PA3-PA7
+    const MATRIX: [[CodyKeyCode; 5]; 8] = {
+        use CodyKeyCode::*;
+        [
+            [KeyQ, KeyE, KeyT, KeyU, KeyO],
+            [KeyA, KeyD, KeyG, KeyJ, KeyL],
+            [Cody, KeyX, KeyV, KeyN, Meta],
+            [KeyZ, KeyC, KeyB, KeyM, Enter],
+            [KeyS, KeyF, KeyH, KeyK, Space],
+            [KeyW, KeyR, KeyY, KeyI, KeyP],
+            [
+                Joystick1Up,
+                Joystick1Down,
+                Joystick1Left,
+                Joystick1Right,
+                Joystick1Fire,
+            ],
+            [
+                Joystick2Up,
+                Joystick2Down,
+                Joystick2Left,
+                Joystick2Right,
+                Joystick2Fire,
+            ],
+        ]
+    };
+
+    fn matrix_keys() -> impl Iterator<Item = (usize, usize, CodyKeyCode)> {
+        MATRIX.into_iter().enumerate().flat_map(|(row, keys)| {
+            keys.into_iter()
+                .enumerate()
+                .map(move |(column, code)| (row, column, code))
+        })
+    }
+
+    #[test]
+    fn test_set_pressed_matrix() {
+        assert_eq!(matrix_keys().count(), CodyKeyCode::COUNT);
+        for (row, column, code) in matrix_keys() {
+            let via = via_with_key(code);
+            let mut expected = [0xF8; 8];
+            expected[row] &= !(1 << (column + 3));
+            assert_eq!(via.get_key_state().borrow().rows(), expected, "{code:?}");
+
+            via.get_key_state().borrow_mut().set_pressed(code, false);
+            assert_eq!(via.get_key_state().borrow().rows(), [0xF8; 8], "{code:?}");
+        }
+    }
+
+    #[test]
+    fn test_set_pressed_whole_row() {
+        let via = via_with_key(CodyKeyCode::Joystick2Up);
+        let mut key_state = via.get_key_state().borrow_mut();
+        for code in MATRIX[6] {
+            key_state.set_pressed(code, true);
+        }
+        // both joysticks held at once, the row select bits are never touched
+        assert_eq!(
+            key_state.rows(),
+            [0xF8, 0xF8, 0xF8, 0xF8, 0xF8, 0xF8, 0x00, 0xF0]
+        );
+    }
+
+    #[test]
+    fn test_ghosting() {
+        let mut via = via_with_key(CodyKeyCode::KeyQ);
+        via.write_u8(VIA_DDRA, 0x07);
+        let read_row = |via: &mut Via, row: u8| {
+            via.write_u8(VIA_IORA, row);
+            via.read_u8(VIA_IORA)
+        };
+        // two keys in a column do not ghost
+        via.get_key_state()
+            .borrow_mut()
+            .set_pressed(CodyKeyCode::KeyA, true);
+        via.get_key_state().borrow_mut().set_ghosting(true);
+        assert_eq!(read_row(&mut via, 0), 0xF0);
+        assert_eq!(read_row(&mut via, 1), 0xF1);
+
+        // the fourth corner of Q, E and A
+        via.get_key_state()
+            .borrow_mut()
+            .set_pressed(CodyKeyCode::KeyE, true);
+        assert_eq!(read_row(&mut via, 1), 0xE1);
+        assert_eq!(read_row(&mut via, 2), 0xFA);
+        via.get_key_state().borrow_mut().set_ghosting(false);
+        assert_eq!(read_row(&mut via, 1), 0xF1);
+
+        // chains through further rows
+        let mut key_state = via.get_key_state().borrow_mut();
+        key_state.set_ghosting(true);
+        key_state.set_pressed(CodyKeyCode::KeyQ, false);
+        key_state.set_pressed(CodyKeyCode::KeyA, false);
+        for code in [CodyKeyCode::KeyD, CodyKeyCode::KeyW, CodyKeyCode::KeyP] {
+            key_state.set_pressed(code, true);
+        }
+        // E and D share column 1, nothing connects them to W and P yet
+        assert_eq!(key_state.read_row(5), 0x70);
+        // A connects column 0 of W to the column of D and E
+        key_state.set_pressed(CodyKeyCode::KeyA, true);
+        assert_eq!(key_state.read_row(5), 0x60);
+        assert_eq!(key_state.read_row(0), 0x60);
+        // the raw rows are unchanged
+        assert_eq!(key_state.rows()[0], 0xE8);
+    }
+
+    #[test]
+    fn test_read_iora_matrix() {
+        for (row, column, code) in matrix_keys() {
+            let mut via = via_with_key(code);
+            for ddr in 0..=0xFF {
+                via.write_u8(VIA_DDRA, ddr);
+                for ior in 0..=0xFF {
+                    via.write_u8(VIA_IORA, ior);
+                    let selected = (ior | !ddr) & 0x7;
+                    let column_bit = 1 << (column + 3);
+                    let value = via.read_u8(VIA_IORA);
+
+                    assert_eq!(
+                        value & 0x7,
+                        selected,
+                        "{code:?} ddr {ddr:02X} ior {ior:02X}"
+                    );
+                    // outputs read back the output register, inputs see the key only in its own row
+                    for bit in (3..8).map(|bit| 1 << bit) {
+                        let expected = if ddr & bit != 0 {
+                            ior & bit
+                        } else if bit == column_bit && selected as usize == row {
+                            0
+                        } else {
+                            bit
+                        };
+                        assert_eq!(
+                            value & bit,
+                            expected,
+                            "{code:?} ddr {ddr:02X} ior {ior:02X}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_iora_cody_scan() {
+        let mut via = via_with_key(CodyKeyCode::KeyD);
+        via.write_u8(VIA_DDRA, 0x07);
+
+        via.write_u8(VIA_IORA, 0x00);
+        assert_eq!(via.read_u8(VIA_IORA), 0xF8);
+        via.write_u8(VIA_IORA, 0x01);
+        assert_eq!(via.read_u8(VIA_IORA), 0xE9);
+    }
+
+    #[test]
+    fn test_read_iora_outputs_read_back() {
+        let mut via = via_with_key(CodyKeyCode::KeyQ);
+        via.write_u8(VIA_DDRA, 0xFF);
+        via.write_u8(VIA_IORA, 0x50);
+        assert_eq!(via.read_u8(VIA_IORA), 0x50);
+
+        // column outputs are not affected by the keyboard
+        via.write_u8(VIA_DDRA, 0x0F);
+        via.write_u8(VIA_IORA, 0x00);
+        assert_eq!(via.read_u8(VIA_IORA), 0xF0);
+    }
+
+    #[test]
+    fn test_port_lines() {
+        let port_a = Rc::new(RefCell::new(PortLines::default()));
+        let port_b = Rc::new(RefCell::new(PortLines::default()));
+        let mut via = Via::with_ports(port_a.clone(), port_b.clone());
+
+        via.write_u8(VIA_DDRB, 0x0F);
+        via.write_u8(VIA_IORB, 0xA5);
+        assert_eq!(port_b.borrow().output, 0xA5);
+        assert_eq!(port_b.borrow().lines(), 0xF5);
+
+        port_b.borrow_mut().input = 0x30;
+        assert_eq!(via.read_u8(VIA_IORB), 0x35);
+
+        port_a.borrow_mut().input = 0x42;
+        assert_eq!(via.read_u8(VIA_IORA), 0x42);
+        assert_eq!(via.read_u8(VIA_IORA_NO_HANDSHAKE), 0x42);
+    }
+
+    #[test]
+    fn test_port_b_inputs() {
+        let mut via = Via::default();
+        via.write_u8(VIA_DDRB, 0x0F);
+        via.write_u8(VIA_IORB, 0xA5);
+        // the input register shows undriven inputs high, not the output register
+        assert_eq!(via.read_u8(VIA_IORB), 0xF5);
+    }
+
+    #[test]
+    fn test_port_a_latch() {
+        let port_a = Rc::new(RefCell::new(PortLines::default()));
+        let port_b = Rc::new(RefCell::new(PortLines::default()));
+        let mut via = Via::with_ports(port_a.clone(), port_b);
+        via.write_u8(VIA_PCR, 0x01);
+        via.write_u8(VIA_ACR, 0x01);
+
+        port_a.borrow_mut().input = 0x11;
+        *via.get_ca1().borrow_mut() = true;
+        via.update(1);
+        port_a.borrow_mut().input = 0x22;
+        assert_eq!(via.read_u8(VIA_IORA_NO_HANDSHAKE), 0x11);
+        assert_eq!(via.read_u8(VIA_IFR), 0x02);
+        // IORA acknowledges the edge, the latch is kept until the next one
+        assert_eq!(via.read_u8(VIA_IORA), 0x11);
+        assert_eq!(via.read_u8(VIA_IFR), 0x00);
+        assert_eq!(via.read_u8(VIA_IORA), 0x11);
+
+        // the negative edge is not active
+        *via.get_ca1().borrow_mut() = false;
+        via.update(2);
+        assert_eq!(via.read_u8(VIA_IORA), 0x11);
+        *via.get_ca1().borrow_mut() = true;
+        via.update(3);
+        assert_eq!(via.read_u8(VIA_IORA), 0x22);
+
+        port_a.borrow_mut().input = 0x33;
+        via.write_u8(VIA_ACR, 0x00);
+        assert_eq!(via.read_u8(VIA_IORA), 0x33);
+    }
+
+    #[test]
+    fn test_port_b_latch() {
+        let port_a = Rc::new(RefCell::new(PortLines::default()));
+        let port_b = Rc::new(RefCell::new(PortLines::default()));
+        let mut via = Via::with_ports(port_a, port_b.clone());
+        via.write_u8(VIA_PCR, 0x10);
+        via.write_u8(VIA_ACR, 0x02);
+        via.write_u8(VIA_IER, 0x90);
+        via.write_u8(VIA_DDRB, 0x0F);
+
+        port_b.borrow_mut().input = 0x30;
+        *via.get_cb1().borrow_mut() = true;
+        assert!(via.update(1).is_irq());
+        port_b.borrow_mut().input = 0x70;
+        // outputs read the output register written after the edge, inputs the latch
+        via.write_u8(VIA_IORA, 0x00);
+        assert_eq!(via.read_u8(VIA_IFR), 0x90);
+        via.write_u8(VIA_IORB, 0x05);
+        assert_eq!(via.read_u8(VIA_IORB), 0x35);
+        assert!(!via.update(2).is_irq());
+
+        via.write_u8(VIA_ACR, 0x00);
+        assert_eq!(via.read_u8(VIA_IORB), 0x75);
+    }
+
+    #[test]
+    fn test_ca1_interrupt() {
+        let mut via = Via::default();
+        via.write_u8(VIA_PCR, 0x01);
+        via.write_u8(VIA_IER, 0x82);
+        assert!(!via.update(1).is_irq());
+
+        *via.get_ca1().borrow_mut() = true;
+        assert!(via.update(2).is_irq());
+        assert_eq!(via.read_u8(VIA_IFR), 0x82);
+
+        // reading port A acknowledges the interrupt
+        via.write_u8(VIA_DDRA, 0x07);
+        via.read_u8(VIA_IORA);
+        assert!(!via.update(3).is_irq());
+
+        // negative edge is ignored
+        *via.get_ca1().borrow_mut() = false;
+        assert!(!via.update(4).is_irq());
+    }
+
+    /// Start a timer with `latch` at cycle 0 by writing its counter registers
+    fn start_timer(via: &mut Via, counter_lo: u16, latch: u16) {
+        via.update(0);
+        via.write_u8(counter_lo, latch as u8);
+        via.write_u8(counter_lo + 1, (latch >> 8) as u8);
+    }
+
+    /// Cycles of the next time out of `flag`, updating in steps of a NOP
+    fn next_time_out(via: &mut Via, flag: u8, from: usize, to: usize) -> Option<usize> {
+        (from..to)
+            .step_by(2)
+            .find(|&cycle| via.update(cycle).is_irq() && via.read_u8(VIA_IFR) & flag != 0)
+    }
+
+    #[test]
+    fn test_t1_one_shot() {
+        let mut via = Via::default();
+        via.write_u8(VIA_IER, 0xC0);
+        start_timer(&mut via, VIA_T1CL, 0x10);
+
+        via.update(1);
+        assert_eq!(via.read_u8(VIA_T1CH), 0x00);
+        assert_eq!(via.peek_u8(VIA_T1CL), 0x10);
+        assert!(!via.update(17).is_irq());
+        assert_eq!(via.peek_u8(VIA_T1CL), 0x00);
+        // N + 2 cycles after the write
+        assert!(via.update(18).is_irq());
+        assert_eq!(via.peek_u8(VIA_T1CL), 0xFF);
+        assert_eq!(via.read_u8(VIA_T1CH), 0xFF);
+
+        // reading the low counter acknowledges it, the counter keeps going without reload or interrupt
+        via.read_u8(VIA_T1CL);
+        assert!(!via.update(20).is_irq());
+        assert_eq!(via.read_u8(VIA_T1CL), 0xFD);
+        assert_eq!(next_time_out(&mut via, 0x40, 20, 0x30000), None);
+    }
+
+    #[test]
+    fn test_t1_free_running() {
+        let mut via = Via::default();
+        via.write_u8(VIA_ACR, 0x40);
+        via.write_u8(VIA_IER, 0xC0);
+        start_timer(&mut via, VIA_T1CL, 0x10);
+
+        // a period of N + 2 cycles, the cycle after the time out reloads the latch
+        let mut time_outs = vec![];
+        let mut from = 2;
+        while let Some(cycle) = next_time_out(&mut via, 0x40, from, 91) {
+            time_outs.push(cycle);
+            via.write_u8(VIA_IFR, 0x40);
+            from = cycle + 2;
+        }
+        assert_eq!(time_outs, [18, 36, 54, 72, 90]);
+        via.update(91);
+        assert_eq!(via.peek_u8(VIA_T1CL), 0x10);
+
+        // a new latch is used from the next reload on
+        via.write_u8(VIA_T1LL, 0x20);
+        assert_eq!(next_time_out(&mut via, 0x40, 92, 200), Some(108));
+        via.write_u8(VIA_IFR, 0x40);
+        assert_eq!(next_time_out(&mut via, 0x40, 110, 200), Some(142));
+    }
+
+    #[test]
+    fn test_t2_one_shot() {
+        let mut via = Via::default();
+        via.write_u8(VIA_IER, 0xA0);
+        start_timer(&mut via, VIA_T2CL, 0x0100);
+
+        assert_eq!(next_time_out(&mut via, 0x20, 2, 1000), Some(0x102));
+        // writing the flag clears it, writing zeros does not touch the other flags
+        via.write_u8(VIA_IFR, 0x00);
+        assert_eq!(via.read_u8(VIA_IFR), 0xA0);
+        via.write_u8(VIA_IFR, 0x7F);
+        assert_eq!(via.read_u8(VIA_IFR), 0x00);
+        assert_eq!(next_time_out(&mut via, 0x20, 0x104, 0x30000), None);
+
+        // writing the high counter starts it again
+        let start = 0x30000;
+        via.update(start);
+        via.write_u8(VIA_T2CL, 0x04);
+        via.write_u8(VIA_T2CH, 0x00);
+        assert_eq!(
+            next_time_out(&mut via, 0x20, start + 2, start + 100),
+            Some(start + 6)
+        );
+    }
+
+    #[test]
+    fn test_read_iora_inputs_pulled_high() {
+        let mut via = via_with_key(CodyKeyCode::Joystick2Fire);
+        via.write_u8(VIA_DDRA, 0x00);
+        via.write_u8(VIA_IORA, 0x00);
+        // all row lines float high and select row 7
+        assert_eq!(via.read_u8(VIA_IORA), 0x7F);
+
+        // partially driven row select
+        via.write_u8(VIA_DDRA, 0x01);
+        assert_eq!(via.read_u8(VIA_IORA), 0xFE);
+    }
+}
@@ -1,15 +1,101 @@
+use crate::interrupt::Interrupt;
 use crate::memory::Memory;
+use crate::memory::contiguous::Contiguous;
+use crate::timing::TimingProfile;
+use rayon::prelude::*;
+use std::str::FromStr;
 
 pub const CONTENT_WIDTH: u8 = 160;
 pub const HIRES_WIDTH: u16 = 2 * CONTENT_WIDTH as u16;
 pub const CONTENT_HEIGHT: u8 = 200;
 pub const BORDER_X: u32 = 4;
 pub const BORDER_Y: u32 = 8;
-pub const WIDTH: u32 = HIRES_WIDTH as u32 + 2 * BORDER_X;
-pub const HEIGHT: u32 = CONTENT_HEIGHT as u32 + 2 * BORDER_Y;
+/// Width of a frame with the normal border
+pub const WIDTH: u32 = Border::Normal.width();
+/// Height of a frame with the normal border
+pub const HEIGHT: u32 = Border::Normal.height();
+
+/// How much of the border around the content area is drawn into a frame
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Border {
+    /// A thin border, like most TVs show
+    #[default]
+    Normal,
+    /// Only the 320x200 content area
+    None,
+    /// The whole top border and a wide border at the sides, like a TV with little overscan
+    Large,
+}
+
+/// Revision of the propeller firmware, which implements the VID in software.
+///
+/// Software written for a specific revision may depend on its register semantics.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Firmware {
+    /// The first firmware: no hires mode, 16 row effects and a single sprite bank at D080
+    Rev1,
+    /// Hires mode, 32 row effects and 16 sprite banks selected by the sprite register
+    #[default]
+    Rev2,
+}
+
+impl Firmware {
+    /// Bit 5 of the control register switches to hires mode
+    pub const fn hires(self) -> bool {
+        matches!(self, Self::Rev2)
+    }
+
+    /// Number of row effect registers at D040 and D060
+    pub const fn row_effects(self) -> u16 {
+        match self {
+            Self::Rev1 => 16,
+            Self::Rev2 => 32,
+        }
+    }
+
+    /// Number of sprite banks of 0x20 bytes from D080, the high nibble of the sprite register selects one
+    pub const fn sprite_banks(self) -> u8 {
+        match self {
+            Self::Rev1 => 1,
+            Self::Rev2 => 16,
+        }
+    }
+}
+
+impl Border {
+    /// Hires pixels left and right of the content area
+    pub const fn x(self) -> u32 {
+        match self {
+            Self::Normal => BORDER_X,
+            Self::None => 0,
+            Self::Large => 16,
+        }
+    }
+
+    /// Lines above and below the content area, at most the 20 lines of the top border
+    pub const fn y(self) -> u32 {
+        match self {
+            Self::Normal => BORDER_Y,
+            Self::None => 0,
+            Self::Large => 20,
+        }
+    }
+
+    /// Width of a frame with this border
+    pub const fn width(self) -> u32 {
+        HIRES_WIDTH as u32 + 2 * self.x()
+    }
+
+    /// Height of a frame with this border
+    pub const fn height(self) -> u32 {
+        CONTENT_HEIGHT as u32 + 2 * self.y()
+    }
+}
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Color {
     r: u8,
     g: u8,
@@ -60,6 +146,16 @@ impl Color {
         Self::LIGHT_GRAY,
     ];
 
+    /// Multiply each color channel by a factor in 0..=1
+    pub fn scale_rgb(self, r: f32, g: f32, b: f32) -> Self {
+        Self {
+            r: (self.r as f32 * r) as u8,
+            g: (self.g as f32 * g) as u8,
+            b: (self.b as f32 * b) as u8,
+            a: self.a,
+        }
+    }
+
     const fn rgb(color: u32) -> Self {
         Self {
             r: ((color >> 16) & 0xFF) as u8,
@@ -70,198 +166,1329 @@ impl Color {
     }
 }
 
-pub fn render_pixels<M: Memory>(memory: &mut M, raw_pixels: &mut [Color]) {
-    let (
-        disable_video,
-        enable_v_scroll,
-        enable_h_scroll,
-        enable_row_effects,
-        bitmap_mode,
-        hires_mode,
-    ) = {
+/// The 16 colors the video hardware can display
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Palette(pub [Color; 16]);
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self(Color::PALETTE)
+    }
+}
+
+impl Palette {
+    /// Built-in palettes that can be selected by name
+    pub const PRESETS: [&str; 3] = ["cody", "pepto", "greyscale"];
+
+    /// The C64 palette by Philip "Pepto" Timmermann as used by VICE, the Cody uses the same color order
+    pub const PEPTO: Self = Self([
+        Color::rgb(0x000000),
+        Color::rgb(0xffffff),
+        Color::rgb(0x68372b),
+        Color::rgb(0x70a4b2),
+        Color::rgb(0x6f3d86),
+        Color::rgb(0x588d43),
+        Color::rgb(0x352879),
+        Color::rgb(0xb8c76f),
+        Color::rgb(0x6f4f25),
+        Color::rgb(0x433900),
+        Color::rgb(0x9a6759),
+        Color::rgb(0x444444),
+        Color::rgb(0x6c6c6c),
+        Color::rgb(0x9ad284),
+        Color::rgb(0x6c5eb5),
+        Color::rgb(0x959595),
+    ]);
+
+    /// The default palette converted to luma
+    pub fn greyscale() -> Self {
+        Self(Color::PALETTE.map(|c| {
+            let y = (0.299 * c.r as f64 + 0.587 * c.g as f64 + 0.114 * c.b as f64).round() as u32;
+            Color::rgb((y << 16) | (y << 8) | y)
+        }))
+    }
+
+    /// Parse 16 colors as hex `RRGGBB` values, optionally prefixed with `#` or `0x`,
+    /// separated by whitespace or commas. Lines starting with `;` are comments.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut colors = vec![];
+        for line in text.lines().map(str::trim).filter(|l| !l.starts_with(';')) {
+            for entry in line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|e| !e.is_empty())
+            {
+                let hex = entry
+                    .strip_prefix('#')
+                    .or_else(|| entry.strip_prefix("0x"))
+                    .unwrap_or(entry);
+                if hex.len() != 6 {
+                    return Err(format!("expected a RRGGBB color, got {entry:?}"));
+                }
+                let rgb = u32::from_str_radix(hex, 16)
+                    .map_err(|e| format!("invalid color {entry:?}: {e}"))?;
+                colors.push(Color::rgb(rgb));
+            }
+        }
+        let colors: [Color; 16] = colors
+            .try_into()
+            .map_err(|c: Vec<_>| format!("expected 16 colors, got {}", c.len()))?;
+        Ok(Self(colors))
+    }
+
+    fn get(&self, index: u8) -> Color {
+        self.0[(index & 0xF) as usize]
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Either the name of a preset or the path of a palette file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cody" => Ok(Self::default()),
+            "pepto" => Ok(Self::PEPTO),
+            "greyscale" => Ok(Self::greyscale()),
+            path => {
+                let text = std::fs::read_to_string(path).map_err(|e| {
+                    format!(
+                        "{path:?} is neither one of the presets {} nor a readable palette file: {e}",
+                        Self::PRESETS.join(", ")
+                    )
+                })?;
+                Self::parse(&text)
+            }
+        }
+    }
+}
+
+/// FNV-1a hash of a rendered frame, stable across platforms
+pub fn hash_pixels(raw_pixels: &[Color]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xCBF29CE484222325;
+    const PRIME: u64 = 0x100000001B3;
+
+    bytemuck::cast_slice::<Color, u8>(raw_pixels)
+        .iter()
+        .fold(OFFSET_BASIS, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(PRIME)
+        })
+}
+
+/// Rows of the frame buffer are drawn starting at this line of the frame,
+/// so that the first content row is the first line after blanking and the 20 line top border
+pub const fn first_line(timing: &TimingProfile, border: Border) -> usize {
+    timing.video.blanking_lines() + 20 - border.y() as usize
+}
+
+/// Sprites are always 12 multicolor pixels wide and 21 lines high, there are no expanded or hires sprites
+const SPRITE_WIDTH: u8 = 12;
+const SPRITE_HEIGHT: u8 = 21;
+/// Sprite data is 63 bytes, the last byte of each 64 byte block is unused
+const SPRITE_SIZE: u16 = 0x40;
+
+/// Start of the propeller RAM, which holds the video registers and everything else the VID reads
+pub const VRAM_START: u16 = 0xA000;
+
+/// The propeller RAM as the VID sees it, reads are plain slice accesses instead of going through the memory map.
+///
+/// Addresses outside of it wrap around.
+pub struct Vram<'a>(&'a [u8]);
+
+impl<'a> Vram<'a> {
+    pub fn new(ram: &'a [u8]) -> Self {
+        Self(ram)
+    }
+}
+
+impl Memory for Vram<'_> {
+    fn read_u8(&mut self, address: u16) -> u8 {
+        self.0[address.wrapping_sub(VRAM_START) as usize % self.0.len()]
+    }
+
+    fn write_u8(&mut self, _address: u16, _value: u8) {}
+
+    fn update(&mut self, _cycle: usize) -> Interrupt {
+        Interrupt::none()
+    }
+}
+
+/// Video registers written by row effects, they stay active until the end of the frame
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+struct RowEffects {
+    base: Option<u8>,
+    scroll: Option<u8>,
+    screen_colors: Option<u8>,
+    sprite: Option<u8>,
+}
+
+impl RowEffects {
+    /// Take over the row effects that are enabled for `tile_row`
+    fn latch<M: Memory>(&mut self, memory: &mut M, firmware: Firmware, tile_row: u8) {
+        for effect_index in 0..firmware.row_effects() {
+            let effect_control = memory.read_u8(0xD040 + effect_index);
+            if effect_control & 0x80 == 0 || effect_control & 0x1F != tile_row {
+                continue;
+            }
+            let effect_data = memory.read_u8(0xD060 + effect_index);
+            match (effect_control >> 5) & 0x3 {
+                0 => self.base = Some(effect_data),
+                1 => self.scroll = Some(effect_data),
+                2 => self.screen_colors = Some(effect_data),
+                3 => self.sprite = Some(effect_data),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Drawing at least this many rows at once spreads them over multiple threads
+const PARALLEL_ROWS: usize = 16;
+
+/// Render the whole frame at once from a snapshot of the propeller RAM, the rows are drawn in parallel
+pub fn render_frame(
+    vram: &[u8],
+    palette: &Palette,
+    border: Border,
+    firmware: Firmware,
+    raw_pixels: &mut [Color],
+) {
+    let mut memory = Vram::new(vram);
+    let mut effects = RowEffects::default();
+    let rows: Vec<_> = (0..border.height() as u16)
+        .map(|row| {
+            latch_row_effects(&mut memory, border, firmware, row, &mut effects);
+            (row, effects)
+        })
+        .collect();
+    draw_rows(vram, palette, border, firmware, raw_pixels, &rows);
+}
+
+/// Render the whole frame at once like [`render_frame`], as indices into the palette instead of colors
+pub fn render_frame_indexed(vram: &[u8], border: Border, firmware: Firmware, indices: &mut [u8]) {
+    // the red channel of each color is its index
+    let palette = Palette(std::array::from_fn(|index| Color {
+        r: index as u8,
+        g: 0,
+        b: 0,
+        a: 255,
+    }));
+    let mut pixels = vec![Color::default(); indices.len()];
+    render_frame(vram, &palette, border, firmware, &mut pixels);
+    for (index, pixel) in indices.iter_mut().zip(pixels) {
+        *index = pixel.r;
+    }
+}
+
+/// Draw the given rows of the frame buffer with the row effects latched up to each of them.
+///
+/// Rows only read the propeller RAM, so they can be drawn in parallel if there are enough of them.
+fn draw_rows(
+    vram: &[u8],
+    palette: &Palette,
+    border: Border,
+    firmware: Firmware,
+    raw_pixels: &mut [Color],
+    rows: &[(u16, RowEffects)],
+) {
+    let mut rows = rows.iter().peekable();
+    let jobs: Vec<_> = raw_pixels
+        .chunks_exact_mut(border.width() as usize)
+        .zip(0..)
+        .filter_map(|(pixels, row)| {
+            rows.next_if(|(next, _)| *next == row)
+                .map(|&(_, effects)| (pixels, row, effects))
+        })
+        .collect();
+    let draw = |(pixels, row, effects): (&mut [Color], u16, RowEffects)| {
+        draw_row(
+            &mut Vram::new(vram),
+            palette,
+            border,
+            firmware,
+            pixels,
+            row,
+            &effects,
+        );
+    };
+    if jobs.len() >= PARALLEL_ROWS {
+        jobs.into_par_iter().for_each(draw);
+    } else {
+        jobs.into_iter().for_each(draw);
+    }
+}
+
+/// Render the whole frame at once from the current video registers
+pub fn render_pixels<M: Memory>(
+    memory: &mut M,
+    palette: &Palette,
+    border: Border,
+    firmware: Firmware,
+    raw_pixels: &mut [Color],
+) {
+    let mut effects = RowEffects::default();
+    for row in 0..border.height() as u16 {
+        render_row(
+            memory,
+            palette,
+            border,
+            firmware,
+            raw_pixels,
+            row,
+            &mut effects,
+        );
+    }
+}
+
+/// Everything a row depends on, drawing it again with the same state gives the same pixels
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct RowState {
+    /// [`Contiguous::generation`] of the propeller RAM
+    generation: u64,
+    /// row effects latched by the rows above
+    effects: RowEffects,
+}
+
+/// Renders the frame row by row as the cpu runs, so register changes in the middle of a frame show up
+/// in the rows drawn after them.
+///
+/// Rows are copied from the last frame if the propeller RAM did not change since they were drawn.
+#[derive(Debug, Clone)]
+pub struct ScanlineRenderer {
+    palette: Palette,
+    timing: TimingProfile,
+    border: Border,
+    firmware: Firmware,
+    frame: usize,
+    row: u16,
+    effects: RowEffects,
+    back: Vec<Color>,
+    front: Vec<Color>,
+    /// state each row was drawn with and the row effects after it
+    back_rows: Vec<Option<(RowState, RowEffects)>>,
+    front_rows: Vec<Option<(RowState, RowEffects)>>,
+}
+
+impl Default for ScanlineRenderer {
+    fn default() -> Self {
+        Self::new(
+            Palette::default(),
+            TimingProfile::default(),
+            Border::default(),
+            Firmware::default(),
+        )
+    }
+}
+
+impl ScanlineRenderer {
+    pub fn new(
+        palette: Palette,
+        timing: TimingProfile,
+        border: Border,
+        firmware: Firmware,
+    ) -> Self {
+        let (width, height) = (border.width() as usize, border.height() as usize);
+        Self {
+            palette,
+            timing,
+            border,
+            firmware,
+            frame: 0,
+            row: 0,
+            effects: RowEffects::default(),
+            back: vec![Color::default(); width * height],
+            front: vec![Color::default(); width * height],
+            back_rows: vec![None; height],
+            front_rows: vec![None; height],
+        }
+    }
+
+    pub const fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Border drawn around the content area, frames are [`Border::width`]x[`Border::height`]
+    pub const fn border(&self) -> Border {
+        self.border
+    }
+
+    /// Draw all rows the beam passed until `cycle` from the propeller RAM, see [`crate::machine::Machine::propeller_ram`]
+    pub fn update(&mut self, vram: &Contiguous, cycle: usize) {
+        let frame = cycle / self.timing.frame_cycles();
+        if frame != self.frame {
+            // finish the rows after the end of the visible area and present the frame
+            self.render_rows(vram, self.border.height() as u16);
+            std::mem::swap(&mut self.back, &mut self.front);
+            std::mem::swap(&mut self.back_rows, &mut self.front_rows);
+            self.frame = frame;
+            self.row = 0;
+            self.effects = RowEffects::default();
+        }
+
+        let line = self.timing.line(cycle);
+        let rows = (line + 1)
+            .saturating_sub(first_line(&self.timing, self.border))
+            .min(self.border.height() as usize);
+        self.render_rows(vram, rows as u16);
+    }
+
+    fn render_rows(&mut self, vram: &Contiguous, end: u16) {
+        let mut memory = Vram::new(&vram.memory);
+        let mut rows = vec![];
+        while self.row < end {
+            let row = self.row as usize;
+            let state = RowState {
+                generation: vram.generation(),
+                effects: self.effects,
+            };
+            match self.front_rows[row] {
+                Some((drawn, effects)) if drawn == state => {
+                    let width = self.border.width() as usize;
+                    let pixels = row * width..(row + 1) * width;
+                    self.back[pixels.clone()].copy_from_slice(&self.front[pixels]);
+                    self.effects = effects;
+                }
+                _ => {
+                    latch_row_effects(
+                        &mut memory,
+                        self.border,
+                        self.firmware,
+                        self.row,
+                        &mut self.effects,
+                    );
+                    rows.push((self.row, self.effects));
+                }
+            }
+            self.back_rows[row] = Some((state, self.effects));
+            self.row += 1;
+        }
+        draw_rows(
+            &vram.memory,
+            &self.palette,
+            self.border,
+            self.firmware,
+            &mut self.back,
+            &rows,
+        );
+    }
+
+    /// Draw a complete frame right away, e.g. after the memory was replaced
+    pub fn redraw(&mut self, vram: &Contiguous) {
+        render_frame(
+            &vram.memory,
+            &self.palette,
+            self.border,
+            self.firmware,
+            &mut self.front,
+        );
+        self.front_rows.fill(None);
+    }
+
+    /// The last completely drawn frame
+    pub fn frame(&self) -> &[Color] {
+        &self.front
+    }
+}
+
+/// Bits of the video control register at D001
+#[derive(Debug, Copy, Clone)]
+struct Control {
+    disable_video: bool,
+    enable_v_scroll: bool,
+    enable_h_scroll: bool,
+    enable_row_effects: bool,
+    bitmap_mode: bool,
+    hires_mode: bool,
+}
+
+impl Control {
+    fn read<M: Memory>(memory: &mut M, firmware: Firmware) -> Self {
         let control = memory.read_u8(0xD001);
-        let hires_mode = (control & 0x20) != 0;
-        (
-            (control & 0x1) != 0,
-            (control & 0x2) != 0 && !hires_mode,
-            (control & 0x4) != 0 && !hires_mode,
-            (control & 0x8) != 0,
-            (control & 0x10) != 0,
+        let hires_mode = (control & 0x20) != 0 && firmware.hires();
+        Self {
+            disable_video: (control & 0x1) != 0,
+            // there is no fine scrolling in hires mode
+            enable_v_scroll: (control & 0x2) != 0 && !hires_mode,
+            enable_h_scroll: (control & 0x4) != 0 && !hires_mode,
+            enable_row_effects: (control & 0x8) != 0,
+            bitmap_mode: (control & 0x10) != 0,
             hires_mode,
-        )
-    };
+        }
+    }
+
+    /// Line of the content area shown in `row` of the frame buffer, `None` in the border
+    fn content_y(&self, border: Border, row: u16) -> Option<u16> {
+        if self.disable_video {
+            return None;
+        }
+        // these depend on the fine scrolling state
+        let height = CONTENT_HEIGHT - if self.enable_v_scroll { 8 } else { 0 };
+        let border_y = border.y() as u16 + if self.enable_v_scroll { 4 } else { 0 };
+        row.checked_sub(border_y).filter(|&y| y < height as u16)
+    }
+}
+
+/// Render one row of the frame buffer, including the border, from the current video registers
+fn render_row<M: Memory>(
+    memory: &mut M,
+    palette: &Palette,
+    border: Border,
+    firmware: Firmware,
+    raw_pixels: &mut [Color],
+    row: u16,
+    effects: &mut RowEffects,
+) {
+    latch_row_effects(memory, border, firmware, row, effects);
+    let width = border.width() as usize;
+    let start = row as usize * width;
+    draw_row(
+        memory,
+        palette,
+        border,
+        firmware,
+        &mut raw_pixels[start..start + width],
+        row,
+        effects,
+    );
+}
+
+/// Take over the row effects of the tile row that starts in `row` of the frame buffer
+fn latch_row_effects<M: Memory>(
+    memory: &mut M,
+    border: Border,
+    firmware: Firmware,
+    row: u16,
+    effects: &mut RowEffects,
+) {
+    let control = Control::read(memory, firmware);
+    // the effects of a tile row apply from its first line on
+    if let Some(y) = control.content_y(border, row)
+        && control.enable_row_effects
+        && y % 8 == 0
+    {
+        effects.latch(memory, firmware, (y / 8) as u8);
+    }
+}
 
+/// Draw `row` of the frame buffer into `pixels` with the row effects latched up to it
+fn draw_row<M: Memory>(
+    memory: &mut M,
+    palette: &Palette,
+    border: Border,
+    firmware: Firmware,
+    pixels: &mut [Color],
+    row: u16,
+    effects: &RowEffects,
+) {
+    let control = Control::read(memory, firmware);
     let color = memory.read_u8(0xD002);
-    raw_pixels.fill(Color::PALETTE[(color & 0xF) as usize]); // fill with border color
-    let color_memory_start = 0xA000u16.wrapping_add(0x400 * (color >> 4) as u16);
+    // fill with border color
+    pixels.fill(palette.get(color));
 
-    if disable_video {
+    let Some(y) = control.content_y(border, row) else {
         return;
+    };
+    let width = {
+        let w = CONTENT_WIDTH as u16 - if control.enable_h_scroll { 2 * 4 } else { 0 };
+        if control.hires_mode { w * 2 } else { w }
+    };
+    let border_x = border.x() as usize + if control.enable_h_scroll { 2 * 2 } else { 0 };
+
+    let base = effects.base.unwrap_or_else(|| memory.read_u8(0xD003)); // editable via 00 row effect
+    let scroll = effects.scroll.unwrap_or_else(|| memory.read_u8(0xD004)); // editable via 01 row effect
+    let screen_colors = effects
+        .screen_colors
+        .unwrap_or_else(|| memory.read_u8(0xD005)); // editable via 10 row effect
+    let sprite = effects.sprite.unwrap_or_else(|| memory.read_u8(0xD006)); // editable via 11 row effect
+
+    let layout = TileLayout {
+        hires_mode: control.hires_mode,
+        bitmap_mode: control.bitmap_mode,
+        base,
+        color,
+        screen_colors,
+    };
+    let v_scroll_amount = if control.enable_v_scroll {
+        scroll & 0x7
+    } else {
+        0
+    };
+    let h_scroll_amount = if control.enable_h_scroll {
+        (scroll >> 4) & 0x3
+    } else {
+        0
+    };
+
+    // decode the background a whole tile at a time, the scrolled row may reach into one more tile
+    let mut background = [0u8; HIRES_WIDTH as usize + 8];
+    let tile_width = layout.tile_width();
+    let scrolled_y = y + v_scroll_amount as u16;
+    for tile_x in 0..(width + h_scroll_amount as u16).div_ceil(tile_width) {
+        let start = (tile_x * tile_width) as usize;
+        layout.tile_row(
+            memory,
+            tile_x,
+            scrolled_y,
+            &mut background[start..start + tile_width as usize],
+        );
     }
+    let background = &background[h_scroll_amount as usize..][..width as usize];
 
-    // these depend on the fine scrolling state
-    let width = {
-        let w = CONTENT_WIDTH as u16 - if enable_h_scroll { 2 * 4 } else { 0 };
-        if hires_mode { w * 2 } else { w }
+    // like fine scrolling, sprites are only available in the multicolor modes
+    let sprites = if control.hires_mode {
+        vec![]
+    } else {
+        row_sprites(memory, firmware, sprite, y)
     };
-    let height = CONTENT_HEIGHT - if enable_v_scroll { 8 } else { 0 };
-    let border_x = BORDER_X as usize + if enable_h_scroll { 2 * 2 } else { 0 };
-    let border_y = BORDER_Y as usize + if enable_v_scroll { 4 } else { 0 };
-
-    let mut base = memory.read_u8(0xD003); // editable via 00 row effect
-    let mut scroll = memory.read_u8(0xD004); // editable via 01 row effect
-    let mut screen_colors = memory.read_u8(0xD005); // editable via 10 row effect
-    let mut sprite = memory.read_u8(0xD006); // editable via 11 row effect
-
-    let mut render_line =
-        |y: u16, memory: &mut M, base: u8, scroll: u8, screen_colors: u8, sprite: u8| {
-            let screen_memory_start = 0xA000u16.wrapping_add(0x400 * (base >> 4) as u16);
-            let character_memory_start = 0xA000u16.wrapping_add(0x800 * (base & 0xF) as u16);
-            let v_scroll_amount = if enable_v_scroll { scroll & 0x7 } else { 0 };
-            let h_scroll_amount = if enable_h_scroll {
-                (scroll >> 4) & 0x3
-            } else {
-                0
-            };
 
-            for x in 0..width {
-                let scrolled_x = x + h_scroll_amount as u16;
-                let scrolled_y = y + v_scroll_amount as u16;
-
-                let tile_x = scrolled_x / if hires_mode { 8 } else { 4 };
-                let tile_y = scrolled_y / 8;
-                let tile_index = tile_y * 40 + tile_x;
-
-                let in_tile_x = scrolled_x % if hires_mode { 8 } else { 4 };
-                let in_tile_y = scrolled_y % 8;
-
-                let palette_index = if hires_mode {
-                    // background, fine scroll & sprites are disabled
-                    let character_data_row = if bitmap_mode {
-                        memory.read_u8(screen_memory_start.wrapping_add(8 * tile_index + in_tile_y))
-                    } else {
-                        let character =
-                            memory.read_u8(screen_memory_start.wrapping_add(tile_index));
-                        memory.read_u8(
-                            character_memory_start.wrapping_add(8 * character as u16 + in_tile_y),
-                        )
-                    };
-                    let local_colors = memory.read_u8(color_memory_start.wrapping_add(tile_index));
-                    let character_data_pixel = (character_data_row >> (7 - in_tile_x)) & 0x1;
-                    match character_data_pixel {
-                        0 => local_colors & 0xF,
-                        1 => local_colors >> 4,
-                        _ => unreachable!(),
-                    }
-                } else {
-                    // background
-                    let character_data_row = if bitmap_mode {
-                        memory.read_u8(screen_memory_start.wrapping_add(8 * tile_index + in_tile_y))
-                    } else {
-                        let character =
-                            memory.read_u8(screen_memory_start.wrapping_add(tile_index));
-                        memory.read_u8(
-                            character_memory_start.wrapping_add(8 * character as u16 + in_tile_y),
-                        )
-                    };
-                    let local_colors = memory.read_u8(color_memory_start.wrapping_add(tile_index));
-                    let character_data_pixel = (character_data_row >> (2 * (3 - in_tile_x))) & 0x3;
-                    let mut palette_index = match character_data_pixel {
-                        0 => local_colors & 0xF,
-                        1 => local_colors >> 4,
-                        2 => screen_colors & 0xF,
-                        3 => screen_colors >> 4,
-                        _ => unreachable!(),
-                    };
+    let content = &mut pixels[border_x..];
+    if control.hires_mode {
+        for (pixel, &palette_index) in content.iter_mut().zip(background) {
+            *pixel = palette.get(palette_index);
+        }
+    } else {
+        for (x, (pair, &palette_index)) in content.chunks_exact_mut(2).zip(background).enumerate() {
+            let palette_index =
+                sprite_pixels(memory, &sprites, sprite, x as u16).unwrap_or(palette_index);
+            pair.fill(palette.get(palette_index));
+        }
+    }
+}
 
-                    // sprites
-                    const SPRITE_WIDTH: u8 = 12;
-                    const SPRITE_HEIGHT: u8 = 21;
-
-                    let sprite_common_color = sprite & 0xF;
-                    let sprite_bank_start = 0xD080u16.wrapping_add(0x20 * ((sprite >> 4) as u16));
-                    for sprite_index in 0..8 {
-                        let sprite_data_start = sprite_bank_start.wrapping_add(4 * sprite_index);
-
-                        let sprite_pos_x = memory.read_u8(sprite_data_start);
-                        let min_x = (sprite_pos_x as i16) - (SPRITE_WIDTH as i16);
-                        let max_x = sprite_pos_x as i16;
-                        if !(min_x..max_x).contains(&(x as i16)) {
-                            continue;
-                        }
-
-                        let sprite_pos_y = memory.read_u8(sprite_data_start.wrapping_add(1));
-                        let min_y = (sprite_pos_y as i16) - (SPRITE_HEIGHT as i16);
-                        let max_y = sprite_pos_y as i16;
-                        if !(min_y..max_y).contains(&(y as i16)) {
-                            continue;
-                        }
-
-                        let sprite_colors = memory.read_u8(sprite_data_start.wrapping_add(2));
-                        let sprite_location = 0xA000u16.wrapping_add(
-                            0x40 * memory.read_u8(sprite_data_start.wrapping_add(3)) as u16,
-                        );
-
-                        let in_sprite_x = (x as i16 - min_x) as u8;
-                        let in_sprite_y = (y as i16 - min_y) as u8;
-                        let sprite_pixel_index = in_sprite_y * SPRITE_WIDTH + in_sprite_x;
-                        let sprite_byte_index = sprite_pixel_index / 4;
-                        let sprite_byte_bit_shift = 2 * (3 - (sprite_pixel_index % 4));
-                        let sprite_pixel_data = (memory
-                            .read_u8(sprite_location.wrapping_add(sprite_byte_index as u16))
-                            >> sprite_byte_bit_shift)
-                            & 0x3;
-                        match sprite_pixel_data {
-                            0 => {} // transparent
-                            1 => palette_index = sprite_colors & 0xF,
-                            2 => palette_index = sprite_colors >> 4,
-                            3 => palette_index = sprite_common_color,
-                            _ => unreachable!(),
-                        };
-                    }
+/// Registers that select where and how the tiles of the background are drawn from
+#[derive(Debug, Copy, Clone)]
+struct TileLayout {
+    hires_mode: bool,
+    bitmap_mode: bool,
+    /// screen map and character set locations
+    base: u8,
+    /// border color and color memory location
+    color: u8,
+    screen_colors: u8,
+}
 
-                    palette_index
-                };
+impl TileLayout {
+    fn read<M: Memory>(memory: &mut M) -> Self {
+        let control = memory.peek_u8(0xD001);
+        Self {
+            hires_mode: (control & 0x20) != 0,
+            bitmap_mode: (control & 0x10) != 0,
+            base: memory.peek_u8(0xD003),
+            color: memory.peek_u8(0xD002),
+            screen_colors: memory.peek_u8(0xD005),
+        }
+    }
+
+    const fn screen_memory_start(&self) -> u16 {
+        0xA000u16.wrapping_add(0x400 * (self.base >> 4) as u16)
+    }
+
+    const fn character_memory_start(&self) -> u16 {
+        0xA000u16.wrapping_add(0x800 * (self.base & 0xF) as u16)
+    }
+
+    const fn color_memory_start(&self) -> u16 {
+        0xA000u16.wrapping_add(0x400 * (self.color >> 4) as u16)
+    }
+
+    /// Pixels per tile row, each pixel of a multicolor tile is as wide as two hires pixels
+    const fn tile_width(&self) -> u16 {
+        if self.hires_mode { 8 } else { 4 }
+    }
+
+    /// Color of a character pixel, 1 bit in hires mode and 2 bits otherwise
+    const fn pixel_color(&self, local_colors: u8, pixel: u8) -> u8 {
+        match pixel {
+            0 => local_colors & 0xF,
+            1 => local_colors >> 4,
+            2 => self.screen_colors & 0xF,
+            _ => self.screen_colors >> 4,
+        }
+    }
+
+    /// Palette indices of the pixels of the tile at `tile_x` in line `y` of the content area, one per pixel of `pixels`
+    fn tile_row<M: Memory>(&self, memory: &mut M, tile_x: u16, y: u16, pixels: &mut [u8]) {
+        let tile_index = (y / 8) * 40 + tile_x;
+        let in_tile_y = y % 8;
+        let character_data_row = if self.bitmap_mode {
+            memory.read_u8(
+                self.screen_memory_start()
+                    .wrapping_add(8 * tile_index + in_tile_y),
+            )
+        } else {
+            let character = memory.read_u8(self.screen_memory_start().wrapping_add(tile_index));
+            memory.read_u8(
+                self.character_memory_start()
+                    .wrapping_add(8 * character as u16 + in_tile_y),
+            )
+        };
+        let local_colors = memory.read_u8(self.color_memory_start().wrapping_add(tile_index));
+        let colors = [0, 1, 2, 3].map(|pixel| self.pixel_color(local_colors, pixel));
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = colors[self.character_pixel(character_data_row, x as u16) as usize];
+        }
+    }
+
+    /// Value of pixel `x` in a row of character data
+    const fn character_pixel(&self, character_data_row: u8, x: u16) -> u8 {
+        if self.hires_mode {
+            (character_data_row >> (7 - x)) & 0x1
+        } else {
+            (character_data_row >> (2 * (3 - x))) & 0x3
+        }
+    }
+}
+
+/// Palette index of the background at `x`, `y` of the content area, `x` is in pixels of the current mode
+fn background_pixel<M: Memory>(memory: &mut M, layout: &TileLayout, x: u16, y: u16) -> u8 {
+    let tile_width = layout.tile_width();
+    let mut pixels = [0; 8];
+    layout.tile_row(
+        memory,
+        x / tile_width,
+        y,
+        &mut pixels[..tile_width as usize],
+    );
+    pixels[(x % tile_width) as usize]
+}
+
+const fn sprite_bank_start(firmware: Firmware, sprite: u8) -> u16 {
+    let bank = (sprite >> 4) % firmware.sprite_banks();
+    0xD080u16.wrapping_add(0x20 * bank as u16)
+}
+
+const fn sprite_location(pointer: u8) -> u16 {
+    0xA000u16.wrapping_add(SPRITE_SIZE * pointer as u16)
+}
+
+/// A sprite that covers the line being drawn
+#[derive(Debug, Copy, Clone)]
+struct RowSprite {
+    min_x: i16,
+    colors: u8,
+    location: u16,
+    /// line of the sprite that is drawn
+    y: u8,
+}
+
+/// Sprites of the bank selected by `sprite` that cover line `y` of the content area, sprite 0 first.
+///
+/// Each sprite has an X and Y position, two colors and a pointer to its data. The positions are of the bottom right
+/// corner, so a sprite at X or Y 0 is outside of the content area, there is no enable bit to hide sprites.
+/// All bits of the registers are part of these values, there are no attribute bits for expansion or color depth.
+fn row_sprites<M: Memory>(
+    memory: &mut M,
+    firmware: Firmware,
+    sprite: u8,
+    y: u16,
+) -> Vec<RowSprite> {
+    let sprite_bank_start = sprite_bank_start(firmware, sprite);
+    let mut sprites = vec![];
+    for sprite_index in 0..8 {
+        let sprite_data_start = sprite_bank_start.wrapping_add(4 * sprite_index);
+
+        let sprite_pos_y = memory.read_u8(sprite_data_start.wrapping_add(1));
+        let min_y = (sprite_pos_y as i16) - (SPRITE_HEIGHT as i16);
+        let max_y = sprite_pos_y as i16;
+        if !(min_y..max_y).contains(&(y as i16)) {
+            continue;
+        }
 
-                let target_color = Color::PALETTE[palette_index as usize];
-                if hires_mode {
-                    let target_pos =
-                        (y as usize + border_y) * WIDTH as usize + (x as usize + border_x);
-                    raw_pixels[target_pos] = target_color;
-                } else {
-                    let target_pos =
-                        (y as usize + border_y) * WIDTH as usize + (2 * x as usize + border_x);
-                    raw_pixels[target_pos] = target_color;
-                    raw_pixels[target_pos + 1] = target_color;
+        let sprite_pos_x = memory.read_u8(sprite_data_start);
+        sprites.push(RowSprite {
+            min_x: (sprite_pos_x as i16) - (SPRITE_WIDTH as i16),
+            colors: memory.read_u8(sprite_data_start.wrapping_add(2)),
+            location: sprite_location(memory.read_u8(sprite_data_start.wrapping_add(3))),
+            y: (y as i16 - min_y) as u8,
+        });
+    }
+    sprites
+}
+
+/// Palette index of the topmost of `sprites` at `x`, `None` if all sprites are transparent there.
+///
+/// Lower sprites are drawn over higher ones, and all of them over the background.
+fn sprite_pixels<M: Memory>(
+    memory: &mut M,
+    sprites: &[RowSprite],
+    sprite: u8,
+    x: u16,
+) -> Option<u8> {
+    let sprite_common_color = sprite & 0xF;
+    sprites.iter().find_map(|row_sprite| {
+        let in_sprite_x = x as i16 - row_sprite.min_x;
+        if !(0..SPRITE_WIDTH as i16).contains(&in_sprite_x) {
+            return None;
+        }
+        match sprite_pixel(memory, row_sprite.location, in_sprite_x as u8, row_sprite.y) {
+            0 => None, // transparent
+            1 => Some(row_sprite.colors & 0xF),
+            2 => Some(row_sprite.colors >> 4),
+            3 => Some(sprite_common_color),
+            _ => unreachable!(),
+        }
+    })
+}
+
+/// 2 bit value of a sprite pixel, 0 is transparent
+fn sprite_pixel<M: Memory>(memory: &mut M, sprite_location: u16, x: u8, y: u8) -> u8 {
+    let sprite_pixel_index = y as u16 * SPRITE_WIDTH as u16 + x as u16;
+    let sprite_byte_index = sprite_pixel_index / 4;
+    let sprite_byte_bit_shift = 2 * (3 - (sprite_pixel_index % 4));
+    (memory.read_u8(sprite_location.wrapping_add(sprite_byte_index)) >> sprite_byte_bit_shift) & 0x3
+}
+
+/// Picture of video memory decoded the way the VID chip sees it, for debugging
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VideoView {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl VideoView {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::BLACK; (width * height) as usize],
+        }
+    }
+
+    fn set(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    /// The 256 characters of the current character set in a 16x16 grid.
+    ///
+    /// Pixels use black and white for the local colors and the screen colors like on screen.
+    pub fn character_set<M: Memory>(memory: &mut M, palette: &Palette) -> Self {
+        let layout = TileLayout::read(memory);
+        let mut view = Self::new(16 * 8, 16 * 8);
+        for character in 0..256u16 {
+            let start = layout.character_memory_start().wrapping_add(8 * character);
+            for y in 0..8 {
+                let row = memory.peek_u8(start.wrapping_add(y));
+                for x in 0..8 {
+                    let pixel = layout.character_pixel(row, x / (8 / layout.tile_width()));
+                    let color = palette.get(layout.pixel_color(0x10, pixel));
+                    let (cell_x, cell_y) = ((character % 16) * 8, (character / 16) * 8);
+                    view.set((cell_x + x) as u32, (cell_y + y) as u32, color);
                 }
             }
-        };
+        }
+        view
+    }
 
-    for y in 0..height {
-        render_line(y as u16, memory, base, scroll, screen_colors, sprite);
+    /// The background drawn from screen map, character set and color memory,
+    /// without fine scrolling, row effects and sprites
+    pub fn screen<M: Memory>(memory: &mut M, palette: &Palette) -> Self {
+        let layout = TileLayout::read(memory);
+        let scale = 8 / layout.tile_width();
+        let mut view = Self::new(HIRES_WIDTH as u32, CONTENT_HEIGHT as u32);
+        let mut peeking = Peek(memory);
+        for y in 0..CONTENT_HEIGHT as u16 {
+            for x in 0..HIRES_WIDTH {
+                let color = palette.get(background_pixel(&mut peeking, &layout, x / scale, y));
+                view.set(x as u32, y as u32, color);
+            }
+        }
+        view
+    }
 
-        let tile_y = y / 8;
-        let in_tile_y = y % 8;
-        if enable_row_effects && in_tile_y == 0 {
-            for effect_index in 0..32 {
-                let effect_control = memory.read_u8(0xD040 + effect_index);
-                if effect_control & 0x80 == 0 {
-                    continue;
+    /// Both colors of every tile in color memory, the low nibble on the left half
+    pub fn color_memory<M: Memory>(memory: &mut M, palette: &Palette) -> Self {
+        let layout = TileLayout::read(memory);
+        let mut view = Self::new(40 * 8, 25 * 8);
+        for tile in 0..40 * 25 {
+            let colors = memory.peek_u8(layout.color_memory_start().wrapping_add(tile));
+            for y in 0..8 {
+                for x in 0..8 {
+                    let color = if x < 4 { colors & 0xF } else { colors >> 4 };
+                    let (tile_x, tile_y) = ((tile % 40) * 8, (tile / 40) * 8);
+                    view.set((tile_x + x) as u32, (tile_y + y) as u32, palette.get(color));
                 }
-                let row = effect_control & 0x1F;
-                if row != tile_y {
-                    continue;
+            }
+        }
+        view
+    }
+
+    /// The 8 sprites of the current sprite bank side by side, transparent pixels are black
+    pub fn sprites<M: Memory>(memory: &mut M, palette: &Palette) -> Self {
+        let sprite = memory.peek_u8(0xD006);
+        let width = 2 * SPRITE_WIDTH as u32 + 2;
+        let mut view = Self::new(8 * width, SPRITE_HEIGHT as u32);
+        let mut peeking = Peek(memory);
+        for sprite_index in 0..8 {
+            let sprite_data_start =
+                sprite_bank_start(Firmware::default(), sprite).wrapping_add(4 * sprite_index);
+            let sprite_colors = peeking.read_u8(sprite_data_start.wrapping_add(2));
+            let location = sprite_location(peeking.read_u8(sprite_data_start.wrapping_add(3)));
+            for y in 0..SPRITE_HEIGHT {
+                for x in 0..SPRITE_WIDTH {
+                    let color = match sprite_pixel(&mut peeking, location, x, y) {
+                        0 => Color::BLACK,
+                        1 => palette.get(sprite_colors & 0xF),
+                        2 => palette.get(sprite_colors >> 4),
+                        _ => palette.get(sprite & 0xF),
+                    };
+                    let view_x = sprite_index as u32 * width + 2 * x as u32;
+                    view.set(view_x, y as u32, color);
+                    view.set(view_x + 1, y as u32, color);
                 }
-                let destination = (effect_control >> 5) & 0x3;
-                let effect_data = memory.read_u8(0xD060 + effect_index);
-                match destination {
-                    0 => base = effect_data,
-                    1 => scroll = effect_data,
-                    2 => screen_colors = effect_data,
-                    3 => sprite = effect_data,
-                    _ => unreachable!(),
+            }
+        }
+        view
+    }
+}
+
+/// Turns the reads of the shared tile and sprite decoding into side effect free peeks
+struct Peek<'a, M>(&'a mut M);
+
+impl<M: Memory> Memory for Peek<'_, M> {
+    fn read_u8(&mut self, address: u16) -> u8 {
+        self.0.peek_u8(address)
+    }
+
+    fn write_u8(&mut self, _address: u16, _value: u8) {}
+
+    fn update(&mut self, _cycle: usize) -> Interrupt {
+        Interrupt::none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::contiguous::Contiguous;
+
+    fn row_color(pixels: &[Color], row: usize) -> Color {
+        pixels[row * WIDTH as usize]
+    }
+
+    /// Bitmap mode with the bitmap at A400, black and white as local colors and red and cyan as screen colors
+    fn bitmap(control: u8) -> Contiguous {
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.write_u8(0xD001, 0x10 | control);
+        memory.write_u8(0xD002, 0x00); // color memory at A000
+        memory.write_u8(0xD003, 0x10); // bitmap at A400
+        memory.write_u8(0xD005, 0x32);
+        memory.force_write_all(0xA000, &[0x10; 1000]);
+        memory
+    }
+
+    fn render(memory: &mut Contiguous) -> Vec<Color> {
+        let mut pixels = vec![Color::default(); (WIDTH * HEIGHT) as usize];
+        render_pixels(
+            memory,
+            &Palette::default(),
+            Border::Normal,
+            Firmware::default(),
+            &mut pixels,
+        );
+        pixels
+    }
+
+    /// Color at `x`, `y` of the content area without scrolling, `x` in hires pixels
+    fn content_color(pixels: &[Color], x: u32, y: u32) -> Color {
+        pixels[((BORDER_Y + y) * WIDTH + BORDER_X + x) as usize]
+    }
+
+    #[test]
+    fn test_parse_palette() {
+        let text = "; test palette\n#000000 #FFFFFF 0x123456, abcdef\n".to_string()
+            + &["808080"; 12].join("\n");
+        let palette = Palette::parse(&text).unwrap();
+        assert_eq!(palette.get(1), Color::WHITE);
+        assert_eq!(palette.get(2), Color::rgb(0x123456));
+        assert_eq!(palette.get(3), Color::rgb(0xabcdef));
+        assert_eq!(palette.get(15), Color::rgb(0x808080));
+
+        assert!(Palette::parse("#000000").is_err());
+        assert!(Palette::parse(&["gggggg"; 16].join(" ")).is_err());
+    }
+
+    #[test]
+    fn test_greyscale() {
+        let palette = Palette::greyscale();
+        assert_eq!(palette.get(0), Color::BLACK);
+        assert_eq!(palette.get(1), Color::WHITE);
+        assert!(palette.0.iter().all(|c| c.r == c.g && c.g == c.b));
+    }
+
+    #[test]
+    fn test_mid_frame_border_change() {
+        let mut vram = Contiguous::new_ram(0x4000);
+        vram.write_u8(0xD001 - VRAM_START, 0x01); // disable video, only the border is drawn
+
+        for timing in [TimingProfile::NTSC, TimingProfile::PAL] {
+            let mut renderer = ScanlineRenderer::new(
+                Palette::default(),
+                timing,
+                Border::Normal,
+                Firmware::default(),
+            );
+            vram.write_u8(0xD002 - VRAM_START, 0x01); // white border
+            let mid_frame =
+                timing.line_start(first_line(&timing, Border::Normal) + HEIGHT as usize / 2);
+            renderer.update(&vram, mid_frame);
+            vram.write_u8(0xD002 - VRAM_START, 0x02); // red border
+            renderer.update(&vram, timing.frame_cycles());
+
+            let frame = renderer.frame();
+            assert_eq!(row_color(frame, 0), Color::WHITE);
+            assert_eq!(row_color(frame, HEIGHT as usize - 1), Color::RED);
+        }
+    }
+
+    #[test]
+    fn test_unchanged_rows_are_copied() {
+        let mut vram = Contiguous::new_ram(0x4000);
+        vram.write_u8(0xD002 - VRAM_START, 0x01); // white border
+        let mut renderer = ScanlineRenderer::default();
+        let frame_cycles = TimingProfile::default().frame_cycles();
+        renderer.update(&vram, frame_cycles);
+        assert_eq!(row_color(renderer.frame(), 0), Color::WHITE);
+
+        // the second frame is copied from the first, including a pixel that was never drawn like this
+        renderer.front[0] = Color::BLACK;
+        renderer.update(&vram, 2 * frame_cycles);
+        assert_eq!(renderer.frame()[0], Color::BLACK);
+
+        // a write makes the next frame draw every row again
+        vram.write_u8(0xD002 - VRAM_START, 0x02); // red border
+        renderer.update(&vram, 3 * frame_cycles);
+        assert_eq!(row_color(renderer.frame(), 0), Color::RED);
+        assert_eq!(row_color(renderer.frame(), HEIGHT as usize - 1), Color::RED);
+    }
+
+    #[test]
+    fn test_parallel_frame_matches() {
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.write_u8(0xD001, 0x0E); // multicolor with fine scrolling and row effects
+        memory.write_u8(0xD003, 0x10);
+        memory.write_u8(0xD004, 0x21);
+        memory.write_u8(0xD040, 0x80 | 0x60 | 3); // other sprite colors from tile row 3 on
+        memory.write_u8(0xD060, 0x07);
+        memory.force_write_all(0xD080, &[40, 50, 0x2A, 0x40]);
+        for offset in 0..0x1000u16 {
+            memory.write_u8(0xA000 + offset, offset.wrapping_mul(97) as u8);
+            memory.write_u8(0xB000 + offset, offset.wrapping_mul(13) as u8);
+        }
+
+        let palette = Palette::default();
+        let mut sequential = vec![Color::default(); (WIDTH * HEIGHT) as usize];
+        render_pixels(
+            &mut memory,
+            &palette,
+            Border::Normal,
+            Firmware::default(),
+            &mut sequential,
+        );
+        let mut parallel = vec![Color::default(); (WIDTH * HEIGHT) as usize];
+        render_frame(
+            &memory.memory[0xA000..0xE000],
+            &palette,
+            Border::Normal,
+            Firmware::default(),
+            &mut parallel,
+        );
+        assert!(sequential == parallel);
+    }
+
+    #[test]
+    fn test_borders() {
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.write_u8(0xD002, 0x01); // white border
+        memory.write_u8(0xD003, 0x10); // screen at A400, characters at A000
+        let palette = Palette::default();
+
+        let mut normal = vec![Color::default(); (WIDTH * HEIGHT) as usize];
+        render_pixels(
+            &mut memory,
+            &palette,
+            Border::Normal,
+            Firmware::default(),
+            &mut normal,
+        );
+        for border in [Border::None, Border::Large] {
+            let (width, height) = (border.width(), border.height());
+            let mut pixels = vec![Color::default(); (width * height) as usize];
+            render_pixels(
+                &mut memory,
+                &palette,
+                border,
+                Firmware::default(),
+                &mut pixels,
+            );
+
+            // the content area is the same, only the amount of border around it differs
+            for y in 0..CONTENT_HEIGHT as u32 {
+                let row = |pixels: &[Color], border: Border, width: u32| {
+                    let start = ((border.y() + y) * width + border.x()) as usize;
+                    pixels[start..start + HIRES_WIDTH as usize].to_vec()
+                };
+                assert_eq!(
+                    row(&pixels, border, width),
+                    row(&normal, Border::Normal, WIDTH)
+                );
+            }
+            if border == Border::Large {
+                assert_eq!(pixels[0], Color::WHITE);
+            }
+        }
+        assert_eq!(
+            first_line(&TimingProfile::NTSC, Border::None),
+            first_line(&TimingProfile::NTSC, Border::Normal) + BORDER_Y as usize
+        );
+    }
+
+    #[test]
+    fn test_hires_bitmap() {
+        let mut memory = bitmap(0x20);
+        // the 8 bytes of each tile follow each other, tile 83 is the fourth tile of tile row 2
+        memory.write_u8(0xA400 + 8 * 83 + 5, 0x81);
+        memory.write_u8(0xA400 + 7999, 0x01); // last line of the last tile
+        let pixels = render(&mut memory);
+
+        assert_eq!(content_color(&pixels, 24, 21), Color::WHITE);
+        assert_eq!(content_color(&pixels, 25, 21), Color::BLACK);
+        assert_eq!(content_color(&pixels, 31, 21), Color::WHITE);
+        for (x, y) in [(23, 21), (32, 21), (24, 20), (24, 22)] {
+            assert_eq!(content_color(&pixels, x, y), Color::BLACK, "{x}, {y}");
+        }
+        assert_eq!(content_color(&pixels, 319, 199), Color::WHITE);
+        assert_eq!(content_color(&pixels, 318, 199), Color::BLACK);
+    }
+
+    #[test]
+    fn test_multicolor_bitmap() {
+        let mut memory = bitmap(0x00);
+        // the four pixels of tile 83 show the local colors and then the screen colors
+        memory.write_u8(0xA400 + 8 * 83 + 5, 0x1B);
+        let pixels = render(&mut memory);
+
+        let colors = [Color::BLACK, Color::WHITE, Color::RED, Color::CYAN];
+        for (pixel, color) in colors.into_iter().enumerate() {
+            let x = 24 + 2 * pixel as u32;
+            assert_eq!(content_color(&pixels, x, 21), color);
+            assert_eq!(content_color(&pixels, x + 1, 21), color);
+        }
+        assert_eq!(content_color(&pixels, 32, 21), Color::BLACK);
+        assert_eq!(content_color(&pixels, 28, 20), Color::BLACK);
+    }
+
+    #[test]
+    fn test_bitmap_scroll() {
+        let mut memory = bitmap(0x06);
+        memory.write_u8(0xD004, 0x13); // 1 pixel left, 3 lines up
+        memory.write_u8(0xA400 + 8 * 83 + 5, 0x1B);
+        let pixels = render(&mut memory);
+
+        // fine scrolling moves the content area by 4 hires pixels and lines into the border
+        let content = |x: u32, y: u32| content_color(&pixels, 4 + x, 4 + y);
+        assert_eq!(content(2 * 13, 18), Color::RED);
+        assert_eq!(content(2 * 14, 18), Color::CYAN);
+        assert_eq!(content(2 * 14, 17), Color::BLACK);
+    }
+
+    #[test]
+    fn test_bitmap_row_effects() {
+        let mut memory = bitmap(0x08);
+        memory.write_u8(0xD040, 0x80 | 2); // bitmap at A800 from tile row 2 on
+        memory.write_u8(0xD060, 0x20);
+        memory.write_u8(0xA400 + 8 * 43, 0xFF);
+        memory.write_u8(0xA400 + 8 * 83 + 5, 0xFF);
+        memory.write_u8(0xA800 + 8 * 83 + 5, 0xAA);
+        let pixels = render(&mut memory);
+
+        // the tiles keep their index into the new bitmap
+        assert_eq!(content_color(&pixels, 24, 8), Color::CYAN);
+        assert_eq!(content_color(&pixels, 24, 21), Color::RED);
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.write_u8(0xD006, 0x03); // cyan as common sprite color
+        // sprite 0 with its left 8 pixels in its first color, sprite 1 two pixels to the right in its second color
+        memory.force_write_all(0xD080, &[40, 30, 0x21, 0x80, 42, 30, 0x21, 0x81]);
+        for y in 0..21 {
+            memory.force_write_all(0xC000 + 3 * y, &[0x55, 0x55, 0x00]);
+        }
+        memory.force_write_all(0xC040, &[0xAA; 63]);
+        memory.force_write_all(0xC040 + 3 * 20, &[0xFF; 3]);
+        let pixels = render(&mut memory);
+
+        // sprite 0 covers sprite 1 where it is not transparent
+        let multicolor = |x: u32, y: u32| content_color(&pixels, 2 * x, y);
+        assert_eq!(multicolor(28, 10), Color::WHITE);
+        assert_eq!(multicolor(35, 10), Color::WHITE);
+        assert_eq!(multicolor(36, 10), Color::RED);
+        assert_eq!(multicolor(41, 10), Color::RED);
+        assert_eq!(multicolor(42, 10), Color::BLACK);
+        assert_eq!(multicolor(36, 29), Color::CYAN);
+    }
+
+    #[test]
+    fn test_sprites_without_enable_bit() {
+        let mut memory = bitmap(0x00);
+        memory.force_write_all(0xA400, &[0xFF; 8000]); // cyan background
+        memory.force_write_all(0xC380, &[0x55; 63]); // after the end of the bitmap
+        let mut sprite_rows = |position: [u8; 2]| {
+            memory.force_write_all(0xD080, &[position[0], position[1], 0x01, 0x8E]);
+            let pixels = render(&mut memory);
+            (0..CONTENT_HEIGHT as u32)
+                .filter(|&y| {
+                    (0..HIRES_WIDTH as u32).any(|x| content_color(&pixels, x, y) == Color::WHITE)
+                })
+                .count()
+        };
+
+        // sprites are drawn over the background, at X or Y 0 they are hidden
+        assert_eq!(sprite_rows([12, 21]), 21);
+        assert_eq!(sprite_rows([0, 100]), 0);
+        assert_eq!(sprite_rows([100, 0]), 0);
+        assert_eq!(sprite_rows([100, 10]), 10);
+    }
+
+    #[test]
+    fn test_sprite_size() {
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.write_u8(0xD006, 0x03); // cyan as common sprite color
+        // the high nibble of the colors is the unused second color, not a mode bit
+        memory.force_write_all(0xD080, &[40, 50, 0xF1, 0x80]);
+        memory.force_write_all(0xC000, &[0x55; 63]);
+        memory.write_u8(0xC03F, 0xFF);
+        let mut sprite_area = |control: u8| {
+            memory.write_u8(0xD001, control);
+            let pixels = render(&mut memory);
+            let mut area = vec![];
+            for y in 0..CONTENT_HEIGHT as u32 {
+                for x in 0..HIRES_WIDTH as u32 {
+                    match content_color(&pixels, x, y) {
+                        Color::BLACK => {}
+                        color => area.push((x, y, color)),
+                    }
                 }
             }
+            area
+        };
+
+        // 24 hires pixels and 21 lines up to the position, the unused last byte is not drawn
+        let area = sprite_area(0x00);
+        assert_eq!(area.len(), 24 * 21);
+        assert!(area.iter().all(|&(x, y, color)| {
+            (56..80).contains(&x) && (29..50).contains(&y) && color == Color::WHITE
+        }));
+        // there are no hires sprites
+        assert!(sprite_area(0x20).is_empty());
+    }
+
+    #[test]
+    fn test_firmware() {
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.write_u8(0xD002, 0x30); // color memory at AC00
+        memory.write_u8(0xD003, 0x10); // screen at A400, characters at A000
+        memory.write_u8(0xD005, 0x20); // red as fourth multicolor color
+        memory.force_write_all(0xA000, &[0xFF; 8]); // every tile shows the solid character 0
+        memory.write_u8(0xD050, 0x80 | 0x40 | 1); // row effect 16, white as fourth color from tile row 1 on
+        memory.write_u8(0xD070, 0x10);
+        memory.write_u8(0xD006, 0x10); // sprite bank 1
+        memory.force_write_all(0xD0A0, &[60, 60, 0x01, 0x80]); // white sprite at C000
+        memory.force_write_all(0xC000, &[0x55; 63]);
+
+        let mut render = |control: u8, firmware| {
+            memory.write_u8(0xD001, control);
+            let mut pixels = vec![Color::default(); (WIDTH * HEIGHT) as usize];
+            render_pixels(
+                &mut memory,
+                &Palette::default(),
+                Border::Normal,
+                firmware,
+                &mut pixels,
+            );
+            pixels
+        };
+        let content = |x: u32, y: u32| ((BORDER_Y + y) * WIDTH + BORDER_X + x) as usize;
+
+        // the first firmware ignores the hires bit and draws the solid character in the fourth multicolor color
+        assert_eq!(render(0x20, Firmware::Rev1)[content(0, 0)], Color::RED);
+        assert_eq!(render(0x20, Firmware::Rev2)[content(0, 0)], Color::BLACK);
+        // it only has 16 row effects
+        assert_eq!(render(0x08, Firmware::Rev1)[content(0, 8)], Color::RED);
+        assert_eq!(render(0x08, Firmware::Rev2)[content(0, 8)], Color::WHITE);
+        // and one sprite bank
+        assert_eq!(
+            render(0x00, Firmware::Rev1)[content(2 * 50, 40)],
+            Color::RED
+        );
+        assert_eq!(
+            render(0x00, Firmware::Rev2)[content(2 * 50, 40)],
+            Color::WHITE
+        );
+    }
+
+    #[test]
+    fn test_row_effect_from_first_line() {
+        for control in [0x08, 0x28] {
+            let mut memory = Contiguous::new_ram(0x10000);
+            memory.write_u8(0xD001, control); // row effects, multicolor or hires
+            memory.write_u8(0xD002, 0x30); // color memory at AC00
+            memory.write_u8(0xD003, 0x10); // screen at A400, characters at A000
+            memory.write_u8(0xD005, 0x20); // red as fourth multicolor color
+            memory.write_u8(0xD040, 0x80 | 1); // change the base from tile row 1 on
+            memory.write_u8(0xD060, 0x20); // screen at A800
+            memory.write_u8(0xA828, 0x01); // first tile of row 1 shows character 1
+            memory.force_write_all(0xA008, &[0xFF; 8]);
+            memory.write_u8(0xAC28, 0x21); // white background, red foreground in hires
+
+            let mut pixels = vec![Color::default(); (WIDTH * HEIGHT) as usize];
+            render_pixels(
+                &mut memory,
+                &Palette::default(),
+                Border::Normal,
+                Firmware::default(),
+                &mut pixels,
+            );
+            let first_line = (BORDER_Y + 8) * WIDTH + BORDER_X;
+            assert_eq!(
+                pixels[first_line as usize],
+                Color::RED,
+                "control 0x{control:02X}"
+            );
         }
     }
+
+    #[test]
+    fn test_video_view() {
+        let palette = Palette::default();
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.write_u8(0xD001, 0x20); // hires
+        memory.write_u8(0xD003, 0x01); // screen at A000, characters at A800
+        memory.write_u8(0xD002, 0x20); // color memory at A800
+        memory.write_u8(0xA000, 0x01); // first tile shows character 1
+        memory.write_u8(0xA808, 0x80); // top left pixel of character 1
+        memory.write_u8(0xA800, 0x21); // first tile is white on red
+
+        let screen = VideoView::screen(&mut memory, &palette);
+        assert_eq!((screen.width, screen.height), (320, 200));
+        assert_eq!(screen.pixels[0], Color::RED);
+        assert_eq!(screen.pixels[1], Color::WHITE);
+
+        let characters = VideoView::character_set(&mut memory, &palette);
+        assert_eq!(characters.pixels[8], Color::WHITE);
+        assert_eq!(characters.pixels[9], Color::BLACK);
+
+        let colors = VideoView::color_memory(&mut memory, &palette);
+        assert_eq!(colors.pixels[0], Color::WHITE);
+        assert_eq!(colors.pixels[4], Color::RED);
+
+        memory.write_u8(0xD083, 0x01); // first sprite at A040
+        memory.write_u8(0xD082, 0x02); // with color 1 red
+        memory.write_u8(0xA040, 0x40); // top left pixel
+        let sprites = VideoView::sprites(&mut memory, &palette);
+        assert_eq!(sprites.pixels[0], Color::RED);
+        assert_eq!(sprites.pixels[1], Color::RED);
+        assert_eq!(sprites.pixels[2], Color::BLACK);
+    }
 }
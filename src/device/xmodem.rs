@@ -0,0 +1,453 @@
+use log::{debug, error, info, warn};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+
+/// Start of a 128 byte block
+const SOH: u8 = 0x01;
+/// End of transmission
+const EOT: u8 = 0x04;
+/// Block received correctly
+const ACK: u8 = 0x06;
+/// Block not received correctly, also requests a checksum transfer
+const NAK: u8 = 0x15;
+/// Cancel the transfer
+const CAN: u8 = 0x18;
+/// Requests a CRC-16 transfer
+const CRC_REQUEST: u8 = b'C';
+/// Fills up the last block
+const PADDING: u8 = 0x1A;
+const BLOCK_SIZE: usize = 128;
+/// Emulated seconds a receiver waits for the first block before it sends NAK again
+const RETRY_SECONDS: usize = 10;
+/// Number of times a receiver sends NAK again before it gives up
+const MAX_RETRIES: usize = 10;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum XmodemTransfer {
+    /// Send the file to the emulated machine
+    Send(PathBuf),
+    /// Receive a file from the emulated machine
+    Receive(PathBuf),
+}
+
+impl FromStr for XmodemTransfer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("send", path)) if !path.is_empty() => Ok(Self::Send(path.into())),
+            Some(("receive", path)) if !path.is_empty() => Ok(Self::Receive(path.into())),
+            _ => Err(format!("expected send:<FILE> or receive:<FILE>, got {s:?}")),
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+/// CRC-16/XMODEM: polynomial 0x1021, initial value 0
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &b| {
+        let mut crc = crc ^ ((b as u16) << 8);
+        for _ in 0..8 {
+            crc = if (crc & 0x8000) != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn send(output: &Sender<u8>, bytes: &[u8]) {
+    for &b in bytes {
+        // the uart is gone when the emulator shuts down, nothing left to do then
+        let _ = output.send(b);
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum SenderState {
+    WaitingForStart,
+    WaitingForBlockAck,
+    WaitingForEotAck,
+    Done,
+}
+
+/// Host side of an upload, write the bytes transmitted by the emulated machine into it
+#[derive(Debug)]
+pub struct XmodemSender {
+    data: Vec<u8>,
+    /// index of the block currently being sent
+    block: usize,
+    crc: bool,
+    state: SenderState,
+    output: Sender<u8>,
+}
+
+impl XmodemSender {
+    pub fn new(data: impl Into<Vec<u8>>, output: Sender<u8>) -> Self {
+        Self {
+            data: data.into(),
+            block: 0,
+            crc: false,
+            state: SenderState::WaitingForStart,
+            output,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == SenderState::Done
+    }
+
+    fn block_count(&self) -> usize {
+        self.data.len().div_ceil(BLOCK_SIZE)
+    }
+
+    fn send_block(&self) {
+        let start = self.block * BLOCK_SIZE;
+        let end = (start + BLOCK_SIZE).min(self.data.len());
+        let mut payload = [PADDING; BLOCK_SIZE];
+        payload[..end - start].copy_from_slice(&self.data[start..end]);
+
+        // block numbers start at 1 and wrap around
+        let number = (self.block + 1) as u8;
+        let mut packet = vec![SOH, number, !number];
+        packet.extend_from_slice(&payload);
+        if self.crc {
+            packet.extend_from_slice(&crc16(&payload).to_be_bytes());
+        } else {
+            packet.push(checksum(&payload));
+        }
+        send(&self.output, &packet);
+    }
+
+    fn send_next(&mut self) {
+        if self.block < self.block_count() {
+            self.state = SenderState::WaitingForBlockAck;
+            self.send_block();
+        } else {
+            self.state = SenderState::WaitingForEotAck;
+            send(&self.output, &[EOT]);
+        }
+    }
+
+    fn receive(&mut self, value: u8) {
+        match (self.state, value) {
+            (SenderState::Done, _) => {}
+            (_, CAN) => {
+                warn!("XMODEM: transfer cancelled by receiver");
+                self.state = SenderState::Done;
+            }
+            (SenderState::WaitingForStart, NAK | CRC_REQUEST) => {
+                self.crc = value == CRC_REQUEST;
+                info!(
+                    "XMODEM: sending {} bytes in {} blocks{}",
+                    self.data.len(),
+                    self.block_count(),
+                    if self.crc { " with CRC" } else { "" }
+                );
+                self.send_next();
+            }
+            (SenderState::WaitingForBlockAck, ACK) => {
+                self.block += 1;
+                self.send_next();
+            }
+            (SenderState::WaitingForBlockAck, NAK) => {
+                debug!("XMODEM: resending block {}", self.block + 1);
+                self.send_block();
+            }
+            (SenderState::WaitingForEotAck, ACK) => {
+                info!("XMODEM: transfer complete");
+                self.state = SenderState::Done;
+            }
+            (SenderState::WaitingForEotAck, NAK) => send(&self.output, &[EOT]),
+            _ => debug!(
+                "XMODEM: ignoring byte 0x{value:02X} while in state {:?}",
+                self.state
+            ),
+        }
+    }
+}
+
+impl Write for XmodemSender {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &b in buf {
+            self.receive(b);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Host side of a download, write the bytes transmitted by the emulated machine into it.
+///
+/// Uses the checksum variant because every sender supports it. The received data is written to
+/// `path` once the transfer completes, with the padding of the last block removed. XMODEM can't tell padding from
+/// data, so data that ends in 0x1A loses those bytes as well.
+#[derive(Debug)]
+pub struct XmodemReceiver {
+    path: PathBuf,
+    data: Vec<u8>,
+    packet: Vec<u8>,
+    /// number of the next expected block
+    expected: u8,
+    /// the first block arrived
+    started: bool,
+    done: bool,
+    /// cycle at which NAK is sent again, set by the first [`Self::poll`]
+    next_retry: Option<usize>,
+    retries: usize,
+    output: Sender<u8>,
+}
+
+impl XmodemReceiver {
+    pub fn new(path: impl Into<PathBuf>, output: Sender<u8>) -> Self {
+        // start the transfer
+        send(&output, &[NAK]);
+        Self {
+            path: path.into(),
+            data: vec![],
+            packet: vec![],
+            expected: 1,
+            started: false,
+            done: false,
+            next_retry: None,
+            retries: 0,
+            output,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Send NAK again every [`RETRY_SECONDS`] until the sender starts, call regularly with the current cycle and the
+    /// cpu clock in Hz
+    pub fn poll(&mut self, cycle: usize, cpu_frequency: u32) {
+        if self.started || self.done {
+            return;
+        }
+        let retry_cycles = RETRY_SECONDS * cpu_frequency as usize;
+        let due = *self.next_retry.get_or_insert(cycle + retry_cycles);
+        if cycle < due {
+            return;
+        }
+        if self.retries == MAX_RETRIES {
+            warn!("XMODEM: no sender after {MAX_RETRIES} retries, giving up");
+            self.done = true;
+            return;
+        }
+        self.retries += 1;
+        debug!("XMODEM: no block yet, sending NAK again");
+        send(&self.output, &[NAK]);
+        self.next_retry = Some(cycle + retry_cycles);
+    }
+
+    const fn packet_len() -> usize {
+        3 + BLOCK_SIZE + 1
+    }
+
+    fn receive(&mut self, value: u8) {
+        if self.done {
+            return;
+        }
+
+        if self.packet.is_empty() {
+            match value {
+                SOH => {
+                    self.started = true;
+                    self.packet.push(value);
+                }
+                EOT => {
+                    send(&self.output, &[ACK]);
+                    self.finish();
+                }
+                CAN => {
+                    warn!("XMODEM: transfer cancelled by sender");
+                    self.done = true;
+                }
+                _ => debug!("XMODEM: ignoring byte 0x{value:02X} outside of block"),
+            }
+            return;
+        }
+
+        self.packet.push(value);
+        if self.packet.len() < Self::packet_len() {
+            return;
+        }
+
+        let packet = std::mem::take(&mut self.packet);
+        let (number, inverse) = (packet[1], packet[2]);
+        let payload = &packet[3..3 + BLOCK_SIZE];
+        if number != !inverse || packet[3 + BLOCK_SIZE] != checksum(payload) {
+            debug!("XMODEM: corrupt block {number}");
+            send(&self.output, &[NAK]);
+        } else if number == self.expected {
+            self.data.extend_from_slice(payload);
+            self.expected = self.expected.wrapping_add(1);
+            send(&self.output, &[ACK]);
+        } else if number == self.expected.wrapping_sub(1) {
+            debug!("XMODEM: duplicate block {number}");
+            send(&self.output, &[ACK]);
+        } else {
+            warn!(
+                "XMODEM: expected block {} but got {number}, cancelling",
+                self.expected
+            );
+            send(&self.output, &[CAN, CAN]);
+            self.done = true;
+        }
+    }
+
+    fn finish(&mut self) {
+        self.done = true;
+        let last_block = self.data.len().saturating_sub(BLOCK_SIZE);
+        let len = self.data[last_block..]
+            .iter()
+            .rposition(|&b| b != PADDING)
+            .map_or(last_block, |i| last_block + i + 1);
+        self.data.truncate(len);
+        match std::fs::write(&self.path, &self.data) {
+            Ok(()) => info!(
+                "XMODEM: received {} bytes into {}",
+                self.data.len(),
+                self.path.display()
+            ),
+            Err(e) => error!("XMODEM: error writing {}: {e}", self.path.display()),
+        }
+    }
+}
+
+impl Write for XmodemReceiver {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &b in buf {
+            self.receive(b);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn test_send_checksum() {
+        let (tx, rx) = mpsc::channel();
+        let mut sender = XmodemSender::new([1, 2, 3], tx);
+        sender.write_all(&[NAK]).unwrap();
+        let packet: Vec<u8> = rx.try_iter().collect();
+        assert_eq!(packet.len(), 3 + BLOCK_SIZE + 1);
+        assert_eq!(&packet[..6], &[SOH, 1, 0xFE, 1, 2, 3]);
+        assert_eq!(packet[6], PADDING);
+        assert_eq!(
+            packet[3 + BLOCK_SIZE],
+            6u8.wrapping_add(PADDING.wrapping_mul(125))
+        );
+
+        sender.write_all(&[ACK]).unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [EOT]);
+        sender.write_all(&[ACK]).unwrap();
+        assert!(sender.is_done());
+    }
+
+    #[test]
+    fn test_send_crc_resend() {
+        let (tx, rx) = mpsc::channel();
+        let mut sender = XmodemSender::new([0; 200], tx);
+        sender.write_all(&[CRC_REQUEST]).unwrap();
+        let first: Vec<u8> = rx.try_iter().collect();
+        assert_eq!(first.len(), 3 + BLOCK_SIZE + 2);
+        sender.write_all(&[NAK]).unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), first);
+        sender.write_all(&[ACK]).unwrap();
+        assert_eq!(&rx.try_iter().collect::<Vec<_>>()[..3], &[SOH, 2, 0xFD]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join("cody_emulator_xmodem_round_trip.bin");
+        let data: Vec<u8> = (0..=255).cycle().take(300).collect();
+
+        let (sender_tx, receiver_rx) = mpsc::channel();
+        let (receiver_tx, sender_rx) = mpsc::channel();
+        let mut sender = XmodemSender::new(data.clone(), sender_tx);
+        let mut receiver = XmodemReceiver::new(&path, receiver_tx);
+        while !(sender.is_done() && receiver.is_done()) {
+            let to_sender: Vec<u8> = sender_rx.try_iter().collect();
+            let to_receiver: Vec<u8> = receiver_rx.try_iter().collect();
+            assert!(
+                !to_sender.is_empty() || !to_receiver.is_empty(),
+                "transfer stalled"
+            );
+            sender.write_all(&to_sender).unwrap();
+            receiver.write_all(&to_receiver).unwrap();
+        }
+
+        let received = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn test_receive_retries() {
+        let path = std::env::temp_dir().join("cody_emulator_xmodem_retries.bin");
+        let (tx, rx) = mpsc::channel();
+        let mut receiver = XmodemReceiver::new(&path, tx);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [NAK]);
+
+        receiver.poll(0, 1000);
+        receiver.poll(9_999, 1000);
+        assert_eq!(rx.try_iter().count(), 0);
+        for retry in 1..=MAX_RETRIES {
+            receiver.poll(retry * 10_000, 1000);
+            assert_eq!(rx.try_iter().collect::<Vec<_>>(), [NAK]);
+        }
+        receiver.poll((MAX_RETRIES + 1) * 10_000, 1000);
+        assert_eq!(rx.try_iter().count(), 0);
+        assert!(receiver.is_done());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_receive_padding() {
+        let path = std::env::temp_dir().join("cody_emulator_xmodem_padding.bin");
+        // data ending in 0x1A loses them only in the last block, where they can't be told apart from padding
+        let data: Vec<u8> = [[1, 2, 3].as_slice(), &[PADDING; 200]].concat();
+
+        let (sender_tx, receiver_rx) = mpsc::channel();
+        let (receiver_tx, sender_rx) = mpsc::channel();
+        let mut sender = XmodemSender::new(data.clone(), sender_tx);
+        let mut receiver = XmodemReceiver::new(&path, receiver_tx);
+        while !(sender.is_done() && receiver.is_done()) {
+            sender
+                .write_all(&sender_rx.try_iter().collect::<Vec<_>>())
+                .unwrap();
+            receiver
+                .write_all(&receiver_rx.try_iter().collect::<Vec<_>>())
+                .unwrap();
+        }
+
+        let received = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(received, data[..BLOCK_SIZE]);
+    }
+}
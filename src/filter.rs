@@ -0,0 +1,310 @@
+use crate::device::vid::{Border, Color};
+use pixels::ScalingMode;
+
+/// Post-processing applied to the rendered frame before it is shown in the window
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisplayFilter {
+    /// Scale the raw pixels to fill the window
+    #[default]
+    None,
+    /// Scale the raw pixels by the largest integer factor that fits the window
+    Integer,
+    /// Darken every third line of a 3x upscaled frame
+    Scanlines,
+    /// Scanlines, an aperture grille mask and a slightly curved screen
+    Crt,
+}
+
+impl DisplayFilter {
+    /// Scale factor of the filtered frame compared to the rendered frame
+    pub const fn scale(self) -> u32 {
+        match self {
+            Self::None | Self::Integer => 1,
+            Self::Scanlines | Self::Crt => 3,
+        }
+    }
+
+    pub const fn scaling_mode(self) -> ScalingMode {
+        match self {
+            Self::Integer => ScalingMode::PixelPerfect,
+            _ => ScalingMode::Fill,
+        }
+    }
+
+    /// Filter `frame` of `size` into `target` of [`Self::scale`] times that size
+    pub fn apply(self, frame: &[Color], size: (u32, u32), target: &mut [Color]) {
+        match self {
+            Self::None | Self::Integer => target.copy_from_slice(frame),
+            Self::Scanlines => scanlines(frame, size, target),
+            Self::Crt => crt(frame, size, target),
+        }
+    }
+}
+
+/// Shape of the pixels shown in the window, the picture is letterboxed to keep it
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AspectRatio {
+    /// Square pixels
+    #[default]
+    Square,
+    /// Stretch the picture vertically to 4:3 like on a TV
+    #[value(name = "4:3")]
+    #[serde(rename = "4:3")]
+    Tv,
+}
+
+/// Turns rendered frames into the picture shown in the window
+#[derive(Debug, Clone, Default)]
+pub struct Presenter {
+    filter: DisplayFilter,
+    aspect_ratio: AspectRatio,
+    border: Border,
+    filtered: Vec<Color>,
+}
+
+impl Presenter {
+    pub const fn new(filter: DisplayFilter, aspect_ratio: AspectRatio, border: Border) -> Self {
+        Self {
+            filter,
+            aspect_ratio,
+            border,
+            filtered: vec![],
+        }
+    }
+
+    /// Size of the rendered frames passed to [`Self::present`]
+    pub const fn frame_size(&self) -> (u32, u32) {
+        (self.border.width(), self.border.height())
+    }
+
+    /// Size of the buffer passed to [`Self::present`]
+    pub const fn buffer_size(&self) -> (u32, u32) {
+        let scale = self.filter.scale();
+        let (width, height) = self.frame_size();
+        let width = width * scale;
+        match self.aspect_ratio {
+            AspectRatio::Square => (width, height * scale),
+            AspectRatio::Tv => (width, width * 3 / 4),
+        }
+    }
+
+    pub const fn scaling_mode(&self) -> ScalingMode {
+        self.filter.scaling_mode()
+    }
+
+    /// Size of the picture before filtering, in pixels of the window at scale 1
+    pub const fn picture_size(&self) -> (u32, u32) {
+        let (width, height) = self.buffer_size();
+        let scale = self.filter.scale();
+        (width / scale, height / scale)
+    }
+
+    /// Largest integer scale at which the picture fits into `available`, at least 1
+    pub const fn fitting_scale(&self, available: (u32, u32)) -> u32 {
+        let (width, height) = self.picture_size();
+        let scale = if available.0 / width < available.1 / height {
+            available.0 / width
+        } else {
+            available.1 / height
+        };
+        if scale > 1 { scale } else { 1 }
+    }
+
+    /// Window size that shows the picture at the integer scale closest to `size`
+    pub const fn snap(&self, size: (u32, u32)) -> (u32, u32) {
+        let (width, height) = self.picture_size();
+        // the width decides, resizing by a corner changes both
+        let scale = (size.0 + width / 2) / width;
+        let scale = if scale > 1 { scale } else { 1 };
+        (width * scale, height * scale)
+    }
+
+    /// Position on the screen content of a pixel of the buffer passed to [`Self::present`], in multicolor pixels.
+    ///
+    /// Positions on the border or outside of the buffer are outside of the content.
+    pub const fn content_position(&self, x: isize, y: isize) -> (i32, i32) {
+        let scale = self.filter.scale() as isize;
+        let (_, frame_height) = self.frame_size();
+        let (_, height) = self.buffer_size();
+        let frame_x = x.div_euclid(scale);
+        let frame_y = (y * frame_height as isize).div_euclid(height as isize);
+        (
+            (frame_x - self.border.x() as isize).div_euclid(2) as i32,
+            (frame_y - self.border.y() as isize) as i32,
+        )
+    }
+
+    /// Filter `frame` of size [`Self::frame_size`] into `target` of size [`Self::buffer_size`]
+    pub fn present(&mut self, frame: &[Color], target: &mut [Color]) {
+        let size = self.frame_size();
+        match self.aspect_ratio {
+            AspectRatio::Square => self.filter.apply(frame, size, target),
+            AspectRatio::Tv => {
+                let scale = self.filter.scale();
+                let (width, filtered_height) = (size.0 * scale, size.1 * scale);
+                self.filtered
+                    .resize((width * filtered_height) as usize, Color::default());
+                self.filter.apply(frame, size, &mut self.filtered);
+
+                // nearest neighbor stretch, rows are only ever repeated
+                let (_, height) = self.buffer_size();
+                for (y, row) in target.chunks_exact_mut(width as usize).enumerate() {
+                    let source_y = y * filtered_height as usize / height as usize;
+                    row.copy_from_slice(
+                        &self.filtered[source_y * width as usize..][..width as usize],
+                    );
+                }
+            }
+        }
+    }
+}
+
+const SCANLINE_BRIGHTNESS: f32 = 0.5;
+
+fn scanlines(frame: &[Color], (width, _): (u32, u32), target: &mut [Color]) {
+    let scale = DisplayFilter::Scanlines.scale();
+    let target_width = (width * scale) as usize;
+    for (y, row) in target.chunks_exact_mut(target_width).enumerate() {
+        let source_row = &frame[(y / scale as usize) * width as usize..][..width as usize];
+        let brightness = if y % scale as usize == scale as usize - 1 {
+            SCANLINE_BRIGHTNESS
+        } else {
+            1.0
+        };
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = source_row[x / scale as usize].scale_rgb(brightness, brightness, brightness);
+        }
+    }
+}
+
+fn crt(frame: &[Color], (width, height): (u32, u32), target: &mut [Color]) {
+    const CURVATURE: f32 = 0.04;
+    const MASK: f32 = 0.75;
+
+    let scale = DisplayFilter::Crt.scale();
+    let target_width = width * scale;
+    let target_height = height * scale;
+    for (y, row) in target.chunks_exact_mut(target_width as usize).enumerate() {
+        // position on the screen in -1..1
+        let v = 2.0 * (y as f32 + 0.5) / target_height as f32 - 1.0;
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let u = 2.0 * (x as f32 + 0.5) / target_width as f32 - 1.0;
+            let curved_u = u * (1.0 + CURVATURE * v * v);
+            let curved_v = v * (1.0 + CURVATURE * u * u);
+            if !(-1.0..1.0).contains(&curved_u) || !(-1.0..1.0).contains(&curved_v) {
+                *pixel = Color::BLACK;
+                continue;
+            }
+
+            let source_x = (curved_u + 1.0) / 2.0 * width as f32;
+            let source_y = (curved_v + 1.0) / 2.0 * height as f32;
+            let source = frame[source_y as usize * width as usize + source_x as usize];
+
+            let scanline = if source_y.fract() > 2.0 / 3.0 {
+                SCANLINE_BRIGHTNESS
+            } else {
+                1.0
+            };
+            let (r, g, b) = match x % 3 {
+                0 => (1.0, MASK, MASK),
+                1 => (MASK, 1.0, MASK),
+                _ => (MASK, MASK, 1.0),
+            };
+            *pixel = source.scale_rgb(r * scanline, g * scanline, b * scanline);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::vid::{BORDER_X, BORDER_Y, HEIGHT, WIDTH};
+
+    fn apply(filter: DisplayFilter, frame: &[Color]) -> Vec<Color> {
+        let scale = filter.scale();
+        let mut target = vec![Color::default(); (WIDTH * scale * HEIGHT * scale) as usize];
+        filter.apply(frame, (WIDTH, HEIGHT), &mut target);
+        target
+    }
+
+    #[test]
+    fn test_scanlines() {
+        let frame = vec![Color::WHITE; (WIDTH * HEIGHT) as usize];
+        let target = apply(DisplayFilter::Scanlines, &frame);
+        let width = (WIDTH * 3) as usize;
+        assert_eq!(target[0], Color::WHITE);
+        assert_eq!(target[width], Color::WHITE);
+        assert_eq!(target[2 * width], Color::WHITE.scale_rgb(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_content_position() {
+        let presenter = Presenter::new(
+            DisplayFilter::Scanlines,
+            AspectRatio::Square,
+            Border::Normal,
+        );
+        assert_eq!(presenter.content_position(0, 0), (-2, -8));
+        let x = (BORDER_X + 2 * 10) as isize * 3;
+        let y = (BORDER_Y + 20) as isize * 3 + 2;
+        assert_eq!(presenter.content_position(x, y), (10, 20));
+
+        let presenter = Presenter::new(DisplayFilter::None, AspectRatio::Tv, Border::Normal);
+        let (_, height) = presenter.buffer_size();
+        assert_eq!(
+            presenter.content_position(0, height as isize - 1),
+            (-2, (HEIGHT - BORDER_Y - 1) as i32)
+        );
+
+        let presenter = Presenter::new(DisplayFilter::None, AspectRatio::Square, Border::None);
+        assert_eq!(presenter.content_position(0, 0), (0, 0));
+        assert_eq!(presenter.buffer_size(), (320, 200));
+    }
+
+    #[test]
+    fn test_integer_scale() {
+        let presenter = Presenter::new(DisplayFilter::Crt, AspectRatio::Square, Border::Normal);
+        assert_eq!(presenter.picture_size(), (WIDTH, HEIGHT));
+        // 4K screen, the height limits the scale
+        assert_eq!(presenter.fitting_scale((3840, 2160)), 10);
+        assert_eq!(presenter.fitting_scale((100, 100)), 1);
+
+        assert_eq!(presenter.snap((WIDTH * 3, HEIGHT)), (WIDTH * 3, HEIGHT * 3));
+        assert_eq!(
+            presenter.snap((WIDTH * 3 - WIDTH / 3, HEIGHT * 4)),
+            (WIDTH * 3, HEIGHT * 3)
+        );
+        assert_eq!(presenter.snap((10, 10)), (WIDTH, HEIGHT));
+
+        let presenter = Presenter::new(DisplayFilter::None, AspectRatio::Tv, Border::Normal);
+        let (width, height) = presenter.snap((WIDTH * 2, 0));
+        assert_eq!((width, height * 4 / 3), (WIDTH * 2, WIDTH * 2));
+    }
+
+    #[test]
+    fn test_tv_aspect_ratio() {
+        let mut presenter = Presenter::new(DisplayFilter::None, AspectRatio::Tv, Border::Normal);
+        let (width, height) = presenter.buffer_size();
+        assert_eq!(width * 3, height * 4);
+
+        let mut frame = vec![Color::BLACK; (WIDTH * HEIGHT) as usize];
+        let last_row = ((HEIGHT - 1) * WIDTH) as usize;
+        frame[last_row..].fill(Color::WHITE);
+        let mut target = vec![Color::default(); (width * height) as usize];
+        presenter.present(&frame, &mut target);
+        assert_eq!(target[0], Color::BLACK);
+        assert_eq!(target[target.len() - 1], Color::WHITE);
+    }
+
+    #[test]
+    fn test_crt_corners_are_black() {
+        let frame = vec![Color::WHITE; (WIDTH * HEIGHT) as usize];
+        let target = apply(DisplayFilter::Crt, &frame);
+        assert_eq!(target[0], Color::BLACK);
+        assert_eq!(target[target.len() - 1], Color::BLACK);
+        let center = target.len() / 2 + (WIDTH * 3 / 2) as usize;
+        assert_ne!(target[center], Color::BLACK);
+    }
+}
@@ -1,280 +1,1580 @@
-use crate::cpu;
+use crate::control::{ControlServer, FrameReport};
 use crate::cpu::Cpu;
-use crate::device::blanking::BlankingRegister;
-use crate::device::keyboard::{Keyboard, KeyboardEmulation};
-use crate::device::uart::{UART_END, UART1_BASE, UART2_BASE, Uart, UartSource};
-use crate::device::via::Via;
+#[cfg(feature = "debugger")]
+use crate::debugger::{Debugger, Panels};
+use crate::device::keyboard::{InputMacro, JoystickBindings, Keyboard, KeyboardEmulation, Typist};
+use crate::device::light_pen::PenState;
+use crate::device::mouse::{BUTTON_LEFT, BUTTON_MIDDLE, BUTTON_RIGHT, MouseState};
+use crate::device::uart::{UartActivity, UartBackend, UartSink, UartSource, UartStdioMode};
+use crate::device::via::CodyKeyCode;
 use crate::device::vid;
-use crate::device::vid::{HEIGHT, WIDTH};
+use crate::device::vid::Border;
+use crate::device::xmodem::{XmodemReceiver, XmodemSender, XmodemTransfer};
+use crate::filter::{AspectRatio, DisplayFilter, Presenter};
+use crate::machine::{Image, ImageError, Machine, MachineBuilder, cartridge_header};
 use crate::memory::Memory;
-use crate::memory::contiguous::Contiguous;
-use crate::memory::mapped::MappedMemory;
-use log::{info, trace};
-use pixels::{Pixels, ScalingMode, SurfaceTexture};
+#[cfg(feature = "metrics")]
+use crate::metrics::{MetricsSample, MetricsServer};
+use crate::replay::{InputPlayer, InputRecording};
+use crate::rewind::Rewind;
+use crate::symbols::cody_addresses;
+#[cfg(feature = "tui")]
+use crate::tui::{Dashboard, DashboardStats, LogBuffer};
+use itertools::Itertools;
+use log::{error, info, trace, warn};
+use pixels::{Pixels, SurfaceTexture};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::Arc;
+use std::str::FromStr;
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex, mpsc};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
+use strum::EnumCount;
+use thiserror::Error;
 use winit::application::ApplicationHandler;
-use winit::dpi::LogicalSize;
-use winit::event::{DeviceEvent, DeviceId, StartCause, WindowEvent};
-use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
-use winit::window::{Window, WindowId};
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::{DeviceEvent, DeviceId, MouseButton, StartCause, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy};
+use winit::keyboard::KeyCode;
+use winit::window::{Fullscreen, Window, WindowId};
 use winit_input_helper::WinitInputHelper;
 
-#[allow(clippy::too_many_arguments)]
-pub fn start(
-    path: impl AsRef<Path>,
-    as_cartridge: bool,
-    mut load_address: Option<u16>,
-    reset_vector: Option<u16>,
-    irq_vector: Option<u16>,
-    nmi_vector: Option<u16>,
-    uart1_source: Option<impl AsRef<Path>>,
-    fix_newlines: bool,
-    physical_keyboard: bool,
-    fast: bool,
-) {
-    let path = path.as_ref();
-    info!(
-        "Loading binary {}{}",
-        path.display(),
-        if as_cartridge { " as cartridge" } else { "" }
-    );
-    let mut data = std::fs::read(path).expect("io error reading binary");
-
-    if as_cartridge {
-        let cartridge_load_address = u16::from_le_bytes(
-            data[0..2]
-                .try_into()
-                .expect("cartridge header must be at least 4 bytes"),
-        );
-        let cartridge_end_address = u16::from_le_bytes(
-            data[2..4]
-                .try_into()
-                .expect("cartridge header must be at least 4 bytes"),
-        );
-        let len = (cartridge_end_address as usize)
-            .checked_sub(cartridge_load_address as usize)
-            .and_then(|len| len.checked_add(1))
-            .expect("cartridge start address must be <= end address");
-        assert!(
-            data.len() - 4 >= len,
-            "cartridge data len {} must be >= implied header len {len}",
-            data.len() - 4
-        );
+/// Cycles after reset before a `--basic` program is typed, CodyBASIC waits for input by then
+const BASIC_BOOT_CYCLES: usize = 1_000_000;
 
-        data = data.drain(4..(len + 4)).collect();
-        if load_address.is_none() {
-            info!("Using load address 0x{cartridge_load_address:04X} from cartridge header");
-            load_address = Some(cartridge_load_address);
-        }
-    }
+/// Frames after the last byte UART1 received until automatic warp ends
+const AUTO_WARP_FRAMES: usize = 30;
 
-    assert!(!data.is_empty(), "data must not be empty");
-    let load_address = load_address.unwrap_or(0xE000);
-    let last_written_address = (load_address as usize + data.len() - 1).min(0xFFFF) as u16;
-    info!("Loading data at addresses 0x{load_address:04X}-0x{last_written_address:04X}");
+/// Interval over which the speed and frame rate in the window title are measured
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
 
-    let mut ram = Contiguous::new_ram(0xA000);
-    let mut propeller_ram = Contiguous::new_ram(0x4000);
-    let mut rom = Contiguous::new_rom(0x2000);
+/// Emulation speeds in percent of real time selectable with F3 and F4
+const SPEEDS: [u32; 5] = [10, 25, 50, 100, 200];
+/// Index of real time in [`SPEEDS`]
+const DEFAULT_SPEED: usize = 3;
 
-    if load_address >= 0xE000 {
-        rom.force_write_all(load_address - 0xE000, &data);
-    } else if load_address >= 0xA000 {
-        let address = load_address - 0xA000;
+/// Running faster than real time, toggled with F9
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Warp {
+    /// start in warp mode
+    pub enabled: bool,
+    /// speed relative to real time while warping, 0 runs as fast as possible
+    pub factor: u32,
+    /// frames not shown between two shown frames while warping
+    pub frame_skip: u32,
+    /// warp while UART1 receives data, e.g. during LOAD 1,0
+    pub auto: bool,
+}
 
-        let mut remaining = data.len();
-        let to_copy = remaining.min((0x4000 - address) as usize);
-        propeller_ram.force_write_all(address, &data[..to_copy]);
+/// Where the emulated machine is displayed
+#[derive(Debug, Clone, Default)]
+pub enum DisplayMode {
+    /// Open a window, the default
+    #[default]
+    Window,
+    /// Run without any output besides the UARTs, keyboard input is not available
+    Headless,
+    /// Run without a window and show a dashboard in the terminal instead
+    #[cfg(feature = "tui")]
+    Dashboard(LogBuffer),
+}
 
-        remaining -= to_copy;
-        if remaining > 0 {
-            rom.force_write_all(0, &data[to_copy..]);
-        }
-    } else {
-        let mut remaining = data.len();
-        let to_copy = remaining.min((0xA000 - load_address) as usize);
-        ram.force_write_all(load_address, &data[..to_copy]);
+/// Process exit code when a program ran into --timeout-cycles, like the `timeout` command
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
 
-        let mut offset = to_copy;
-        remaining -= to_copy;
-        let to_copy = remaining.min(0x4000);
-        if remaining > 0 {
-            propeller_ram.force_write_all(0, &data[offset..(offset + to_copy)]);
+/// Conditions that stop the emulation on their own, e.g. for batch runs
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RunLimits {
+    /// stop after this many cpu cycles
+    pub max_cycles: Option<usize>,
+    /// stop after this many frames
+    pub max_frames: Option<usize>,
+    /// stop when the cpu halts
+    pub until_stp: bool,
+    /// stop when the cpu halts and exit with the value of the A register
+    pub stp_exit_code: bool,
+    /// fail with [`TIMEOUT_EXIT_CODE`] if the program did not halt or exit after this many cpu cycles
+    pub timeout_cycles: Option<usize>,
+    /// process exit code when one of the limits was reached
+    pub exit_code: i32,
+    /// print the final cpu state to stdout when one of the limits was reached
+    pub dump_state: bool,
+}
 
-            offset += to_copy;
-            remaining -= to_copy;
-            if remaining > 0 {
-                rom.force_write_all(0, &data[offset..]);
-            }
+impl RunLimits {
+    fn reached<M: Memory>(&self, cpu: &Cpu<M>, frames: usize) -> Option<StopReason> {
+        let stops_on_stp = self.until_stp || self.stp_exit_code || self.timeout_cycles.is_some();
+        if stops_on_stp && !cpu.is_running() {
+            Some(StopReason::Halted)
+        } else if self.timeout_cycles.is_some_and(|max| cpu.cycle() >= max) {
+            Some(StopReason::Timeout)
+        } else if self.max_cycles.is_some_and(|max| cpu.cycle() >= max) {
+            Some(StopReason::CycleLimit)
+        } else if self.max_frames.is_some_and(|max| frames >= max) {
+            Some(StopReason::FrameLimit)
+        } else {
+            None
         }
     }
-    drop(data);
 
-    if let Some(reset_vector) = reset_vector.or_else(|| if !(load_address..=last_written_address).contains(&cpu::RESET_VECTOR) {
-        // fall back to load address so we directly jump to it on startup
-        info!(
-            "Using load address 0x{load_address:04X} as reset vector, because the reset vector location was not written to"
-        );
-        Some(load_address)
-    } else {
-        None
-    }) {
-        // override value set from data
-        info!("Setting reset vector to 0x{reset_vector:04X}");
-        rom.force_write_u16(cpu::RESET_VECTOR - 0xE000, reset_vector);
-    } else {
-        info!(
-            "Using reset vector 0x{:04X} from ROM", rom.read_u16(cpu::RESET_VECTOR - 0xE000)
-        );
+    /// Process exit code of a run that stopped for `reason`, before checking expectations
+    fn exit_code<M: Memory>(&self, reason: StopReason, cpu: &Cpu<M>) -> i32 {
+        match reason {
+            StopReason::Exited(code) => code.into(),
+            StopReason::Halted if self.stp_exit_code => cpu.a.into(),
+            StopReason::Timeout => TIMEOUT_EXIT_CODE,
+            _ => self.exit_code,
+        }
     }
-    if let Some(irq_vector) = irq_vector {
-        info!("Setting irq vector to 0x{irq_vector:04X}");
-        rom.force_write_u16(cpu::IRQ_VECTOR - 0xE000, irq_vector);
-    } else {
-        info!(
-            "Using irq vector 0x{:04X} from ROM",
-            rom.read_u16(cpu::IRQ_VECTOR - 0xE000)
-        );
+}
+
+/// Why the emulation stopped on its own
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum StopReason {
+    /// the program wrote an exit code to the host bridge
+    Exited(u8),
+    Halted,
+    Timeout,
+    CycleLimit,
+    FrameLimit,
+    /// a breakpoint paused the machine without a window to resume it
+    Breakpoint(u16),
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exited(code) => write!(f, "program exited with code {code}"),
+            Self::Halted => write!(f, "cpu halted"),
+            Self::Timeout => write!(f, "timed out"),
+            Self::CycleLimit => write!(f, "cycle limit reached"),
+            Self::FrameLimit => write!(f, "frame limit reached"),
+            Self::Breakpoint(address) => write!(f, "breakpoint at 0x{address:04X}"),
+        }
     }
-    if let Some(nmi_vector) = nmi_vector {
-        info!("Setting nmi vector to 0x{nmi_vector:04X}");
-        rom.force_write_u16(cpu::NMI_VECTOR - 0xE000, nmi_vector);
-    } else {
-        info!(
-            "Using nmi vector 0x{:04X} from ROM",
-            rom.read_u16(cpu::NMI_VECTOR - 0xE000)
-        );
+}
+
+/// Condition on the final machine state, checked when a run limit was reached
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Expectation {
+    A(u8),
+    X(u8),
+    Y(u8),
+    Pc(u16),
+    Memory(u16, u8),
+    /// the recent UART1 output contains this text
+    Uart1(String),
+}
+
+impl Expectation {
+    fn check(&self, machine: &mut Machine, uart1: &UartActivity) -> Result<(), String> {
+        let cpu = &mut machine.cpu;
+        let (name, expected, actual) = match self {
+            Self::A(value) => ("A".to_string(), *value as u16, cpu.a as u16),
+            Self::X(value) => ("X".to_string(), *value as u16, cpu.x as u16),
+            Self::Y(value) => ("Y".to_string(), *value as u16, cpu.y as u16),
+            Self::Pc(value) => ("PC".to_string(), *value, cpu.pc),
+            Self::Memory(address, value) => (
+                format!("0x{address:04X}"),
+                *value as u16,
+                cpu.memory.read_u8(*address) as u16,
+            ),
+            Self::Uart1(text) => {
+                let output = uart1.tx_lines().join("\n");
+                return if output.contains(text.as_str()) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "UART1 output does not contain {text:?}, got {output:?}"
+                    ))
+                };
+            }
+        };
+        if expected == actual {
+            Ok(())
+        } else {
+            Err(format!(
+                "expected {name} to be 0x{expected:02X}, got 0x{actual:02X}"
+            ))
+        }
     }
+}
 
-    let mut memory = MappedMemory::new();
-    memory.add_memory(0x0000, 0xA000, ram);
-    memory.add_memory(0xA000, 0x4000, propeller_ram);
-    memory.add_memory(0xE000, 0x2000, rom);
+impl FromStr for Expectation {
+    type Err = String;
 
-    let via = Via::default();
-    let key_state = Rc::clone(via.get_key_state());
-    memory.add_memory(0x9F00, 0x0100, via);
+    /// `a=N`, `x=N`, `y=N`, `pc=N`, `ADDRESS=N` or `uart1=TEXT`, numbers are decimal or hex with a `0x` prefix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected TARGET=VALUE, got {s:?}"))?;
+        let byte = || clap_num::maybe_hex::<u8>(value);
+        match target.to_ascii_lowercase().as_str() {
+            "a" => byte().map(Self::A),
+            "x" => byte().map(Self::X),
+            "y" => byte().map(Self::Y),
+            "pc" => clap_num::maybe_hex::<u16>(value).map(Self::Pc),
+            "uart1" => Ok(Self::Uart1(value.to_string())),
+            address => {
+                let address = clap_num::maybe_hex::<u16>(address).map_err(|_| {
+                    format!("unknown target {target:?}, expected a, x, y, pc, uart1 or an address")
+                })?;
+                byte().map(|value| Self::Memory(address, value))
+            }
+        }
+    }
+}
 
-    // TODO: better UART support
-    let uart1_data: Vec<u8> = if let Some(path) = uart1_source {
-        let path = path.as_ref();
-        info!(
-            "Loading UART1 source {}{}",
-            path.display(),
+/// Host side of the emulation, everything about the machine itself is set up by a [`MachineBuilder`]
+#[derive(Debug, Default)]
+pub struct FrontendOptions {
+    /// file used to fill the UART1 receive buffer
+    pub uart1_source: Option<PathBuf>,
+    /// file that receives all bytes transmitted on UART1, `-` for stdout
+    pub uart1_sink: Option<PathBuf>,
+    pub uart1_stdio: Option<UartStdioMode>,
+    pub uart1_xmodem: Option<XmodemTransfer>,
+    /// normalize the newlines of the UART1 source
+    pub fix_newlines: bool,
+    pub physical_keyboard: bool,
+    /// host keys of the two joysticks
+    pub joysticks: JoystickBindings,
+    /// host keys that run input macros
+    pub macros: Vec<InputMacro>,
+    /// start with autofire on the joystick fire buttons
+    pub autofire: bool,
+    pub warp: Warp,
+    pub palette: vid::Palette,
+    pub display_filter: DisplayFilter,
+    pub aspect_ratio: AspectRatio,
+    /// how much of the border around the content area is shown
+    pub border: Border,
+    pub fullscreen: bool,
+    /// integer scale of the picture, the window snaps to multiples of it when resized
+    pub scale: Option<u32>,
+    pub limits: RunLimits,
+    pub expectations: Vec<Expectation>,
+    pub control_address: Option<String>,
+    pub record_input: Option<PathBuf>,
+    pub playback_input: Option<PathBuf>,
+    /// CSV file for the memory access profile, written at exit
+    pub profile: Option<PathBuf>,
+    /// PPM file for the memory access heatmap, written at exit
+    pub heatmap: Option<PathBuf>,
+    /// listing of the loaded binary with the executed instructions marked, written at exit
+    pub coverage: Option<PathBuf>,
+    /// flat profile and call graph of the subroutines, written at exit
+    pub call_profile: Option<PathBuf>,
+    /// CSV file for the most recent device register accesses, written at exit
+    pub device_log: Option<PathBuf>,
+    /// names of addresses in the coverage report, the call profile and the device log
+    pub symbols: HashMap<u16, String>,
+    /// CodyBASIC program typed on the keyboard after boot
+    pub basic: Option<PathBuf>,
+    /// binary of a second machine whose UART2 is linked to this one
+    pub uart2_link: Option<PathBuf>,
+    /// cycles a byte takes through the UART2 link
+    pub link_latency: usize,
+    /// binary loaded into the machine, named in the window title and when the machine rejects it
+    pub binary: PathBuf,
+    #[cfg(feature = "metrics")]
+    pub metrics_address: Option<String>,
+    pub display: DisplayMode,
+}
+
+/// Runs the machine until the window is closed or a run limit is reached, UART1, UART2, the palette and the border are set on the builder here.
+///
+/// `machine` is called on the thread that runs the machine, because a [`Machine`] is not [`Send`].
+pub fn start(
+    machine: impl FnOnce() -> MachineBuilder + Send,
+    options: FrontendOptions,
+) -> Result<(), LoadError> {
+    let FrontendOptions {
+        uart1_source,
+        uart1_sink,
+        uart1_stdio,
+        uart1_xmodem,
+        fix_newlines,
+        physical_keyboard,
+        joysticks,
+        macros,
+        autofire,
+        warp,
+        palette,
+        display_filter,
+        aspect_ratio,
+        border,
+        fullscreen,
+        scale,
+        limits,
+        expectations,
+        control_address,
+        record_input,
+        playback_input,
+        profile,
+        heatmap,
+        coverage,
+        call_profile,
+        device_log,
+        symbols,
+        basic,
+        uart2_link,
+        link_latency,
+        binary,
+        #[cfg(feature = "metrics")]
+        metrics_address,
+        display,
+    } = options;
+    // the machine is not Send, it is built on the thread that runs it
+    let build = || {
+        // TODO: better UART support
+        let uart1_data: Vec<u8> = if let Some(path) = uart1_source {
+            info!(
+                "Loading UART1 source {}{}",
+                path.display(),
+                if fix_newlines {
+                    " with fixed newlines"
+                } else {
+                    ""
+                }
+            );
             if fix_newlines {
-                " with fixed newlines"
+                fix_newlines_in(BufReader::new(
+                    File::open(&path).map_err(|e| LoadError::io(&path, e))?,
+                ))
             } else {
-                ""
+                read_file(&path)?
             }
-        );
-        if fix_newlines {
-            let mut data = vec![];
-            let f = File::open(path).expect("error opening uart1 data file");
-            let r = BufReader::new(f);
-            for l in r.lines().map_while(Result::ok).filter(|l| !l.is_empty()) {
-                data.extend(l.bytes());
-                data.push(b'\n');
-            }
-            // CodyBASIC requires an empty line to terminate the LOAD command
-            data.push(b'\n');
-            data
         } else {
-            std::fs::read(path).expect("error reading uart1 data file")
+            vec![]
+        };
+        let mut uart1_source = UartSource::new(uart1_data);
+        if let Some(mode) = uart1_stdio {
+            info!("Bridging UART1 to stdin/stdout ({mode:?})");
+            uart1_source = uart1_source.with_stdin(mode);
         }
-    } else {
-        vec![]
-    };
-    let uart1 = Uart::new(UartSource::new(uart1_data));
-    let (_uart1_rx, _uart1_tx) = (
-        Rc::clone(uart1.get_receive_buffer()),
-        Rc::clone(uart1.get_transmit_buffer()),
-    );
-    memory.add_memory(UART1_BASE, UART_END, uart1);
-    let uart2 = Uart::new(UartSource::empty());
-    let (_uart2_rx, _uart2_tx) = (
-        Rc::clone(uart2.get_receive_buffer()),
-        Rc::clone(uart2.get_transmit_buffer()),
-    );
-    memory.add_memory(UART2_BASE, UART_END, uart2);
+        // BASIC programs dropped onto the window
+        let (uart1_input, uart1_input_rx) = mpsc::channel();
+        uart1_source = uart1_source.with_stream(uart1_input_rx);
+        let uart1_sink = if let Some(mode) = uart1_stdio {
+            UartSink::stdout(mode)
+        } else if let Some(path) = uart1_sink {
+            if path == Path::new("-") {
+                info!("Writing UART1 output to stdout");
+                UartSink::new(std::io::stdout())
+            } else {
+                info!("Writing UART1 output to {}", path.display());
+                UartSink::new(BufWriter::new(
+                    File::create(&path).map_err(|e| LoadError::io(&path, e))?,
+                ))
+            }
+        } else {
+            UartSink::discard()
+        };
+        let mut xmodem_receiver = None;
+        let (uart1_source, uart1_sink) = match uart1_xmodem {
+            Some(XmodemTransfer::Send(path)) => {
+                info!("Sending {} over XMODEM on UART1", path.display());
+                let data = read_file(&path)?;
+                let (tx, rx) = mpsc::channel();
+                (
+                    UartSource::empty().with_stream(rx),
+                    UartSink::new(XmodemSender::new(data, tx)),
+                )
+            }
+            Some(XmodemTransfer::Receive(path)) => {
+                info!("Receiving {} over XMODEM on UART1", path.display());
+                let (tx, rx) = mpsc::channel();
+                let receiver = Rc::new(RefCell::new(XmodemReceiver::new(path, tx)));
+                xmodem_receiver = Some(Rc::clone(&receiver));
+                (
+                    UartSource::empty().with_stream(rx),
+                    UartSink::shared(receiver),
+                )
+            }
+            None => (uart1_source, uart1_sink),
+        };
+
+        // lines typed into the serial console of the debugger
+        #[cfg_attr(not(feature = "debugger"), allow(unused_variables))]
+        let (uart2_input, uart2_input_rx) = mpsc::channel();
+        let mut remote = None;
+        let (uart2_source, uart2_sink) = if uart2_link.is_some() {
+            let [local, peer] = UartBackend::link(link_latency);
+            remote = Some(peer);
+            local.into_parts()
+        } else {
+            (UartSource::empty(), UartSink::discard())
+        };
+        // the framebuffer of the machine looks like the window
+        let builder = machine()
+            .uart1(uart1_source, uart1_sink)
+            .uart2(uart2_source.with_stream(uart2_input_rx), uart2_sink)
+            .palette(palette)
+            .border(border);
 
-    memory.add_memory(0xD000, 0x1, BlankingRegister::default());
+        let mut machine = builder.build().map_err(|e| LoadError::binary(&binary, e))?;
+        let timing = *machine.timing();
 
-    let mut app = App {
-        state: None,
-        cpu: Cpu::new(memory),
-        keyboard: Keyboard::new(
-            if physical_keyboard {
-                KeyboardEmulation::Physical
+        // the peer runs with default settings, only its binary and the timing are chosen
+        let peer = uart2_link.zip(remote).map(|(path, remote)| {
+            let data = read_file(&path)?;
+            let (source, sink) = remote.into_parts();
+            let peer = Machine::builder().uart2(source, sink).timing(timing);
+            let peer = if is_cartridge(&path, &data) {
+                peer.cartridge(data)
             } else {
-                KeyboardEmulation::Logical
+                peer.rom(data)
+            };
+            info!(
+                "Linking UART2 to a second machine running {}",
+                path.display()
+            );
+            peer.build().map_err(|e| LoadError::binary(&path, e))
+        });
+        let peer = peer.transpose()?;
+        let key_state = Rc::clone(&machine.devices().key_state);
+        let uart1_activity = Rc::clone(&machine.devices().uart1_activity);
+        let renderer = machine.scanline_renderer();
+
+        let control = control_address
+            .as_deref()
+            .map(|address| ControlServer::bind(address).map_err(|e| LoadError::socket(address, e)))
+            .transpose()?;
+        #[cfg(feature = "metrics")]
+        let metrics = metrics_address
+            .as_deref()
+            .map(|address| MetricsServer::bind(address).map_err(|e| LoadError::socket(address, e)))
+            .transpose()?;
+
+        let playback = playback_input
+            .map(|path| {
+                info!("Replaying input from {}", path.display());
+                let recording = File::open(&path)
+                    .map(BufReader::new)
+                    .and_then(InputRecording::read_from)
+                    .map_err(|e| LoadError::io(&path, e))?;
+                machine.restore(&recording.snapshot);
+                Ok::<_, LoadError>(InputPlayer::new(recording.events))
+            })
+            .transpose()?;
+        let typist = basic
+            .map(|path| {
+                info!("Typing {} after boot", path.display());
+                let text = std::fs::read_to_string(&path).map_err(|e| LoadError::io(&path, e))?;
+                Ok::<_, LoadError>(Typist::new(&text, machine.cpu.cycle() + BASIC_BOOT_CYCLES))
+            })
+            .transpose()?;
+        let recording = record_input.map(|path| {
+            info!("Recording input to {}", path.display());
+            let mut recording = InputRecording::new(machine.snapshot());
+            recording.record(machine.cpu.cycle(), &key_state.borrow());
+            (path.to_path_buf(), recording)
+        });
+        #[cfg(feature = "debugger")]
+        let panels =
+            Panels::with_symbols(symbols).with_uart_inputs(uart1_input.clone(), uart2_input);
+
+        let mut keyboard = Keyboard::new(key_state);
+        keyboard.set_macros(macros.iter().map(|m| m.steps.clone()).collect());
+        keyboard.set_autofire(autofire);
+
+        Ok(Emulator {
+            machine,
+            keyboard,
+            host_keys: [false; CodyKeyCode::COUNT],
+            warp,
+            last_rx_frame: None,
+            last_rx_bytes: 0,
+            skipped_frames: 0,
+            speed: DEFAULT_SPEED,
+            last_frame_start: Instant::now(),
+            limits,
+            frames: 0,
+            last_frame_cycles: 0,
+            last_frame_time: Duration::ZERO,
+            stopped_by: None,
+            control,
+            uart1_input,
+            renderer,
+            rewind: Rewind::default(),
+            rewinding: false,
+            #[cfg(feature = "metrics")]
+            metrics,
+            uart1_activity,
+            recording,
+            playback,
+            typist,
+            xmodem_receiver,
+            peer,
+            window: None,
+            quit: false,
+            status: Status {
+                program: binary
+                    .file_name()
+                    .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+                ..Status::default()
             },
-            key_state,
-        ),
-        fast,
-        last_frame_start: Instant::now(),
-        input: WinitInputHelper::new(),
+            status_start: (Instant::now(), 0, 0),
+            #[cfg(feature = "debugger")]
+            panels,
+        })
+    };
+
+    // writes the reports of a finished run, returns the exit code if a run limit was reached
+    let finish = |mut emulator: Emulator| {
+        report_illegal_opcodes(&emulator.machine.cpu);
+
+        let mut failed = false;
+        if let Some((path, recording)) = &emulator.recording {
+            info!(
+                "Saving {} input events to {}",
+                recording.events.len(),
+                path.display()
+            );
+            failed |= !write_report(path, "input recording", |w| recording.write_to(w));
+        }
+
+        if let Some(path) = profile {
+            info!("Saving memory access profile to {}", path.display());
+            failed |= !write_report(&path, "memory access profile", |w| {
+                emulator.machine.profile().unwrap().write_csv(w)
+            });
+        }
+        if let Some(path) = heatmap {
+            info!("Saving memory access heatmap to {}", path.display());
+            failed |= !write_report(&path, "memory access heatmap", |w| {
+                emulator.machine.profile().unwrap().write_heatmap(w)
+            });
+        }
+        if let Some(path) = coverage {
+            info!("Saving coverage report to {}", path.display());
+            let image = emulator.machine.image();
+            failed |= !write_report(&path, "coverage report", |w| {
+                emulator.machine.coverage().unwrap().write_report(
+                    image.data(),
+                    image.load_address(),
+                    &symbols,
+                    w,
+                )
+            });
+        }
+        if let Some(path) = call_profile {
+            info!("Saving call profile to {}", path.display());
+            failed |= !write_report(&path, "call profile", |w| {
+                emulator
+                    .machine
+                    .call_profiler()
+                    .unwrap()
+                    .write_report(&symbols, w)
+            });
+        }
+        if let Some(path) = device_log {
+            info!("Saving device log to {}", path.display());
+            let mut names = cody_addresses();
+            names.extend(symbols.clone());
+            failed |= !write_report(&path, "device log", |w| {
+                emulator.machine.device_log().unwrap().write_csv(&names, w)
+            });
+        }
+
+        let Some(reason) = emulator.stopped_by else {
+            // a report that could not be written fails the run
+            return failed.then_some(1);
+        };
+        if limits.dump_state {
+            println!("{}", emulator.machine.cpu);
+            for (address, port_a, port_b) in &emulator.machine.devices().expansion_ports {
+                println!(
+                    "VIA 0x{address:04X} PA:0x{:02X} PB:0x{:02X}",
+                    port_a.borrow().lines(),
+                    port_b.borrow().lines()
+                );
+            }
+        }
+        for expectation in expectations {
+            if let Err(e) =
+                expectation.check(&mut emulator.machine, &emulator.uart1_activity.borrow())
+            {
+                error!("Expectation failed: {e}");
+                failed = true;
+            }
+        }
+        let exit_code = limits.exit_code(reason, &emulator.machine.cpu);
+        // flush all sinks before exiting
+        drop(emulator);
+        Some(if failed && reason != StopReason::Timeout {
+            1
+        } else {
+            exit_code
+        })
+    };
+
+    let exit_code = match display {
+        DisplayMode::Window => {
+            info!("Starting event loop");
+            let event_loop = EventLoop::with_user_event()
+                .build()
+                .expect("event loop created");
+            event_loop.set_control_flow(ControlFlow::Wait);
+            let proxy = event_loop.create_proxy();
+            let (commands, commands_rx) = mpsc::channel();
+            let frame = Arc::new(Mutex::new(FrameSlot::default()));
+            let link = WindowLink {
+                commands: commands_rx,
+                frame: Arc::clone(&frame),
+                proxy: proxy.clone(),
+            };
+            std::thread::scope(|scope| {
+                let emulation = std::thread::Builder::new()
+                    .name("emulation".to_string())
+                    .spawn_scoped(scope, move || {
+                        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                            let mut emulator = build()?;
+                            emulator.window = Some(link);
+                            emulator.run_threaded();
+                            Ok(finish(emulator))
+                        }));
+                        // the window may already be closed
+                        let _ = proxy.send_event(EmulationEvent::Stopped);
+                        result
+                    })
+                    .expect("emulation thread started");
+
+                let mut app = App {
+                    state: None,
+                    input: WinitInputHelper::new(),
+                    keyboard_emulation: if physical_keyboard {
+                        KeyboardEmulation::Physical
+                    } else {
+                        KeyboardEmulation::Logical
+                    },
+                    joysticks,
+                    macro_keys: macros.iter().map(|m| m.key.0).collect(),
+                    held: [false; CodyKeyCode::COUNT],
+                    pointer: (0, 0, 0),
+                    presenter: Presenter::new(display_filter, aspect_ratio, border),
+                    fullscreen,
+                    scale,
+                    commands,
+                    frame,
+                    pixels: vec![],
+                    title: "Cody".to_string(),
+                };
+                event_loop.run_app(&mut app).expect("application running");
+                // closing the command channel stops the emulation thread
+                drop(app);
+                emulation
+                    .join()
+                    .expect("emulation thread joined")
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })?
+        }
+        DisplayMode::Headless => {
+            info!("Running headless");
+            let mut emulator = build()?;
+            emulator.run_headless(|_| true);
+            finish(emulator)
+        }
+        #[cfg(feature = "tui")]
+        DisplayMode::Dashboard(logs) => {
+            info!("Running headless with dashboard");
+            let mut emulator = build()?;
+            let mut dashboard = Dashboard::new(logs);
+            emulator.run_headless(|emulator| dashboard.update(&emulator.dashboard_stats()));
+            finish(emulator)
+        }
     };
 
-    info!("Starting event loop");
-    let event_loop = EventLoop::new().expect("event loop created");
-    event_loop.set_control_flow(ControlFlow::Poll);
-    event_loop.run_app(&mut app).expect("application running");
+    if let Some(exit_code) = exit_code {
+        std::process::exit(exit_code);
+    }
+    Ok(())
 }
 
-struct App<M> {
-    state: Option<State>,
-    cpu: Cpu<M>,
+/// A file or socket needed to start the emulator could not be opened
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// the machine rejected the binary, e.g. because of an invalid cartridge header
+    #[error("{}: {message}", path.display())]
+    Binary {
+        path: PathBuf,
+        /// offset in the file the error is about
+        offset: Option<usize>,
+        message: String,
+    },
+    /// the control socket or the metrics endpoint could not listen on its address
+    #[error("{address}: {source}")]
+    Socket {
+        address: String,
+        source: std::io::Error,
+    },
+}
+
+impl LoadError {
+    fn io(path: &Path, source: std::io::Error) -> Self {
+        Self::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    fn binary(path: &Path, error: ImageError) -> Self {
+        Self::Binary {
+            path: path.to_path_buf(),
+            offset: error.offset,
+            message: error.message,
+        }
+    }
+
+    fn socket(address: &str, source: std::io::Error) -> Self {
+        Self::Socket {
+            address: address.to_string(),
+            source,
+        }
+    }
+}
+
+/// Writes a report of a finished run, logs the error if it could not be written
+fn write_report(
+    path: &Path,
+    name: &str,
+    write: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+) -> bool {
+    let result = File::create(path).and_then(|f| {
+        let mut w = BufWriter::new(f);
+        write(&mut w)?;
+        w.flush()
+    });
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Error writing {name} to {}: {e}", path.display());
+            false
+        }
+    }
+}
+
+/// Reads a file needed to start the emulator
+pub fn read_file(path: &Path) -> Result<Vec<u8>, LoadError> {
+    std::fs::read(path).map_err(|e| LoadError::io(path, e))
+}
+
+fn report_illegal_opcodes<M: Memory>(cpu: &Cpu<M>) {
+    let illegal_opcodes = cpu.illegal_opcodes();
+    if illegal_opcodes.is_empty() {
+        return;
+    }
+
+    let total: usize = illegal_opcodes.values().sum();
+    warn!(
+        "Encountered {total} illegal opcode(s): {}",
+        illegal_opcodes
+            .iter()
+            .map(|(byte, count)| format!("0x{byte:02X} ({count}x)"))
+            .join(", ")
+    );
+}
+
+/// Whether a binary should be loaded as a cartridge, because of its extension or a matching header
+fn is_cartridge(path: &Path, data: &[u8]) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("cart"))
+        || cartridge_header(data).is_ok_and(|(_, len)| len + 4 == data.len())
+}
+
+/// Normalize line endings and drop empty lines of a text for CodyBASIC's LOAD 1,0 command
+fn fix_newlines_in(r: impl BufRead) -> Vec<u8> {
+    let mut data = vec![];
+    for l in r.lines().map_while(Result::ok).filter(|l| !l.is_empty()) {
+        data.extend(l.bytes());
+        data.push(b'\n');
+    }
+    // CodyBASIC requires an empty line to terminate the LOAD command
+    data.push(b'\n');
+    data
+}
+
+/// Machine and everything that runs alongside it, on the emulation thread when there is a window
+struct Emulator {
+    machine: Machine,
     keyboard: Keyboard,
-    fast: bool,
+    /// cody keys held on the host as of the last update of the window
+    host_keys: [bool; CodyKeyCode::COUNT],
+    warp: Warp,
+    /// frame in which UART1 last received data, for automatic warp
+    last_rx_frame: Option<usize>,
+    last_rx_bytes: usize,
+    skipped_frames: u32,
+    /// index into [`SPEEDS`] used outside of warp mode
+    speed: usize,
     last_frame_start: Instant,
+    limits: RunLimits,
+    frames: usize,
+    last_frame_cycles: usize,
+    last_frame_time: Duration,
+    /// set when a run limit was reached or the program exited
+    stopped_by: Option<StopReason>,
+    control: Option<ControlServer>,
+    uart1_input: mpsc::Sender<u8>,
+    renderer: vid::ScanlineRenderer,
+    rewind: Rewind,
+    rewinding: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Option<MetricsServer>,
+    uart1_activity: Rc<RefCell<UartActivity>>,
+    recording: Option<(PathBuf, InputRecording)>,
+    playback: Option<InputPlayer>,
+    typist: Option<Typist>,
+    /// download on UART1, polled to repeat its request until the program starts sending
+    xmodem_receiver: Option<Rc<RefCell<XmodemReceiver>>>,
+    /// second machine linked to UART2, kept in step with the first one
+    peer: Option<Machine>,
+    window: Option<WindowLink>,
+    /// the window closed
+    quit: bool,
+    status: Status,
+    /// start of the current status interval, the cycle and the published frames since then
+    status_start: (Instant, usize, u32),
+    #[cfg(feature = "debugger")]
+    panels: Panels,
+}
+
+/// Requests of the window to the emulation thread
+enum Command {
+    /// cody keys held on the host, only sent when they change
+    Keys([bool; CodyKeyCode::COUNT]),
+    /// host pointer over the screen content in multicolor pixels and its buttons, only sent when it changes
+    Pointer(i32, i32, u8),
+    TogglePause,
+    /// run the paused machine until the end of the frame
+    AdvanceFrame,
+    WarmReset,
+    HardReset,
+    Nmi,
+    /// step back through the rewind buffer while true
+    Rewind(bool),
+    SlowDown,
+    SpeedUp,
+    ToggleWarp,
+    ToggleAutofire,
+    /// run the input macro with this index
+    RunMacro(usize),
+    /// a file was dropped onto the window
+    Load(PathBuf),
+    /// run the debugger panels with this input
+    #[cfg(feature = "debugger")]
+    Debugger(egui::Context, egui::RawInput),
+    Quit,
+}
+
+/// Notifications of the emulation thread to the window
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum EmulationEvent {
+    /// a new frame is waiting in the [`FrameSlot`]
+    Frame,
+    /// the emulation ended, e.g. because a run limit was reached
+    Stopped,
+}
+
+/// State of the emulation shown in the window title
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+struct Status {
+    /// file name of the loaded binary or cartridge
+    program: String,
+    /// emulated time in percent of real time
+    speed: u32,
+    /// frames presented per second
+    fps: u32,
+    paused: bool,
+    warping: bool,
+    breakpoint_hit: Option<u16>,
+}
+
+impl Status {
+    fn title(&self) -> String {
+        let mut title = "Cody".to_string();
+        if !self.program.is_empty() {
+            title += &format!(" - {}", self.program);
+        }
+        if let Some(address) = self.breakpoint_hit {
+            title += &format!(" - breakpoint at 0x{address:04X}");
+        } else if self.paused {
+            title += " - paused";
+        } else {
+            let warp = if self.warping { "warp " } else { "" };
+            title += &format!(" - {warp}{}% - {} fps", self.speed, self.fps);
+        }
+        title
+    }
+}
+
+/// Latest frame of the emulation thread, overwritten if the window did not present it in time
+#[derive(Default)]
+struct FrameSlot {
+    pixels: Vec<vid::Color>,
+    warping: bool,
+    /// window title
+    title: String,
+    /// the frame was not presented yet
+    fresh: bool,
+    /// output of all runs of the debugger panels since the last presented frame
+    #[cfg(feature = "debugger")]
+    debugger: Option<egui::FullOutput>,
+}
+
+/// Connection of the emulation thread to the window
+struct WindowLink {
+    commands: mpsc::Receiver<Command>,
+    frame: Arc<Mutex<FrameSlot>>,
+    proxy: EventLoopProxy<EmulationEvent>,
+}
+
+/// The window, it only forwards input to the emulation thread and presents its frames
+struct App {
+    state: Option<State>,
     input: WinitInputHelper,
+    keyboard_emulation: KeyboardEmulation,
+    joysticks: JoystickBindings,
+    /// host keys that run the macro with the same index
+    macro_keys: Vec<KeyCode>,
+    /// cody keys last sent to the emulation thread
+    held: [bool; CodyKeyCode::COUNT],
+    /// pointer last sent to the emulation thread
+    pointer: (i32, i32, u8),
+    presenter: Presenter,
+    fullscreen: bool,
+    /// integer scale of the picture, the window snaps to multiples of it when resized
+    scale: Option<u32>,
+    commands: mpsc::Sender<Command>,
+    frame: Arc<Mutex<FrameSlot>>,
+    /// frame being presented, it takes turns with the one in the slot
+    pixels: Vec<vid::Color>,
+    /// title last set on the window
+    title: String,
 }
 
 struct State {
     pixels: Pixels<'static>,
     window: Arc<Window>,
+    #[cfg(feature = "debugger")]
+    debugger: Debugger,
+}
+
+impl Emulator {
+    /// While recording or replaying input, hotkeys and dropped files that change the machine state are ignored
+    const fn is_replay_active(&self) -> bool {
+        self.recording.is_some() || self.playback.is_some()
+    }
+
+    fn is_warping(&self) -> bool {
+        self.warp.enabled
+            || (self.warp.auto
+                && self
+                    .last_rx_frame
+                    .is_some_and(|frame| self.frames - frame < AUTO_WARP_FRAMES))
+    }
+
+    /// Execute one instruction and render the rows the beam reached
+    fn step_instruction(&mut self) -> u8 {
+        if let Some(playback) = &mut self.playback {
+            playback.apply(
+                self.machine.cpu.cycle(),
+                &mut self.keyboard.key_state.borrow_mut(),
+            );
+            if playback.is_finished() {
+                info!("Input playback finished");
+                self.playback = None;
+            }
+        }
+        if let Some(typist) = &mut self.typist {
+            typist.apply(
+                self.machine.cpu.cycle(),
+                &mut self.keyboard.key_state.borrow_mut(),
+            );
+            if typist.is_finished() {
+                info!("Finished typing");
+                self.typist = None;
+            }
+        }
+        if let Some(receiver) = &self.xmodem_receiver {
+            let mut receiver = receiver.borrow_mut();
+            receiver.poll(
+                self.machine.cpu.cycle(),
+                self.machine.timing().cpu_frequency,
+            );
+            if receiver.is_done() {
+                drop(receiver);
+                self.xmodem_receiver = None;
+            }
+        }
+        let cycles = self.machine.step_instruction();
+        self.renderer
+            .update(&self.machine.propeller_ram(), self.machine.cpu.cycle());
+        if let Some(peer) = &mut self.peer {
+            while peer.cpu.is_running() && peer.cpu.cycle() < self.machine.cpu.cycle() {
+                peer.step_instruction();
+            }
+        }
+        cycles
+    }
+
+    /// Run the paused machine until the frame in progress is complete, stops early at breakpoints
+    fn advance_frame(&mut self) {
+        let frame_cycles = self.machine.timing().frame_cycles();
+        let end = (self.machine.cpu.cycle() / frame_cycles + 1) * frame_cycles;
+        self.machine.resume();
+        while !self.machine.is_paused()
+            && self.machine.cpu.is_running()
+            && self.machine.cpu.cycle() < end
+        {
+            self.step_instruction();
+        }
+        self.machine.pause();
+        self.rewind.record(&mut self.machine);
+    }
+
+    /// Emulate one frame worth of cycles, returns the reason if a run limit was reached
+    fn step_frame(&mut self) -> Option<StopReason> {
+        let timing = *self.machine.timing();
+        let frame_duration = timing.frame_duration();
+
+        if self.rewinding {
+            let elapsed = self.last_frame_start.elapsed();
+            if elapsed < frame_duration {
+                self.wait(frame_duration - elapsed);
+            }
+            self.last_frame_start = Instant::now();
+            if self.rewind.step_back(&mut self.machine) {
+                self.renderer.redraw(&self.machine.propeller_ram());
+            }
+            return None;
+        }
+
+        let max_cycles = self
+            .limits
+            .max_cycles
+            .into_iter()
+            .chain(self.limits.timeout_cycles)
+            .min()
+            .unwrap_or(usize::MAX);
+        let mut total_cycles = 0;
+        let mut total_instructions = 0usize;
+        // a paused machine is paced like normal to not spin
+        let frame_time = if self.is_warping() && !self.machine.is_paused() {
+            // without a factor the frame is filled with as many cycles as possible
+            let frame_cycles = match self.warp.factor {
+                0 => usize::MAX,
+                factor => factor as usize * timing.frame_cycles(),
+            };
+            while self.machine.cpu.is_running()
+                && self.machine.cpu.cycle() < max_cycles
+                && total_cycles < frame_cycles
+                && self.last_frame_start.elapsed() < frame_duration
+            {
+                total_cycles += self.step_instruction() as usize;
+                total_instructions += 1;
+            }
+            let elapsed = self.last_frame_start.elapsed();
+            if elapsed < frame_duration && self.warp.factor > 0 {
+                self.wait(frame_duration - elapsed);
+            }
+            let elapsed = self.last_frame_start.elapsed();
+            self.last_frame_start = Instant::now();
+            elapsed
+        } else {
+            // sleep to get to the frame rate of the video standard
+            let elapsed = self.last_frame_start.elapsed();
+            if elapsed < frame_duration {
+                self.wait(frame_duration - elapsed);
+            }
+
+            let now = Instant::now();
+            let realtime_elapsed = now - self.last_frame_start;
+            self.last_frame_start = now;
+            let emulated_elapsed = realtime_elapsed * SPEEDS[self.speed] / 100;
+            let mut catchup = Duration::ZERO;
+            while !self.machine.is_paused()
+                && self.machine.cpu.is_running()
+                && self.machine.cpu.cycle() < max_cycles
+                && catchup < emulated_elapsed
+            {
+                let cycles = self.step_instruction();
+                total_cycles += cycles as usize;
+                total_instructions += 1;
+                catchup += timing.cycles_duration(cycles as usize);
+            }
+
+            realtime_elapsed
+        };
+        trace!(
+            "frame time: {frame_time:?}, instructions: {total_instructions}, cycles: {total_cycles}"
+        );
+
+        if !self.machine.is_paused() {
+            self.rewind.record(&mut self.machine);
+        }
+        let rx_bytes = self.uart1_activity.borrow().rx_bytes;
+        if rx_bytes != self.last_rx_bytes {
+            self.last_rx_bytes = rx_bytes;
+            self.last_rx_frame = Some(self.frames);
+        }
+        self.frames += 1;
+        self.last_frame_cycles = total_cycles;
+        self.last_frame_time = frame_time;
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            let uart1 = self.uart1_activity.borrow();
+            metrics.update(MetricsSample {
+                cycles: self.machine.cpu.cycle(),
+                frames: self.frames,
+                irqs: self.machine.cpu.irq_count(),
+                nmis: self.machine.cpu.nmi_count(),
+                uart1_rx_bytes: uart1.rx_bytes,
+                uart1_tx_bytes: uart1.tx_bytes,
+            });
+        }
+        if let Some(code) = self.machine.host_exit() {
+            return Some(StopReason::Exited(code));
+        }
+        self.limits.reached(&self.machine.cpu, self.frames)
+    }
+
+    /// Reset the machine and load a file dropped onto the window.
+    ///
+    /// `.bas` files are queued on UART1 for `LOAD 1,0`, everything else is loaded as a binary,
+    /// as a cartridge if it has a matching header.
+    fn load_dropped(&mut self, path: &Path) {
+        let is_basic = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("bas"));
+        if is_basic {
+            let data = match File::open(path) {
+                Ok(f) => fix_newlines_in(BufReader::new(f)),
+                Err(e) => {
+                    warn!("Error opening dropped file {}: {e}", path.display());
+                    return;
+                }
+            };
+            info!(
+                "Queued {} on UART1, use LOAD 1,0 to load it",
+                path.display()
+            );
+            self.machine.warm_reset();
+            if data.into_iter().any(|b| self.uart1_input.send(b).is_err()) {
+                warn!("UART1 is not accepting dropped files");
+            }
+            return;
+        }
+
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Error reading dropped file {}: {e}", path.display());
+                return;
+            }
+        };
+        let as_cartridge = is_cartridge(path, &data);
+        info!(
+            "Loading dropped file {}{}",
+            path.display(),
+            if as_cartridge { " as cartridge" } else { "" }
+        );
+        let image = match Image::new(data, as_cartridge, None) {
+            Ok(image) => image,
+            Err(e) => {
+                warn!("Error loading dropped file {}: {e}", path.display());
+                return;
+            }
+        };
+
+        if let Some(name) = path.file_name() {
+            self.status.program = name.to_string_lossy().into_owned();
+        }
+        self.machine.load(image);
+    }
+
+    /// Sleep for `duration`, commands of the window are handled in the meantime
+    fn wait(&mut self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while !self.quit {
+            let Some(window) = &self.window else {
+                sleep(deadline.saturating_duration_since(Instant::now()));
+                return;
+            };
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match window.commands.recv_timeout(timeout) {
+                Ok(command) => self.handle(command),
+                Err(RecvTimeoutError::Timeout) => return,
+                Err(RecvTimeoutError::Disconnected) => self.quit = true,
+            }
+        }
+    }
+
+    /// Handle the commands the window sent since the last frame
+    fn handle_pending(&mut self) {
+        while !self.quit {
+            let Some(window) = &self.window else {
+                return;
+            };
+            match window.commands.try_recv() {
+                Ok(command) => self.handle(command),
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => self.quit = true,
+            }
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        let replay_active = self.is_replay_active();
+        match command {
+            Command::Keys(held) => self.host_keys = held,
+            Command::Pointer(x, y, buttons) => {
+                let devices = self.machine.devices();
+                if let Some(mouse) = &devices.mouse {
+                    *mouse.borrow_mut() = MouseState::at(x, y, buttons);
+                }
+                if let Some(pen) = &devices.light_pen {
+                    *pen.borrow_mut() = PenState::at(x, y, buttons & BUTTON_LEFT != 0);
+                }
+            }
+            Command::TogglePause => {
+                self.machine.toggle_pause();
+                info!(
+                    "{}",
+                    if self.machine.is_paused() {
+                        "Paused"
+                    } else {
+                        "Resumed"
+                    }
+                );
+            }
+            Command::AdvanceFrame => {
+                if self.machine.is_paused() {
+                    self.advance_frame();
+                }
+            }
+            Command::WarmReset if !replay_active => self.machine.warm_reset(),
+            Command::HardReset if !replay_active => self.machine.hard_reset(),
+            Command::Nmi if !replay_active => {
+                info!("NMI button pressed");
+                self.machine.trigger_nmi();
+            }
+            Command::Rewind(rewinding) if !replay_active => self.rewinding = rewinding,
+            Command::SlowDown => self.set_speed(self.speed.saturating_sub(1)),
+            Command::SpeedUp => self.set_speed((self.speed + 1).min(SPEEDS.len() - 1)),
+            Command::ToggleWarp => {
+                self.warp.enabled = !self.warp.enabled;
+                info!("Warp {}", if self.warp.enabled { "on" } else { "off" });
+            }
+            Command::ToggleAutofire => {
+                let autofire = !self.keyboard.autofire();
+                self.keyboard.set_autofire(autofire);
+                info!("Autofire {}", if autofire { "on" } else { "off" });
+            }
+            Command::RunMacro(index) => self.keyboard.run_macro(index),
+            Command::Load(_) if replay_active => {
+                warn!("Ignoring dropped file while recording or replaying input");
+            }
+            Command::Load(path) => self.load_dropped(&path),
+            #[cfg(feature = "debugger")]
+            Command::Debugger(context, input) => {
+                let output =
+                    self.panels
+                        .run(&context, input, &mut self.machine, self.renderer.palette());
+                if let Some(window) = &self.window {
+                    let mut frame = window.frame.lock().unwrap();
+                    match &mut frame.debugger {
+                        Some(pending) => pending.append(output),
+                        None => frame.debugger = Some(output),
+                    }
+                }
+            }
+            Command::Quit => self.quit = true,
+            // hotkeys that change the machine state while recording or replaying input
+            Command::WarmReset | Command::HardReset | Command::Nmi | Command::Rewind(_) => {}
+        }
+    }
+
+    /// Select an index into [`SPEEDS`]
+    fn set_speed(&mut self, speed: usize) {
+        if speed != self.speed {
+            self.speed = speed;
+            info!("Speed {}%", SPEEDS[speed]);
+        }
+    }
+
+    /// Emulate frames and hand them to the window until it closes or a run limit is reached
+    fn run_threaded(&mut self) {
+        loop {
+            self.handle_pending();
+            if self.quit {
+                return;
+            }
+
+            if self.playback.is_none() && self.typist.is_none() {
+                self.keyboard.update(self.host_keys);
+            }
+            if let Some((_, recording)) = &mut self.recording {
+                recording.record(self.machine.cpu.cycle(), &self.keyboard.key_state.borrow());
+            }
+
+            if let Some(reason) = self.step_frame() {
+                info!("Stopping emulation: {reason}");
+                self.stopped_by = Some(reason);
+                return;
+            }
+            self.publish_frame();
+        }
+    }
+
+    /// Hand the finished frame to the window, unless it is skipped while warping
+    fn publish_frame(&mut self) {
+        let warping = self.is_warping();
+        if warping && self.skipped_frames < self.warp.frame_skip {
+            self.skipped_frames += 1;
+            return;
+        }
+        self.skipped_frames = 0;
+
+        self.report_frame();
+        self.update_status(warping);
+        let Some(window) = &self.window else {
+            return;
+        };
+        {
+            let mut frame = window.frame.lock().unwrap();
+            frame.pixels.clear();
+            frame.pixels.extend_from_slice(self.renderer.frame());
+            frame.warping = warping;
+            frame.title = self.status.title();
+            frame.fresh = true;
+        }
+        // the window may already be closed
+        let _ = window.proxy.send_event(EmulationEvent::Frame);
+    }
+
+    /// Send the state after the last frame to the clients of the control socket
+    fn report_frame(&mut self) {
+        if let Some(control) = &mut self.control {
+            control.broadcast(FrameReport {
+                frame: self.frames,
+                cycle: self.machine.cpu.cycle(),
+                pc: self.machine.cpu.pc,
+                running: self.machine.cpu.is_running(),
+                hash: vid::hash_pixels(self.renderer.frame()),
+            });
+        }
+    }
+
+    /// Count a published frame, the speed and frame rate are measured again after every [`STATUS_INTERVAL`]
+    fn update_status(&mut self, warping: bool) {
+        let (start, start_cycle, frames) = &mut self.status_start;
+        *frames += 1;
+        let elapsed = start.elapsed();
+        if elapsed >= STATUS_INTERVAL {
+            let cycle = self.machine.cpu.cycle();
+            let seconds = elapsed.as_secs_f64();
+            let cpu_frequency = self.machine.timing().cpu_frequency as f64;
+            self.status.speed = (cycle.saturating_sub(*start_cycle) as f64 * 100.0
+                / (seconds * cpu_frequency))
+                .round() as u32;
+            self.status.fps = (*frames as f64 / seconds).round() as u32;
+            self.status_start = (Instant::now(), cycle, 0);
+        }
+        self.status.paused = self.machine.is_paused();
+        self.status.warping = warping;
+        self.status.breakpoint_hit = self.machine.breakpoint_hit();
+    }
+
+    /// Emulate frames without a window until a run limit is reached or `on_frame` returns false
+    fn run_headless(&mut self, mut on_frame: impl FnMut(&Self) -> bool) {
+        loop {
+            let reason = self.step_frame().or_else(|| {
+                self.machine
+                    .is_paused()
+                    .then_some(StopReason::Breakpoint(self.machine.cpu.pc))
+            });
+            if let Some(reason) = reason {
+                info!("Stopping emulation: {reason}");
+                self.stopped_by = Some(reason);
+                return;
+            }
+
+            self.report_frame();
+            if !on_frame(self) {
+                return;
+            }
+        }
+    }
+
+    #[cfg(feature = "tui")]
+    fn dashboard_stats(&self) -> DashboardStats {
+        let frame_seconds = self.last_frame_time.as_secs_f64();
+        DashboardStats {
+            running: self.machine.cpu.is_running(),
+            cycle: self.machine.cpu.cycle(),
+            frames: self.frames,
+            speed: if frame_seconds > 0.0 {
+                self.last_frame_cycles as f64
+                    / (frame_seconds * self.machine.timing().cpu_frequency as f64)
+            } else {
+                0.0
+            },
+            irqs: self.machine.cpu.irq_count(),
+            nmis: self.machine.cpu.nmi_count(),
+            uart1: self.uart1_activity.borrow().clone(),
+        }
+    }
+}
+
+impl App {
+    fn send(&self, command: Command) {
+        // the emulation thread stops by itself and notifies the window
+        let _ = self.commands.send(command);
+    }
+
+    /// Take the latest frame of the emulation thread, apply the filters to it and render it.
+    ///
+    /// The slot is only locked to swap the frame with the last presented one, so the emulation thread never waits
+    /// for the filters.
+    fn present(&mut self) {
+        let Some(state) = &mut self.state else {
+            return;
+        };
+
+        let mut frame = self.frame.lock().unwrap();
+        let fresh = std::mem::take(&mut frame.fresh);
+        let warping = frame.warping;
+        if fresh {
+            std::mem::swap(&mut frame.pixels, &mut self.pixels);
+            if frame.title != self.title {
+                self.title.clone_from(&frame.title);
+                state.window.set_title(&self.title);
+            }
+        }
+        #[cfg(feature = "debugger")]
+        let debugger_output = frame.debugger.take();
+        drop(frame);
+
+        if fresh {
+            let raw_pixels: &mut [vid::Color] = bytemuck::cast_slice_mut(state.pixels.frame_mut());
+            self.presenter.present(&self.pixels, raw_pixels);
+            if warping {
+                let width = self.presenter.buffer_size().0;
+                draw_warp_indicator(raw_pixels, width, width / self.presenter.frame_size().0);
+            }
+        }
+
+        #[cfg(feature = "debugger")]
+        {
+            if let Some(output) = debugger_output {
+                state.debugger.finish(&state.window, output);
+            }
+            if let Some((context, input)) = state.debugger.take_input(&state.window) {
+                let _ = self.commands.send(Command::Debugger(context, input));
+            }
+            let debugger = &mut state.debugger;
+            state
+                .pixels
+                .render_with(|encoder, render_target, context| {
+                    context.scaling_renderer.render(encoder, render_target);
+                    debugger.render(encoder, render_target, context);
+                    Ok(())
+                })
+                .expect("render error");
+        }
+        #[cfg(not(feature = "debugger"))]
+        state.pixels.render().expect("render error");
+    }
 }
 
-impl<M: Memory> ApplicationHandler for App<M> {
+impl ApplicationHandler<EmulationEvent> for App {
     fn new_events(&mut self, _: &ActiveEventLoop, _: StartCause) {
         self.input.step();
     }
 
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let (width, height) = self.presenter.picture_size();
+        // as large as fits three quarters of the screen in physical pixels, so it is not tiny on high-DPI screens
+        let scale = self.scale.or_else(|| {
+            let monitor = event_loop
+                .primary_monitor()
+                .or_else(|| event_loop.available_monitors().next())?;
+            let size = monitor.size();
+            Some(
+                self.presenter
+                    .fitting_scale((size.width * 3 / 4, size.height * 3 / 4)),
+            )
+        });
+        let attributes = Window::default_attributes()
+            .with_title(&self.title)
+            .with_min_inner_size(PhysicalSize::new(width, height))
+            .with_fullscreen(self.fullscreen.then_some(Fullscreen::Borderless(None)));
+        let attributes = match scale {
+            Some(scale) => {
+                attributes.with_inner_size(PhysicalSize::new(width * scale, height * scale))
+            }
+            // the scale factor of the screen is only applied to logical sizes
+            None => attributes.with_inner_size(LogicalSize::new(width * 2, height * 2)),
+        };
         let window = Arc::new(
             event_loop
-                .create_window(
-                    Window::default_attributes()
-                        .with_title("Cody")
-                        .with_min_inner_size(LogicalSize::new(WIDTH, HEIGHT)),
-                )
+                .create_window(attributes)
                 .expect("window created"),
         );
         let mut pixels = {
             let window_size = window.inner_size();
             let surface_texture =
                 SurfaceTexture::new(window_size.width, window_size.height, Arc::clone(&window));
-            Pixels::new(WIDTH, HEIGHT, surface_texture).expect("pixels framebuffer created")
+            let (width, height) = self.presenter.buffer_size();
+            Pixels::new(width, height, surface_texture).expect("pixels framebuffer created")
         };
-        pixels.set_scaling_mode(ScalingMode::Fill);
-        self.state = Some(State { window, pixels });
+        pixels.set_scaling_mode(self.presenter.scaling_mode());
+        self.state = Some(State {
+            #[cfg(feature = "debugger")]
+            debugger: Debugger::new(&window, &pixels),
+            window,
+            pixels,
+        });
+    }
+
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: EmulationEvent) {
+        match event {
+            EmulationEvent::Frame => {
+                if let Some(state) = &self.state {
+                    state.window.request_redraw();
+                }
+            }
+            EmulationEvent::Stopped => {
+                self.state = None;
+                event_loop.exit();
+            }
+        }
     }
 
     fn window_event(&mut self, _: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
-        if self.input.process_window_event(&event) {
-            let Some(state) = &mut self.state else {
-                return;
-            };
+        if let WindowEvent::DroppedFile(path) = &event {
+            self.send(Command::Load(path.clone()));
+        }
+        #[cfg(feature = "debugger")]
+        if let Some(state) = &mut self.state {
+            state.debugger.on_window_event(&state.window, &event);
+        }
 
-            let raw_pixels = state.pixels.frame_mut();
-            vid::render_pixels(&mut self.cpu.memory, bytemuck::cast_slice_mut(raw_pixels));
-            state.pixels.render().expect("render error");
+        if self.input.process_window_event(&event) {
+            self.present();
         }
     }
 
@@ -286,18 +1586,49 @@ impl<M: Memory> ApplicationHandler for App<M> {
         self.input.end_step();
 
         if self.input.close_requested() || self.input.destroyed() {
+            self.send(Command::Quit);
             // Drop GPU/surface resources while the event loop is still alive.
             self.state = None;
             event_loop.exit();
             return;
         }
 
-        self.keyboard.update(&self.input);
+        #[cfg(feature = "debugger")]
+        let typing = self
+            .state
+            .as_ref()
+            .is_some_and(|state| state.debugger.wants_keyboard_input());
+        #[cfg(not(feature = "debugger"))]
+        let typing = false;
+        if !typing {
+            let held = self.keyboard_emulation.held(&self.input, &self.joysticks);
+            if held != self.held {
+                self.held = held;
+                self.send(Command::Keys(held));
+            }
+            for (index, &key) in self.macro_keys.iter().enumerate() {
+                if self.input.key_pressed(key) {
+                    self.send(Command::RunMacro(index));
+                }
+            }
+        }
 
         let Some(state) = &mut self.state else {
             return;
         };
 
+        if self.input.key_pressed(KeyCode::Enter) && self.input.held_alt() {
+            self.fullscreen = state.window.fullscreen().is_none();
+            state
+                .window
+                .set_fullscreen(self.fullscreen.then_some(Fullscreen::Borderless(None)));
+        }
+
+        #[cfg(feature = "debugger")]
+        if self.input.key_pressed(KeyCode::F12) {
+            state.debugger.toggle();
+        }
+
         if let Some(size) = self.input.window_resized()
             && size.width > 0
             && size.height > 0
@@ -308,51 +1639,170 @@ impl<M: Memory> ApplicationHandler for App<M> {
                 .expect("framebuffer resized");
         }
 
-        const FPS: f64 = 60.0 / 1.001;
-        const FRAME_NANOS: f64 = FPS / 1000000000.0;
-        const FRAME_DURATION: Duration = Duration::from_nanos((1.0 / FRAME_NANOS) as u64);
-        const _: () = assert!(FRAME_DURATION.as_nanos() > 0);
+        #[cfg(feature = "debugger")]
+        let pointing = state.debugger.wants_pointer_input();
+        #[cfg(not(feature = "debugger"))]
+        let pointing = false;
+        if !pointing && let Some(cursor) = self.input.cursor() {
+            let (x, y) = state
+                .pixels
+                .window_pos_to_pixel(cursor)
+                .map_or_else(|outside| outside, |(x, y)| (x as isize, y as isize));
+            let (x, y) = self.presenter.content_position(x, y);
+            let buttons = [
+                (MouseButton::Left, BUTTON_LEFT),
+                (MouseButton::Right, BUTTON_RIGHT),
+                (MouseButton::Middle, BUTTON_MIDDLE),
+            ]
+            .into_iter()
+            .filter(|&(button, _)| self.input.mouse_held(button))
+            .fold(0, |buttons, (_, bit)| buttons | bit);
+            if (x, y, buttons) != self.pointer {
+                self.pointer = (x, y, buttons);
+                self.send(Command::Pointer(x, y, buttons));
+            }
+        }
 
-        let mut total_cycles = 0;
-        let mut total_instructions = 0usize;
-        let frame_time = if self.fast {
-            while self.last_frame_start.elapsed() < FRAME_DURATION {
-                total_cycles += self.cpu.step_instruction() as usize;
-                total_instructions += 1;
+        let hotkeys = [
+            (KeyCode::F5, Command::TogglePause),
+            (KeyCode::F6, Command::WarmReset),
+            (KeyCode::F7, Command::HardReset),
+            (KeyCode::F10, Command::Nmi),
+            (KeyCode::F2, Command::AdvanceFrame),
+            (KeyCode::F3, Command::SlowDown),
+            (KeyCode::F4, Command::SpeedUp),
+            (KeyCode::F9, Command::ToggleWarp),
+            (KeyCode::F11, Command::ToggleAutofire),
+        ];
+        for (key, command) in hotkeys {
+            if self.input.key_pressed(key) {
+                self.send(command);
             }
-            let elapsed = self.last_frame_start.elapsed();
-            self.last_frame_start = Instant::now();
-            elapsed
-        } else {
-            // sleep to get to ~60 fps
-            let elapsed = self.last_frame_start.elapsed();
-            if elapsed < FRAME_DURATION {
-                sleep(FRAME_DURATION - elapsed);
+        }
+        if self.input.key_pressed(KeyCode::F8) {
+            self.send(Command::Rewind(true));
+        } else if self.input.key_released(KeyCode::F8) {
+            self.send(Command::Rewind(false));
+        }
+    }
+}
+
+/// Draw a ">>" into the top left corner of a presented frame of `width` pixels per row, `scale` times the rendered frame
+fn draw_warp_indicator(target: &mut [vid::Color], width: u32, scale: u32) {
+    const SIZE: usize = 7;
+    let scale = scale as usize;
+    let width = width as usize;
+    for y in 0..SIZE {
+        // each arrow is a triangle pointing to the right
+        let length = SIZE / 2 + 1 - y.abs_diff(SIZE / 2);
+        for x in (0..length).flat_map(|x| [x, x + SIZE / 2 + 1]) {
+            for (dy, dx) in (0..scale).flat_map(|dy| (0..scale).map(move |dx| (dy, dx))) {
+                let index = ((y + 2) * scale + dy) * width + (x + 2) * scale + dx;
+                if let Some(pixel) = target.get_mut(index) {
+                    *pixel = vid::Color::WHITE;
+                }
             }
+        }
+    }
+}
 
-            const CYCLE_FREQUENCY: f64 = 1000000.0;
-            const CYCLE_FREQUENCY_NANOS: f64 = CYCLE_FREQUENCY / 1000000000.0;
-            const CYCLE_DURATION: Duration =
-                Duration::from_nanos((1.0 / CYCLE_FREQUENCY_NANOS) as u64);
-            const _: () = assert!(CYCLE_DURATION.as_nanos() > 0);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::contiguous::Contiguous;
 
-            let now = Instant::now();
-            let realtime_elapsed = now - self.last_frame_start;
-            self.last_frame_start = now;
-            let mut catchup = Duration::ZERO;
-            while catchup < realtime_elapsed {
-                let cycles = self.cpu.step_instruction();
-                total_cycles += cycles as usize;
-                total_instructions += 1;
-                catchup += CYCLE_DURATION * cycles as u32;
-            }
+    #[test]
+    fn test_status_title() {
+        let mut status = Status {
+            program: "game.cart".to_string(),
+            speed: 100,
+            fps: 60,
+            ..Status::default()
+        };
+        assert_eq!(status.title(), "Cody - game.cart - 100% - 60 fps");
+        status.warping = true;
+        status.speed = 850;
+        assert_eq!(status.title(), "Cody - game.cart - warp 850% - 60 fps");
+        status.paused = true;
+        assert_eq!(status.title(), "Cody - game.cart - paused");
+        status.breakpoint_hit = Some(0xE002);
+        assert_eq!(status.title(), "Cody - game.cart - breakpoint at 0xE002");
+        assert_eq!(Status::default().title(), "Cody - 0% - 0 fps");
+    }
 
-            realtime_elapsed
+    #[test]
+    fn test_parse_expectation() {
+        assert_eq!("a=0x42".parse(), Ok(Expectation::A(0x42)));
+        assert_eq!("PC=0xE010".parse(), Ok(Expectation::Pc(0xE010)));
+        assert_eq!("0x0200=7".parse(), Ok(Expectation::Memory(0x0200, 7)));
+        assert_eq!(
+            "uart1=READY".parse(),
+            Ok(Expectation::Uart1("READY".to_string()))
+        );
+        assert!("a=0x100".parse::<Expectation>().is_err());
+        assert!("z=1".parse::<Expectation>().is_err());
+        assert!("a".parse::<Expectation>().is_err());
+    }
+
+    #[test]
+    fn test_run_limits() {
+        let mut memory = Contiguous::new_ram(0x10000);
+        // LDA #$05, STP
+        memory.force_write_all(0x0200, &[0xA9, 0x05, 0xDB]);
+        memory.write_u16(0xFFFC, 0x0200);
+        let mut cpu = Cpu::new(memory);
+        let limits = RunLimits {
+            stp_exit_code: true,
+            timeout_cycles: Some(1000),
+            ..RunLimits::default()
         };
-        trace!(
-            "frame time: {frame_time:?}, instructions: {total_instructions}, cycles: {total_cycles}"
+        assert_eq!(limits.reached(&cpu, 0), None);
+        cpu.run();
+        let reason = limits.reached(&cpu, 0).unwrap();
+        assert_eq!(reason, StopReason::Halted);
+        assert_eq!(limits.exit_code(reason, &cpu), 5);
+        assert_eq!(limits.exit_code(StopReason::Exited(3), &cpu), 3);
+        assert_eq!(
+            limits.exit_code(StopReason::Timeout, &cpu),
+            TIMEOUT_EXIT_CODE
         );
+    }
+
+    #[test]
+    fn test_load_error() {
+        let error = read_file(Path::new("missing/program.bin")).unwrap_err();
+        assert!(matches!(error, LoadError::Io { .. }));
+        assert!(error.to_string().starts_with("missing/program.bin: "));
+
+        let error = LoadError::binary(
+            Path::new("program.cart"),
+            cartridge_header(&[0x00, 0xE0]).unwrap_err(),
+        );
+        assert_eq!(
+            error.to_string(),
+            "program.cart: cartridge header must be at least 4 bytes, the file has 2"
+        );
+        assert!(matches!(
+            error,
+            LoadError::Binary {
+                offset: Some(0),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_write_report() {
+        let path = std::env::temp_dir().join("cody_emulator_write_report.txt");
+        assert!(write_report(&path, "report", |w| w.write_all(b"report")));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "report");
+        std::fs::remove_file(&path).unwrap();
 
-        state.window.request_redraw();
+        assert!(!write_report(
+            Path::new("missing/report.txt"),
+            "report",
+            |w| w.write_all(b"report")
+        ));
+        assert!(!write_report(&std::env::temp_dir(), "report", |_| Ok(())));
     }
 }
@@ -1,3 +1,4 @@
+/// Levels of the IRQ and NMI lines driven by a device
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Interrupt {
     irq: bool,
@@ -47,3 +48,107 @@ impl Default for Interrupt {
         Self::none()
     }
 }
+
+/// Interrupt inputs of the cpu, sampled once per instruction.
+///
+/// IRQ is level triggered: it is serviced as long as a device holds the line and interrupts are enabled.
+/// NMI is edge triggered: a rising edge is latched until it is serviced, a held line does not trigger again.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct InterruptController {
+    irq: bool,
+    /// NMI line level at the last sample
+    nmi_line: bool,
+    nmi_pending: bool,
+    /// cycle of the sample that first saw the IRQ line held
+    irq_asserted: Option<usize>,
+    /// cycle of the sample that saw the last NMI edge
+    nmi_asserted: Option<usize>,
+}
+
+impl InterruptController {
+    /// Sample the combined lines of all devices at `cycle`
+    pub const fn sample(&mut self, lines: Interrupt, cycle: usize) {
+        if !lines.is_irq() {
+            self.irq_asserted = None;
+        } else if !self.irq {
+            self.irq_asserted = Some(cycle);
+        }
+        self.irq = lines.is_irq();
+        if lines.is_nmi() && !self.nmi_line {
+            self.nmi_pending = true;
+            self.nmi_asserted = Some(cycle);
+        }
+        self.nmi_line = lines.is_nmi();
+    }
+
+    /// Whether a device holds the IRQ line
+    pub const fn irq(&self) -> bool {
+        self.irq
+    }
+
+    /// Whether a device holds the NMI line
+    pub const fn nmi(&self) -> bool {
+        self.nmi_line
+    }
+
+    /// Whether an NMI edge was latched and not serviced yet
+    pub const fn nmi_pending(&self) -> bool {
+        self.nmi_pending
+    }
+
+    /// Cycle since which a device holds the IRQ line
+    pub const fn irq_asserted(&self) -> Option<usize> {
+        self.irq_asserted
+    }
+
+    /// Cycle of the last NMI edge, kept after it was serviced
+    pub const fn nmi_asserted(&self) -> Option<usize> {
+        self.nmi_asserted
+    }
+
+    /// Clear the latched NMI when it is serviced
+    pub const fn acknowledge_nmi(&mut self) {
+        self.nmi_pending = false;
+    }
+
+    /// Latch or clear an NMI edge, when restoring a snapshot
+    pub const fn set_nmi_pending(&mut self, nmi_pending: bool) {
+        self.nmi_pending = nmi_pending;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nmi_edge() {
+        let mut controller = InterruptController::default();
+        controller.sample(Interrupt::nmi(), 0);
+        assert!(controller.nmi_pending());
+        controller.acknowledge_nmi();
+        // a held line does not trigger again
+        controller.sample(Interrupt::nmi(), 1);
+        assert!(!controller.nmi_pending());
+        controller.sample(Interrupt::none(), 2);
+        controller.sample(Interrupt::nmi(), 3);
+        assert!(controller.nmi_pending());
+        // latched until serviced
+        controller.sample(Interrupt::none(), 4);
+        assert!(controller.nmi_pending());
+        assert_eq!(controller.nmi_asserted(), Some(3));
+    }
+
+    #[test]
+    fn test_irq_level() {
+        let mut controller = InterruptController::default();
+        controller.sample(Interrupt::irq(), 10);
+        assert!(controller.irq());
+        controller.sample(Interrupt::irq(), 20);
+        assert!(controller.irq());
+        assert_eq!(controller.irq_asserted(), Some(10));
+        controller.sample(Interrupt::none(), 30);
+        assert!(!controller.irq());
+        assert_eq!(controller.irq_asserted(), None);
+    }
+}
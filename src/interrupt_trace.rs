@@ -0,0 +1,197 @@
+use crate::device::blanking::VblankInterrupt;
+use crate::device::uart::{STAT_IRQ, UART_STAT, UART1_BASE, UART2_BASE};
+use crate::device::via::{VIA_IER, VIA_IFR};
+use crate::memory::Memory;
+use crate::symbols::VIA_BASE;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+
+/// Number of interrupts kept in [`InterruptTrace::log`]
+const LOG_SIZE: usize = 256;
+/// Names of the VIA interrupt flags from bit 0
+const VIA_FLAGS: [&str; 7] = ["CA2", "CA1", "SR", "CB2", "CB1", "timer 2", "timer 1"];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InterruptKind {
+    Irq,
+    Nmi,
+    Brk,
+}
+
+impl Display for InterruptKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Irq => "IRQ",
+            Self::Nmi => "NMI",
+            Self::Brk => "BRK",
+        })
+    }
+}
+
+/// One interrupt taken by the cpu
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InterruptEvent {
+    pub kind: InterruptKind,
+    /// cpu cycle when the interrupt was serviced
+    pub cycle: usize,
+    /// address of the interrupted instruction or of the BRK
+    pub from: u16,
+    /// address the vector pointed to
+    pub target: u16,
+    /// devices that flagged an interrupt, e.g. `VIA timer 1`
+    pub sources: Vec<String>,
+    /// cycles since the line was asserted, sampled once per instruction, `None` for BRK
+    pub latency: Option<usize>,
+}
+
+impl Display for InterruptEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at cycle {}", self.kind, self.cycle)?;
+        if !self.sources.is_empty() {
+            write!(f, " from {}", self.sources.join(", "))?;
+        }
+        write!(f, ": 0x{:04X} -> 0x{:04X}", self.from, self.target)?;
+        if let Some(latency) = self.latency {
+            write!(f, ", {latency} cycles after it was raised")?;
+        }
+        Ok(())
+    }
+}
+
+/// Log of the interrupts taken together with the device that raised them
+#[derive(Debug, Clone)]
+pub struct InterruptTrace {
+    vblank_interrupt: VblankInterrupt,
+    /// addresses of the VIAs, the Cody's own first
+    vias: Vec<u16>,
+    log: VecDeque<InterruptEvent>,
+}
+
+impl InterruptTrace {
+    pub fn new(vblank_interrupt: VblankInterrupt, expansion_vias: &[u16]) -> Self {
+        Self {
+            vblank_interrupt,
+            vias: [VIA_BASE]
+                .into_iter()
+                .chain(expansion_vias.iter().copied())
+                .collect(),
+            log: VecDeque::new(),
+        }
+    }
+
+    /// The most recent interrupts, oldest first
+    pub fn log(&self) -> &VecDeque<InterruptEvent> {
+        &self.log
+    }
+
+    pub fn record(&mut self, event: InterruptEvent) {
+        if self.log.len() == LOG_SIZE {
+            self.log.pop_front();
+        }
+        self.log.push_back(event);
+    }
+
+    /// Devices that currently flag an enabled interrupt, checked right after the interrupt was taken.
+    ///
+    /// `nmi_button` tells whether the NMI button was pressed, the only other source of an NMI is vertical blanking.
+    pub fn sources(
+        &self,
+        kind: InterruptKind,
+        memory: &mut impl Memory,
+        nmi_button: bool,
+    ) -> Vec<String> {
+        match kind {
+            InterruptKind::Brk => vec![],
+            InterruptKind::Nmi if nmi_button => vec!["NMI button".to_string()],
+            InterruptKind::Nmi if self.vblank_interrupt == VblankInterrupt::Nmi => {
+                vec!["VBLANK".to_string()]
+            }
+            InterruptKind::Nmi => vec![],
+            InterruptKind::Irq => {
+                let mut sources = vec![];
+                for &base in &self.vias {
+                    let name = if base == VIA_BASE {
+                        "VIA".to_string()
+                    } else {
+                        format!("VIA at 0x{base:04X}")
+                    };
+                    let flags = memory.peek_u8(base + VIA_IFR) & memory.peek_u8(base + VIA_IER);
+                    for (bit, flag) in VIA_FLAGS.iter().enumerate().rev() {
+                        if flags & (1 << bit) == 0 {
+                            continue;
+                        }
+                        if base == VIA_BASE
+                            && *flag == "CA1"
+                            && self.vblank_interrupt == VblankInterrupt::ViaCa1
+                        {
+                            sources.push("VBLANK (VIA CA1)".to_string());
+                        } else {
+                            sources.push(format!("{name} {flag}"));
+                        }
+                    }
+                }
+                for (name, base) in [("UART1", UART1_BASE), ("UART2", UART2_BASE)] {
+                    if memory.peek_u8(base + UART_STAT) & STAT_IRQ != 0 {
+                        sources.push(name.to_string());
+                    }
+                }
+                sources
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::contiguous::Contiguous;
+
+    #[test]
+    fn test_sources() {
+        let mut memory = Contiguous::new_ram(0x10000);
+        // timer 1 and CA1 flagged and enabled, timer 2 only flagged
+        memory.force_write_all(VIA_BASE + VIA_IFR, &[0xE2, 0xC2]);
+        memory.force_write_all(UART2_BASE + UART_STAT, &[STAT_IRQ]);
+
+        let trace = InterruptTrace::new(VblankInterrupt::ViaCa1, &[]);
+        assert_eq!(
+            trace.sources(InterruptKind::Irq, &mut memory, false),
+            ["VIA timer 1", "VBLANK (VIA CA1)", "UART2"]
+        );
+        assert_eq!(
+            trace.sources(InterruptKind::Nmi, &mut memory, true),
+            ["NMI button"]
+        );
+        assert!(
+            trace
+                .sources(InterruptKind::Nmi, &mut memory, false)
+                .is_empty()
+        );
+
+        let trace = InterruptTrace::new(VblankInterrupt::Nmi, &[]);
+        assert_eq!(
+            trace.sources(InterruptKind::Irq, &mut memory, false),
+            ["VIA timer 1", "VIA CA1", "UART2"]
+        );
+        assert_eq!(
+            trace.sources(InterruptKind::Nmi, &mut memory, false),
+            ["VBLANK"]
+        );
+    }
+
+    #[test]
+    fn test_event_display() {
+        let event = InterruptEvent {
+            kind: InterruptKind::Irq,
+            cycle: 1234,
+            from: 0x0234,
+            target: 0xE100,
+            sources: vec!["VIA timer 1".to_string()],
+            latency: Some(7),
+        };
+        assert_eq!(
+            event.to_string(),
+            "IRQ at cycle 1234 from VIA timer 1: 0x0234 -> 0xE100, 7 cycles after it was raised"
+        );
+    }
+}
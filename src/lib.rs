@@ -1,7 +1,32 @@
 pub mod assembler;
+pub mod bench;
+pub mod breakpoint;
+pub mod callstack;
+pub mod config;
+pub mod control;
+pub mod coverage;
 pub mod cpu;
+#[cfg(feature = "debugger")]
+pub mod debugger;
+pub mod decode_cache;
 pub mod device;
+pub mod filter;
 pub mod frontend;
 pub mod interrupt;
+pub mod interrupt_trace;
+pub mod lockstep;
+pub mod machine;
 pub mod memory;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod opcode;
+pub mod profiler;
+pub mod replay;
+pub mod rewind;
+pub mod rom;
+pub mod symbols;
+pub mod timing;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod vcd;
+pub mod watch;
@@ -0,0 +1,257 @@
+use crate::cpu::{Cpu, CpuState};
+use crate::machine::Machine;
+use crate::memory::Memory;
+use crate::memory::logging::{LoggingMemory, MemoryAccess};
+use crate::replay::{InputEvent, InputPlayer};
+use std::fmt::{Display, Formatter};
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Registers and bus traffic of one cpu after executing one instruction
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StepState {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub s: u8,
+    pub p: u8,
+    pub pc: u16,
+    pub cycles: u8,
+    pub running: bool,
+    pub accesses: Vec<MemoryAccess>,
+}
+
+impl StepState {
+    fn capture<M: Memory>(cpu: &Cpu<LoggingMemory<M>>, cycles: u8) -> Self {
+        Self {
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            s: cpu.s,
+            p: cpu.p.into_bits(),
+            pc: cpu.pc,
+            cycles,
+            running: cpu.is_running(),
+            accesses: cpu.memory.log().to_vec(),
+        }
+    }
+}
+
+impl Display for StepState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PC:0x{:04X} A:0x{:02X} X:0x{:02X} Y:0x{:02X} S:0x{:02X} P:0x{:02X} cycles:{} running:{}",
+            self.pc, self.a, self.x, self.y, self.s, self.p, self.cycles, self.running
+        )?;
+        for access in &self.accesses {
+            write!(
+                f,
+                "\n  {:?} 0x{:04X} = 0x{:02X}",
+                access.access_type, access.address, access.value
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The first instruction after which both machines disagreed
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Divergence {
+    /// number of instructions both machines executed identically before this one
+    pub instruction: usize,
+    /// program counter of the divergent instruction
+    pub pc: u16,
+    pub left: StepState,
+    pub right: StepState,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "machines diverged at instruction {} (PC 0x{:04X})",
+            self.instruction, self.pc
+        )?;
+        writeln!(f, "left:  {}", self.left)?;
+        write!(f, "right: {}", self.right)
+    }
+}
+
+/// Runs two machines instruction by instruction and compares registers and bus accesses after each step.
+///
+/// Both machines have to be driven by the same inputs, so any difference points at the emulation itself.
+#[derive(Debug)]
+pub struct Lockstep<L, R> {
+    pub left: Cpu<LoggingMemory<L>>,
+    pub right: Cpu<LoggingMemory<R>>,
+    instructions: usize,
+}
+
+impl<L: Memory, R: Memory> Lockstep<L, R> {
+    pub fn new(left: L, right: R) -> Self {
+        Self {
+            left: Cpu::new(LoggingMemory::new(left)),
+            right: Cpu::new(LoggingMemory::new(right)),
+            instructions: 0,
+        }
+    }
+
+    /// number of instructions both machines executed identically
+    pub const fn instructions(&self) -> usize {
+        self.instructions
+    }
+
+    /// Execute one instruction on both machines
+    pub fn step(&mut self) -> Result<(), Box<Divergence>> {
+        let pc = self.left.pc;
+        self.left.memory.reset_log();
+        self.right.memory.reset_log();
+        let left_cycles = self.left.step_instruction();
+        let right_cycles = self.right.step_instruction();
+
+        let left = StepState::capture(&self.left, left_cycles);
+        let right = StepState::capture(&self.right, right_cycles);
+        if left != right {
+            return Err(Box::new(Divergence {
+                instruction: self.instructions,
+                pc,
+                left,
+                right,
+            }));
+        }
+
+        self.instructions += 1;
+        Ok(())
+    }
+
+    /// Step until both machines stopped or `max_instructions` were executed
+    pub fn run(&mut self, max_instructions: usize) -> Result<(), Box<Divergence>> {
+        for _ in 0..max_instructions {
+            if !self.left.is_running() && !self.right.is_running() {
+                break;
+            }
+            self.step()?;
+        }
+        Ok(())
+    }
+}
+
+/// Registers and a hash of the memory and devices of a [`Machine`] at the end of a frame
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FrameState {
+    pub cycle: usize,
+    pub cpu: CpuState,
+    /// hash of the RAM, the propeller RAM and the device state
+    pub hash: u64,
+}
+
+impl FrameState {
+    fn capture(machine: &mut Machine) -> Self {
+        let snapshot = machine.snapshot();
+        let mut hasher = DefaultHasher::new();
+        (&snapshot.ram, &snapshot.propeller_ram, &snapshot.devices).hash(&mut hasher);
+        Self {
+            cycle: machine.cpu.cycle(),
+            cpu: snapshot.cpu,
+            hash: hasher.finish(),
+        }
+    }
+}
+
+impl Display for FrameState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let cpu = &self.cpu;
+        write!(
+            f,
+            "cycle:{} PC:0x{:04X} A:0x{:02X} X:0x{:02X} Y:0x{:02X} S:0x{:02X} P:0x{:02X} running:{} hash:0x{:016X}",
+            self.cycle, cpu.pc, cpu.a, cpu.x, cpu.y, cpu.s, cpu.p, cpu.run, self.hash
+        )
+    }
+}
+
+/// The first frame after which both machines disagreed
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FrameDivergence {
+    /// number of frames both machines ran identically before this one
+    pub frame: usize,
+    pub left: FrameState,
+    pub right: FrameState,
+}
+
+impl Display for FrameDivergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "machines diverged in frame {}", self.frame)?;
+        writeln!(f, "left:  {}", self.left)?;
+        write!(f, "right: {}", self.right)
+    }
+}
+
+/// Runs two whole [`Machine`]s frame by frame with the same recorded input and compares their state after each frame.
+///
+/// Compares configurations that have to behave the same, e.g. two [`crate::cpu::Engine`]s, including their devices.
+/// [`Lockstep`] then finds the instruction of a divergence.
+pub struct MachineLockstep {
+    pub left: Machine,
+    pub right: Machine,
+    left_input: InputPlayer,
+    right_input: InputPlayer,
+    frames: usize,
+}
+
+impl MachineLockstep {
+    pub fn new(left: Machine, right: Machine, input: Vec<InputEvent>) -> Self {
+        Self {
+            left,
+            right,
+            left_input: InputPlayer::new(input.clone()),
+            right_input: InputPlayer::new(input),
+            frames: 0,
+        }
+    }
+
+    /// number of frames both machines ran identically
+    pub const fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Run both machines to the end of the frame in progress
+    pub fn step_frame(&mut self) -> Result<(), Box<FrameDivergence>> {
+        let left = run_frame(&mut self.left, &mut self.left_input);
+        let right = run_frame(&mut self.right, &mut self.right_input);
+        if left != right {
+            return Err(Box::new(FrameDivergence {
+                frame: self.frames,
+                left,
+                right,
+            }));
+        }
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Run until both machines stopped or `max_frames` frames passed
+    pub fn run(&mut self, max_frames: usize) -> Result<(), Box<FrameDivergence>> {
+        for _ in 0..max_frames {
+            if !self.left.cpu.is_running() && !self.right.cpu.is_running() {
+                break;
+            }
+            self.step_frame()?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `machine` to the end of the frame in progress, or until it stops or pauses
+fn run_frame(machine: &mut Machine, input: &mut InputPlayer) -> FrameState {
+    let frame_cycles = machine.timing().frame_cycles();
+    let end = (machine.cpu.cycle() / frame_cycles + 1) * frame_cycles;
+    while machine.cpu.is_running() && !machine.is_paused() && machine.cpu.cycle() < end {
+        input.apply(
+            machine.cpu.cycle(),
+            &mut machine.devices().key_state.borrow_mut(),
+        );
+        machine.step_instruction();
+    }
+    FrameState::capture(machine)
+}
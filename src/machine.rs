@@ -0,0 +1,1843 @@
+use crate::breakpoint::Breakpoint;
+use crate::callstack::{CallStack, FrameKind};
+use crate::config::MemoryRegion;
+use crate::coverage::Coverage;
+use crate::cpu;
+use crate::cpu::{Cpu, CpuState, Engine, IllegalOpcodePolicy};
+use crate::device::blanking::{BlankingRegister, VblankInterrupt};
+use crate::device::button::NmiButton;
+use crate::device::host_bridge::{HOST_BRIDGE_SIZE, HostBridge};
+use crate::device::light_pen::{LIGHT_PEN_SIZE, LightPen, PenState};
+use crate::device::mouse::{MOUSE_SIZE, Mouse, MouseState};
+use crate::device::raster::{RASTER_SIZE, RasterRegister};
+use crate::device::uart::{
+    UART_SIZE, UART1_BASE, UART2_BASE, Uart, UartActivity, UartSink, UartSource,
+};
+use crate::device::via::{KeyState, PortLines, VIA_IFR, Via};
+use crate::device::vid;
+use crate::device::vid::{Border, Color, Firmware, Palette};
+use crate::interrupt_trace::{InterruptEvent, InterruptKind, InterruptTrace};
+use crate::memory::Memory;
+use crate::memory::contiguous::{Contiguous, Rom};
+use crate::memory::guard::{GuardPolicy, GuardState, MemoryGuard, Violation};
+use crate::memory::logging::{AccessProfile, DeviceLog};
+use crate::memory::mapped::{
+    MappedMemory, MemoryMapBuilder, PRIORITY_DEVICE, PRIORITY_GUARD, PRIORITY_MEMORY,
+};
+use crate::profiler::CallProfiler;
+use crate::symbols::VIA_BASE;
+use crate::timing::TimingProfile;
+use crate::vcd::{VcdSignal, VcdWriter};
+use crate::watch::{Watch, Watches};
+use log::{debug, error, info, warn};
+use std::cell::{Ref, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::rc::Rc;
+
+/// Cpu state, RAM contents and device state of a [`Machine`] at one point in time.
+///
+/// The cycle counter is not included, the devices continue from the current cycle. What the host feeds into the
+/// devices (keys, UART input, mouse and light pen) is not included either.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Snapshot {
+    pub cpu: CpuState,
+    pub ram: Box<[u8]>,
+    pub propeller_ram: Box<[u8]>,
+    /// VIAs, UARTs, bank latches, additional memory regions and the other devices, see
+    /// [`MappedMemory::save_states`]
+    pub devices: Vec<Vec<u8>>,
+}
+
+type SharedLines = Rc<RefCell<PortLines>>;
+/// What the cpu does after a [`Trap`] ran
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TrapAction {
+    /// execute the instruction at the program counter, which the trap may have changed
+    Continue,
+    /// return from the subroutine the trap replaces, as if it executed RTS
+    Return,
+}
+
+/// Host-side routine that runs when the program counter reaches its address, e.g. to skip a memory test or load a file instantly
+pub type Trap = Box<dyn FnMut(&mut Cpu<MappedMemory>) -> TrapAction>;
+
+/// Shared state of the devices attached by [`MachineBuilder`]
+#[derive(Debug, Default)]
+pub struct Devices {
+    pub key_state: Rc<RefCell<KeyState>>,
+    pub uart1_activity: Rc<RefCell<UartActivity>>,
+    pub uart2_activity: Rc<RefCell<UartActivity>>,
+    /// address, port A and port B of each expansion VIA
+    pub expansion_ports: Vec<(u16, SharedLines, SharedLines)>,
+    /// exit code written to the host bridge
+    pub host_exit: Rc<RefCell<Option<u8>>>,
+    /// pointer of the mouse, if one is attached
+    pub mouse: Option<Rc<RefCell<MouseState>>>,
+    /// pen of the light pen, if one is attached
+    pub light_pen: Option<Rc<RefCell<PenState>>>,
+}
+
+/// The Cody's cpu with RAM, propeller RAM and ROM.
+///
+/// Use [`Machine::builder`] to attach the Cody's devices, or add them to [`Machine::memory_mut`] yourself.
+pub struct Machine {
+    pub cpu: Cpu<MappedMemory>,
+    ram: Rc<RefCell<Contiguous>>,
+    propeller_ram: Rc<RefCell<Contiguous>>,
+    rom: Rc<RefCell<Contiguous<Rom>>>,
+    image: Image,
+    vectors: Vectors,
+    timing: TimingProfile,
+    devices: Devices,
+    nmi_pressed: Rc<RefCell<bool>>,
+    /// the NMI button was pressed since the last traced NMI
+    nmi_button: bool,
+    guard: Option<Guard>,
+    profile: Option<Rc<RefCell<AccessProfile>>>,
+    device_log: Option<Rc<RefCell<DeviceLog>>>,
+    coverage: Option<Coverage>,
+    call_profiler: Option<CallProfiler>,
+    interrupt_trace: Option<InterruptTrace>,
+    /// waveform output and the number of bytes UART1 had sent at the last sample
+    vcd: Option<(VcdWriter, usize)>,
+    call_stack: CallStack,
+    /// pause once the call stack is less deep, set by [`Machine::step_out`]
+    step_out_depth: Option<usize>,
+    watches: Watches,
+    traps: HashMap<u16, Trap>,
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    /// execute the instruction at a breakpoint after resuming from it
+    leaving_breakpoint: bool,
+    /// address of the breakpoint the machine is paused at
+    breakpoint_hit: Option<u16>,
+    paused: bool,
+    palette: Palette,
+    border: Border,
+    firmware: Firmware,
+    /// last frame returned by [`Machine::framebuffer`]
+    frame: Vec<Color>,
+    /// last frame returned by [`Machine::framebuffer_indexed`]
+    indexed_frame: Vec<u8>,
+}
+
+struct Guard {
+    policy: GuardPolicy,
+    state: Rc<RefCell<GuardState>>,
+    /// violations and the program counter of the instruction, each is only logged once
+    reported: HashSet<(Violation, u16)>,
+}
+
+impl Machine {
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder::default()
+    }
+
+    /// Machine without any devices
+    pub fn new(image: Image, vectors: Vectors) -> Self {
+        let ram = Rc::new(RefCell::new(Contiguous::new_ram(0xA000)));
+        let propeller_ram = Rc::new(RefCell::new(Contiguous::new_ram(0x4000)));
+        let rom = Rc::new(RefCell::new(Contiguous::new_rom(0x2000)));
+        image.write_to(
+            &mut ram.borrow_mut(),
+            &mut propeller_ram.borrow_mut(),
+            &mut rom.borrow_mut(),
+        );
+        vectors.write_to(&mut rom.borrow_mut(), &image);
+
+        let mut memory = MappedMemory::new();
+        memory.add_ram(0x0000, 0xA000, PRIORITY_MEMORY, Rc::clone(&ram));
+        memory.add_ram(0xA000, 0x4000, PRIORITY_MEMORY, Rc::clone(&propeller_ram));
+        memory.add_rom(0xE000, 0x2000, PRIORITY_MEMORY, Rc::clone(&rom));
+        let nmi_button = NmiButton::default();
+        let nmi_pressed = Rc::clone(nmi_button.get_pressed());
+        memory.add_device(nmi_button);
+
+        let cpu = Cpu::new(memory);
+        Self {
+            call_stack: CallStack::new(cpu.pc),
+            step_out_depth: None,
+            cpu,
+            ram,
+            propeller_ram,
+            rom,
+            image,
+            vectors,
+            timing: TimingProfile::default(),
+            devices: Devices::default(),
+            nmi_pressed,
+            nmi_button: false,
+            guard: None,
+            profile: None,
+            device_log: None,
+            coverage: None,
+            call_profiler: None,
+            interrupt_trace: None,
+            vcd: None,
+            watches: Watches::default(),
+            traps: HashMap::new(),
+            breakpoints: BTreeMap::new(),
+            leaving_breakpoint: false,
+            breakpoint_hit: None,
+            paused: false,
+            palette: Palette::default(),
+            border: Border::default(),
+            firmware: Firmware::default(),
+            frame: vec![],
+            indexed_frame: vec![],
+        }
+    }
+
+    /// Cpu clock and video timing the devices were attached with
+    pub const fn timing(&self) -> &TimingProfile {
+        &self.timing
+    }
+
+    /// Propeller firmware revision the video registers behave like
+    pub const fn firmware(&self) -> Firmware {
+        self.firmware
+    }
+
+    pub const fn devices(&self) -> &Devices {
+        &self.devices
+    }
+
+    /// The 16K of RAM shared with the propeller at 0xA000, which holds everything the VID reads
+    pub fn propeller_ram(&self) -> Ref<'_, Contiguous> {
+        self.propeller_ram.borrow()
+    }
+
+    /// Frame of [`Border::width`]x[`Border::height`] pixels rendered from the current propeller RAM,
+    /// with the palette, border and firmware of the builder.
+    ///
+    /// The whole frame shows the video registers as they are now, unlike the frontend that draws each row when the
+    /// beam reaches it.
+    pub fn framebuffer(&mut self) -> &[Color] {
+        self.frame.resize(
+            (self.border.width() * self.border.height()) as usize,
+            Color::default(),
+        );
+        vid::render_frame(
+            &self.propeller_ram.borrow().memory,
+            &self.palette,
+            self.border,
+            self.firmware,
+            &mut self.frame,
+        );
+        &self.frame
+    }
+
+    /// Renderer that draws each row when the beam reaches it, paced by the same clock as the blanking register
+    pub fn scanline_renderer(&self) -> vid::ScanlineRenderer {
+        vid::ScanlineRenderer::new(self.palette, self.timing, self.border, self.firmware)
+    }
+
+    /// Like [`Self::framebuffer`], but with the palette index of each pixel
+    pub fn framebuffer_indexed(&mut self) -> &[u8] {
+        self.indexed_frame
+            .resize((self.border.width() * self.border.height()) as usize, 0);
+        vid::render_frame_indexed(
+            &self.propeller_ram.borrow().memory,
+            self.border,
+            self.firmware,
+            &mut self.indexed_frame,
+        );
+        &self.indexed_frame
+    }
+
+    /// Access counters, if profiling was enabled in the builder
+    pub fn profile(&self) -> Option<Ref<'_, AccessProfile>> {
+        self.profile.as_ref().map(|profile| profile.borrow())
+    }
+
+    /// Recent accesses to device registers, if device logging was enabled in the builder
+    pub fn device_log(&self) -> Option<Ref<'_, DeviceLog>> {
+        self.device_log
+            .as_ref()
+            .map(|device_log| device_log.borrow())
+    }
+
+    /// Executed instructions, if coverage tracking was enabled in the builder
+    pub const fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Cycles per subroutine, if call profiling was enabled in the builder
+    pub const fn call_profiler(&self) -> Option<&CallProfiler> {
+        self.call_profiler.as_ref()
+    }
+
+    /// Recently taken interrupts, if interrupt tracing was enabled in the builder
+    pub const fn interrupt_trace(&self) -> Option<&InterruptTrace> {
+        self.interrupt_trace.as_ref()
+    }
+
+    /// The loaded binary
+    pub const fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// Memory map, devices added later take precedence over RAM and ROM
+    pub fn memory_mut(&mut self) -> &mut MappedMemory {
+        &mut self.cpu.memory
+    }
+
+    pub const fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.step_out_depth = None;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.leaving_breakpoint = true;
+        self.breakpoint_hit = None;
+    }
+
+    /// Address of the breakpoint that paused the machine, until it is resumed
+    pub const fn breakpoint_hit(&self) -> Option<u16> {
+        self.breakpoint_hit
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if self.paused {
+            self.resume();
+        } else {
+            self.pause();
+        }
+    }
+
+    pub const fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// Resume and pause again after returning from the current subroutine or interrupt handler.
+    ///
+    /// Returns false if there is nothing to return from.
+    pub fn step_out(&mut self) -> bool {
+        let depth = self.call_stack.depth();
+        if depth <= 1 {
+            return false;
+        }
+        self.resume();
+        self.step_out_depth = Some(depth);
+        true
+    }
+
+    /// Breakpoints by the address of the instruction they pause before
+    pub const fn breakpoints(&self) -> &BTreeMap<u16, Breakpoint> {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut BTreeMap<u16, Breakpoint> {
+        &mut self.breakpoints
+    }
+
+    /// Add a breakpoint, replacing the one at the same address
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.insert(breakpoint.address, breakpoint);
+    }
+
+    /// Press the NMI button, the NMI line is asserted during the next instruction
+    pub fn trigger_nmi(&mut self) {
+        *self.nmi_pressed.borrow_mut() = true;
+        self.nmi_button = true;
+    }
+
+    /// Values that are checked for changes after every instruction, changes are logged at info level
+    pub const fn watches(&self) -> &Watches {
+        &self.watches
+    }
+
+    pub fn watch(&mut self, watch: Watch) {
+        self.watches.add(watch, &mut self.cpu);
+    }
+
+    pub fn unwatch(&mut self, index: usize) {
+        self.watches.remove(index);
+    }
+
+    /// Run `trap` instead of the instruction at `address`, replaces an earlier trap there
+    pub fn add_trap(&mut self, address: u16, trap: Trap) {
+        self.traps.insert(address, trap);
+    }
+
+    pub fn remove_trap(&mut self, address: u16) {
+        self.traps.remove(&address);
+    }
+
+    /// Execute one instruction even if paused, returns the number of elapsed cycles
+    pub fn single_step(&mut self) -> u8 {
+        let paused = self.paused;
+        self.resume();
+        let cycles = self.step_instruction();
+        self.paused = paused;
+        cycles
+    }
+
+    /// Execute one instruction unless paused, returns the number of elapsed cycles
+    pub fn step_instruction(&mut self) -> u8 {
+        if self.paused {
+            return 0;
+        }
+        if !self.leaving_breakpoint
+            && let Some(breakpoint) = self.breakpoints.get(&self.cpu.pc)
+            && let Some(hit) = breakpoint.check(&mut self.cpu)
+        {
+            for message in hit.messages {
+                info!("Breakpoint at 0x{:04X}: {message}", self.cpu.pc);
+            }
+            if hit.pause {
+                info!("Breakpoint at 0x{:04X}", self.cpu.pc);
+                self.pause();
+                self.breakpoint_hit = Some(self.cpu.pc);
+                return 0;
+            }
+        }
+        self.leaving_breakpoint = false;
+        if self.cpu.is_running()
+            && let Some(trap) = self.traps.get_mut(&self.cpu.pc)
+        {
+            let address = self.cpu.pc;
+            if trap(&mut self.cpu) == TrapAction::Return {
+                self.cpu.return_from_subroutine();
+            }
+            debug!("Trap at 0x{address:04X} continues at 0x{:04X}", self.cpu.pc);
+        }
+
+        let (s, irqs, nmis) = (self.cpu.s, self.cpu.irq_count(), self.cpu.nmi_count());
+        let (pc_before, cycle_before) = (self.cpu.pc, self.cpu.cycle());
+        if let Some(device_log) = &self.device_log {
+            device_log.borrow_mut().begin_instruction(cycle_before);
+        }
+        let mut pc = pc_before;
+        let cycles = self.cpu.step_instruction();
+        if let Some((address, _)) = self.cpu.last_instruction() {
+            pc = address;
+            if let Some(profile) = &self.profile {
+                profile.borrow_mut().record_instruction(pc, cycles);
+            }
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record(pc);
+            }
+        }
+        if let Some(device_log) = &self.device_log {
+            device_log.borrow_mut().end_instruction(pc);
+        }
+        let interrupt = if self.cpu.nmi_count() != nmis {
+            Some(FrameKind::Nmi)
+        } else if self.cpu.irq_count() != irqs {
+            Some(FrameKind::Irq)
+        } else {
+            None
+        };
+        if let Some(call_profiler) = &mut self.call_profiler {
+            call_profiler.record(&self.cpu, interrupt.map(|_| s), cycles);
+        }
+        self.call_stack
+            .record(&self.cpu, interrupt.map(|kind| (kind, pc_before, s)));
+        self.trace_interrupt(interrupt, pc_before, cycle_before);
+        self.sample_vcd();
+        if let Some(depth) = self.step_out_depth
+            && self.call_stack.depth() < depth
+        {
+            info!("Stepped out to 0x{:04X}", self.cpu.pc);
+            self.pause();
+        }
+        if let Some(guard) = &mut self.guard {
+            let violations = guard.state.borrow_mut().take_violations();
+            for violation in violations {
+                if guard.policy == GuardPolicy::Trap {
+                    error!("Trapped on {violation} by instruction at 0x{pc:04X}");
+                    let mut state = self.cpu.state();
+                    state.pc = pc;
+                    state.run = false;
+                    self.cpu.restore_state(&state);
+                    break;
+                } else if guard.reported.insert((violation, pc)) {
+                    warn!("{violation} by instruction at 0x{pc:04X}");
+                }
+            }
+        }
+        for change in self.watches.check(&mut self.cpu, pc) {
+            info!("Watch: {change}");
+        }
+        if let Some(code) = self.host_exit()
+            && self.cpu.is_running()
+        {
+            info!("Program exited with code {code}");
+            let mut state = self.cpu.state();
+            state.run = false;
+            self.cpu.restore_state(&state);
+            return cycles;
+        }
+        if !self.cpu.is_running() && cycles > 0 {
+            info!("Cpu stopped at 0x{pc:04X}, a reset starts it again");
+        }
+        cycles
+    }
+
+    /// Write the device signals after the last step to the waveform output
+    fn sample_vcd(&mut self) {
+        let Some((vcd, tx_bytes)) = &mut self.vcd else {
+            return;
+        };
+        let (irq, nmi) = (self.cpu.interrupts().irq(), self.cpu.interrupts().nmi());
+        let memory = &mut self.cpu.memory;
+        let via_flags = memory.peek_u8(VIA_BASE + VIA_IFR);
+        let uart1 = self.devices.uart1_activity.borrow();
+        let values: Vec<_> = vcd
+            .signals()
+            .iter()
+            .map(|signal| match signal {
+                VcdSignal::Irq => Some(irq.into()),
+                VcdSignal::Nmi => Some(nmi.into()),
+                VcdSignal::Vblank => Some(memory.peek_u8(0xD000) & 1),
+                VcdSignal::ViaT1 => Some((via_flags >> 6) & 1),
+                VcdSignal::ViaT2 => Some((via_flags >> 5) & 1),
+                VcdSignal::ViaPb7 => Some(memory.peek_u8(VIA_BASE) >> 7),
+                VcdSignal::Uart1Tx => uart1.last_tx.filter(|_| uart1.tx_bytes != *tx_bytes),
+            })
+            .collect();
+        *tx_bytes = uart1.tx_bytes;
+        if let Err(e) = vcd.sample(self.cpu.cycle(), &values) {
+            warn!("Stopping waveform output: {e}");
+            self.vcd = None;
+        }
+    }
+
+    /// Log an interrupt taken by the last step, `pc` and `cycle` are from before it
+    fn trace_interrupt(&mut self, interrupt: Option<FrameKind>, pc: u16, cycle: usize) {
+        let Some(trace) = &mut self.interrupt_trace else {
+            return;
+        };
+        let interrupts = self.cpu.interrupts();
+        let (kind, from, latency) = match (interrupt, self.cpu.last_instruction()) {
+            (Some(FrameKind::Nmi), _) => (InterruptKind::Nmi, pc, interrupts.nmi_asserted()),
+            (Some(_), _) => (InterruptKind::Irq, pc, interrupts.irq_asserted()),
+            (None, Some((address, 0x00))) => (InterruptKind::Brk, address, None),
+            (None, _) => return,
+        };
+        let target = match kind {
+            InterruptKind::Brk => self.cpu.pc,
+            _ => self
+                .cpu
+                .last_instruction()
+                .map_or(self.cpu.pc, |(address, _)| address),
+        };
+        let nmi_button = kind == InterruptKind::Nmi && std::mem::take(&mut self.nmi_button);
+        let event = InterruptEvent {
+            kind,
+            cycle,
+            from,
+            target,
+            sources: trace.sources(kind, &mut self.cpu.memory, nmi_button),
+            latency: latency.map(|asserted| cycle - asserted),
+        };
+        info!("{event}");
+        trace.record(event);
+    }
+
+    /// Exit code the program wrote to the host bridge, it stopped the cpu
+    pub fn host_exit(&self) -> Option<u8> {
+        *self.devices.host_exit.borrow()
+    }
+
+    /// Check writes to ROM and reads of uninitialized RAM, the loaded binary counts as initialized
+    fn guard_memory(&mut self, policy: GuardPolicy) {
+        let state = Rc::new(RefCell::new(GuardState::default()));
+        state
+            .borrow_mut()
+            .mark_initialized(self.image.load_address..=self.image.last_written_address());
+        let memory = &mut self.cpu.memory;
+        memory.add_memory(
+            0x0000,
+            0xA000,
+            PRIORITY_GUARD,
+            MemoryGuard::ram(Rc::clone(&self.ram), 0x0000, Rc::clone(&state)),
+        );
+        memory.add_memory(
+            0xA000,
+            0x4000,
+            PRIORITY_GUARD,
+            MemoryGuard::ram(Rc::clone(&self.propeller_ram), 0xA000, Rc::clone(&state)),
+        );
+        memory.add_memory(
+            0xE000,
+            0x2000,
+            PRIORITY_GUARD,
+            MemoryGuard::rom(Rc::clone(&self.rom), 0xE000, Rc::clone(&state)),
+        );
+        self.guard = Some(Guard {
+            policy,
+            state,
+            reported: HashSet::new(),
+        });
+    }
+
+    /// Jump through the reset vector, memory is left untouched
+    pub fn warm_reset(&mut self) {
+        info!("Warm reset");
+        self.devices.host_exit.take();
+        self.reset_cpu();
+        if let Some(call_profiler) = &mut self.call_profiler {
+            call_profiler.reset(self.cpu.pc);
+        }
+        self.call_stack.reset(self.cpu.pc);
+        self.step_out_depth = None;
+    }
+
+    /// Clear RAM, reload the ROM and reset the cpu
+    pub fn hard_reset(&mut self) {
+        info!("Hard reset");
+        self.devices.host_exit.take();
+        let mut ram = Contiguous::new_ram(0xA000);
+        let mut propeller_ram = Contiguous::new_ram(0x4000);
+        let mut rom = Contiguous::new_rom(0x2000);
+        self.image.write_to(&mut ram, &mut propeller_ram, &mut rom);
+        self.vectors.write_to(&mut rom, &self.image);
+        *self.ram.borrow_mut() = ram;
+        // keep counting, the renderer compares generations to skip unchanged rows
+        self.propeller_ram
+            .borrow_mut()
+            .force_write_all(0, &propeller_ram.memory);
+        *self.rom.borrow_mut() = rom;
+        self.cpu.flush_decode_cache();
+        if let Some(guard) = &self.guard {
+            let mut state = guard.state.borrow_mut();
+            state.clear();
+            state.mark_initialized(self.image.load_address..=self.image.last_written_address());
+        }
+        self.reset_cpu();
+        if let Some(call_profiler) = &mut self.call_profiler {
+            call_profiler.reset(self.cpu.pc);
+        }
+        self.call_stack.reset(self.cpu.pc);
+        self.step_out_depth = None;
+    }
+
+    /// Reset the cpu and forget the violations of its reset sequence, whose dummy reads may touch uninitialized stack,
+    /// which is not a bug of the program. Violations from before the reset are still reported.
+    fn reset_cpu(&mut self) {
+        let pending = self
+            .guard
+            .as_ref()
+            .map(|guard| guard.state.borrow().violation_count());
+        self.cpu.reset();
+        if let (Some(guard), Some(pending)) = (&self.guard, pending) {
+            guard.state.borrow_mut().truncate_violations(pending);
+        }
+    }
+
+    pub fn snapshot(&mut self) -> Snapshot {
+        // updates the mapped regions, the RAM must not be borrowed meanwhile
+        let devices = self.cpu.memory.save_states(self.cpu.cycle());
+        Snapshot {
+            cpu: self.cpu.state(),
+            ram: self.ram.borrow().memory.clone(),
+            propeller_ram: self.propeller_ram.borrow().memory.clone(),
+            devices,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.cpu.restore_state(&snapshot.cpu);
+        self.ram.borrow_mut().memory.copy_from_slice(&snapshot.ram);
+        self.propeller_ram
+            .borrow_mut()
+            .force_write_all(0, &snapshot.propeller_ram);
+        let cycle = self.cpu.cycle();
+        self.cpu.memory.restore_states(&snapshot.devices, cycle);
+        self.cpu.flush_decode_cache();
+        // the frames of the snapshot are unknown
+        self.call_stack.reset(self.cpu.pc);
+        self.step_out_depth = None;
+    }
+
+    /// Replace the loaded binary and hard reset
+    pub fn load(&mut self, image: Image) {
+        self.image = image;
+        self.hard_reset();
+    }
+}
+
+/// Builds a [`Machine`] with the VIA, both UARTs and the blanking register attached
+pub struct MachineBuilder {
+    binary: Option<(Vec<u8>, bool)>,
+    load_address: Option<u16>,
+    vectors: Vectors,
+    uart1: Uart,
+    uart2: Uart,
+    timing: TimingProfile,
+    vblank_interrupt: VblankInterrupt,
+    mirror_via: bool,
+    keyboard_ghosting: bool,
+    open_bus: bool,
+    expansion_vias: Vec<u16>,
+    host_bridge: Option<(u16, HostBridge)>,
+    mouse: Option<u16>,
+    light_pen: Option<u16>,
+    raster: Option<u16>,
+    palette: Palette,
+    border: Border,
+    firmware: Firmware,
+    memory_regions: Vec<MemoryRegion>,
+    memory_map: MemoryMapBuilder,
+    illegal_opcode_policy: IllegalOpcodePolicy,
+    engine: Engine,
+    memory_guard: GuardPolicy,
+    profile: bool,
+    device_log: bool,
+    coverage: bool,
+    call_profile: bool,
+    trace_interrupts: bool,
+    vcd: Option<(Box<dyn Write>, Vec<VcdSignal>)>,
+    watches: Vec<Watch>,
+    breakpoints: Vec<Breakpoint>,
+    traps: Vec<(u16, Trap)>,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        Self {
+            binary: None,
+            load_address: None,
+            vectors: Vectors::default(),
+            uart1: Uart::new(UartSource::empty(), UartSink::discard()),
+            uart2: Uart::new(UartSource::empty(), UartSink::discard()),
+            timing: TimingProfile::default(),
+            vblank_interrupt: VblankInterrupt::default(),
+            mirror_via: false,
+            keyboard_ghosting: false,
+            open_bus: false,
+            expansion_vias: vec![],
+            host_bridge: None,
+            mouse: None,
+            light_pen: None,
+            raster: None,
+            palette: Palette::default(),
+            border: Border::default(),
+            firmware: Firmware::default(),
+            memory_regions: vec![],
+            memory_map: MemoryMapBuilder::default(),
+            illegal_opcode_policy: IllegalOpcodePolicy::default(),
+            engine: Engine::default(),
+            memory_guard: GuardPolicy::default(),
+            profile: false,
+            device_log: false,
+            coverage: false,
+            call_profile: false,
+            trace_interrupts: false,
+            vcd: None,
+            watches: vec![],
+            breakpoints: vec![],
+            traps: vec![],
+        }
+    }
+}
+
+impl MachineBuilder {
+    /// Binary loaded at 0xE000 or [`Self::load_address`]
+    pub fn rom(mut self, data: Vec<u8>) -> Self {
+        self.binary = Some((data, false));
+        self
+    }
+
+    /// Binary with a cartridge header, loaded at the address from the header unless [`Self::load_address`] is set
+    pub fn cartridge(mut self, data: Vec<u8>) -> Self {
+        self.binary = Some((data, true));
+        self
+    }
+
+    pub fn load_address(mut self, load_address: u16) -> Self {
+        self.load_address = Some(load_address);
+        self
+    }
+
+    pub fn vectors(mut self, vectors: Vectors) -> Self {
+        self.vectors = vectors;
+        self
+    }
+
+    pub fn uart1(mut self, source: UartSource, sink: UartSink) -> Self {
+        self.uart1 = Uart::new(source, sink);
+        self
+    }
+
+    pub fn uart2(mut self, source: UartSource, sink: UartSink) -> Self {
+        self.uart2 = Uart::new(source, sink);
+        self
+    }
+
+    /// Cpu clock and video standard the UARTs and the blanking register are paced with
+    pub fn timing(mut self, timing: TimingProfile) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    pub fn vblank_interrupt(mut self, vblank_interrupt: VblankInterrupt) -> Self {
+        self.vblank_interrupt = vblank_interrupt;
+        self
+    }
+
+    /// Repeat the VIA's 16 registers over its whole 256 byte region like the partially decoded real hardware
+    pub fn mirror_via(mut self, mirror_via: bool) -> Self {
+        self.mirror_via = mirror_via;
+        self
+    }
+
+    /// Keys held together show up in other rows like in the keyboard matrix without diodes
+    pub fn keyboard_ghosting(mut self, keyboard_ghosting: bool) -> Self {
+        self.keyboard_ghosting = keyboard_ghosting;
+        self
+    }
+
+    /// Reads of unmapped addresses return the last value on the bus instead of 0
+    pub fn open_bus(mut self, open_bus: bool) -> Self {
+        self.open_bus = open_bus;
+        self
+    }
+
+    /// Report writes to ROM and reads of uninitialized RAM
+    pub fn memory_guard(mut self, policy: GuardPolicy) -> Self {
+        self.memory_guard = policy;
+        self
+    }
+
+    /// Count memory accesses and the cycles spent per instruction, see [`Machine::profile`]
+    pub fn profile(mut self, profile: bool) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Track which instructions were executed, see [`Machine::coverage`]
+    pub fn coverage(mut self, coverage: bool) -> Self {
+        self.coverage = coverage;
+        self
+    }
+
+    /// Record reads and writes of the VIAs, video registers and UARTs, see [`Machine::device_log`]
+    pub fn device_log(mut self, device_log: bool) -> Self {
+        self.device_log = device_log;
+        self
+    }
+
+    /// Attribute cycles to subroutines, see [`Machine::call_profiler`]
+    pub fn call_profile(mut self, call_profile: bool) -> Self {
+        self.call_profile = call_profile;
+        self
+    }
+
+    /// Log every IRQ, NMI and BRK with the device that raised it, see [`Machine::interrupt_trace`]
+    pub fn trace_interrupts(mut self, trace_interrupts: bool) -> Self {
+        self.trace_interrupts = trace_interrupts;
+        self
+    }
+
+    /// Write `signals` as Value Change Dump to `w` while running
+    pub fn vcd(mut self, w: Box<dyn Write>, signals: Vec<VcdSignal>) -> Self {
+        self.vcd = Some((w, signals));
+        self
+    }
+
+    /// Log changes of a register or memory after every instruction, see [`Machine::watches`]
+    pub fn watch(mut self, watch: Watch) -> Self {
+        self.watches.push(watch);
+        self
+    }
+
+    /// Pause or run actions before executing an instruction, see [`Machine::add_breakpoint`]
+    pub fn breakpoint(mut self, breakpoint: Breakpoint) -> Self {
+        self.breakpoints.push(breakpoint);
+        self
+    }
+
+    /// Run a host-side routine when the program counter reaches `address`, see [`Machine::add_trap`]
+    pub fn trap(mut self, address: u16, trap: Trap) -> Self {
+        self.traps.push((address, trap));
+        self
+    }
+
+    /// Attach an additional VIA with 16 registers at `address`
+    pub fn expansion_via(mut self, address: u16) -> Self {
+        self.expansion_vias.push(address);
+        self
+    }
+
+    /// Map the [`HostBridge`]'s registers at `address`, see [`Machine::host_exit`]
+    pub fn host_bridge(mut self, address: u16, bridge: HostBridge) -> Self {
+        self.host_bridge = Some((address, bridge));
+        self
+    }
+
+    /// Map a [`Mouse`]'s registers at `address`, see [`Devices::mouse`]
+    pub fn mouse(mut self, address: u16) -> Self {
+        self.mouse = Some(address);
+        self
+    }
+
+    /// Map a [`LightPen`]'s registers at `address`, see [`Devices::light_pen`]
+    pub fn light_pen(mut self, address: u16) -> Self {
+        self.light_pen = Some(address);
+        self
+    }
+
+    /// Map a [`RasterRegister`]'s registers at `address`
+    pub fn raster(mut self, address: u16) -> Self {
+        self.raster = Some(address);
+        self
+    }
+
+    /// Map additional RAM or ROM, regions added later take precedence
+    pub fn memory_region(mut self, region: MemoryRegion) -> Self {
+        self.memory_regions.push(region);
+        self
+    }
+
+    /// Map the regions of `map` over the Cody's memory, its devices and the [`Self::memory_region`]s
+    pub fn memory_map(mut self, map: MemoryMapBuilder) -> Self {
+        self.memory_map.extend(map);
+        self
+    }
+
+    pub fn illegal_opcode_policy(mut self, policy: IllegalOpcodePolicy) -> Self {
+        self.illegal_opcode_policy = policy;
+        self
+    }
+
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Colors of [`Machine::framebuffer`]
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    /// Border around the content area of [`Machine::framebuffer`]
+    pub fn border(mut self, border: Border) -> Self {
+        self.border = border;
+        self
+    }
+
+    /// Propeller firmware revision [`Machine::framebuffer`] draws like
+    pub fn firmware(mut self, firmware: Firmware) -> Self {
+        self.firmware = firmware;
+        self
+    }
+
+    pub fn build(self) -> Result<Machine, ImageError> {
+        let (data, as_cartridge) = self.binary.ok_or("no binary to load")?;
+        let image = Image::new(data, as_cartridge, self.load_address)?;
+        let mut machine = Machine::new(image, self.vectors);
+        machine.timing = self.timing;
+        machine.palette = self.palette;
+        machine.border = self.border;
+        machine.firmware = self.firmware;
+        machine
+            .cpu
+            .set_illegal_opcode_policy(self.illegal_opcode_policy);
+        machine.cpu.set_engine(self.engine);
+        if self.memory_guard != GuardPolicy::Off {
+            machine.guard_memory(self.memory_guard);
+        }
+        if self.profile {
+            let profile = Rc::new(RefCell::new(AccessProfile::default()));
+            machine.cpu.memory.set_profile(Some(Rc::clone(&profile)));
+            machine.profile = Some(profile);
+        }
+        if self.coverage {
+            machine.coverage = Some(Coverage::default());
+        }
+        if self.call_profile {
+            machine.call_profiler = Some(CallProfiler::new(machine.cpu.pc));
+        }
+        if self.trace_interrupts {
+            machine.interrupt_trace = Some(InterruptTrace::new(
+                self.vblank_interrupt,
+                &self.expansion_vias,
+            ));
+        }
+        if let Some((w, signals)) = self.vcd {
+            let vcd = VcdWriter::new(w, signals, self.timing.cpu_frequency)
+                .map_err(|e| format!("io error writing waveform header: {e}"))?;
+            machine.vcd = Some((vcd, 0));
+        }
+        let memory = &mut machine.cpu.memory;
+        memory.set_open_bus(self.open_bus);
+
+        let via = Via::default();
+        let key_state = Rc::clone(via.get_key_state());
+        key_state.borrow_mut().set_ghosting(self.keyboard_ghosting);
+        let via_ca1 = Rc::clone(via.get_ca1());
+        if self.mirror_via {
+            memory.add_mirrored(0x9F00, 0x0100, 0x10, PRIORITY_DEVICE, via);
+        } else {
+            memory.add_partial(0x9F00, 0x0100, 0x10, PRIORITY_DEVICE, via);
+        }
+
+        let uart1_activity = Rc::clone(self.uart1.get_activity());
+        let uart2_activity = Rc::clone(self.uart2.get_activity());
+        for (base, mut uart) in [(UART1_BASE, self.uart1), (UART2_BASE, self.uart2)] {
+            uart.set_cpu_frequency(self.timing.cpu_frequency);
+            memory.add_memory(base, UART_SIZE, PRIORITY_DEVICE, uart);
+        }
+
+        let mut blanking = match self.vblank_interrupt {
+            VblankInterrupt::None => BlankingRegister::default(),
+            VblankInterrupt::Nmi => BlankingRegister::with_nmi(),
+            VblankInterrupt::ViaCa1 => BlankingRegister::with_ca1(via_ca1),
+        };
+        blanking.set_timing(self.timing);
+        memory.add_memory(0xD000, 0x1, PRIORITY_DEVICE, blanking);
+
+        let mut expansion_ports = vec![];
+        let mut devices = vec![
+            (0x9F00, 0x9FFF, "VIA".to_string()),
+            (0xD000, 0xD07F, "VID".to_string()),
+            (UART1_BASE, UART1_BASE + UART_SIZE - 1, "UART1".to_string()),
+            (UART2_BASE, UART2_BASE + UART_SIZE - 1, "UART2".to_string()),
+        ];
+        for address in self.expansion_vias {
+            devices.push((address, address + 0xF, format!("VIA at 0x{address:04X}")));
+            info!("Attaching expansion VIA at 0x{address:04X}");
+            let port_a = Rc::new(RefCell::new(PortLines::default()));
+            let port_b = Rc::new(RefCell::new(PortLines::default()));
+            add_device_at(
+                memory,
+                "expansion VIA",
+                address,
+                0x10,
+                Via::with_ports(port_a.clone(), port_b.clone()),
+            )?;
+            expansion_ports.push((address, port_a, port_b));
+        }
+
+        let mut host_exit = Rc::default();
+        if let Some((address, bridge)) = self.host_bridge {
+            info!("Attaching host bridge at 0x{address:04X}");
+            host_exit = Rc::clone(bridge.get_exit());
+            add_device_at(memory, "host bridge", address, HOST_BRIDGE_SIZE, bridge)?;
+        }
+
+        let mut mouse = None;
+        if let Some(address) = self.mouse {
+            info!("Attaching mouse at 0x{address:04X}");
+            let device = Mouse::default();
+            mouse = Some(Rc::clone(device.get_state()));
+            add_device_at(memory, "mouse", address, MOUSE_SIZE, device)?;
+        }
+
+        let mut light_pen = None;
+        if let Some(address) = self.light_pen {
+            info!("Attaching light pen at 0x{address:04X}");
+            let mut device = LightPen::default();
+            device.set_timing(self.timing);
+            light_pen = Some(Rc::clone(device.get_state()));
+            add_device_at(memory, "light pen", address, LIGHT_PEN_SIZE, device)?;
+        }
+
+        if let Some(address) = self.raster {
+            info!("Attaching raster register at 0x{address:04X}");
+            let mut device = RasterRegister::default();
+            device.set_timing(self.timing);
+            add_device_at(memory, "raster register", address, RASTER_SIZE, device)?;
+        }
+
+        if self.device_log {
+            let device_log = Rc::new(RefCell::new(DeviceLog::new(devices)));
+            memory.set_device_log(Some(Rc::clone(&device_log)));
+            machine.device_log = Some(device_log);
+        }
+
+        for region in &self.memory_regions {
+            info!(
+                "Mapping 0x{:04X} bytes of {} at 0x{:04X}",
+                region.size,
+                if region.read_only { "ROM" } else { "RAM" },
+                region.start
+            );
+            region.add_to(memory)?;
+        }
+        self.memory_map.apply_to(memory);
+
+        for watch in self.watches {
+            machine.watch(watch);
+        }
+        for breakpoint in self.breakpoints {
+            machine.add_breakpoint(breakpoint);
+        }
+        for (address, trap) in self.traps {
+            machine.add_trap(address, trap);
+        }
+
+        machine.devices = Devices {
+            key_state,
+            uart1_activity,
+            uart2_activity,
+            expansion_ports,
+            host_exit,
+            mouse,
+            light_pen,
+        };
+        Ok(machine)
+    }
+}
+
+/// Map an opt-in device at an address from the user, it must not collide with the other devices
+fn add_device_at(
+    memory: &mut MappedMemory,
+    name: &str,
+    address: u16,
+    size: u16,
+    device: impl Memory + 'static,
+) -> Result<(), String> {
+    if let Some(other) = memory.overlapping(address, size, PRIORITY_DEVICE) {
+        return Err(format!(
+            "{name} at 0x{address:04X} overlaps the device at 0x{other:04X}"
+        ));
+    }
+    memory.add_memory(address, size, PRIORITY_DEVICE, device);
+    Ok(())
+}
+
+/// Run machines in one thread for `cycles` each, always stepping the one that is furthest behind.
+///
+/// Keeps the clocks of machines that talk to each other in step, e.g. over a [`crate::device::uart::UartBackend::link`].
+/// Halted and paused machines are skipped.
+pub fn run_together(machines: &mut [&mut Machine], cycles: usize) {
+    let ends: Vec<_> = machines
+        .iter()
+        .map(|machine| machine.cpu.cycle() + cycles)
+        .collect();
+    while let Some(machine) = machines
+        .iter_mut()
+        .zip(&ends)
+        .filter(|(machine, end)| {
+            machine.cpu.is_running() && !machine.is_paused() && machine.cpu.cycle() < **end
+        })
+        .map(|(machine, _)| machine)
+        .min_by_key(|machine| machine.cpu.cycle())
+    {
+        machine.step_instruction();
+    }
+}
+
+/// A binary and the address it is loaded at
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Image {
+    data: Vec<u8>,
+    load_address: u16,
+}
+
+impl Image {
+    pub fn new(
+        mut data: Vec<u8>,
+        as_cartridge: bool,
+        load_address: Option<u16>,
+    ) -> Result<Self, ImageError> {
+        let mut load_address = load_address;
+        let mut header_len = 0;
+        if as_cartridge {
+            let (cartridge_load_address, len) = cartridge_header(&data)?;
+            data = data.drain(4..(len + 4)).collect();
+            header_len = CartridgeHeader::LEN;
+            if load_address.is_none() {
+                info!("Using load address 0x{cartridge_load_address:04X} from cartridge header");
+                load_address = Some(cartridge_load_address);
+            }
+        }
+
+        if data.is_empty() {
+            return Err("data must not be empty".into());
+        }
+        let load_address = load_address.unwrap_or(0xE000);
+        let limit = 0x10000 - load_address as usize;
+        if data.len() > limit {
+            return Err(ImageError {
+                offset: Some(header_len + limit),
+                message: format!(
+                    "{} bytes loaded at 0x{load_address:04X} exceed the limit of {limit} bytes up to 0xFFFF",
+                    data.len()
+                ),
+            });
+        }
+        Ok(Self { data, load_address })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub const fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    fn last_written_address(&self) -> u16 {
+        (self.load_address as usize + self.data.len() - 1).min(0xFFFF) as u16
+    }
+
+    fn write_to(
+        &self,
+        ram: &mut Contiguous,
+        propeller_ram: &mut Contiguous,
+        rom: &mut Contiguous<Rom>,
+    ) {
+        let data = &self.data[..];
+        let load_address = self.load_address;
+        info!(
+            "Loading data at addresses 0x{load_address:04X}-0x{:04X}",
+            self.last_written_address()
+        );
+
+        if load_address >= 0xE000 {
+            rom.force_write_all(load_address - 0xE000, data);
+        } else if load_address >= 0xA000 {
+            let address = load_address - 0xA000;
+
+            let mut remaining = data.len();
+            let to_copy = remaining.min((0x4000 - address) as usize);
+            propeller_ram.force_write_all(address, &data[..to_copy]);
+
+            remaining -= to_copy;
+            if remaining > 0 {
+                rom.force_write_all(0, &data[to_copy..]);
+            }
+        } else {
+            let mut remaining = data.len();
+            let to_copy = remaining.min((0xA000 - load_address) as usize);
+            ram.force_write_all(load_address, &data[..to_copy]);
+
+            let mut offset = to_copy;
+            remaining -= to_copy;
+            let to_copy = remaining.min(0x4000);
+            if remaining > 0 {
+                propeller_ram.force_write_all(0, &data[offset..(offset + to_copy)]);
+
+                offset += to_copy;
+                remaining -= to_copy;
+                if remaining > 0 {
+                    rom.force_write_all(0, &data[offset..]);
+                }
+            }
+        }
+    }
+}
+
+/// Why a binary can't be loaded into the machine
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ImageError {
+    /// offset in the file the error is about
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+impl ImageError {
+    fn at(offset: usize, message: String) -> Self {
+        Self {
+            offset: Some(offset),
+            message,
+        }
+    }
+}
+
+impl From<String> for ImageError {
+    fn from(message: String) -> Self {
+        Self {
+            offset: None,
+            message,
+        }
+    }
+}
+
+impl From<&str> for ImageError {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// The header in front of the data of a cartridge: start and end address of the data, both inclusive
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CartridgeHeader {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl CartridgeHeader {
+    pub const LEN: usize = 4;
+
+    /// Header for `len` bytes of data loaded at `start`
+    pub fn for_data(start: u16, len: usize) -> Result<Self, String> {
+        let end = (start as usize + len)
+            .checked_sub(1)
+            .filter(|&end| len > 0 && end <= 0xFFFF)
+            .ok_or_else(|| format!("{len} bytes of data don't fit at 0x{start:04X}-0xFFFF"))?;
+        Ok(Self {
+            start,
+            end: end as u16,
+        })
+    }
+
+    /// Read the header at the start of `data`, the data itself is not checked
+    pub fn read(data: &[u8]) -> Result<Self, String> {
+        let [l0, h0, l1, h1, ..] = data[..] else {
+            return Err(format!(
+                "cartridge header must be at least 4 bytes, the file has {}",
+                data.len()
+            ));
+        };
+        Ok(Self {
+            start: u16::from_le_bytes([l0, h0]),
+            end: u16::from_le_bytes([l1, h1]),
+        })
+    }
+
+    /// Length of the data implied by the addresses
+    pub fn data_len(&self) -> Result<usize, String> {
+        if self.start > self.end {
+            return Err(format!(
+                "cartridge start address 0x{:04X} at offset 0 must be <= end address 0x{:04X} at offset 2",
+                self.start, self.end
+            ));
+        }
+        Ok((self.end - self.start) as usize + 1)
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::LEN] {
+        let [l0, h0] = self.start.to_le_bytes();
+        let [l1, h1] = self.end.to_le_bytes();
+        [l0, h0, l1, h1]
+    }
+}
+
+/// Load address and data length from a cartridge header
+pub fn cartridge_header(data: &[u8]) -> Result<(u16, usize), ImageError> {
+    let header = CartridgeHeader::read(data).map_err(|e| ImageError::at(0, e))?;
+    let len = header.data_len().map_err(|e| ImageError::at(0, e))?;
+    let available = data.len() - CartridgeHeader::LEN;
+    if available < len {
+        return Err(ImageError::at(
+            data.len(),
+            format!(
+                "cartridge data from offset 4 has {available} bytes, but the header implies {len}"
+            ),
+        ));
+    }
+    Ok((header.start, len))
+}
+
+/// Overrides for the vectors at the end of the ROM
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Vectors {
+    pub reset: Option<u16>,
+    pub irq: Option<u16>,
+    pub nmi: Option<u16>,
+}
+
+impl Vectors {
+    fn write_to(&self, rom: &mut Contiguous<Rom>, image: &Image) {
+        let load_address = image.load_address;
+        let last_written_address = image.last_written_address();
+        if let Some(reset_vector) = self.reset.or_else(|| if !(load_address..=last_written_address).contains(&cpu::RESET_VECTOR) {
+            // fall back to load address so we directly jump to it on startup
+            info!(
+                "Using load address 0x{load_address:04X} as reset vector, because the reset vector location was not written to"
+            );
+            Some(load_address)
+        } else {
+            None
+        }) {
+            // override value set from data
+            info!("Setting reset vector to 0x{reset_vector:04X}");
+            rom.force_write_u16(cpu::RESET_VECTOR - 0xE000, reset_vector);
+        } else {
+            info!(
+                "Using reset vector 0x{:04X} from ROM", rom.read_u16(cpu::RESET_VECTOR - 0xE000)
+            );
+        }
+        if let Some(irq_vector) = self.irq {
+            info!("Setting irq vector to 0x{irq_vector:04X}");
+            rom.force_write_u16(cpu::IRQ_VECTOR - 0xE000, irq_vector);
+        } else {
+            info!(
+                "Using irq vector 0x{:04X} from ROM",
+                rom.read_u16(cpu::IRQ_VECTOR - 0xE000)
+            );
+        }
+        if let Some(nmi_vector) = self.nmi {
+            info!("Setting nmi vector to 0x{nmi_vector:04X}");
+            rom.force_write_u16(cpu::NMI_VECTOR - 0xE000, nmi_vector);
+        } else {
+            info!(
+                "Using nmi vector 0x{:04X} from ROM",
+                rom.read_u16(cpu::NMI_VECTOR - 0xE000)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::raster::RASTER_COMPARE;
+    use crate::device::uart::UART_CNTL;
+    use crate::memory::guard::ViolationKind;
+    use crate::memory::logging::MemoryAccess;
+
+    fn machine() -> Machine {
+        // INC $10, BRA -4
+        let image = Image::new(vec![0xE6, 0x10, 0x80, 0xFC], false, None).unwrap();
+        Machine::new(image, Vectors::default())
+    }
+
+    #[test]
+    fn test_memory_map() {
+        // LDA $C000, STA $E100, STP
+        let program = vec![0xAD, 0x00, 0xC0, 0x8D, 0x00, 0xE1, 0xDB];
+        let mut machine = Machine::builder()
+            .rom(program.clone())
+            .memory_map(
+                MemoryMapBuilder::new()
+                    .ram(0xC000, 0x1000, &[0x42])
+                    .ram(0xE000, 0x2000, &program),
+            )
+            .build()
+            .unwrap();
+        machine.cpu.memory.write_u8(0xFFFC, 0x00);
+        machine.cpu.memory.write_u8(0xFFFD, 0xE0);
+        machine.warm_reset();
+        while machine.cpu.is_running() {
+            machine.step_instruction();
+        }
+        // the ROM was replaced by RAM
+        assert_eq!(machine.cpu.memory.read_u8(0xE100), 0x42);
+    }
+
+    #[test]
+    fn test_device_regions() {
+        let mut machine = Machine::builder()
+            .rom(vec![0xDB])
+            .mouse(0x9E00)
+            .raster(0x9E08)
+            .build()
+            .unwrap();
+        // only the VIA's 16 registers are decoded, the rest of its page is unmapped instead of RAM
+        machine.cpu.memory.write_u8(0x9F20, 0x42);
+        assert_eq!(machine.cpu.memory.read_u8(0x9F20), 0);
+        // the registers of the devices shadow the propeller RAM
+        machine.propeller_ram.borrow_mut().memory[0x3480] = 0x42;
+        assert_eq!(machine.cpu.memory.read_u8(UART1_BASE + UART_CNTL), 0);
+        machine.cpu.memory.write_u8(0x9E08 + RASTER_COMPARE, 0x42);
+        assert_eq!(machine.cpu.memory.read_u8(0x9E08 + RASTER_COMPARE), 0x42);
+
+        let error = Machine::builder()
+            .rom(vec![0xDB])
+            .mouse(0x9E00)
+            .light_pen(0x9E02)
+            .build()
+            .err()
+            .map(|e| e.message);
+        assert_eq!(
+            error.as_deref(),
+            Some("light pen at 0x9E02 overlaps the device at 0x9E00")
+        );
+    }
+
+    #[test]
+    fn test_framebuffer() {
+        let mut machine = Machine::builder()
+            .rom(vec![0xDB])
+            .palette(Palette::greyscale())
+            .border(Border::Large)
+            .build()
+            .unwrap();
+        machine.cpu.memory.write_u8(0xD002, 0x01); // white border
+        let frame = machine.framebuffer();
+        let frame_len = frame.len();
+        assert_eq!(
+            frame_len,
+            (Border::Large.width() * Border::Large.height()) as usize
+        );
+        assert_eq!(frame[0], Palette::greyscale().0[1]);
+
+        machine.cpu.memory.write_u8(0xD002, 0x0E);
+        let indexed = machine.framebuffer_indexed();
+        assert_eq!(indexed.len(), frame_len);
+        assert_eq!(indexed[0], 0x0E);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut machine = machine();
+        machine.pause();
+        assert_eq!(machine.step_instruction(), 0);
+        machine.resume();
+        assert_ne!(machine.step_instruction(), 0);
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 1);
+    }
+
+    #[test]
+    fn test_trigger_nmi() {
+        let mut machine = machine();
+        machine.step_instruction();
+        machine.trigger_nmi();
+        machine.step_instruction();
+        assert_eq!(machine.cpu.nmi_count(), 1);
+        assert!(machine.cpu.p.irqb_disable());
+        machine.step_instruction();
+        assert_eq!(machine.cpu.nmi_count(), 1);
+    }
+
+    #[test]
+    fn test_trace_interrupts() {
+        let mut machine = machine();
+        machine.interrupt_trace = Some(InterruptTrace::new(VblankInterrupt::None, &[]));
+        machine.step_instruction();
+        machine.trigger_nmi();
+        machine.step_instruction();
+        let event = &machine.interrupt_trace().unwrap().log()[0];
+        assert_eq!(event.kind, InterruptKind::Nmi);
+        assert_eq!(event.from, 0xE002);
+        assert_eq!(event.sources, ["NMI button"]);
+        assert_eq!(event.latency, Some(0));
+        assert_eq!(machine.interrupt_trace().unwrap().log().len(), 1);
+    }
+
+    #[test]
+    fn test_run_together() {
+        let (mut a, mut b, mut c) = (machine(), machine(), machine());
+        c.pause();
+        let start = a.cpu.cycle();
+        run_together(&mut [&mut a, &mut b, &mut c], 100);
+        // INC zp takes 5 cycles, BRA 3
+        assert!((start + 100..start + 105).contains(&a.cpu.cycle()));
+        assert_eq!(a.cpu.cycle(), b.cpu.cycle());
+        assert_eq!(a.cpu.memory.read_u8(0x10), b.cpu.memory.read_u8(0x10));
+        assert_eq!(c.cpu.cycle(), start);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut machine = machine();
+        machine.add_breakpoint(Breakpoint::at(0xE002));
+        machine.step_instruction();
+        assert_eq!(machine.step_instruction(), 0);
+        assert!(machine.is_paused());
+        assert_eq!(machine.cpu.pc, 0xE002);
+        assert_eq!(machine.breakpoint_hit(), Some(0xE002));
+
+        assert_ne!(machine.single_step(), 0);
+        assert!(machine.is_paused());
+        assert_eq!(machine.cpu.pc, 0xE000);
+        assert_eq!(machine.breakpoint_hit(), None);
+
+        machine.resume();
+        machine.step_instruction();
+        assert_eq!(machine.step_instruction(), 0);
+        assert!(machine.is_paused());
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 2);
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut machine = machine();
+        machine.add_breakpoint("0xE002 if mem[0x10] == 2; log {mem[0x10]}".parse().unwrap());
+        machine.add_breakpoint("0xE000; continue".parse().unwrap());
+        for _ in 0..3 {
+            machine.step_instruction();
+        }
+        assert!(!machine.is_paused());
+        machine.step_instruction();
+        assert!(machine.is_paused());
+        assert_eq!(machine.cpu.pc, 0xE002);
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 2);
+    }
+
+    #[test]
+    fn test_step_out() {
+        // JSR sub, INC $10, STP; sub: NOP, NOP, RTS
+        let program = vec![0x20, 0x06, 0xE0, 0xE6, 0x10, 0xDB, 0xEA, 0xEA, 0x60];
+        let image = Image::new(program, false, None).unwrap();
+        let mut machine = Machine::new(image, Vectors::default());
+        assert!(!machine.step_out());
+        machine.step_instruction();
+        assert_eq!(machine.call_stack().depth(), 2);
+
+        assert!(machine.step_out());
+        for _ in 0..10 {
+            machine.step_instruction();
+        }
+        assert!(machine.is_paused());
+        assert_eq!(machine.cpu.pc, 0xE003);
+        assert_eq!(machine.call_stack().depth(), 1);
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 0);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut machine = machine();
+        machine.step_instruction();
+        let snapshot = machine.snapshot();
+        for _ in 0..3 {
+            machine.step_instruction();
+        }
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 2);
+
+        let cycle = machine.cpu.cycle();
+        machine.restore(&snapshot);
+        assert_eq!(machine.cpu.pc, 0xE002);
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 1);
+        assert_eq!(machine.cpu.cycle(), cycle);
+    }
+
+    #[test]
+    fn test_snapshot_devices() {
+        // LDA #$40, STA ACR, LDA #$20, STA T1CL, LDA #0, STA T1CH, BRA -2
+        let program = vec![
+            0xA9, 0x40, 0x8D, 0x0B, 0x9F, 0xA9, 0x20, 0x8D, 0x04, 0x9F, 0xA9, 0x00, 0x8D, 0x05,
+            0x9F, 0x80, 0xFE,
+        ];
+        let mut machine = Machine::builder().rom(program).build().unwrap();
+        for _ in 0..7 {
+            machine.step_instruction();
+        }
+        let snapshot = machine.snapshot();
+        for _ in 0..20 {
+            machine.step_instruction();
+        }
+        let expected = machine.snapshot();
+        assert_ne!(expected.devices, snapshot.devices);
+
+        // the free-running timer continues where it was instead of where it is now
+        machine.restore(&snapshot);
+        assert_eq!(machine.snapshot(), snapshot);
+        for _ in 0..20 {
+            machine.step_instruction();
+        }
+        assert_eq!(machine.snapshot(), expected);
+    }
+
+    #[test]
+    fn test_trap() {
+        // JSR wait, STP, wait: BRA wait
+        let program = vec![0x20, 0x04, 0xE0, 0xDB, 0x80, 0xFE];
+        let mut machine = Machine::builder()
+            .rom(program)
+            .trap(
+                0xE004,
+                Box::new(|cpu| {
+                    cpu.memory.write_u8(0x10, 0x42);
+                    TrapAction::Return
+                }),
+            )
+            .build()
+            .unwrap();
+        for _ in 0..3 {
+            machine.step_instruction();
+        }
+        assert!(!machine.cpu.is_running());
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 0x42);
+        assert_eq!(machine.cpu.s, cpu::INITIAL_STACK_POINTER);
+    }
+
+    #[test]
+    fn test_builder() {
+        assert!(Machine::builder().build().is_err());
+
+        // LDA #$FF, STA $9E02, LDA #$42, STA $9E00, STP
+        let program = vec![
+            0xA9, 0xFF, 0x8D, 0x02, 0x9E, 0xA9, 0x42, 0x8D, 0x00, 0x9E, 0xDB,
+        ];
+        let mut machine = Machine::builder()
+            .rom(program)
+            .expansion_via(0x9E00)
+            .build()
+            .unwrap();
+        while machine.cpu.is_running() {
+            machine.step_instruction();
+        }
+        let (address, _, port_b) = &machine.devices().expansion_ports[0];
+        assert_eq!(*address, 0x9E00);
+        assert_eq!(port_b.borrow().lines(), 0x42);
+    }
+
+    #[test]
+    fn test_device_log() {
+        // LDA #$05, STA $10, STA VIA_T1LL, STP
+        let program = vec![0xA9, 0x05, 0x85, 0x10, 0x8D, 0x06, 0x9F, 0xDB];
+        let mut machine = Machine::builder()
+            .rom(program)
+            .device_log(true)
+            .build()
+            .unwrap();
+        while machine.cpu.is_running() {
+            machine.step_instruction();
+        }
+        let log = machine.device_log().unwrap();
+        let accesses = log.accesses();
+        assert_eq!(accesses.len(), 1);
+        assert_eq!(accesses[0].access, MemoryAccess::write(0x9F06, 0x05));
+        assert_eq!(accesses[0].pc, 0xE004);
+    }
+
+    #[test]
+    fn test_host_bridge() {
+        // LDA #$07, STA $9E03, loop: BRA loop
+        let program = vec![0xA9, 0x07, 0x8D, 0x03, 0x9E, 0x80, 0xFE];
+        let mut machine = Machine::builder()
+            .rom(program)
+            .host_bridge(
+                0x9E00,
+                HostBridge::new(UartSource::empty(), UartSink::discard()),
+            )
+            .build()
+            .unwrap();
+        for _ in 0..4 {
+            machine.step_instruction();
+        }
+        assert!(!machine.cpu.is_running());
+        assert_eq!(machine.host_exit(), Some(7));
+        assert_eq!(machine.cpu.pc, 0xE005);
+
+        machine.warm_reset();
+        assert_eq!(machine.host_exit(), None);
+    }
+
+    #[test]
+    fn test_memory_guard() {
+        // LDA $10, STA $E000, STP
+        let program = vec![0xA5, 0x10, 0x8D, 0x00, 0xE0, 0xDB];
+        let mut machine = Machine::builder()
+            .rom(program.clone())
+            .memory_guard(GuardPolicy::Warn)
+            .build()
+            .unwrap();
+        while machine.cpu.is_running() {
+            machine.step_instruction();
+        }
+        let reported = &machine.guard.as_ref().unwrap().reported;
+        assert!(reported.contains(&(
+            Violation {
+                kind: ViolationKind::UninitializedRead,
+                address: 0x10
+            },
+            0xE000
+        )));
+        assert!(reported.contains(&(
+            Violation {
+                kind: ViolationKind::RomWrite,
+                address: 0xE000
+            },
+            0xE002
+        )));
+
+        let mut machine = Machine::builder()
+            .rom(program)
+            .memory_guard(GuardPolicy::Trap)
+            .build()
+            .unwrap();
+        machine.step_instruction();
+        assert!(!machine.cpu.is_running());
+        assert_eq!(machine.cpu.pc, 0xE000);
+    }
+
+    #[test]
+    fn test_memory_guard_reset() {
+        let mut machine = Machine::builder()
+            .rom(vec![0xDB])
+            .memory_guard(GuardPolicy::Warn)
+            .build()
+            .unwrap();
+        let state = Rc::clone(&machine.guard.as_ref().unwrap().state);
+        // the reset sequence reads the uninitialized stack
+        machine.warm_reset();
+        assert_eq!(state.borrow().violation_count(), 0);
+
+        // a violation from before the reset is kept
+        machine.cpu.memory.write_u8(0xE000, 0);
+        machine.warm_reset();
+        assert_eq!(
+            state.borrow_mut().take_violations(),
+            [Violation {
+                kind: ViolationKind::RomWrite,
+                address: 0xE000
+            }]
+        );
+    }
+
+    #[test]
+    fn test_resets() {
+        let mut machine = machine();
+        for _ in 0..4 {
+            machine.step_instruction();
+        }
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 2);
+
+        machine.warm_reset();
+        assert_eq!(machine.cpu.pc, 0xE000);
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 2);
+
+        machine.hard_reset();
+        assert_eq!(machine.cpu.pc, 0xE000);
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 0);
+    }
+
+    #[test]
+    fn test_cartridge_header() {
+        let header = CartridgeHeader::for_data(0xE000, 0x100).unwrap();
+        assert_eq!(header.to_bytes(), [0x00, 0xE0, 0xFF, 0xE0]);
+        assert_eq!(CartridgeHeader::read(&header.to_bytes()), Ok(header));
+        assert_eq!(header.data_len(), Ok(0x100));
+        assert!(CartridgeHeader::for_data(0xFF00, 0x101).is_err());
+        assert!(CartridgeHeader::for_data(0xE000, 0).is_err());
+
+        assert!(CartridgeHeader::read(&[0x00, 0xE0, 0xFF]).is_err());
+        let reversed = CartridgeHeader {
+            start: 0xE001,
+            end: 0xE000,
+        };
+        assert!(reversed.data_len().is_err());
+
+        assert_eq!(
+            cartridge_header(&[0x00, 0x02, 0x01, 0x02, 1, 2, 3]),
+            Ok((0x0200, 2))
+        );
+        assert_eq!(
+            cartridge_header(&[0x00, 0x02, 0x01, 0x02, 1]).map_err(|e| e.offset),
+            Err(Some(5))
+        );
+    }
+
+    #[test]
+    fn test_image_limit() {
+        assert!(Image::new(vec![0; 0x2000], false, None).is_ok());
+        let error = Image::new(vec![0; 0x2001], false, None).unwrap_err();
+        assert_eq!(error.offset, Some(0x2000));
+        assert_eq!(
+            error.message,
+            "8193 bytes loaded at 0xE000 exceed the limit of 8192 bytes up to 0xFFFF"
+        );
+
+        // the offset counts the cartridge header
+        let mut cartridge = CartridgeHeader::for_data(0xFFF0, 0x10)
+            .unwrap()
+            .to_bytes()
+            .to_vec();
+        cartridge.extend([0; 0x10]);
+        assert!(Image::new(cartridge.clone(), true, None).is_ok());
+        let error = Image::new(cartridge, true, Some(0xFFF8)).unwrap_err();
+        assert_eq!(error.offset, Some(4 + 8));
+    }
+}
@@ -1,15 +1,85 @@
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
 use clap_num::maybe_hex;
-use cody_emulator::assembler::disassemble;
+use cody_emulator::assembler;
+use cody_emulator::assembler::ListingFormat;
+use cody_emulator::bench;
+use cody_emulator::bench::BenchEnd;
+use cody_emulator::breakpoint::Breakpoint;
+use cody_emulator::config;
+use cody_emulator::config::{Config, MemoryRegion};
+use cody_emulator::coverage;
+use cody_emulator::cpu;
+use cody_emulator::cpu::{Engine, IllegalOpcodePolicy};
+use cody_emulator::device::blanking::VblankInterrupt;
+use cody_emulator::device::host_bridge::HostBridge;
+use cody_emulator::device::keyboard::{
+    InputMacro, JOYSTICK1, JOYSTICK2, JoystickBindings, KeyboardEmulation,
+};
+use cody_emulator::device::uart::{UartSink, UartSource, UartStdioMode};
+use cody_emulator::device::vid::{Border, Firmware, Palette};
+use cody_emulator::device::xmodem::XmodemTransfer;
+use cody_emulator::filter::{AspectRatio, DisplayFilter};
 use cody_emulator::frontend;
+use cody_emulator::frontend::{DisplayMode, Expectation, FrontendOptions, RunLimits, Warp};
+use cody_emulator::machine::{CartridgeHeader, Machine, Vectors};
+use cody_emulator::memory::guard::GuardPolicy;
+use cody_emulator::rom::{self, RomVersion};
+use cody_emulator::symbols;
+use cody_emulator::timing::{TimingProfile, VideoStandard};
+#[cfg(feature = "tui")]
+use cody_emulator::tui::LogBuffer;
+use cody_emulator::vcd::VcdSignal;
+use cody_emulator::watch::Watch;
+use log::{info, warn};
+use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    /// Binary file
-    file: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+
+    /// Each time this option is added increases the default logging level
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a binary in the emulator
+    Run(RunArgs),
+    /// Assemble a source file into a binary
+    Asm(AsmArgs),
+    /// Print the disassembly of a binary
+    Dasm(DasmArgs),
+    /// Run a binary headless until a run limit is reached and check the final state, exits with 1 if an expectation failed
+    Test(TestArgs),
+    /// Run a binary headless until STP or a target address and print the exact cycle and instruction counts with a per-opcode histogram, exits with 1 if the cycle limit was reached
+    Bench(BenchArgs),
+    /// Print and check the header of a cartridge, can also strip the header or add one to a plain binary
+    CartInfo(CartInfoArgs),
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Binary file, can also be set in the config file
+    file: Option<PathBuf>,
+
+    /// Machine configuration file, `cody.toml` in the working directory is used if it exists.
+    ///
+    /// Options given on the command line take precedence over the config file.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// CodyBASIC ROM to run if no binary file is given, defaults to `$CODY_ROM` or `cody_emulator/codybasic.bin` in the XDG data directories
+    #[arg(long, conflicts_with = "file")]
+    rom: Option<PathBuf>,
 
     /// Load the binary file as a cartridge, expects the file to have a cartridge header
     #[arg(long, default_value_t = false)]
@@ -35,6 +105,30 @@ struct Cli {
     #[arg(long)]
     uart1_source: Option<PathBuf>,
 
+    /// Path of file that receives all bytes transmitted on UART1, use `-` for stdout
+    #[arg(long)]
+    uart1_sink: Option<PathBuf>,
+
+    /// Connect UART1 to the terminal: receive from stdin and transmit to stdout.
+    ///
+    /// Data from --uart1-source is received first. Put the terminal into raw mode yourself (e.g. `stty raw -echo`) to forward single key presses.
+    #[arg(long, value_enum, conflicts_with = "uart1_sink")]
+    uart1_stdio: Option<UartStdioMode>,
+
+    /// Transfer a file over XMODEM on UART1, either `send:<FILE>` to the Cody or `receive:<FILE>` from the Cody
+    #[arg(long, conflicts_with_all = ["uart1_source", "uart1_sink", "uart1_stdio"])]
+    uart1_xmodem: Option<XmodemTransfer>,
+
+    /// Run a second Cody with this binary in the background, its UART2 connected to UART2 of the first one by a null modem cable.
+    ///
+    /// The binary is loaded as a cartridge if it has a matching header. Use this to test two player serial games and file transfers.
+    #[arg(long)]
+    uart2_link: Option<PathBuf>,
+
+    /// Cycles between a byte leaving one end of the --uart2-link cable and arriving at the other
+    #[arg(long, default_value_t = 0, requires = "uart2_link")]
+    link_latency: usize,
+
     /// This option will normalize newlines when reading text data for the UART.
     ///
     /// Use this when your input text file might have CRLF-style line endings or to make sure it works for CodyBASIC's LOAD 1,0 command.
@@ -45,17 +139,448 @@ struct Cli {
     #[arg(long, default_value_t = false)]
     physical_keyboard: bool,
 
-    /// Run the cpu as fast as possible.
+    /// Joystick keys, set by the `[joystick1]` and `[joystick2]` sections of the config
+    #[arg(skip)]
+    joysticks: JoystickBindings,
+
+    /// Host keys that run input macros, set by the `[[macros]]` sections of the config
+    #[arg(skip)]
+    macros: Vec<InputMacro>,
+
+    /// Start with autofire on the joystick fire buttons, held buttons are pressed and released every 3 frames. F11 toggles autofire
+    #[arg(long, default_value_t = false)]
+    autofire: bool,
+
+    /// Start in warp mode, running the cpu faster than real time. F9 toggles warp mode.
     #[arg(long, default_value_t = false)]
     fast: bool,
 
-    /// Each time this option is added increases the default logging level
-    #[arg(short, long, action = clap::ArgAction::Count)]
-    verbose: u8,
+    /// Speed relative to real time while warping, 0 runs the cpu as fast as possible.
+    #[arg(long, default_value_t = 0)]
+    warp_factor: u32,
+
+    /// Number of frames not shown between two shown frames while warping.
+    #[arg(long, default_value_t = 0)]
+    frame_skip: u32,
+
+    /// Warp while UART1 receives data, e.g. while CodyBASIC loads a program with LOAD 1,0.
+    #[arg(long, default_value_t = false)]
+    auto_warp: bool,
+
+    /// What to do when the cpu encounters an opcode that is undefined on the 65C02.
+    ///
+    /// The number of illegal opcodes encountered is reported at exit.
+    #[arg(long, value_enum, default_value_t = IllegalOpcodePolicy::Nop)]
+    on_illegal: IllegalOpcodePolicy,
+
+    /// How the cpu fetches instructions, the cached engine speeds up warp mode and headless runs
+    #[arg(long, value_enum, default_value_t = Engine::Interpreter)]
+    engine: Engine,
+
+    /// Color palette, either one of the presets `cody`, `pepto` and `greyscale` or a file with 16 hex RRGGBB colors
+    #[arg(long, default_value = "cody")]
+    palette: Palette,
+
+    /// Post-processing applied to the picture shown in the window
+    #[arg(long, value_enum, default_value_t = DisplayFilter::None)]
+    display_filter: DisplayFilter,
+
+    /// Shape of the pixels, the picture is letterboxed to keep it
+    #[arg(long, value_enum, default_value_t = AspectRatio::Square)]
+    aspect_ratio: AspectRatio,
+
+    /// How much of the border around the 320x200 content area is shown
+    #[arg(long, value_enum, default_value_t = Border::Normal)]
+    border: Border,
+
+    /// Start in fullscreen mode, toggle with Alt+Enter
+    #[arg(long, default_value_t = false)]
+    fullscreen: bool,
+
+    /// Show the picture at this integer scale, resizing the window snaps to multiples of the picture.
+    ///
+    /// Without it the window starts as large as fits the screen and can be resized freely.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    scale: Option<u32>,
+
+    /// Stop the emulator after this many cpu cycles
+    #[arg(long)]
+    max_cycles: Option<usize>,
+
+    /// Stop the emulator after this many frames
+    #[arg(long)]
+    max_frames: Option<usize>,
+
+    /// Stop the emulator when the cpu executes STP
+    #[arg(long, default_value_t = false)]
+    until_stp: bool,
+
+    /// Stop the emulator when the cpu executes STP and exit with the value of the A register, letting test programs report their result
+    #[arg(long, default_value_t = false)]
+    stp_exit_code: bool,
+
+    /// Fail with exit code 124 if the program neither executed STP nor exited through the host bridge after this many cpu cycles
+    #[arg(long)]
+    timeout_cycles: Option<usize>,
+
+    /// Exit code when the emulator was stopped by --max-cycles, --max-frames or --until-stp
+    #[arg(long, default_value_t = 0)]
+    exit_code: i32,
+
+    /// Print the final cpu state when the emulator was stopped by --max-cycles, --max-frames or --until-stp
+    #[arg(long, default_value_t = false)]
+    dump_state: bool,
+
+    /// Interrupt raised at the start of vertical blanking
+    #[arg(long, value_enum, default_value_t = VblankInterrupt::None)]
+    vblank_interrupt: VblankInterrupt,
+
+    /// Video standard of the propeller, decides the frame rate and the length of vertical blanking
+    #[arg(long, value_enum, default_value_t = VideoStandard::Ntsc)]
+    video_standard: VideoStandard,
+
+    /// Revision of the propeller firmware, software written for one may depend on its video registers
+    #[arg(long, value_enum, default_value_t = Firmware::Rev2)]
+    firmware: Firmware,
+
+    /// Cpu clock in Hz, the real hardware runs at 1MHz
+    #[arg(long, default_value_t = TimingProfile::CODY_FREQUENCY, value_parser = clap::value_parser!(u32).range(TimingProfile::MIN_FREQUENCY as i64..=TimingProfile::MAX_FREQUENCY as i64))]
+    cpu_frequency: u32,
+
+    /// Repeat the VIA's 16 registers over its whole 256 byte region like the partially decoded real hardware
+    #[arg(long, default_value_t = false)]
+    mirror_via: bool,
+
+    /// Keys held together show up in other rows like in the keyboard matrix without diodes, three keys in a rectangle make the fourth one appear pressed
+    #[arg(long, default_value_t = false)]
+    keyboard_ghosting: bool,
+
+    /// Reads of unmapped addresses return the last value on the bus instead of 0, some software unintentionally depends on it
+    #[arg(long, default_value_t = false)]
+    open_bus: bool,
+
+    /// Check for writes to ROM and reads of RAM that was never written, the loaded binary counts as written
+    #[arg(long, value_enum, default_value_t = GuardPolicy::Off)]
+    memory_guard: GuardPolicy,
+
+    /// Attach an additional VIA, e.g. for user-port peripherals, can be given multiple times.
+    ///
+    /// Its port lines are printed by --dump-state.
+    #[arg(long, value_parser=maybe_hex::<u16>)]
+    expansion_via: Vec<u16>,
+
+    /// Map the host bridge's 8 registers at this address, letting test programs print to stdout, read stdin, read the cycle counter and stop the emulation with a process exit code.
+    ///
+    /// Registers: +0 putchar, +1 getchar (0 if no input), +2 status (bit 0: input available), +3 exit code, +4..+7 cycle counter (little endian, latched by reading +4).
+    #[arg(long, value_parser=maybe_hex::<u16>, conflicts_with = "uart1_stdio")]
+    host_bridge: Option<u16>,
+
+    /// Map a mouse's registers at this address, driven by the host pointer over the window, which also serves as a pair of paddles.
+    ///
+    /// Registers: +0 x position (0-159), +1 y position (0-199), +2 buttons (bit 0: left, bit 1: right, bit 2: middle).
+    #[arg(long, value_parser=maybe_hex::<u16>)]
+    mouse: Option<u16>,
+
+    /// Map a light pen's registers at this address, it latches the beam position when the beam passes the host pointer and its button is the left mouse button.
+    ///
+    /// Registers: +0 latched x position (0-159), +1 latched y position (0-199), +2 status (bit 0: latched since the last read of the status, bit 1: button held).
+    #[arg(long, value_parser=maybe_hex::<u16>)]
+    light_pen: Option<u16>,
+
+    /// Map a raster line counter at this address, with an IRQ when the beam reaches a compare line for split screen effects.
+    ///
+    /// Registers: +0/+1 line since the start of vertical blanking (9 bits), +2/+3 compare line (9 bits), +4 control (bit 0: compare line reached, write 1 to acknowledge, bit 7: IRQ while bit 0 is set).
+    #[arg(long, value_parser=maybe_hex::<u16>)]
+    raster: Option<u16>,
+
+    /// Listen on this address (e.g. 127.0.0.1:6502) and stream a line with frame number, cycle count and frame hash to every client after each rendered frame
+    #[arg(long)]
+    control_socket: Option<String>,
+
+    /// Record the keyboard and joystick input together with the initial machine state to this file.
+    ///
+    /// Hotkeys that reset, rewind or interrupt the machine and dropped files are ignored while recording.
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Replay input recorded with --record, the emulation runs exactly like when it was recorded
+    #[arg(long, conflicts_with = "record")]
+    playback: Option<PathBuf>,
+
+    /// Type this CodyBASIC program on the keyboard one second after boot, the host keyboard is ignored until it is typed in
+    #[arg(long, conflicts_with_all = ["record", "playback"])]
+    basic: Option<PathBuf>,
+
+    /// Write the number of reads, writes and executes of every address and the cycles spent per instruction as CSV to this file at exit
+    #[arg(long)]
+    profile: Option<PathBuf>,
+
+    /// Write the reads (green), writes (red) and executes (blue) of every address as 256x256 PPM image to this file at exit
+    #[arg(long)]
+    heatmap: Option<PathBuf>,
+
+    /// Write a listing of the loaded binary with every executed instruction marked to this file at exit
+    #[arg(long)]
+    coverage: Option<PathBuf>,
+
+    /// Write the cycles spent in every subroutine as flat profile and call graph to this file at exit
+    #[arg(long)]
+    call_profile: Option<PathBuf>,
+
+    /// Write the most recent reads and writes of the VIA, video registers and UARTs with the instruction and cycle of each as CSV to this file at exit
+    #[arg(long)]
+    device_log: Option<PathBuf>,
+
+    /// Write the IRQ and NMI lines, vertical blanking, VIA timer flags and UART1 output as Value Change Dump to this file, e.g. for GTKWave
+    #[arg(long)]
+    vcd: Option<PathBuf>,
+
+    /// Signals written to --vcd, separated by commas
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = VcdSignal::ALL)]
+    vcd_signals: Vec<VcdSignal>,
+
+    /// Symbol file with one `name = value` definition per line, used to name addresses in --coverage, --call-profile and --device-log
+    #[arg(long)]
+    symbols: Option<PathBuf>,
+
+    /// Log every change of a register (`a`, `x`, `y`, `s`, `p`, `pc`), byte (`ADDRESS`), word (`ADDRESS.w`) or range of bytes (`START-END`) at info level, can be given multiple times
+    #[arg(long)]
+    watch: Vec<Watch>,
+
+    /// Pause before executing the instruction at an address, `ADDRESS[ if CONDITION][; ACTION]...`, see the README, can be given multiple times
+    #[arg(long = "break")]
+    breakpoints: Vec<Breakpoint>,
+
+    /// Log every IRQ, NMI and BRK with its cycle, the device that raised it, the vector target and its latency at info level
+    #[arg(long, default_value_t = false)]
+    trace_interrupts: bool,
+
+    /// Serve Prometheus metrics on this address (e.g. 127.0.0.1:9650) at /metrics
+    #[cfg(feature = "metrics")]
+    #[arg(long)]
+    metrics_address: Option<String>,
+
+    /// Run without a window, useful together with --max-cycles, --max-frames or --until-stp
+    #[arg(long, default_value_t = false)]
+    headless: bool,
+
+    /// Show a terminal dashboard with emulator statistics, UART1 output and log messages
+    #[cfg(feature = "tui")]
+    #[arg(long, default_value_t = false, requires = "headless")]
+    dashboard: bool,
+}
+
+#[derive(Args)]
+struct AsmArgs {
+    /// Source file, see the README for the syntax
+    file: PathBuf,
+
+    /// Output file, defaults to the source file with a .bin extension
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Address the binary is loaded at
+    #[arg(long, value_parser=maybe_hex::<u16>, default_value = "0xE000")]
+    origin: u16,
+
+    /// Don't predefine the names of the Cody's hardware registers (e.g. `VID_CTRL`), for generic 6502 programs
+    #[arg(long, default_value_t = false)]
+    no_cody_symbols: bool,
+}
+
+#[derive(Args)]
+struct DasmArgs {
+    /// Binary file
+    file: PathBuf,
+
+    /// Address the binary is loaded at
+    #[arg(long, value_parser=maybe_hex::<u16>, default_value = "0xE000")]
+    load_address: u16,
+
+    /// File with one `name = value` definition per line, matching addresses are replaced by their name
+    #[arg(long)]
+    symbols: Option<PathBuf>,
+
+    /// Don't name the Cody's hardware registers (e.g. `VID_CTRL`), for generic 6502 programs
+    #[arg(long, default_value_t = false)]
+    no_cody_symbols: bool,
+
+    /// Only decode code reachable from the vectors (or the load address if the binary does not contain them), the rest is printed as `.byte` data
+    #[arg(long, default_value_t = false)]
+    analyze: bool,
+
+    /// Report of `run --coverage` or file with one hex address per line, the executed addresses are code, implies --analyze
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Print a listing or source that assembles to the same binary with `asm` (native) or ca65
+    #[arg(long, value_enum, default_value_t = ListingFormat::Plain)]
+    format: ListingFormat,
+}
+
+#[derive(Args)]
+struct TestArgs {
+    #[command(flatten)]
+    run: RunArgs,
+
+    /// Check the final state: `a=N`, `x=N`, `y=N`, `pc=N`, `ADDRESS=N` or `uart1=TEXT` (recent UART1 output contains TEXT), can be given multiple times
+    #[arg(long = "expect")]
+    expectations: Vec<Expectation>,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    /// Binary file
+    file: PathBuf,
+
+    /// Load the binary file as a cartridge, expects the file to have a cartridge header
+    #[arg(long, default_value_t = false)]
+    as_cartridge: bool,
+
+    /// Load address, default value is 0xE000
+    #[arg(long, value_parser=maybe_hex::<u16>)]
+    load_address: Option<u16>,
+
+    /// Stop when the program counter reaches this address, before executing the instruction there
+    #[arg(long, value_parser=maybe_hex::<u16>)]
+    until_pc: Option<u16>,
+
+    /// Give up after this many cpu cycles
+    #[arg(long, default_value_t = 1_000_000_000)]
+    max_cycles: u64,
+
+    /// How the cpu fetches instructions
+    #[arg(long, value_enum, default_value_t = Engine::Interpreter)]
+    engine: Engine,
+}
+
+#[derive(Args)]
+struct CartInfoArgs {
+    /// Cartridge file, or plain binary together with --add-header
+    file: PathBuf,
+
+    /// Write the data of the cartridge without its header to this file
+    #[arg(long, conflicts_with = "add_header")]
+    strip_header: Option<PathBuf>,
+
+    /// Write the plain binary with a header for --load-address to this file
+    #[arg(long)]
+    add_header: Option<PathBuf>,
+
+    /// Load address written by --add-header
+    #[arg(long, value_parser=maybe_hex::<u16>, default_value = "0xE000")]
+    load_address: u16,
+}
+
+impl RunArgs {
+    /// Fill in everything that was not given on the command line from `config`
+    fn apply_config(
+        &mut self,
+        config: Config,
+        matches: &ArgMatches,
+    ) -> Result<Vec<MemoryRegion>, String> {
+        let is_default = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+
+        let binary = config.binary;
+        if self.rom.is_none() {
+            self.file = self.file.take().or(binary.path);
+        }
+        self.as_cartridge |= binary.cartridge;
+        self.load_address = self.load_address.or(binary.load_address);
+        self.reset_vector = self.reset_vector.or(binary.reset_vector);
+        self.irq_vector = self.irq_vector.or(binary.irq_vector);
+        self.nmi_vector = self.nmi_vector.or(binary.nmi_vector);
+
+        if let Some(vblank_interrupt) = config.devices.vblank_interrupt
+            && is_default("vblank_interrupt")
+        {
+            self.vblank_interrupt = vblank_interrupt;
+        }
+        if let Some(video_standard) = config.devices.video_standard
+            && is_default("video_standard")
+        {
+            self.video_standard = video_standard;
+        }
+        if let Some(firmware) = config.devices.firmware
+            && is_default("firmware")
+        {
+            self.firmware = firmware;
+        }
+        if let Some(cpu_frequency) = config.devices.cpu_frequency
+            && is_default("cpu_frequency")
+        {
+            self.cpu_frequency = TimingProfile::check_frequency(cpu_frequency)?;
+        }
+        self.mirror_via |= config.devices.mirror_via;
+        self.keyboard_ghosting |= config.devices.keyboard_ghosting;
+        self.open_bus |= config.devices.open_bus;
+        if self.expansion_via.is_empty() {
+            self.expansion_via = config.devices.expansion_vias;
+        }
+        self.host_bridge = self.host_bridge.or(config.devices.host_bridge);
+        self.mouse = self.mouse.or(config.devices.mouse);
+        self.light_pen = self.light_pen.or(config.devices.light_pen);
+        self.raster = self.raster.or(config.devices.raster);
+
+        let uart1 = config.uart1;
+        self.uart1_source = self.uart1_source.take().or(uart1.source);
+        self.uart1_sink = self.uart1_sink.take().or(uart1.sink);
+        self.uart1_stdio = self.uart1_stdio.or(uart1.stdio);
+        self.fix_newlines |= uart1.fix_newlines;
+
+        let display = config.display;
+        if let Some(palette) = display.palette
+            && is_default("palette")
+        {
+            self.palette = palette.parse()?;
+        }
+        if let Some(filter) = display.filter
+            && is_default("display_filter")
+        {
+            self.display_filter = filter;
+        }
+        if let Some(aspect_ratio) = display.aspect_ratio
+            && is_default("aspect_ratio")
+        {
+            self.aspect_ratio = aspect_ratio;
+        }
+        if let Some(border) = display.border
+            && is_default("border")
+        {
+            self.border = border;
+        }
+        self.fullscreen |= display.fullscreen;
+        if let Some(scale) = display.scale
+            && self.scale.is_none()
+        {
+            if scale == 0 {
+                return Err("display scale must be at least 1".to_string());
+            }
+            self.scale = Some(scale);
+        }
+
+        self.physical_keyboard |= config.keymap == Some(KeyboardEmulation::Physical);
+        for (joystick, codes) in [
+            (&config.joystick1, JOYSTICK1),
+            (&config.joystick2, JOYSTICK2),
+        ] {
+            for (keys, code) in joystick.keys().into_iter().zip(codes) {
+                if let Some(keys) = keys {
+                    self.joysticks.bind(code, keys.iter().map(|key| key.0));
+                }
+            }
+        }
+
+        self.macros = config.macros;
+        self.autofire |= config.autofire;
+
+        Ok(config.memory)
+    }
 }
 
 pub fn main() {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
     // To change the log level, set the `RUST_LOG` environment variable. See the `env_logger`
     // documentation for more information.
@@ -69,26 +594,417 @@ pub fn main() {
             }
         }
     }
+
+    match cli.command {
+        Command::Run(args) => run(args, matches.subcommand_matches("run").unwrap(), vec![]),
+        Command::Asm(args) => {
+            env_logger::init();
+            asm(args);
+        }
+        Command::Dasm(args) => {
+            env_logger::init();
+            dasm(args);
+        }
+        Command::Test(mut args) => {
+            let limits = &args.run;
+            if limits.max_cycles.is_none()
+                && limits.max_frames.is_none()
+                && limits.timeout_cycles.is_none()
+                && !limits.until_stp
+                && !limits.stp_exit_code
+            {
+                Cli::command()
+                    .error(
+                        clap::error::ErrorKind::MissingRequiredArgument,
+                        "test needs at least one of --max-cycles, --max-frames, --timeout-cycles, --until-stp or --stp-exit-code",
+                    )
+                    .exit()
+            }
+            args.run.headless = true;
+            run(
+                args.run,
+                matches.subcommand_matches("test").unwrap(),
+                args.expectations,
+            );
+        }
+        Command::Bench(args) => {
+            env_logger::init();
+            bench(args);
+        }
+        Command::CartInfo(args) => {
+            env_logger::init();
+            cart_info(args);
+        }
+    }
+}
+
+fn run(mut args: RunArgs, matches: &ArgMatches, expectations: Vec<Expectation>) {
+    let display = if args.headless {
+        DisplayMode::Headless
+    } else {
+        DisplayMode::Window
+    };
+    #[cfg(feature = "tui")]
+    let display = if args.dashboard {
+        let logs = LogBuffer::default();
+        env_logger::Builder::from_default_env()
+            .target(env_logger::Target::Pipe(Box::new(logs.clone())))
+            .init();
+        DisplayMode::Dashboard(logs)
+    } else {
+        env_logger::init();
+        display
+    };
+    #[cfg(not(feature = "tui"))]
     env_logger::init();
 
-    frontend::start(
-        &cli.file,
-        cli.as_cartridge,
-        cli.load_address,
-        cli.reset_vector,
-        cli.irq_vector,
-        cli.nmi_vector,
-        cli.uart1_source.as_deref(),
-        cli.fix_newlines,
-        cli.physical_keyboard,
-        cli.fast,
+    let config_path = args.config.clone().or_else(|| {
+        Path::new(config::DEFAULT_PATH)
+            .exists()
+            .then(|| config::DEFAULT_PATH.into())
+    });
+    let memory_regions = match config_path {
+        Some(path) => {
+            info!("Loading config {}", path.display());
+            Config::load(&path)
+                .and_then(|config| args.apply_config(config, matches))
+                .unwrap_or_else(|e| {
+                    Cli::command()
+                        .error(clap::error::ErrorKind::InvalidValue, e)
+                        .exit()
+                })
+        }
+        None => vec![],
+    };
+    let file = match args.file {
+        Some(file) => file,
+        None => find_rom(args.rom.as_deref()),
+    };
+
+    info!(
+        "Loading binary {}{}",
+        file.display(),
+        if args.as_cartridge {
+            " as cartridge"
+        } else {
+            ""
+        }
+    );
+    let data = frontend::read_file(&file).unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    });
+    let profile = args.profile.is_some() || args.heatmap.is_some();
+    let coverage = args.coverage.is_some();
+    let call_profile = args.call_profile.is_some();
+    let device_log = args.device_log.is_some();
+    let vcd = args.vcd.map(|path| {
+        info!("Writing waveforms to {}", path.display());
+        File::create(&path).unwrap_or_else(|e| {
+            eprintln!("error: {}: {e}", path.display());
+            std::process::exit(1);
+        })
+    });
+    // the machine is not Send, the frontend builds it on the thread that runs it
+    let machine = move || {
+        let mut builder = Machine::builder()
+            .vectors(Vectors {
+                reset: args.reset_vector,
+                irq: args.irq_vector,
+                nmi: args.nmi_vector,
+            })
+            .vblank_interrupt(args.vblank_interrupt)
+            .mirror_via(args.mirror_via)
+            .open_bus(args.open_bus)
+            .memory_guard(args.memory_guard)
+            .profile(profile)
+            .coverage(coverage)
+            .call_profile(call_profile)
+            .timing(TimingProfile::new(args.cpu_frequency, args.video_standard))
+            .engine(args.engine)
+            .trace_interrupts(args.trace_interrupts)
+            .device_log(device_log)
+            .firmware(args.firmware)
+            .keyboard_ghosting(args.keyboard_ghosting)
+            .illegal_opcode_policy(args.on_illegal);
+        builder = if args.as_cartridge {
+            builder.cartridge(data)
+        } else {
+            builder.rom(data)
+        };
+        if let Some(load_address) = args.load_address {
+            builder = builder.load_address(load_address);
+        }
+        for address in args.expansion_via {
+            builder = builder.expansion_via(address);
+        }
+        if let Some(file) = vcd {
+            builder = builder.vcd(Box::new(BufWriter::new(file)), args.vcd_signals);
+        }
+        for region in memory_regions {
+            builder = builder.memory_region(region);
+        }
+        for watch in args.watch {
+            builder = builder.watch(watch);
+        }
+        for breakpoint in args.breakpoints {
+            builder = builder.breakpoint(breakpoint);
+        }
+        if let Some(address) = args.host_bridge {
+            let bridge = HostBridge::new(
+                UartSource::empty().with_stdin(UartStdioMode::Raw),
+                UartSink::stdout(UartStdioMode::Raw),
+            );
+            builder = builder.host_bridge(address, bridge);
+        }
+        if let Some(address) = args.mouse {
+            builder = builder.mouse(address);
+        }
+        if let Some(address) = args.light_pen {
+            builder = builder.light_pen(address);
+        }
+        if let Some(address) = args.raster {
+            builder = builder.raster(address);
+        }
+        builder
+    };
+
+    let result = frontend::start(
+        machine,
+        FrontendOptions {
+            uart1_source: args.uart1_source,
+            uart1_sink: args.uart1_sink,
+            uart1_stdio: args.uart1_stdio,
+            uart1_xmodem: args.uart1_xmodem,
+            fix_newlines: args.fix_newlines,
+            physical_keyboard: args.physical_keyboard,
+            joysticks: args.joysticks,
+            macros: args.macros,
+            autofire: args.autofire,
+            warp: Warp {
+                enabled: args.fast,
+                factor: args.warp_factor,
+                frame_skip: args.frame_skip,
+                auto: args.auto_warp,
+            },
+            palette: args.palette,
+            display_filter: args.display_filter,
+            aspect_ratio: args.aspect_ratio,
+            border: args.border,
+            fullscreen: args.fullscreen,
+            scale: args.scale,
+            limits: RunLimits {
+                max_cycles: args.max_cycles,
+                max_frames: args.max_frames,
+                until_stp: args.until_stp,
+                stp_exit_code: args.stp_exit_code,
+                timeout_cycles: args.timeout_cycles,
+                exit_code: args.exit_code,
+                dump_state: args.dump_state,
+            },
+            expectations,
+            control_address: args.control_socket,
+            record_input: args.record,
+            playback_input: args.playback,
+            profile: args.profile,
+            heatmap: args.heatmap,
+            coverage: args.coverage,
+            call_profile: args.call_profile,
+            device_log: args.device_log,
+            symbols: read_symbols(args.symbols.as_deref()),
+            basic: args.basic,
+            uart2_link: args.uart2_link,
+            link_latency: args.link_latency,
+            binary: file,
+            #[cfg(feature = "metrics")]
+            metrics_address: args.metrics_address,
+            display,
+        },
     );
+    if let Err(e) = result {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    }
+}
+
+fn asm(args: AsmArgs) {
+    let text = std::fs::read_to_string(&args.file).unwrap_or_else(|e| {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::Io,
+                format!("error reading {}: {e}", args.file.display()),
+            )
+            .exit()
+    });
+    let constants = if args.no_cody_symbols {
+        HashMap::new()
+    } else {
+        symbols::cody_symbols().into_iter().collect()
+    };
+    let mut binary = vec![];
+    if let Err(e) = assembler::parse_with_constants(&text, constants)
+        .and_then(|instructions| assembler::assemble_at(&instructions, args.origin, &mut binary))
+    {
+        eprintln!("{}", e.render(&args.file.display().to_string(), &text));
+        std::process::exit(1);
+    }
+
+    let output = args
+        .output
+        .unwrap_or_else(|| args.file.with_extension("bin"));
+    info!("Writing {} bytes to {}", binary.len(), output.display());
+    std::fs::write(&output, binary).unwrap_or_else(|e| exit_with_error(&output, e));
+}
+
+fn dasm(args: DasmArgs) {
+    let data = std::fs::read(&args.file).unwrap_or_else(|e| exit_with_error(&args.file, e));
+    let mut symbols = if args.no_cody_symbols {
+        HashMap::new()
+    } else {
+        symbols::cody_addresses()
+    };
+    symbols.extend(read_symbols(args.symbols.as_deref()));
+    let instructions = if args.analyze || args.trace.is_some() {
+        let mut entries = assembler::vector_entries(&data, args.load_address);
+        if let Some(path) = &args.trace {
+            let text = std::fs::read_to_string(path).unwrap_or_else(|e| exit_with_error(path, e));
+            entries.extend(coverage::parse_executed(&text).unwrap_or_else(|e| {
+                eprintln!("{}: {e}", path.display());
+                std::process::exit(1);
+            }));
+        }
+        let code = assembler::find_code(&data, args.load_address, entries);
+        assembler::disassemble_code(&data, args.load_address, &code)
+    } else {
+        assembler::disassemble(&data, args.load_address)
+    };
+    assembler::write_listing(
+        &instructions,
+        &symbols,
+        args.format,
+        std::io::stdout().lock(),
+    )
+    .expect("io error writing listing");
 }
 
-#[allow(dead_code)]
-fn dis(data: &[u8]) {
-    let instructions = disassemble(data);
-    for insn in instructions {
-        println!("{insn}");
+fn bench(args: BenchArgs) {
+    let data = std::fs::read(&args.file).unwrap_or_else(|e| exit_with_error(&args.file, e));
+    let mut builder = Machine::builder().engine(args.engine);
+    builder = if args.as_cartridge {
+        builder.cartridge(data)
+    } else {
+        builder.rom(data)
+    };
+    if let Some(load_address) = args.load_address {
+        builder = builder.load_address(load_address);
     }
+    let mut machine = builder.build().unwrap_or_else(|e| {
+        eprintln!("{}: {e}", args.file.display());
+        std::process::exit(1);
+    });
+
+    let report = bench::run(&mut machine, args.until_pc, args.max_cycles);
+    println!("{report}");
+    if report.end == BenchEnd::CycleLimit {
+        std::process::exit(1);
+    }
+}
+
+fn cart_info(args: CartInfoArgs) {
+    let data = std::fs::read(&args.file).unwrap_or_else(|e| exit_with_error(&args.file, e));
+
+    if let Some(output) = &args.add_header {
+        let header = CartridgeHeader::for_data(args.load_address, data.len())
+            .unwrap_or_else(|e| exit_with_error(&args.file, e));
+        let cartridge = [&header.to_bytes()[..], &data].concat();
+        std::fs::write(output, cartridge).unwrap_or_else(|e| exit_with_error(output, e));
+        println!(
+            "Wrote {} with header 0x{:04X}-0x{:04X}",
+            output.display(),
+            header.start,
+            header.end
+        );
+        return;
+    }
+
+    let header = CartridgeHeader::read(&data).unwrap_or_else(|e| exit_with_error(&args.file, e));
+    println!(
+        "header: start 0x{:04X}, end 0x{:04X}",
+        header.start, header.end
+    );
+    let len = header
+        .data_len()
+        .unwrap_or_else(|e| exit_with_error(&args.file, e));
+    let available = data.len() - CartridgeHeader::LEN;
+    if available < len {
+        exit_with_error(
+            &args.file,
+            format!(
+                "header needs {len} bytes of data, but the file only has {available} after the header"
+            ),
+        );
+    }
+    let data = &data[CartridgeHeader::LEN..CartridgeHeader::LEN + len];
+    println!(
+        "load range: 0x{:04X}-0x{:04X} ({len} bytes)",
+        header.start, header.end
+    );
+    // like the machine, which jumps to the load address unless the data contains the reset vector
+    let reset_vector = (header.start..header.end)
+        .contains(&cpu::RESET_VECTOR)
+        .then(|| {
+            let offset = (cpu::RESET_VECTOR - header.start) as usize;
+            u16::from_le_bytes([data[offset], data[offset + 1]])
+        });
+    match reset_vector {
+        Some(entry) => println!("entry point: 0x{entry:04X} (reset vector)"),
+        None => println!("entry point: 0x{:04X} (load address)", header.start),
+    }
+    if available > len {
+        println!(
+            "warning: {} bytes after the data are ignored",
+            available - len
+        );
+    }
+
+    if let Some(output) = &args.strip_header {
+        std::fs::write(output, data).unwrap_or_else(|e| exit_with_error(output, e));
+        println!("Wrote {} without header", output.display());
+    }
+}
+
+/// Look for the CodyBASIC ROM if no binary file was given, exits if there is none or it is corrupt
+fn find_rom(rom: Option<&Path>) -> PathBuf {
+    let path = rom::find_rom(rom, |key| env::var_os(key)).unwrap_or_else(|e| {
+        Cli::command()
+            .error(clap::error::ErrorKind::MissingRequiredArgument, e)
+            .exit()
+    });
+    info!("Using CodyBASIC ROM {}", path.display());
+    let data = std::fs::read(&path).unwrap_or_else(|e| exit_with_error(&path, e));
+    rom::verify_rom(&data).unwrap_or_else(|e| exit_with_error(&path, e));
+    match rom::identify_rom(&data, rom::KNOWN_ROMS) {
+        RomVersion::Known(version) => info!("Detected CodyBASIC {version}"),
+        RomVersion::Unknown { crc32 } => warn!(
+            "Unknown ROM image {} with CRC-32 0x{crc32:08X}, it may be modified or corrupt",
+            path.display()
+        ),
+    }
+    path
+}
+
+fn exit_with_error(path: &Path, message: impl Display) -> ! {
+    eprintln!("{}: {message}", path.display());
+    std::process::exit(1);
+}
+
+fn read_symbols(path: Option<&Path>) -> HashMap<u16, String> {
+    let Some(path) = path else {
+        return HashMap::new();
+    };
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| exit_with_error(path, e));
+    assembler::parse_symbols(&text).unwrap_or_else(|e| {
+        eprintln!("{}", e.render(&path.display().to_string(), &text));
+        std::process::exit(1);
+    })
 }
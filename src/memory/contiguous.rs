@@ -5,6 +5,8 @@ use std::marker::PhantomData;
 
 pub struct Contiguous<M = Ram> {
     pub memory: Box<[u8]>,
+    /// counts writes, changes of [`Self::memory`] that bypass the write methods must call [`Self::touch`]
+    generation: u64,
     _phantom: PhantomData<M>,
 }
 
@@ -43,6 +45,7 @@ impl<M: MemoryMode> Contiguous<M> {
     pub fn new(size: usize) -> Self {
         Self {
             memory: vec![0; size].into_boxed_slice(),
+            generation: 0,
             _phantom: PhantomData,
         }
     }
@@ -59,6 +62,16 @@ impl<M: MemoryMode> Contiguous<M> {
         memory
     }
 
+    /// Changes whenever the contents may have changed, e.g. to skip redrawing unchanged video memory
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Mark the contents as changed after writing to [`Self::memory`] directly
+    pub fn touch(&mut self) {
+        self.generation += 1;
+    }
+
     pub fn force_write_u8(&mut self, address: u16, value: u8) {
         self.force_write_all(address, &[value]);
     }
@@ -71,6 +84,7 @@ impl<M: MemoryMode> Contiguous<M> {
         let remaining = self.memory.len().saturating_sub(address as usize);
         let to_copy = data.len().min(remaining);
         if to_copy > 0 {
+            self.touch();
             (&mut self.memory[address as usize..])
                 .write_all(&data[..to_copy])
                 .unwrap();
@@ -83,8 +97,14 @@ impl<M: MemoryMode> Memory for Contiguous<M> {
         self.memory[address as usize % self.memory.len()]
     }
 
+    /// Addresses past the end wrap around, a write there would not invalidate the aliased address
+    fn is_cacheable(&mut self, address: u16) -> bool {
+        (address as usize) < self.memory.len()
+    }
+
     fn write_u8(&mut self, address: u16, value: u8) {
         if M::is_writeable() {
+            self.touch();
             self.memory[address as usize % self.memory.len()] = value;
         }
     }
@@ -92,4 +112,23 @@ impl<M: MemoryMode> Memory for Contiguous<M> {
     fn update(&mut self, _cycle: usize) -> Interrupt {
         Interrupt::none()
     }
+
+    fn next_event(&mut self, _cycle: usize) -> Option<usize> {
+        None
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        if M::is_writeable() {
+            self.memory.to_vec()
+        } else {
+            vec![]
+        }
+    }
+
+    fn restore_state(&mut self, state: &[u8], _cycle: usize) {
+        if M::is_writeable() && state.len() == self.memory.len() {
+            self.memory.copy_from_slice(state);
+            self.touch();
+        }
+    }
 }
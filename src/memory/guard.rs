@@ -0,0 +1,200 @@
+use crate::interrupt::Interrupt;
+use crate::memory::Memory;
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+/// What to do when a [`MemoryGuard`] detects a suspicious access
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum GuardPolicy {
+    /// Don't check memory accesses
+    #[default]
+    Off,
+    /// Log the first occurrence of each access with the program counter of the instruction
+    Warn,
+    /// Log the access and halt the cpu with the program counter pointing at the instruction
+    Trap,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ViolationKind {
+    RomWrite,
+    UninitializedRead,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub address: u16,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ViolationKind::RomWrite => write!(f, "write to ROM at 0x{:04X}", self.address),
+            ViolationKind::UninitializedRead => {
+                write!(f, "read of uninitialized RAM at 0x{:04X}", self.address)
+            }
+        }
+    }
+}
+
+/// Initialized addresses and detected violations, shared by all guarded regions
+#[derive(Debug)]
+pub struct GuardState {
+    initialized: Box<[bool]>,
+    violations: Vec<Violation>,
+}
+
+impl Default for GuardState {
+    fn default() -> Self {
+        Self {
+            initialized: vec![false; 0x10000].into_boxed_slice(),
+            violations: vec![],
+        }
+    }
+}
+
+impl GuardState {
+    /// Forget all writes, e.g. after the RAM was cleared
+    pub fn clear(&mut self) {
+        self.initialized.fill(false);
+        self.violations.clear();
+    }
+
+    /// Mark memory that was written without going through the cpu
+    pub fn mark_initialized(&mut self, addresses: RangeInclusive<u16>) {
+        self.initialized[*addresses.start() as usize..=*addresses.end() as usize].fill(true);
+    }
+
+    pub fn take_violations(&mut self) -> Vec<Violation> {
+        std::mem::take(&mut self.violations)
+    }
+
+    /// Number of violations that were not taken yet
+    pub fn violation_count(&self) -> usize {
+        self.violations.len()
+    }
+
+    /// Forget the violations after the first `count`
+    pub fn truncate_violations(&mut self, count: usize) {
+        self.violations.truncate(count);
+    }
+}
+
+/// Wraps RAM or ROM mapped at `base` and records writes to ROM and reads of never written RAM
+#[derive(Debug)]
+pub struct MemoryGuard<M> {
+    inner: M,
+    base: u16,
+    read_only: bool,
+    state: Rc<RefCell<GuardState>>,
+}
+
+impl<M: Memory> MemoryGuard<M> {
+    pub const fn ram(inner: M, base: u16, state: Rc<RefCell<GuardState>>) -> Self {
+        Self {
+            inner,
+            base,
+            read_only: false,
+            state,
+        }
+    }
+
+    pub const fn rom(inner: M, base: u16, state: Rc<RefCell<GuardState>>) -> Self {
+        Self {
+            inner,
+            base,
+            read_only: true,
+            state,
+        }
+    }
+}
+
+impl<M: Memory> Memory for MemoryGuard<M> {
+    fn read_u8(&mut self, address: u16) -> u8 {
+        if !self.read_only {
+            let address = self.base.wrapping_add(address);
+            let mut state = self.state.borrow_mut();
+            if !state.initialized[address as usize] {
+                state.violations.push(Violation {
+                    kind: ViolationKind::UninitializedRead,
+                    address,
+                });
+            }
+        }
+        self.inner.read_u8(address)
+    }
+
+    fn peek_u8(&mut self, address: u16) -> u8 {
+        self.inner.peek_u8(address)
+    }
+
+    fn write_u8(&mut self, address: u16, value: u8) {
+        let absolute = self.base.wrapping_add(address);
+        let mut state = self.state.borrow_mut();
+        if self.read_only {
+            state.violations.push(Violation {
+                kind: ViolationKind::RomWrite,
+                address: absolute,
+            });
+        } else {
+            state.initialized[absolute as usize] = true;
+        }
+        drop(state);
+        self.inner.write_u8(address, value);
+    }
+
+    fn update(&mut self, cycle: usize) -> Interrupt {
+        self.inner.update(cycle)
+    }
+
+    fn next_event(&mut self, cycle: usize) -> Option<usize> {
+        self.inner.next_event(cycle)
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.inner.save_state()
+    }
+
+    fn restore_state(&mut self, state: &[u8], cycle: usize) {
+        self.inner.restore_state(state, cycle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::contiguous::Contiguous;
+
+    #[test]
+    fn test_guard() {
+        let state = Rc::new(RefCell::new(GuardState::default()));
+        let mut ram = MemoryGuard::ram(Contiguous::new_ram(0x100), 0x1000, Rc::clone(&state));
+        let mut rom = MemoryGuard::rom(Contiguous::new_rom(0x100), 0xE000, Rc::clone(&state));
+        state.borrow_mut().mark_initialized(0x1000..=0x100F);
+
+        ram.read_u8(0x05);
+        ram.write_u8(0x20, 1);
+        ram.read_u8(0x20);
+        assert!(state.borrow_mut().take_violations().is_empty());
+
+        ram.read_u8(0x21);
+        rom.write_u8(0x10, 1);
+        rom.read_u8(0x10);
+        assert_eq!(
+            state.borrow_mut().take_violations(),
+            [
+                Violation {
+                    kind: ViolationKind::UninitializedRead,
+                    address: 0x1021
+                },
+                Violation {
+                    kind: ViolationKind::RomWrite,
+                    address: 0xE010
+                }
+            ]
+        );
+    }
+}
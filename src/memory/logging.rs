@@ -1,5 +1,10 @@
 use crate::interrupt::Interrupt;
 use crate::memory::Memory;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+
+/// Number of accesses kept in a [`DeviceLog`]
+const DEVICE_LOG_SIZE: usize = 1 << 20;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum MemoryAccessType {
@@ -71,6 +76,10 @@ impl<M: Memory> Memory for LoggingMemory<M> {
         value
     }
 
+    fn peek_u8(&mut self, address: u16) -> u8 {
+        self.inner.peek_u8(address)
+    }
+
     fn write_u8(&mut self, address: u16, value: u8) {
         self.inner.write_u8(address, value);
         self.log.push(MemoryAccess::write(address, value));
@@ -79,4 +88,309 @@ impl<M: Memory> Memory for LoggingMemory<M> {
     fn update(&mut self, cycle: usize) -> Interrupt {
         self.inner.update(cycle)
     }
+
+    fn next_event(&mut self, cycle: usize) -> Option<usize> {
+        self.inner.next_event(cycle)
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.inner.save_state()
+    }
+
+    fn restore_state(&mut self, state: &[u8], cycle: usize) {
+        self.inner.restore_state(state, cycle);
+    }
+}
+
+/// Per-address access counters and the cycles spent in the instruction at each address
+#[derive(Debug, Clone)]
+pub struct AccessProfile {
+    reads: Box<[u64]>,
+    writes: Box<[u64]>,
+    executes: Box<[u64]>,
+    cycles: Box<[u64]>,
+}
+
+impl Default for AccessProfile {
+    fn default() -> Self {
+        Self {
+            reads: vec![0; 0x10000].into_boxed_slice(),
+            writes: vec![0; 0x10000].into_boxed_slice(),
+            executes: vec![0; 0x10000].into_boxed_slice(),
+            cycles: vec![0; 0x10000].into_boxed_slice(),
+        }
+    }
+}
+
+impl AccessProfile {
+    pub fn record(&mut self, access: MemoryAccess) {
+        let counters = match access.access_type {
+            MemoryAccessType::Read => &mut self.reads,
+            MemoryAccessType::Write => &mut self.writes,
+        };
+        counters[access.address as usize] += 1;
+    }
+
+    /// Count an executed instruction at `pc` that took `cycles` cycles
+    pub fn record_instruction(&mut self, pc: u16, cycles: u8) {
+        self.executes[pc as usize] += 1;
+        self.cycles[pc as usize] += cycles as u64;
+    }
+
+    pub fn reads(&self, address: u16) -> u64 {
+        self.reads[address as usize]
+    }
+
+    pub fn writes(&self, address: u16) -> u64 {
+        self.writes[address as usize]
+    }
+
+    pub fn executes(&self, address: u16) -> u64 {
+        self.executes[address as usize]
+    }
+
+    pub fn cycles(&self, address: u16) -> u64 {
+        self.cycles[address as usize]
+    }
+
+    /// Write one line per accessed address, hottest instructions can be found by sorting by cycles
+    pub fn write_csv(&self, mut w: impl Write) -> std::io::Result<()> {
+        writeln!(w, "address,reads,writes,executes,cycles")?;
+        for address in 0..=u16::MAX {
+            let (reads, writes, executes, cycles) = (
+                self.reads(address),
+                self.writes(address),
+                self.executes(address),
+                self.cycles(address),
+            );
+            if reads != 0 || writes != 0 || executes != 0 {
+                writeln!(w, "0x{address:04X},{reads},{writes},{executes},{cycles}")?;
+            }
+        }
+        w.flush()
+    }
+
+    /// Render a 256x256 binary PPM image with one pixel per address and one row per page.
+    ///
+    /// Writes are red, reads green and executes blue, brightness scales logarithmically with the count.
+    pub fn write_heatmap(&self, mut w: impl Write) -> std::io::Result<()> {
+        fn scale(counters: &[u64]) -> impl Fn(u64) -> u8 {
+            let max = counters.iter().copied().max().unwrap_or(0);
+            let max = ((max + 1) as f64).ln();
+            move |count| {
+                if count == 0 {
+                    0
+                } else {
+                    // even a single access should be visible
+                    (64.0 + 191.0 * ((count + 1) as f64).ln() / max) as u8
+                }
+            }
+        }
+
+        let (red, green, blue) = (
+            scale(&self.writes),
+            scale(&self.reads),
+            scale(&self.executes),
+        );
+        write!(w, "P6\n256 256\n255\n")?;
+        for address in 0..=u16::MAX {
+            w.write_all(&[
+                red(self.writes(address)),
+                green(self.reads(address)),
+                blue(self.executes(address)),
+            ])?;
+        }
+        w.flush()
+    }
+}
+
+/// Access to a device register by an instruction
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DeviceAccess {
+    pub access: MemoryAccess,
+    /// address of the instruction
+    pub pc: u16,
+    /// cpu cycle at the start of the instruction
+    pub cycle: usize,
+}
+
+impl DeviceAccess {
+    /// e.g. `cycle 1234: 0xE123 wrote 0x10 to 0x9F06 VIA_T1LL`
+    pub fn format(&self, symbols: &HashMap<u16, String>) -> String {
+        let MemoryAccess {
+            access_type,
+            address,
+            value,
+        } = self.access;
+        let (verb, preposition) = match access_type {
+            MemoryAccessType::Read => ("read", "from"),
+            MemoryAccessType::Write => ("wrote", "to"),
+        };
+        let mut text = format!(
+            "cycle {}: 0x{:04X} {verb} 0x{value:02X} {preposition} 0x{address:04X}",
+            self.cycle, self.pc
+        );
+        if let Some(name) = symbols.get(&address) {
+            text += &format!(" {name}");
+        }
+        text
+    }
+}
+
+/// The most recent reads and writes of device registers, with the instruction and cycle of each.
+///
+/// The memory records accesses with [`DeviceLog::record`], the machine brackets every instruction with
+/// [`DeviceLog::begin_instruction`] and [`DeviceLog::end_instruction`] to stamp them.
+#[derive(Debug, Clone)]
+pub struct DeviceLog {
+    /// inclusive address range and name of each logged device
+    devices: Vec<(u16, u16, String)>,
+    accesses: VecDeque<DeviceAccess>,
+    /// accesses of the instruction being executed, their pc is known once it finished
+    pending: usize,
+    cycle: usize,
+}
+
+impl DeviceLog {
+    pub fn new(devices: Vec<(u16, u16, String)>) -> Self {
+        Self {
+            devices,
+            accesses: VecDeque::new(),
+            pending: 0,
+            cycle: 0,
+        }
+    }
+
+    /// Name of the logged device at `address`
+    pub fn device(&self, address: u16) -> Option<&str> {
+        self.devices
+            .iter()
+            .find(|(start, end, _)| (*start..=*end).contains(&address))
+            .map(|(_, _, name)| name.as_str())
+    }
+
+    /// Oldest first
+    pub fn accesses(&self) -> &VecDeque<DeviceAccess> {
+        &self.accesses
+    }
+
+    /// Keep `access` if it is to one of the devices
+    pub fn record(&mut self, access: MemoryAccess) {
+        if self.device(access.address).is_none() {
+            return;
+        }
+        if self.accesses.len() == DEVICE_LOG_SIZE {
+            self.accesses.pop_front();
+        }
+        self.accesses.push_back(DeviceAccess {
+            access,
+            pc: 0,
+            cycle: self.cycle,
+        });
+        self.pending += 1;
+    }
+
+    pub fn begin_instruction(&mut self, cycle: usize) {
+        self.cycle = cycle;
+    }
+
+    /// Stamp the accesses since [`Self::begin_instruction`] with the address of the instruction
+    pub fn end_instruction(&mut self, pc: u16) {
+        let pending = self.pending.min(self.accesses.len());
+        for access in self.accesses.iter_mut().rev().take(pending) {
+            access.pc = pc;
+        }
+        self.pending = 0;
+    }
+
+    /// Write one line per access, `symbols` name the registers
+    pub fn write_csv(
+        &self,
+        symbols: &HashMap<u16, String>,
+        mut w: impl Write,
+    ) -> std::io::Result<()> {
+        writeln!(w, "cycle,pc,device,register,access,address,value")?;
+        for access in &self.accesses {
+            let MemoryAccess {
+                access_type,
+                address,
+                value,
+            } = access.access;
+            writeln!(
+                w,
+                "{},0x{:04X},{},{},{},0x{address:04X},0x{value:02X}",
+                access.cycle,
+                access.pc,
+                self.device(address).unwrap_or_default(),
+                symbols.get(&address).map_or("", String::as_str),
+                match access_type {
+                    MemoryAccessType::Read => "read",
+                    MemoryAccessType::Write => "write",
+                },
+            )?;
+        }
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let mut profile = AccessProfile::default();
+        profile.record(MemoryAccess::read(0xE000, 0xEA));
+        profile.record(MemoryAccess::read(0xE000, 0xEA));
+        profile.record(MemoryAccess::write(0x0010, 1));
+        profile.record_instruction(0xE000, 2);
+        profile.record_instruction(0xE000, 2);
+        assert_eq!(profile.reads(0xE000), 2);
+        assert_eq!(profile.writes(0x0010), 1);
+        assert_eq!(profile.executes(0xE000), 2);
+        assert_eq!(profile.cycles(0xE000), 4);
+
+        let mut csv = vec![];
+        profile.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "address,reads,writes,executes,cycles\n0x0010,0,1,0,0\n0xE000,2,0,2,4\n"
+        );
+
+        let mut heatmap = vec![];
+        profile.write_heatmap(&mut heatmap).unwrap();
+        let header = b"P6\n256 256\n255\n";
+        assert_eq!(heatmap.len(), header.len() + 3 * 0x10000);
+        let pixel = |address: usize| &heatmap[header.len() + 3 * address..][..3];
+        assert_eq!(pixel(0x0000), [0, 0, 0]);
+        assert_eq!(pixel(0x0010), [255, 0, 0]);
+        assert_eq!(pixel(0xE000), [0, 255, 255]);
+    }
+
+    #[test]
+    fn test_device_log() {
+        let mut log = DeviceLog::new(vec![(0x9F00, 0x9FFF, "VIA".to_string())]);
+        log.begin_instruction(100);
+        log.record(MemoryAccess::read(0x0010, 1));
+        log.record(MemoryAccess::write(0x9F06, 0x10));
+        log.end_instruction(0xE123);
+        log.begin_instruction(104);
+        log.record(MemoryAccess::read(0x9F0D, 0xC0));
+        log.end_instruction(0xE126);
+        assert_eq!(log.accesses().len(), 2);
+
+        let symbols = HashMap::from([(0x9F06, "VIA_T1LL".to_string())]);
+        assert_eq!(
+            log.accesses()[0].format(&symbols),
+            "cycle 100: 0xE123 wrote 0x10 to 0x9F06 VIA_T1LL"
+        );
+        let mut csv = vec![];
+        log.write_csv(&symbols, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "cycle,pc,device,register,access,address,value\n\
+             100,0xE123,VIA,VIA_T1LL,write,0x9F06,0x10\n\
+             104,0xE126,VIA,,read,0x9F0D,0xC0\n"
+        );
+    }
 }
@@ -1,9 +1,142 @@
 use crate::interrupt::Interrupt;
 use crate::memory::Memory;
+use crate::memory::contiguous::{Contiguous, MemoryMode, Ram, Rom};
+use crate::memory::logging::{AccessProfile, DeviceLog, MemoryAccess};
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::rc::Rc;
 
-#[derive(Default)]
+/// Priority of the RAM and ROM
+pub const PRIORITY_MEMORY: i8 = 0;
+/// Priority of the memory guards, they wrap the RAM and ROM below them
+pub const PRIORITY_GUARD: i8 = 1;
+/// Priority of the devices, their registers shadow the memory at the same addresses
+pub const PRIORITY_DEVICE: i8 = 2;
+/// Priority of additional memory regions, they are mapped over everything else
+pub const PRIORITY_USER: i8 = 3;
+
+/// Memory or device mapped into a [`MappedMemory`]
+struct Region {
+    start: u16,
+    size: u16,
+    /// number of decoded bytes at the start of the region
+    decoded_size: u16,
+    /// the decoded bytes repeat over the whole region, otherwise the rest of it is unmapped
+    mirrored: bool,
+    priority: i8,
+    /// writes go to the region below, for read-only overlays
+    write_through: bool,
+    /// the same memory as `memory`, for the fast path of the page table
+    plain: Option<Plain>,
+    memory: Box<dyn Memory>,
+    /// cycle from which it has to be updated again, `usize::MAX` if only accesses change it
+    next_event: usize,
+    /// it was read or written since its last update
+    accessed: bool,
+    /// interrupt lines it held at its last update
+    interrupt: Interrupt,
+}
+
+/// RAM or ROM without side effects, the page table accesses its bytes directly instead of through [`Memory`]
+#[derive(Clone)]
+enum Plain {
+    Ram(Rc<RefCell<Contiguous<Ram>>>),
+    Rom(Rc<RefCell<Contiguous<Rom>>>),
+}
+
+/// Entry of the page table of a [`MappedMemory`]
+#[derive(Clone, Default)]
+struct Page {
+    /// indices into the regions of the page, highest priority first, ending at the first region that covers
+    /// the whole page and takes its writes
+    regions: Vec<usize>,
+    /// plain memory that covers the whole page and the offset of the page in it
+    plain: Option<(Plain, usize)>,
+}
+
+impl Region {
+    fn end(&self) -> usize {
+        self.start as usize + self.size as usize
+    }
+
+    fn contains(&self, address: u16) -> bool {
+        (self.start as usize..self.end()).contains(&(address as usize))
+    }
+
+    /// Every address of the page starting at `page_start` is in the region
+    fn covers_page(&self, page_start: u16) -> bool {
+        self.start <= page_start && self.end() >= page_start as usize + PAGE_SIZE
+    }
+
+    fn overlaps(&self, start: u16, size: u16) -> bool {
+        size != 0
+            && self.size != 0
+            && (self.start as usize) < start as usize + size as usize
+            && (start as usize) < self.end()
+    }
+
+    /// Address relative to the start of the region that `address` decodes to, `None` if it is not decoded
+    fn offset(&self, address: u16) -> Option<u16> {
+        let offset = address - self.start;
+        if offset < self.decoded_size {
+            Some(offset)
+        } else if self.mirrored {
+            Some(offset % self.decoded_size)
+        } else {
+            None
+        }
+    }
+}
+
+/// Number of addresses in each entry of the page table of a [`MappedMemory`]
+const PAGE_SIZE: usize = 0x100;
+
+/// Regions of memory and devices in the 64K address space.
+///
+/// Every region is mapped with a priority, regions of a higher priority shadow the ones of a lower priority
+/// and regions of the same priority must not overlap. The memory of a region sees addresses relative to the
+/// start of the region, so the registers of a device are offsets from wherever it is mapped.
+///
+/// Accesses look up the regions of their 256 byte page in a page table, so they take the same time no matter
+/// how many regions are mapped. Pages that are entirely RAM or ROM mapped with [`Self::add_ram`] or
+/// [`Self::add_rom`] are read and written directly, only the other pages go through their [`Memory`].
+///
+/// Regions are only updated at the cycles they ask for with [`Memory::next_event`] and after they were read or
+/// written, so idle memory and devices cost nothing per instruction.
 pub struct MappedMemory {
-    memories: Vec<(u16, u16, Box<dyn Memory>)>,
+    /// ordered by priority
+    memories: Vec<Region>,
+    pages: Vec<Page>,
+    open_bus: bool,
+    /// last value that was read or written
+    bus: u8,
+    profile: Option<Rc<RefCell<AccessProfile>>>,
+    device_log: Option<Rc<RefCell<DeviceLog>>>,
+    /// cycle of the last update
+    cycle: usize,
+    /// cycles at which regions are due and their indices, entries of events that moved since are skipped
+    events: BinaryHeap<Reverse<(usize, usize)>>,
+    /// number of regions that held the IRQ and the NMI line at their last update
+    irq_holders: usize,
+    nmi_holders: usize,
+}
+
+impl Default for MappedMemory {
+    fn default() -> Self {
+        Self {
+            memories: vec![],
+            pages: vec![Page::default(); 0x10000 / PAGE_SIZE],
+            open_bus: false,
+            bus: 0,
+            profile: None,
+            device_log: None,
+            cycle: 0,
+            events: BinaryHeap::new(),
+            irq_holders: 0,
+            nmi_holders: 0,
+        }
+    }
 }
 
 impl MappedMemory {
@@ -11,44 +144,957 @@ impl MappedMemory {
         Self::default()
     }
 
-    pub fn add_memory(&mut self, address: u16, size: u16, memory: impl Memory + 'static) {
-        self.memories.push((address, size, Box::new(memory)));
+    pub fn add_memory(
+        &mut self,
+        address: u16,
+        size: u16,
+        priority: i8,
+        memory: impl Memory + 'static,
+    ) {
+        self.add_mirrored(address, size, size, priority, memory);
+    }
+
+    /// Map plain RAM, accesses to the pages it covers skip the dynamic dispatch of [`Self::add_memory`]
+    pub fn add_ram(
+        &mut self,
+        address: u16,
+        size: u16,
+        priority: i8,
+        ram: Rc<RefCell<Contiguous<Ram>>>,
+    ) {
+        let plain =
+            (ram.borrow().memory.len() >= size as usize).then(|| Plain::Ram(Rc::clone(&ram)));
+        self.insert(Region {
+            start: address,
+            size,
+            decoded_size: size,
+            mirrored: true,
+            priority,
+            write_through: false,
+            plain,
+            memory: Box::new(ram),
+            next_event: 0,
+            accessed: false,
+            interrupt: Interrupt::none(),
+        });
+    }
+
+    /// Map plain ROM, accesses to the pages it covers skip the dynamic dispatch of [`Self::add_memory`]
+    pub fn add_rom(
+        &mut self,
+        address: u16,
+        size: u16,
+        priority: i8,
+        rom: Rc<RefCell<Contiguous<Rom>>>,
+    ) {
+        let plain =
+            (rom.borrow().memory.len() >= size as usize).then(|| Plain::Rom(Rc::clone(&rom)));
+        self.insert(Region {
+            start: address,
+            size,
+            decoded_size: size,
+            mirrored: true,
+            priority,
+            write_through: false,
+            plain,
+            memory: Box::new(rom),
+            next_event: 0,
+            accessed: false,
+            interrupt: Interrupt::none(),
+        });
+    }
+
+    /// Map `memory` that only decodes the first `decoded_size` bytes, they repeat over the whole region
+    pub fn add_mirrored(
+        &mut self,
+        address: u16,
+        size: u16,
+        decoded_size: u16,
+        priority: i8,
+        memory: impl Memory + 'static,
+    ) {
+        self.insert(Region {
+            start: address,
+            size,
+            decoded_size,
+            mirrored: true,
+            priority,
+            write_through: false,
+            plain: None,
+            memory: Box::new(memory),
+            next_event: 0,
+            accessed: false,
+            interrupt: Interrupt::none(),
+        });
+    }
+
+    /// Map `memory` that only decodes the first `decoded_size` bytes, the rest of the region is unmapped but
+    /// still shadows the regions below it
+    pub fn add_partial(
+        &mut self,
+        address: u16,
+        size: u16,
+        decoded_size: u16,
+        priority: i8,
+        memory: impl Memory + 'static,
+    ) {
+        self.insert(Region {
+            start: address,
+            size,
+            decoded_size,
+            mirrored: false,
+            priority,
+            write_through: false,
+            plain: None,
+            memory: Box::new(memory),
+            next_event: 0,
+            accessed: false,
+            interrupt: Interrupt::none(),
+        });
+    }
+
+    /// Map `memory` for reads only, writes go to the region below it like to a RAM under a ROM
+    pub fn add_overlay(
+        &mut self,
+        address: u16,
+        size: u16,
+        priority: i8,
+        memory: impl Memory + 'static,
+    ) {
+        self.insert(Region {
+            start: address,
+            size,
+            decoded_size: size,
+            mirrored: false,
+            priority,
+            write_through: true,
+            plain: None,
+            memory: Box::new(memory),
+            next_event: 0,
+            accessed: false,
+            interrupt: Interrupt::none(),
+        });
+    }
+
+    fn insert(&mut self, region: Region) {
+        debug_assert!(
+            self.overlapping(region.start, region.size, region.priority)
+                .is_none(),
+            "region at 0x{:04X} with size 0x{:04X} overlaps another region of priority {}",
+            region.start,
+            region.size,
+            region.priority
+        );
+        let index = self
+            .memories
+            .partition_point(|other| other.priority <= region.priority);
+        self.memories.insert(index, region);
+        self.map_pages();
+        self.reschedule(self.cycle);
+    }
+
+    /// Make every region due at `cycle`, after the indices of the regions changed or the cycle went back
+    fn reschedule(&mut self, cycle: usize) {
+        self.events.clear();
+        for (index, region) in self.memories.iter_mut().enumerate() {
+            region.next_event = cycle;
+            region.accessed = false;
+            self.events.push(Reverse((cycle, index)));
+        }
+    }
+
+    /// Update the region at `index` to the current cycle and schedule its next update, `event` is the entry that
+    /// was taken from the queue
+    fn update_region(&mut self, index: usize, event: usize) {
+        let cycle = self.cycle;
+        let region = &mut self.memories[index];
+        // the entry of the next event is still queued if the region is updated for an access
+        let queued = region.next_event != event;
+        let interrupt = region.memory.update(cycle);
+        self.irq_holders =
+            self.irq_holders + interrupt.is_irq() as usize - region.interrupt.is_irq() as usize;
+        self.nmi_holders =
+            self.nmi_holders + interrupt.is_nmi() as usize - region.interrupt.is_nmi() as usize;
+        region.interrupt = interrupt;
+        region.accessed = false;
+        let next_event = region
+            .memory
+            .next_event(cycle)
+            .map_or(usize::MAX, |event| event.max(cycle + 1));
+        if next_event != usize::MAX && !(queued && next_event == region.next_event) {
+            self.events.push(Reverse((next_event, index)));
+        }
+        region.next_event = next_event;
+    }
+
+    /// Memory of the region at `index` for a read or write, which may change its interrupt lines or its next event,
+    /// so it is updated again at the next update
+    fn access(&mut self, index: usize) -> &mut Box<dyn Memory> {
+        let region = &mut self.memories[index];
+        if !region.accessed {
+            region.accessed = true;
+            self.events.push(Reverse((self.cycle, index)));
+        }
+        &mut region.memory
+    }
+
+    /// Rebuild the page table after the regions changed
+    fn map_pages(&mut self) {
+        for (number, page) in self.pages.iter_mut().enumerate() {
+            let page_start = (number * PAGE_SIZE) as u16;
+            page.regions.clear();
+            page.plain = None;
+            for (index, region) in self.memories.iter().enumerate().rev() {
+                if region.overlaps(page_start, PAGE_SIZE as u16) {
+                    if page.regions.is_empty()
+                        && region.covers_page(page_start)
+                        && let Some(plain) = &region.plain
+                    {
+                        page.plain = Some((plain.clone(), (page_start - region.start) as usize));
+                    }
+                    page.regions.push(index);
+                    // everything below is shadowed
+                    if region.covers_page(page_start) && !region.write_through {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Start of a region of `priority` that overlaps `size` bytes at `address`
+    pub fn overlapping(&self, address: u16, size: u16, priority: i8) -> Option<u16> {
+        self.memories
+            .iter()
+            .find(|region| region.priority == priority && region.overlaps(address, size))
+            .map(|region| region.start)
     }
 
+    /// States of the regions mapped with [`PRIORITY_DEVICE`] or higher at `cycle`, see [`Memory::save_state`].
+    ///
+    /// The RAM and ROM below them are left out, their owner saves them.
+    pub fn save_states(&mut self, cycle: usize) -> Vec<Vec<u8>> {
+        // regions that were not due yet still hold the state of their last update
+        self.reschedule(self.cycle);
+        self.update(cycle);
+        self.memories
+            .iter()
+            .filter(|region| region.priority >= PRIORITY_DEVICE)
+            .map(|region| region.memory.save_state())
+            .collect()
+    }
+
+    /// Restore the states saved by [`Self::save_states`] with the same regions mapped, they continue from `cycle`
+    pub fn restore_states(&mut self, states: &[Vec<u8>], cycle: usize) {
+        for (region, state) in self
+            .memories
+            .iter_mut()
+            .filter(|region| region.priority >= PRIORITY_DEVICE)
+            .zip(states)
+        {
+            region.memory.restore_state(state, cycle);
+        }
+        // their interrupt lines and next events changed
+        self.cycle = cycle;
+        self.reschedule(cycle);
+    }
+
+    /// Device without any registers, it is only updated
     pub fn add_device(&mut self, memory: impl Memory + 'static) {
-        self.add_memory(0, 0, memory);
+        self.add_memory(0, 0, PRIORITY_DEVICE, memory);
+    }
+
+    /// Map `banked` at `address` and its bank select latch at `latch_address`.
+    ///
+    /// The latch is mapped one priority higher, so it may be inside of the banked region.
+    pub fn add_banked(
+        &mut self,
+        address: u16,
+        size: u16,
+        priority: i8,
+        banked: Banked,
+        latch_address: u16,
+    ) {
+        let latch = banked.latch();
+        self.add_memory(address, size, priority, banked);
+        self.add_memory(latch_address, 1, priority + 1, latch);
+    }
+
+    /// Reads of unmapped addresses return the last value on the bus instead of 0
+    pub fn set_open_bus(&mut self, open_bus: bool) {
+        self.open_bus = open_bus;
+    }
+
+    /// Count every access, including ones to unmapped addresses
+    pub fn set_profile(&mut self, profile: Option<Rc<RefCell<AccessProfile>>>) {
+        self.profile = profile;
+    }
+
+    /// Record reads and writes of the devices in `device_log`
+    pub fn set_device_log(&mut self, device_log: Option<Rc<RefCell<DeviceLog>>>) {
+        self.device_log = device_log;
+    }
+
+    /// Regions at the page of `address`, highest priority first
+    fn page(&self, address: u16) -> impl Iterator<Item = usize> {
+        self.pages[address as usize / PAGE_SIZE]
+            .regions
+            .iter()
+            .copied()
+    }
+
+    fn region(&mut self, address: u16) -> Option<&mut Region> {
+        let index = self.region_index(address)?;
+        Some(&mut self.memories[index])
+    }
+
+    fn region_index(&self, address: u16) -> Option<usize> {
+        self.page(address)
+            .find(|&index| self.memories[index].contains(address))
+    }
+
+    /// Read `address` if its page is plain memory
+    fn read_plain(&self, address: u16) -> Option<u8> {
+        let (plain, offset) = self.pages[address as usize / PAGE_SIZE].plain.as_ref()?;
+        let index = offset + address as usize % PAGE_SIZE;
+        Some(match plain {
+            Plain::Ram(ram) => ram.borrow().memory[index],
+            Plain::Rom(rom) => rom.borrow().memory[index],
+        })
+    }
+
+    /// Write `address` if its page is plain memory, writes to ROM are ignored
+    fn write_plain(&self, address: u16, value: u8) -> bool {
+        let Some((plain, offset)) = &self.pages[address as usize / PAGE_SIZE].plain else {
+            return false;
+        };
+        if let Plain::Ram(ram) = plain {
+            let index = offset + address as usize % PAGE_SIZE;
+            let mut ram = ram.borrow_mut();
+            ram.touch();
+            ram.memory[index] = value;
+        }
+        true
+    }
+
+    /// Index of the region that reads `address` and the address in it
+    fn find(&self, address: u16) -> Option<(usize, u16)> {
+        let index = self.region_index(address)?;
+        Some((index, self.memories[index].offset(address)?))
+    }
+
+    /// Region that takes writes to `address`, overlays pass them on to the region below
+    fn find_writable(&self, address: u16) -> Option<(usize, u16)> {
+        let index = self.page(address).find(|&index| {
+            let region = &self.memories[index];
+            region.contains(address) && !region.write_through
+        })?;
+        Some((index, self.memories[index].offset(address)?))
+    }
+}
+
+/// Start, size, priority, whether it is an overlay and the memory of a region of a [`MemoryMapBuilder`]
+type MapRegion = (u16, u16, i8, bool, Box<dyn Memory>);
+
+/// Regions to map over the Cody's memory and devices, see [`crate::machine::MachineBuilder::memory_map`].
+///
+/// E.g. extra RAM at 0xC000, or RAM with a copy of the binary at 0xE000 to patch the ROM while developing.
+#[derive(Default)]
+pub struct MemoryMapBuilder {
+    regions: Vec<MapRegion>,
+}
+
+impl MemoryMapBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// RAM of `size` bytes filled with `data`, mapped with [`PRIORITY_USER`]
+    pub fn ram(self, start: u16, size: u16, data: &[u8]) -> Self {
+        self.memory(
+            start,
+            size,
+            PRIORITY_USER,
+            Contiguous::<Ram>::from_bytes(size as usize, data),
+        )
+    }
+
+    /// ROM of `size` bytes filled with `data`, mapped with [`PRIORITY_USER`]
+    pub fn rom(self, start: u16, size: u16, data: &[u8]) -> Self {
+        self.memory(
+            start,
+            size,
+            PRIORITY_USER,
+            Contiguous::<Rom>::from_bytes(size as usize, data),
+        )
+    }
+
+    /// Any memory or device, see [`MappedMemory::add_memory`]
+    pub fn memory(
+        mut self,
+        start: u16,
+        size: u16,
+        priority: i8,
+        memory: impl Memory + 'static,
+    ) -> Self {
+        self.regions
+            .push((start, size, priority, false, Box::new(memory)));
+        self
+    }
+
+    /// Memory that is only read, writes go to the region below, see [`MappedMemory::add_overlay`]
+    pub fn overlay(
+        mut self,
+        start: u16,
+        size: u16,
+        priority: i8,
+        memory: impl Memory + 'static,
+    ) -> Self {
+        self.regions
+            .push((start, size, priority, true, Box::new(memory)));
+        self
+    }
+
+    /// Append the regions of `other`
+    pub fn extend(&mut self, other: Self) {
+        self.regions.extend(other.regions);
+    }
+
+    /// Add the regions to `memory`, they must not overlap its regions of the same priority
+    pub fn apply_to(self, memory: &mut MappedMemory) {
+        for (start, size, priority, overlay, region) in self.regions {
+            if overlay {
+                memory.add_overlay(start, size, priority, region);
+            } else {
+                memory.add_memory(start, size, priority, region);
+            }
+        }
+    }
+
+    pub fn build(self) -> MappedMemory {
+        let mut memory = MappedMemory::new();
+        self.apply_to(&mut memory);
+        memory
+    }
+}
+
+/// Region that maps one of several banks at a time, switched by writing to its [`BankLatch`]
+pub struct Banked {
+    banks: Vec<Box<dyn Memory>>,
+    selected: Rc<RefCell<u8>>,
+}
+
+impl Banked {
+    pub fn new(banks: Vec<Box<dyn Memory>>) -> Self {
+        assert!(!banks.is_empty(), "banked memory needs at least one bank");
+        Self {
+            banks,
+            selected: Rc::new(RefCell::new(0)),
+        }
+    }
+
+    /// Split `data` into banks of `bank_size` bytes, the last bank is padded with zeros
+    pub fn from_bytes<M: MemoryMode + 'static>(bank_size: u16, data: &[u8]) -> Self {
+        let bank_size = bank_size as usize;
+        let banks = data
+            .chunks(bank_size)
+            .map(|bank| Box::new(Contiguous::<M>::from_bytes(bank_size, bank)) as Box<dyn Memory>)
+            .collect::<Vec<_>>();
+        if banks.is_empty() {
+            Self::new(vec![Box::new(Contiguous::<M>::new(bank_size))])
+        } else {
+            Self::new(banks)
+        }
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.banks.len()
+    }
+
+    /// Index of the mapped bank, the latch value wraps around the number of banks
+    pub fn selected(&self) -> usize {
+        *self.selected.borrow() as usize % self.banks.len()
+    }
+
+    /// Register that selects the bank, reads return the last written value
+    pub fn latch(&self) -> BankLatch {
+        BankLatch {
+            selected: Rc::clone(&self.selected),
+        }
+    }
+}
+
+impl Memory for Banked {
+    fn read_u8(&mut self, address: u16) -> u8 {
+        let bank = self.selected();
+        self.banks[bank].read_u8(address)
+    }
+
+    fn peek_u8(&mut self, address: u16) -> u8 {
+        let bank = self.selected();
+        self.banks[bank].peek_u8(address)
+    }
+
+    fn write_u8(&mut self, address: u16, value: u8) {
+        let bank = self.selected();
+        self.banks[bank].write_u8(address, value);
+    }
+
+    fn update(&mut self, cycle: usize) -> Interrupt {
+        let mut interrupt = Interrupt::none();
+        for bank in &mut self.banks {
+            interrupt = interrupt.or(bank.update(cycle));
+        }
+        interrupt
+    }
+
+    fn next_event(&mut self, cycle: usize) -> Option<usize> {
+        self.banks
+            .iter_mut()
+            .filter_map(|bank| bank.next_event(cycle))
+            .min()
+    }
+
+    /// The latch value followed by the states of all banks
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![*self.selected.borrow()];
+        for bank in &self.banks {
+            state.extend(bank.save_state());
+        }
+        state
+    }
+
+    fn restore_state(&mut self, state: &[u8], cycle: usize) {
+        let sizes = self
+            .banks
+            .iter()
+            .map(|bank| bank.save_state().len())
+            .collect::<Vec<_>>();
+        let Some((&selected, mut state)) = state.split_first() else {
+            return;
+        };
+        if state.len() != sizes.iter().sum::<usize>() {
+            return;
+        }
+        *self.selected.borrow_mut() = selected;
+        for (bank, size) in self.banks.iter_mut().zip(sizes) {
+            let (bank_state, rest) = state.split_at(size);
+            bank.restore_state(bank_state, cycle);
+            state = rest;
+        }
+    }
+}
+
+/// Memory-mapped bank select register of a [`Banked`] region
+#[derive(Debug, Clone)]
+pub struct BankLatch {
+    selected: Rc<RefCell<u8>>,
+}
+
+impl Memory for BankLatch {
+    fn read_u8(&mut self, _address: u16) -> u8 {
+        *self.selected.borrow()
+    }
+
+    fn write_u8(&mut self, _address: u16, value: u8) {
+        *self.selected.borrow_mut() = value;
+    }
+
+    fn update(&mut self, _cycle: usize) -> Interrupt {
+        Interrupt::none()
+    }
+
+    fn next_event(&mut self, _cycle: usize) -> Option<usize> {
+        None
     }
 }
 
 impl Memory for MappedMemory {
     fn read_u8(&mut self, address: u16) -> u8 {
-        for (start, size, memory) in self.memories.iter_mut().rev() {
-            if *size == 0 {
-                continue;
+        let value = if let Some(value) = self.read_plain(address) {
+            value
+        } else {
+            match self.find(address) {
+                Some((index, offset)) => self.access(index).read_u8(offset),
+                None if self.open_bus => self.bus,
+                None => 0, // fallback
             }
-            if (*start..=start.saturating_add(*size - 1)).contains(&address) {
-                return memory.read_u8(address - *start);
+        };
+        self.bus = value;
+        if let Some(profile) = &self.profile {
+            profile
+                .borrow_mut()
+                .record(MemoryAccess::read(address, value));
+        }
+        if let Some(device_log) = &self.device_log {
+            device_log
+                .borrow_mut()
+                .record(MemoryAccess::read(address, value));
+        }
+        value
+    }
+
+    fn peek_u8(&mut self, address: u16) -> u8 {
+        if let Some(value) = self.read_plain(address) {
+            return value;
+        }
+        match self.find(address) {
+            Some((index, offset)) => self.memories[index].memory.peek_u8(offset),
+            None if self.open_bus => self.bus,
+            None => 0,
+        }
+    }
+
+    fn is_cacheable(&mut self, address: u16) -> bool {
+        // every fetch has to reach the profile and the bus latch, and a write to a mirror would not
+        // invalidate the other addresses of the same byte
+        if self.profile.is_some() || self.open_bus {
+            return false;
+        }
+        // a write to an overlaid address would not invalidate it
+        match self.region(address) {
+            Some(region) if region.size == region.decoded_size && !region.write_through => {
+                region.memory.is_cacheable(address - region.start)
             }
+            _ => false,
         }
-        0 // fallback
     }
 
     fn write_u8(&mut self, address: u16, value: u8) {
-        for (start, size, memory) in self.memories.iter_mut().rev() {
-            if *size == 0 {
-                continue;
-            }
-            if (*start..=start.saturating_add(*size - 1)).contains(&address) {
-                return memory.write_u8(address - *start, value);
-            }
+        self.bus = value;
+        if let Some(profile) = &self.profile {
+            profile
+                .borrow_mut()
+                .record(MemoryAccess::write(address, value));
+        }
+        if let Some(device_log) = &self.device_log {
+            device_log
+                .borrow_mut()
+                .record(MemoryAccess::write(address, value));
+        }
+        if self.write_plain(address, value) {
+            return;
+        }
+        if let Some((index, offset)) = self.find_writable(address) {
+            self.access(index).write_u8(offset, value);
         }
     }
 
     fn update(&mut self, cycle: usize) -> Interrupt {
+        if cycle < self.cycle {
+            // restored to an earlier state
+            self.reschedule(cycle);
+        }
+        self.cycle = cycle;
+        while let Some(&Reverse((event, index))) = self.events.peek()
+            && event <= cycle
+        {
+            self.events.pop();
+            let region = &self.memories[index];
+            if region.next_event == event || region.accessed {
+                self.update_region(index, event);
+            }
+        }
+
         let mut interrupt = Interrupt::none();
-        for (_, _, memory) in &mut self.memories {
-            interrupt = interrupt.or(memory.update(cycle));
+        if self.irq_holders > 0 {
+            interrupt = interrupt.or(Interrupt::irq());
+        }
+        if self.nmi_holders > 0 {
+            interrupt = interrupt.or(Interrupt::nmi());
         }
         interrupt
     }
+
+    fn next_event(&mut self, _cycle: usize) -> Option<usize> {
+        self.events.peek().map(|&Reverse((event, _))| event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_banked_rom() {
+        let data: Vec<u8> = (0..3).flat_map(|bank| [bank; 0x10]).collect();
+        let mut memory = MappedMemory::new();
+        memory.add_banked(
+            0x8000,
+            0x10,
+            PRIORITY_MEMORY,
+            Banked::from_bytes::<Rom>(0x10, &data),
+            0x9000,
+        );
+
+        assert_eq!(memory.read_u8(0x8005), 0);
+        assert!(!memory.is_cacheable(0x8005));
+        memory.write_u8(0x9000, 2);
+        assert_eq!(memory.read_u8(0x8005), 2);
+        assert_eq!(memory.read_u8(0x9000), 2);
+        memory.write_u8(0x8005, 0xFF);
+        assert_eq!(memory.read_u8(0x8005), 2);
+        // wraps around the number of banks
+        memory.write_u8(0x9000, 4);
+        assert_eq!(memory.read_u8(0x8005), 1);
+    }
+
+    #[test]
+    fn test_banked_ram() {
+        let banked = Banked::from_bytes::<Ram>(0x100, &[0; 0x200]);
+        assert_eq!(banked.bank_count(), 2);
+        let mut memory = MappedMemory::new();
+        memory.add_banked(0x1000, 0x100, PRIORITY_MEMORY, banked, 0x2000);
+
+        memory.write_u8(0x1000, 0xAA);
+        memory.write_u8(0x2000, 1);
+        assert_eq!(memory.read_u8(0x1000), 0);
+        memory.write_u8(0x1000, 0xBB);
+        memory.write_u8(0x2000, 0);
+        assert_eq!(memory.read_u8(0x1000), 0xAA);
+    }
+
+    #[test]
+    fn test_mirrored() {
+        let mut memory = MappedMemory::new();
+        memory.add_mirrored(
+            0x9F00,
+            0x100,
+            0x10,
+            PRIORITY_DEVICE,
+            Contiguous::<Ram>::new(0x10),
+        );
+        memory.write_u8(0x9F03, 0x42);
+        assert_eq!(memory.read_u8(0x9F13), 0x42);
+        assert_eq!(memory.read_u8(0x9FF3), 0x42);
+        assert!(!memory.is_cacheable(0x9F03));
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut memory = MappedMemory::new();
+        memory.add_memory(
+            0x0000,
+            0x100,
+            PRIORITY_MEMORY,
+            Contiguous::<Ram>::new(0x100),
+        );
+        memory.write_u8(0x10, 0x42);
+        assert_eq!(memory.read_u8(0x8000), 0);
+        assert!(memory.is_cacheable(0x10));
+        assert!(!memory.is_cacheable(0x8000));
+
+        memory.set_open_bus(true);
+        assert!(!memory.is_cacheable(0x10));
+        memory.read_u8(0x10);
+        assert_eq!(memory.read_u8(0x8000), 0x42);
+        memory.write_u8(0x8000, 0x17);
+        assert_eq!(memory.read_u8(0x8000), 0x17);
+    }
+
+    #[test]
+    fn test_partial() {
+        let mut memory = MappedMemory::new();
+        memory.add_memory(
+            0x9F00,
+            0x100,
+            PRIORITY_MEMORY,
+            Contiguous::<Ram>::from_bytes(0x100, &[0x17; 0x100]),
+        );
+        memory.add_partial(
+            0x9F00,
+            0x100,
+            0x10,
+            PRIORITY_DEVICE,
+            Contiguous::<Ram>::new(0x10),
+        );
+        memory.write_u8(0x9F03, 0x42);
+        memory.write_u8(0x9F13, 0x55);
+        assert_eq!(memory.read_u8(0x9F03), 0x42);
+        // the rest of the page is unmapped, not the memory below it
+        assert_eq!(memory.read_u8(0x9F13), 0);
+        memory.set_open_bus(true);
+        assert_eq!(memory.read_u8(0x9F13), 0);
+        memory.read_u8(0x9F03);
+        assert_eq!(memory.read_u8(0x9F13), 0x42);
+    }
+
+    #[test]
+    fn test_page_table() {
+        let mut memory = MappedMemory::new();
+        memory.add_memory(
+            0x0000,
+            0x8000,
+            PRIORITY_MEMORY,
+            Contiguous::<Ram>::new(0x8000),
+        );
+        // regions that cross a page boundary and share their pages with other regions
+        memory.add_memory(
+            0x10F0,
+            0x20,
+            PRIORITY_DEVICE,
+            Contiguous::<Ram>::from_bytes(0x20, &[1; 0x20]),
+        );
+        memory.add_memory(
+            0x1110,
+            0x1,
+            PRIORITY_DEVICE,
+            Contiguous::<Ram>::from_bytes(0x1, &[2]),
+        );
+        memory.write_u8(0x1111, 3);
+        assert_eq!(memory.read_u8(0x10EF), 0);
+        assert_eq!(memory.read_u8(0x10F0), 1);
+        assert_eq!(memory.read_u8(0x110F), 1);
+        assert_eq!(memory.read_u8(0x1110), 2);
+        assert_eq!(memory.read_u8(0x1111), 3);
+        assert_eq!(memory.read_u8(0x8000), 0);
+    }
+
+    #[test]
+    fn test_plain() {
+        let ram = Rc::new(RefCell::new(Contiguous::new_ram(0x2000)));
+        let rom = Rc::new(RefCell::new(Contiguous::new_rom(0x1000)));
+        let mut memory = MappedMemory::new();
+        memory.add_ram(0x0000, 0x2000, PRIORITY_MEMORY, Rc::clone(&ram));
+        memory.add_rom(0xF000, 0x1000, PRIORITY_MEMORY, Rc::clone(&rom));
+        memory.add_memory(
+            0x1F00,
+            0x10,
+            PRIORITY_DEVICE,
+            Contiguous::<Ram>::from_bytes(0x10, &[0x17; 0x10]),
+        );
+        assert!(memory.pages[0x10].plain.is_some());
+        assert!(memory.pages[0x1F].plain.is_none());
+
+        let generation = ram.borrow().generation();
+        memory.write_u8(0x1000, 0x42);
+        assert_eq!(ram.borrow().memory[0x1000], 0x42);
+        assert!(ram.borrow().generation() > generation);
+        assert_eq!(memory.read_u8(0x1000), 0x42);
+        // the rest of the page of the device still reaches the RAM
+        memory.write_u8(0x1F10, 0x55);
+        assert_eq!(memory.read_u8(0x1F10), 0x55);
+        assert_eq!(memory.read_u8(0x1F00), 0x17);
+
+        rom.borrow_mut().force_write_u8(0x0FFC, 0xE0);
+        memory.write_u8(0xFFFC, 0x00);
+        assert_eq!(memory.read_u8(0xFFFC), 0xE0);
+        assert!(memory.is_cacheable(0xFFFC));
+    }
+
+    #[test]
+    fn test_priorities() {
+        let mut memory = MemoryMapBuilder::new()
+            .memory(0x1000, 0x100, 1, Contiguous::<Ram>::from_bytes(0x100, &[1]))
+            .memory(0x1000, 0x1000, 0, Contiguous::<Ram>::new(0x1000))
+            .build();
+        // the region of priority 1 shadows the one added later
+        assert_eq!(memory.read_u8(0x1000), 1);
+        memory.add_memory(0x1000, 0x10, 2, Contiguous::<Ram>::from_bytes(0x10, &[3]));
+        assert_eq!(memory.read_u8(0x1000), 3);
+        assert_eq!(memory.read_u8(0x1010), 0);
+        assert_eq!(memory.read_u8(0x1100), 0);
+        assert_eq!(memory.overlapping(0x10FF, 0x10, 1), Some(0x1000));
+        assert_eq!(memory.overlapping(0x1100, 0x10, 1), None);
+        assert_eq!(memory.overlapping(0x2000, 0x10, 0), None);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "overlaps another region")]
+    fn test_overlap() {
+        let mut memory = MappedMemory::new();
+        memory.add_memory(0x1000, 0x100, 0, Contiguous::<Ram>::new(0x100));
+        memory.add_memory(0x10FF, 0x1, 0, Contiguous::<Ram>::new(0x1));
+    }
+
+    #[test]
+    fn test_end_of_address_space() {
+        let mut memory = MappedMemory::new();
+        memory.add_memory(0xFFFE, 0x4, 0, Contiguous::<Ram>::from_bytes(0x4, &[1, 2]));
+        assert_eq!(memory.read_u8(0xFFFF), 2);
+        assert_eq!(memory.read_u8(0x0000), 0);
+    }
+
+    #[test]
+    fn test_overlay() {
+        let ram = Rc::new(RefCell::new(Contiguous::new_ram(0x2000)));
+        let mut memory = MemoryMapBuilder::new()
+            .memory(0xE000, 0x2000, 0, Rc::clone(&ram))
+            .overlay(
+                0xE000,
+                0x2000,
+                1,
+                Contiguous::<Rom>::from_bytes(0x2000, &[0x42]),
+            )
+            .build();
+        memory.write_u8(0xE000, 0x17);
+        assert_eq!(memory.read_u8(0xE000), 0x42);
+        assert_eq!(ram.borrow().memory[0], 0x17);
+        assert!(!memory.is_cacheable(0xE000));
+    }
+
+    /// Device that is due every 100 cycles and holds the IRQ line from its second update on
+    struct Scheduled {
+        updates: Rc<RefCell<Vec<usize>>>,
+    }
+
+    impl Memory for Scheduled {
+        fn read_u8(&mut self, _address: u16) -> u8 {
+            0
+        }
+
+        fn write_u8(&mut self, _address: u16, _value: u8) {}
+
+        fn update(&mut self, cycle: usize) -> Interrupt {
+            let mut updates = self.updates.borrow_mut();
+            updates.push(cycle);
+            if updates.len() >= 2 {
+                Interrupt::irq()
+            } else {
+                Interrupt::none()
+            }
+        }
+
+        fn next_event(&mut self, cycle: usize) -> Option<usize> {
+            Some(cycle - cycle % 100 + 100)
+        }
+    }
+
+    #[test]
+    fn test_scheduled_updates() {
+        let updates = Rc::new(RefCell::new(vec![]));
+        let mut memory = MappedMemory::new();
+        memory.add_memory(
+            0x0000,
+            0x8000,
+            PRIORITY_MEMORY,
+            Contiguous::<Ram>::new(0x8000),
+        );
+        memory.add_memory(
+            0x9000,
+            0x10,
+            PRIORITY_DEVICE,
+            Scheduled {
+                updates: Rc::clone(&updates),
+            },
+        );
+
+        for cycle in (0..250).step_by(5) {
+            memory.update(cycle);
+        }
+        assert_eq!(*updates.borrow(), [0, 100, 200]);
+        // the lines of the last update are held in between
+        assert!(memory.update(250).is_irq());
+        // an access updates it again at the next update
+        memory.read_u8(0x9000);
+        memory.update(252);
+        memory.update(260);
+        assert_eq!(*updates.borrow(), [0, 100, 200, 252]);
+        // a cycle before the last update, like after restoring a snapshot, updates every region
+        memory.update(10);
+        assert_eq!(updates.borrow().last(), Some(&10));
+        memory.update(20);
+        assert_eq!(updates.borrow().len(), 5);
+    }
 }
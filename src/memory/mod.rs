@@ -1,9 +1,9 @@
 use crate::interrupt::Interrupt;
 use std::cell::RefCell;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
 
 pub mod contiguous;
+pub mod guard;
 pub mod logging;
 pub mod mapped;
 pub mod zero;
@@ -11,10 +11,21 @@ pub mod zero;
 pub trait Memory {
     fn read_u8(&mut self, address: u16) -> u8;
 
+    /// Read without side effects like clearing interrupt flags, for debuggers
+    fn peek_u8(&mut self, address: u16) -> u8 {
+        self.read_u8(address)
+    }
+
     fn read_u8_zp(&mut self, address: u8) -> u8 {
         self.read_u8(address as u16)
     }
 
+    /// Reads of `address` have no side effects and its value only changes when it is written through
+    /// this memory, which lets the cpu cache decoded instructions
+    fn is_cacheable(&mut self, _address: u16) -> bool {
+        false
+    }
+
     fn read_u16(&mut self, address: u16) -> u16 {
         let l = self.read_u8(address);
         let h = self.read_u8(address.wrapping_add(1));
@@ -45,14 +56,41 @@ pub trait Memory {
         self.write_u8_zp(address.wrapping_add(1), h);
     }
 
+    /// Advance the device to `cycle`, returns the interrupt lines it holds, see [`crate::interrupt::InterruptController`]
     fn update(&mut self, cycle: usize) -> Interrupt;
+
+    /// Cycle from which the device has to be updated again after an update at `cycle`, `None` if only reads and
+    /// writes change it.
+    ///
+    /// Until then its registers and interrupt lines must not change on their own, [`mapped::MappedMemory`] skips the
+    /// updates in between and updates it again after every read or write. By default it is updated every instruction.
+    fn next_event(&mut self, cycle: usize) -> Option<usize> {
+        Some(cycle + 1)
+    }
+
+    /// State that changes while running, for [`crate::machine::Snapshot`]s. ROM and devices without state save
+    /// nothing.
+    fn save_state(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    /// Continue from a state saved by [`Self::save_state`] at `cycle`, states of other memories are ignored
+    fn restore_state(&mut self, _state: &[u8], _cycle: usize) {}
 }
 
-impl<M: Memory> Memory for Box<M> {
+impl<M: Memory + ?Sized> Memory for Box<M> {
     fn read_u8(&mut self, address: u16) -> u8 {
         (**self).read_u8(address)
     }
 
+    fn peek_u8(&mut self, address: u16) -> u8 {
+        (**self).peek_u8(address)
+    }
+
+    fn is_cacheable(&mut self, address: u16) -> bool {
+        (**self).is_cacheable(address)
+    }
+
     fn read_u8_zp(&mut self, address: u8) -> u8 {
         (**self).read_u8_zp(address)
     }
@@ -84,6 +122,18 @@ impl<M: Memory> Memory for Box<M> {
     fn update(&mut self, cycle: usize) -> Interrupt {
         (**self).update(cycle)
     }
+
+    fn next_event(&mut self, cycle: usize) -> Option<usize> {
+        (**self).next_event(cycle)
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        (**self).save_state()
+    }
+
+    fn restore_state(&mut self, state: &[u8], cycle: usize) {
+        (**self).restore_state(state, cycle);
+    }
 }
 
 impl<M: Memory> Memory for Rc<RefCell<M>> {
@@ -91,6 +141,14 @@ impl<M: Memory> Memory for Rc<RefCell<M>> {
         self.borrow_mut().read_u8(address)
     }
 
+    fn peek_u8(&mut self, address: u16) -> u8 {
+        self.borrow_mut().peek_u8(address)
+    }
+
+    fn is_cacheable(&mut self, address: u16) -> bool {
+        self.borrow_mut().is_cacheable(address)
+    }
+
     fn read_u8_zp(&mut self, address: u8) -> u8 {
         self.borrow_mut().read_u8_zp(address)
     }
@@ -122,42 +180,16 @@ impl<M: Memory> Memory for Rc<RefCell<M>> {
     fn update(&mut self, cycle: usize) -> Interrupt {
         self.borrow_mut().update(cycle)
     }
-}
-
-impl<M: Memory> Memory for Arc<Mutex<M>> {
-    fn read_u8(&mut self, address: u16) -> u8 {
-        self.lock().unwrap().read_u8(address)
-    }
-
-    fn read_u8_zp(&mut self, address: u8) -> u8 {
-        self.lock().unwrap().read_u8_zp(address)
-    }
-
-    fn read_u16(&mut self, address: u16) -> u16 {
-        self.lock().unwrap().read_u16(address)
-    }
-
-    fn read_u16_zp(&mut self, address: u8) -> u16 {
-        self.lock().unwrap().read_u16_zp(address)
-    }
 
-    fn write_u8(&mut self, address: u16, value: u8) {
-        self.lock().unwrap().write_u8(address, value);
+    fn next_event(&mut self, cycle: usize) -> Option<usize> {
+        self.borrow_mut().next_event(cycle)
     }
 
-    fn write_u8_zp(&mut self, address: u8, value: u8) {
-        self.lock().unwrap().write_u8_zp(address, value);
+    fn save_state(&self) -> Vec<u8> {
+        self.borrow().save_state()
     }
 
-    fn write_u16(&mut self, address: u16, value: u16) {
-        self.lock().unwrap().write_u16(address, value);
-    }
-
-    fn write_u16_zp(&mut self, address: u8, value: u16) {
-        self.lock().unwrap().write_u16_zp(address, value);
-    }
-
-    fn update(&mut self, cycle: usize) -> Interrupt {
-        self.lock().unwrap().update(cycle)
+    fn restore_state(&mut self, state: &[u8], cycle: usize) {
+        self.borrow_mut().restore_state(state, cycle);
     }
 }
@@ -14,4 +14,8 @@ impl Memory for ZeroMemory {
     fn update(&mut self, _cycle: usize) -> Interrupt {
         Interrupt::none()
     }
+
+    fn next_event(&mut self, _cycle: usize) -> Option<usize> {
+        None
+    }
 }
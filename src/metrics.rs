@@ -0,0 +1,212 @@
+use log::{debug, info, warn};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval over which the per-second rates are averaged
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Counters sampled from the emulator once per frame
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct MetricsSample {
+    pub cycles: usize,
+    pub frames: usize,
+    pub irqs: usize,
+    pub nmis: usize,
+    pub uart1_rx_bytes: usize,
+    pub uart1_tx_bytes: usize,
+}
+
+#[derive(Debug)]
+struct Snapshot {
+    started: Instant,
+    sample: MetricsSample,
+    window_start: Instant,
+    window_sample: MetricsSample,
+    frame_rate: f64,
+    irq_rate: f64,
+}
+
+impl Snapshot {
+    fn render(&self) -> String {
+        let s = &self.sample;
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            let _ = writeln!(out, "{name} {value}");
+        };
+        metric(
+            "cody_uptime_seconds",
+            "gauge",
+            "Seconds since the emulator started",
+            &self.started.elapsed().as_secs_f64(),
+        );
+        metric(
+            "cody_cycles_total",
+            "counter",
+            "Emulated cpu cycles",
+            &s.cycles,
+        );
+        metric("cody_frames_total", "counter", "Emulated frames", &s.frames);
+        metric(
+            "cody_frame_rate",
+            "gauge",
+            "Emulated frames per second",
+            &self.frame_rate,
+        );
+        metric("cody_irqs_total", "counter", "Serviced IRQs", &s.irqs);
+        metric(
+            "cody_irqs_per_second",
+            "gauge",
+            "Serviced IRQs per second",
+            &self.irq_rate,
+        );
+        metric("cody_nmis_total", "counter", "Serviced NMIs", &s.nmis);
+        metric(
+            "cody_uart1_rx_bytes_total",
+            "counter",
+            "Bytes received by UART1",
+            &s.uart1_rx_bytes,
+        );
+        metric(
+            "cody_uart1_tx_bytes_total",
+            "counter",
+            "Bytes transmitted by UART1",
+            &s.uart1_tx_bytes,
+        );
+        out
+    }
+}
+
+/// Serves the latest [`MetricsSample`] in the Prometheus text format over HTTP from a background thread
+#[derive(Debug)]
+pub struct MetricsServer {
+    address: SocketAddr,
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+impl MetricsServer {
+    pub fn bind(address: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        info!("Metrics endpoint listening on http://{address}/metrics");
+
+        let now = Instant::now();
+        let snapshot = Arc::new(Mutex::new(Snapshot {
+            started: now,
+            sample: MetricsSample::default(),
+            window_start: now,
+            window_sample: MetricsSample::default(),
+            frame_rate: 0.0,
+            irq_rate: 0.0,
+        }));
+        let shared = Arc::clone(&snapshot);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = serve(stream, &shared) {
+                            debug!("Metrics endpoint: error serving client: {e}");
+                        }
+                    }
+                    Err(e) => warn!("Metrics endpoint: error accepting client: {e}"),
+                }
+            }
+        });
+
+        Ok(Self { address, snapshot })
+    }
+
+    pub const fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Publish the current counters, called once per frame
+    pub fn update(&self, sample: MetricsSample) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.sample = sample;
+
+        let elapsed = snapshot.window_start.elapsed();
+        if elapsed >= RATE_WINDOW {
+            let seconds = elapsed.as_secs_f64();
+            snapshot.frame_rate = (sample.frames - snapshot.window_sample.frames) as f64 / seconds;
+            snapshot.irq_rate = (sample.irqs - snapshot.window_sample.irqs) as f64 / seconds;
+            snapshot.window_start = Instant::now();
+            snapshot.window_sample = sample;
+        }
+    }
+}
+
+fn serve(stream: TcpStream, snapshot: &Mutex<Snapshot>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip the headers, we don't need any of them
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", snapshot.lock().unwrap().render()),
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_string()),
+    };
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn get(server: &MetricsServer, path: &str) -> String {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_scrape() {
+        let server = MetricsServer::bind("127.0.0.1:0").unwrap();
+        server.update(MetricsSample {
+            cycles: 1234,
+            frames: 5,
+            irqs: 3,
+            nmis: 1,
+            uart1_rx_bytes: 7,
+            uart1_tx_bytes: 8,
+        });
+
+        let response = get(&server, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("# TYPE cody_cycles_total counter\n"));
+        assert!(response.contains("cody_cycles_total 1234\n"));
+        assert!(response.contains("cody_frames_total 5\n"));
+        assert!(response.contains("cody_irqs_total 3\n"));
+        assert!(response.contains("cody_nmis_total 1\n"));
+        assert!(response.contains("cody_uart1_rx_bytes_total 7\n"));
+        assert!(response.contains("cody_uart1_tx_bytes_total 8\n"));
+    }
+
+    #[test]
+    fn test_unknown_path() {
+        let server = MetricsServer::bind("127.0.0.1:0").unwrap();
+        assert!(get(&server, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::opcode::{Opcode, get_instruction};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Cycles spent in one subroutine
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct FunctionStats {
+    pub calls: u64,
+    /// cycles spent in the subroutine itself
+    pub self_cycles: u64,
+    /// cycles spent in the subroutine and everything it called
+    pub total_cycles: u64,
+}
+
+/// Calls from one subroutine to another
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct CallStats {
+    pub calls: u64,
+    /// cycles spent in the callee and everything it called
+    pub cycles: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Frame {
+    function: u16,
+    /// stack pointer after returning, the frame is left as soon as the stack shrinks to it
+    return_s: u16,
+}
+
+/// Attributes cycles to subroutines by following JSR, RTS, interrupts and RTI.
+///
+/// Subroutines are identified by their entry address, interrupt handlers count as called by the interrupted code.
+#[derive(Debug, Clone)]
+pub struct CallProfiler {
+    stack: Vec<Frame>,
+    functions: HashMap<u16, FunctionStats>,
+    calls: HashMap<(u16, u16), CallStats>,
+    cycles: u64,
+}
+
+impl CallProfiler {
+    /// Start profiling with the code at `entry` as outermost function
+    pub fn new(entry: u16) -> Self {
+        let mut profiler = Self {
+            stack: vec![],
+            functions: HashMap::new(),
+            calls: HashMap::new(),
+            cycles: 0,
+        };
+        profiler.reset(entry);
+        profiler
+    }
+
+    /// Forget the call stack after a reset, the collected statistics are kept
+    pub fn reset(&mut self, entry: u16) {
+        self.stack.clear();
+        self.stack.push(Frame {
+            function: entry,
+            return_s: u16::MAX,
+        });
+        self.functions.entry(entry).or_default().calls += 1;
+    }
+
+    pub fn function(&self, address: u16) -> Option<&FunctionStats> {
+        self.functions.get(&address)
+    }
+
+    pub fn call(&self, caller: u16, callee: u16) -> Option<&CallStats> {
+        self.calls.get(&(caller, callee))
+    }
+
+    /// Account one step of `cpu` that took `cycles`, `interrupted_s` is the stack pointer before it if an interrupt was serviced
+    pub fn record<M: Memory>(&mut self, cpu: &Cpu<M>, interrupted_s: Option<u8>, cycles: u8) {
+        if let (Some(s), Some((address, _))) = (interrupted_s, cpu.last_instruction()) {
+            self.enter(address, s as u16);
+        }
+
+        let cycles = cycles as u64;
+        self.cycles += cycles;
+        let current = self.stack.last().unwrap().function;
+        self.functions.entry(current).or_default().self_cycles += cycles;
+        // recursive functions must only be counted once
+        for function in self.stack.iter().map(|frame| frame.function).unique() {
+            self.functions.entry(function).or_default().total_cycles += cycles;
+        }
+        for call in self
+            .stack
+            .iter()
+            .tuple_windows()
+            .map(|(caller, callee)| (caller.function, callee.function))
+            .unique()
+        {
+            self.calls.entry(call).or_default().cycles += cycles;
+        }
+
+        if let Some((_, byte)) = cpu.last_instruction()
+            && get_instruction(byte).is_some_and(|meta| meta.opcode == Opcode::JSR)
+        {
+            self.enter(cpu.pc, cpu.s as u16 + 2);
+        }
+        // covers RTS, RTI and code that drops its return address
+        while self.stack.len() > 1 && self.stack.last().unwrap().return_s <= cpu.s as u16 {
+            self.stack.pop();
+        }
+    }
+
+    fn enter(&mut self, function: u16, return_s: u16) {
+        let caller = self.stack.last().unwrap().function;
+        self.stack.push(Frame { function, return_s });
+        self.functions.entry(function).or_default().calls += 1;
+        self.calls.entry((caller, function)).or_default().calls += 1;
+    }
+
+    /// Write a flat profile sorted by self cycles followed by the callers and callees of every subroutine
+    pub fn write_report(
+        &self,
+        symbols: &HashMap<u16, String>,
+        mut w: impl Write,
+    ) -> std::io::Result<()> {
+        let name = |address: u16| {
+            symbols
+                .get(&address)
+                .cloned()
+                .unwrap_or_else(|| format!("${address:04X}"))
+        };
+        let percent = |cycles: u64| {
+            if self.cycles == 0 {
+                0.0
+            } else {
+                100.0 * cycles as f64 / self.cycles as f64
+            }
+        };
+
+        writeln!(w, "Flat profile, {} cycles in total", self.cycles)?;
+        writeln!(
+            w,
+            "{:>7} {:>12} {:>7} {:>12} {:>10}  function",
+            "self%", "self", "total%", "total", "calls"
+        )?;
+        let functions = self
+            .functions
+            .iter()
+            .sorted_by_key(|(address, stats)| (std::cmp::Reverse(stats.self_cycles), **address));
+        for (&address, stats) in functions.clone() {
+            writeln!(
+                w,
+                "{:>6.2}% {:>12} {:>6.2}% {:>12} {:>10}  {}",
+                percent(stats.self_cycles),
+                stats.self_cycles,
+                percent(stats.total_cycles),
+                stats.total_cycles,
+                stats.calls,
+                name(address)
+            )?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "Call graph, cycles include everything called")?;
+        for (&address, stats) in functions {
+            writeln!(w)?;
+            for ((caller, _), call) in self
+                .calls
+                .iter()
+                .filter(|((_, callee), _)| *callee == address)
+                .sorted_by_key(|(call, _)| *call)
+            {
+                writeln!(
+                    w,
+                    "    {:>12} {:>10}  from {}",
+                    call.cycles,
+                    call.calls,
+                    name(*caller)
+                )?;
+            }
+            writeln!(
+                w,
+                "{:>16} {:>10}  {}",
+                stats.total_cycles,
+                stats.calls,
+                name(address)
+            )?;
+            for ((_, callee), call) in self
+                .calls
+                .iter()
+                .filter(|((caller, _), _)| *caller == address)
+                .sorted_by_key(|(call, _)| *call)
+            {
+                writeln!(
+                    w,
+                    "    {:>12} {:>10}  to {}",
+                    call.cycles,
+                    call.calls,
+                    name(*callee)
+                )?;
+            }
+        }
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+    use crate::memory::contiguous::Contiguous;
+
+    #[test]
+    fn test_call_profiler() {
+        // main: JSR sub, JSR sub, STP; sub: NOP, RTS
+        let program = [0x20, 0x07, 0x02, 0x20, 0x07, 0x02, 0xDB, 0xEA, 0x60];
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.force_write_all(0x0200, &program);
+        memory.write_u16(0xFFFC, 0x0200);
+        let mut cpu = Cpu::new(memory);
+        cpu.s = 0xFF;
+
+        let mut profiler = CallProfiler::new(cpu.pc);
+        while cpu.is_running() {
+            let cycles = cpu.step_instruction();
+            profiler.record(&cpu, None, cycles);
+        }
+
+        // JSR 6, NOP 2, RTS 6
+        let sub = profiler.function(0x0207).unwrap();
+        assert_eq!(sub.calls, 2);
+        assert_eq!(sub.self_cycles, 2 * (2 + 6));
+        assert_eq!(sub.total_cycles, sub.self_cycles);
+        let main = profiler.function(0x0200).unwrap();
+        assert_eq!(main.self_cycles, 2 * 6 + 3);
+        assert_eq!(main.total_cycles, main.self_cycles + sub.total_cycles);
+        assert_eq!(
+            profiler.call(0x0200, 0x0207),
+            Some(&CallStats {
+                calls: 2,
+                cycles: sub.total_cycles
+            })
+        );
+
+        let mut report = vec![];
+        let symbols = HashMap::from([(0x0207, "sub".to_string())]);
+        profiler.write_report(&symbols, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("from $0200"));
+        assert!(report.contains("to sub"));
+    }
+}
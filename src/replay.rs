@@ -0,0 +1,218 @@
+use crate::cpu::CpuState;
+use crate::device::via::KeyState;
+use crate::machine::Snapshot;
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 4] = b"CIM2";
+
+/// Keyboard and joystick state that became active at a cpu cycle
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct InputEvent {
+    pub cycle: usize,
+    pub rows: [u8; 8],
+}
+
+/// Initial machine state and all input changes after it, replaying it reproduces a run exactly
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InputRecording {
+    pub snapshot: Snapshot,
+    pub events: Vec<InputEvent>,
+}
+
+impl InputRecording {
+    pub fn new(snapshot: Snapshot) -> Self {
+        Self {
+            snapshot,
+            events: vec![],
+        }
+    }
+
+    /// Record `key_state` at `cycle` if it changed since the last event
+    pub fn record(&mut self, cycle: usize, key_state: &KeyState) {
+        let rows = key_state.rows();
+        if self.events.last().is_none_or(|last| last.rows != rows) {
+            self.events.push(InputEvent { cycle, rows });
+        }
+    }
+
+    pub fn write_to(&self, mut w: impl Write) -> std::io::Result<()> {
+        let Snapshot {
+            cpu,
+            ram,
+            propeller_ram,
+            devices,
+        } = &self.snapshot;
+        w.write_all(MAGIC)?;
+        w.write_all(&[cpu.a, cpu.x, cpu.y, cpu.s, cpu.p])?;
+        w.write_all(&cpu.pc.to_le_bytes())?;
+        w.write_all(&[cpu.run as u8, cpu.wai as u8, cpu.nmi_pending as u8])?;
+        for memory in [ram, propeller_ram] {
+            w.write_all(&(memory.len() as u32).to_le_bytes())?;
+            w.write_all(memory)?;
+        }
+        w.write_all(&(devices.len() as u32).to_le_bytes())?;
+        for device in devices {
+            w.write_all(&(device.len() as u32).to_le_bytes())?;
+            w.write_all(device)?;
+        }
+        w.write_all(&(self.events.len() as u32).to_le_bytes())?;
+        for event in &self.events {
+            w.write_all(&(event.cycle as u64).to_le_bytes())?;
+            w.write_all(&event.rows)?;
+        }
+        w.flush()
+    }
+
+    pub fn read_from(mut r: impl Read) -> std::io::Result<Self> {
+        fn read<const N: usize>(r: &mut impl Read) -> std::io::Result<[u8; N]> {
+            let mut buf = [0; N];
+            r.read_exact(&mut buf)?;
+            Ok(buf)
+        }
+        fn read_len(r: &mut impl Read) -> std::io::Result<usize> {
+            Ok(u32::from_le_bytes(read(r)?) as usize)
+        }
+
+        if &read::<4>(&mut r)? != MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not an input recording",
+            ));
+        }
+        let [a, x, y, s, p] = read(&mut r)?;
+        let pc = u16::from_le_bytes(read(&mut r)?);
+        let [run, wai, nmi_pending] = read(&mut r)?;
+        let cpu = CpuState {
+            a,
+            x,
+            y,
+            s,
+            p,
+            pc,
+            run: run != 0,
+            wai: wai != 0,
+            nmi_pending: nmi_pending != 0,
+        };
+        let mut memories = [vec![], vec![]];
+        for memory in &mut memories {
+            memory.resize(read_len(&mut r)?, 0);
+            r.read_exact(memory)?;
+        }
+        let [ram, propeller_ram] = memories.map(Vec::into_boxed_slice);
+        let mut devices = vec![vec![]; read_len(&mut r)?];
+        for device in &mut devices {
+            device.resize(read_len(&mut r)?, 0);
+            r.read_exact(device)?;
+        }
+
+        let count = read_len(&mut r)?;
+        let mut events = Vec::with_capacity(count);
+        for _ in 0..count {
+            let cycle = u64::from_le_bytes(read(&mut r)?) as usize;
+            let rows = read(&mut r)?;
+            events.push(InputEvent { cycle, rows });
+        }
+
+        Ok(Self {
+            snapshot: Snapshot {
+                cpu,
+                ram,
+                propeller_ram,
+                devices,
+            },
+            events,
+        })
+    }
+}
+
+/// Feeds the events of an [`InputRecording`] back into the keyboard
+#[derive(Debug, Clone)]
+pub struct InputPlayer {
+    events: Vec<InputEvent>,
+    next: usize,
+}
+
+impl InputPlayer {
+    pub fn new(events: Vec<InputEvent>) -> Self {
+        Self { events, next: 0 }
+    }
+
+    /// Apply all events up to and including `cycle`, call before executing the instruction at `cycle`
+    pub fn apply(&mut self, cycle: usize, key_state: &mut KeyState) {
+        while let Some(event) = self.events.get(self.next)
+            && event.cycle <= cycle
+        {
+            key_state.set_rows(event.rows);
+            self.next += 1;
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::via::CodyKeyCode;
+
+    fn recording() -> InputRecording {
+        let snapshot = Snapshot {
+            cpu: CpuState {
+                a: 1,
+                x: 2,
+                y: 3,
+                s: 0xFD,
+                p: 0x34,
+                pc: 0xE000,
+                run: true,
+                wai: false,
+                nmi_pending: true,
+            },
+            ram: vec![0x42; 0xA000].into_boxed_slice(),
+            propeller_ram: vec![0x24; 0x4000].into_boxed_slice(),
+            devices: vec![vec![1, 2, 3], vec![]],
+        };
+        let mut recording = InputRecording::new(snapshot);
+        let mut key_state = KeyState::default();
+        key_state.set_rows([0xFF; 8]);
+        recording.record(0, &key_state);
+        recording.record(10, &key_state);
+        key_state.set_pressed(CodyKeyCode::KeyA, true);
+        recording.record(20, &key_state);
+        recording
+    }
+
+    #[test]
+    fn test_record_only_changes() {
+        let recording = recording();
+        assert_eq!(recording.events.len(), 2);
+        assert_eq!(recording.events[1].cycle, 20);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let recording = recording();
+        let mut data = vec![];
+        recording.write_to(&mut data).unwrap();
+        assert_eq!(InputRecording::read_from(&data[..]).unwrap(), recording);
+        assert!(InputRecording::read_from(&data[1..]).is_err());
+    }
+
+    #[test]
+    fn test_playback() {
+        let recording = recording();
+        let mut player = InputPlayer::new(recording.events.clone());
+        let mut key_state = KeyState::default();
+        key_state.set_pressed(CodyKeyCode::KeyQ, true);
+
+        player.apply(0, &mut key_state);
+        assert_eq!(key_state.rows(), recording.events[0].rows);
+        player.apply(19, &mut key_state);
+        assert_eq!(key_state.rows(), recording.events[0].rows);
+        player.apply(25, &mut key_state);
+        assert_eq!(key_state.rows(), recording.events[1].rows);
+        assert!(player.is_finished());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::machine::{Machine, Snapshot};
+use std::collections::VecDeque;
+
+/// Ring buffer of periodic [`Snapshot`]s to step a machine backwards in time
+#[derive(Debug, Clone)]
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+    /// take a snapshot every this many frames
+    interval: usize,
+    capacity: usize,
+    frames: usize,
+}
+
+impl Default for Rewind {
+    /// A snapshot every 10 frames for the last 30 seconds
+    fn default() -> Self {
+        Self::new(10, 30 * 60 / 10)
+    }
+}
+
+impl Rewind {
+    pub fn new(interval: usize, capacity: usize) -> Self {
+        assert!(interval > 0, "rewind interval must be positive");
+        Self {
+            snapshots: VecDeque::with_capacity(capacity),
+            interval,
+            capacity,
+            frames: 0,
+        }
+    }
+
+    /// Call once per emulated frame
+    pub fn record(&mut self, machine: &mut Machine) {
+        if self.frames.is_multiple_of(self.interval) {
+            if self.snapshots.len() == self.capacity {
+                self.snapshots.pop_front();
+            }
+            if self.capacity > 0 {
+                self.snapshots.push_back(machine.snapshot());
+            }
+        }
+        self.frames += 1;
+    }
+
+    /// Restore the most recent snapshot and drop it, returns false if there is nothing left to rewind
+    pub fn step_back(&mut self, machine: &mut Machine) -> bool {
+        let Some(snapshot) = self.snapshots.pop_back() else {
+            return false;
+        };
+        machine.restore(&snapshot);
+        self.frames = 0;
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Image, Vectors};
+    use crate::memory::Memory;
+
+    #[test]
+    fn test_rewind() {
+        // INC $10, BRA -4
+        let image = Image::new(vec![0xE6, 0x10, 0x80, 0xFC], false, None).unwrap();
+        let mut machine = Machine::new(image, Vectors::default());
+        let mut rewind = Rewind::new(2, 2);
+        for _ in 0..6 {
+            rewind.record(&mut machine);
+            // one "frame" increments $10 once
+            machine.step_instruction();
+            machine.step_instruction();
+        }
+        // snapshots were taken before frames 0, 2 and 4, only the last two are kept
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 6);
+
+        assert!(rewind.step_back(&mut machine));
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 4);
+        assert!(rewind.step_back(&mut machine));
+        assert_eq!(machine.cpu.memory.read_u8(0x10), 2);
+        assert!(!rewind.step_back(&mut machine));
+    }
+}
@@ -0,0 +1,209 @@
+use crate::cpu::RESET_VECTOR;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Environment variable with the path of the CodyBASIC ROM
+pub const ROM_ENV_VAR: &str = "CODY_ROM";
+/// Name of the ROM in the `cody_emulator` directory of the XDG data directories
+pub const ROM_FILE_NAME: &str = "codybasic.bin";
+/// The ROM fills 0xE000-0xFFFF
+pub const ROM_SIZE: usize = 0x2000;
+
+/// Where to look for the ROM: `rom` or [`ROM_ENV_VAR`] if given, else the XDG data directories.
+///
+/// `env` looks up environment variables, usually [`std::env::var_os`].
+pub fn search_paths(rom: Option<&Path>, env: impl Fn(&str) -> Option<OsString>) -> Vec<PathBuf> {
+    if let Some(rom) = rom {
+        return vec![rom.to_path_buf()];
+    }
+    if let Some(rom) = env(ROM_ENV_VAR).filter(|rom| !rom.is_empty()) {
+        return vec![rom.into()];
+    }
+
+    let data_home = env("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env("HOME").map(|home| Path::new(&home).join(".local/share")));
+    let data_dirs = env("XDG_DATA_DIRS")
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+    data_home
+        .into_iter()
+        .chain(std::env::split_paths(&data_dirs))
+        // the spec says to ignore relative paths
+        .filter(|dir| dir.is_absolute())
+        .map(|dir| dir.join("cody_emulator").join(ROM_FILE_NAME))
+        .collect()
+}
+
+/// The first of the [`search_paths`] that exists
+pub fn find_rom(
+    rom: Option<&Path>,
+    env: impl Fn(&str) -> Option<OsString>,
+) -> Result<PathBuf, String> {
+    let paths = search_paths(rom, env);
+    paths
+        .iter()
+        .find(|path| path.is_file())
+        .cloned()
+        .ok_or_else(|| {
+            format!(
+                "no CodyBASIC ROM found, looked for {}; pass a binary file, --rom or set {ROM_ENV_VAR}",
+                paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
+
+/// Check that `data` looks like a complete ROM image that can boot
+pub fn verify_rom(data: &[u8]) -> Result<(), String> {
+    if data.len() != ROM_SIZE {
+        return Err(format!(
+            "ROM must be {ROM_SIZE} bytes, got {}, the file may be truncated or no ROM",
+            data.len()
+        ));
+    }
+    let offset = (RESET_VECTOR - 0xE000) as usize;
+    let reset_vector = u16::from_le_bytes([data[offset], data[offset + 1]]);
+    if reset_vector < 0xE000 {
+        return Err(format!(
+            "reset vector 0x{reset_vector:04X} does not point into the ROM, the file may be corrupt"
+        ));
+    }
+    Ok(())
+}
+
+/// A ROM release recognized by the CRC-32 of its image
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct KnownRom {
+    pub crc32: u32,
+    pub version: &'static str,
+}
+
+/// Released CodyBASIC ROMs, an image is only added here once it was checked against a release
+pub const KNOWN_ROMS: &[KnownRom] = &[];
+
+/// Which ROM an image is
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RomVersion {
+    Known(&'static str),
+    /// not in the table, a modified or corrupt image or a release this emulator doesn't know yet
+    Unknown {
+        crc32: u32,
+    },
+}
+
+/// Look up the image in `known`, usually [`KNOWN_ROMS`]
+pub fn identify_rom(data: &[u8], known: &[KnownRom]) -> RomVersion {
+    let crc32 = crc32(data);
+    known
+        .iter()
+        .find(|rom| rom.crc32 == crc32)
+        .map_or(RomVersion::Unknown { crc32 }, |rom| {
+            RomVersion::Known(rom.version)
+        })
+}
+
+/// CRC-32/ISO-HDLC as computed by zip and `crc32`: reflected polynomial 0xEDB88320
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &b| {
+        let mut crc = crc ^ b as u32;
+        for _ in 0..8 {
+            crc = if (crc & 1) != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+        |key| vars.iter().find(|(k, _)| *k == key).map(|(_, v)| v.into())
+    }
+
+    #[test]
+    fn test_search_paths() {
+        let vars = [
+            ("HOME", "/home/cody"),
+            ("XDG_DATA_DIRS", "/opt/share:relative"),
+        ];
+        assert_eq!(
+            search_paths(None, env(&vars)),
+            [
+                PathBuf::from("/home/cody/.local/share/cody_emulator/codybasic.bin"),
+                PathBuf::from("/opt/share/cody_emulator/codybasic.bin"),
+            ]
+        );
+        assert_eq!(
+            search_paths(None, env(&[("CODY_ROM", "basic.bin")])),
+            [PathBuf::from("basic.bin")]
+        );
+        assert_eq!(
+            search_paths(
+                Some(Path::new("rom.bin")),
+                env(&[("CODY_ROM", "basic.bin")])
+            ),
+            [PathBuf::from("rom.bin")]
+        );
+        assert_eq!(
+            search_paths(None, env(&[("XDG_DATA_HOME", "/data")]))[0],
+            PathBuf::from("/data/cody_emulator/codybasic.bin")
+        );
+    }
+
+    #[test]
+    fn test_verify_rom() {
+        let mut rom = vec![0; ROM_SIZE];
+        assert!(verify_rom(&rom).is_err());
+        rom[ROM_SIZE - 4..ROM_SIZE - 2].copy_from_slice(&[0x00, 0xE0]);
+        assert_eq!(verify_rom(&rom), Ok(()));
+        assert!(verify_rom(&rom[1..]).is_err());
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn test_identify_rom() {
+        let mut rom = vec![0; ROM_SIZE];
+        rom[ROM_SIZE - 4..ROM_SIZE - 2].copy_from_slice(&[0x00, 0xE0]);
+        let known = [KnownRom {
+            crc32: crc32(&rom),
+            version: "1.0",
+        }];
+        assert_eq!(identify_rom(&rom, &known), RomVersion::Known("1.0"));
+
+        // a single flipped bit makes a known image unknown
+        let mut corrupt = rom.clone();
+        corrupt[0x100] ^= 0x01;
+        assert_eq!(verify_rom(&corrupt), Ok(()));
+        assert_eq!(
+            identify_rom(&corrupt, &known),
+            RomVersion::Unknown {
+                crc32: crc32(&corrupt)
+            }
+        );
+
+        let unknown = vec![0xEA; ROM_SIZE];
+        assert!(matches!(
+            identify_rom(&unknown, &known),
+            RomVersion::Unknown { .. }
+        ));
+        assert!(matches!(
+            identify_rom(&rom, KNOWN_ROMS),
+            RomVersion::Unknown { .. }
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::device::uart::{
+    UART_CMND, UART_CNTL, UART_RXBF, UART_RXHD, UART_RXTL, UART_STAT, UART_TXBF, UART_TXHD,
+    UART_TXTL, UART1_BASE, UART2_BASE,
+};
+use crate::device::via::{
+    VIA_ACR, VIA_DDRA, VIA_DDRB, VIA_IER, VIA_IFR, VIA_IORA, VIA_IORA_NO_HANDSHAKE, VIA_IORB,
+    VIA_PCR, VIA_SR, VIA_T1CH, VIA_T1CL, VIA_T1LH, VIA_T1LL, VIA_T2CH, VIA_T2CL,
+};
+use std::collections::HashMap;
+
+pub const VIA_BASE: u16 = 0x9F00;
+
+const VIA_REGISTERS: [(&str, u16); 16] = [
+    ("IORB", VIA_IORB),
+    ("IORA", VIA_IORA),
+    ("DDRB", VIA_DDRB),
+    ("DDRA", VIA_DDRA),
+    ("T1CL", VIA_T1CL),
+    ("T1CH", VIA_T1CH),
+    ("T1LL", VIA_T1LL),
+    ("T1LH", VIA_T1LH),
+    ("T2CL", VIA_T2CL),
+    ("T2CH", VIA_T2CH),
+    ("SR", VIA_SR),
+    ("ACR", VIA_ACR),
+    ("PCR", VIA_PCR),
+    ("IFR", VIA_IFR),
+    ("IER", VIA_IER),
+    ("IORA_NO_HANDSHAKE", VIA_IORA_NO_HANDSHAKE),
+];
+
+const UART_REGISTERS: [(&str, u16); 9] = [
+    ("CNTL", UART_CNTL),
+    ("CMND", UART_CMND),
+    ("STAT", UART_STAT),
+    ("RXHD", UART_RXHD),
+    ("RXTL", UART_RXTL),
+    ("TXHD", UART_TXHD),
+    ("TXTL", UART_TXTL),
+    ("RXBF", UART_RXBF),
+    ("TXBF", UART_TXBF),
+];
+
+const VID_REGISTERS: [(&str, u16); 9] = [
+    ("VID_BLANK", 0xD000),
+    ("VID_CTRL", 0xD001),
+    ("VID_COLOR", 0xD002),
+    ("VID_BASE", 0xD003),
+    ("VID_SCROLL", 0xD004),
+    ("VID_SCREEN_COLORS", 0xD005),
+    ("VID_SPRITE", 0xD006),
+    ("VID_EFFECT_CTRL", 0xD040),
+    ("VID_EFFECT_DATA", 0xD060),
+];
+
+/// Names of the Cody's hardware registers, e.g. `VID_CTRL` or `VIA_IFR`, together with their address.
+///
+/// The base addresses of the VIA and the UARTs come before their first register.
+pub fn cody_symbols() -> Vec<(String, u16)> {
+    let mut symbols = vec![("VIA_BASE".to_string(), VIA_BASE)];
+    symbols.extend(
+        VIA_REGISTERS
+            .iter()
+            .map(|&(name, offset)| (format!("VIA_{name}"), VIA_BASE + offset)),
+    );
+    for (uart, base) in [("UART1", UART1_BASE), ("UART2", UART2_BASE)] {
+        symbols.push((format!("{uart}_BASE"), base));
+        symbols.extend(
+            UART_REGISTERS
+                .iter()
+                .map(|&(name, offset)| (format!("{uart}_{name}"), base + offset)),
+        );
+    }
+    symbols.extend(
+        VID_REGISTERS
+            .iter()
+            .map(|&(name, address)| (name.to_string(), address)),
+    );
+    symbols
+}
+
+/// [`cody_symbols`] by address for the disassembler, registers take precedence over base addresses
+pub fn cody_addresses() -> HashMap<u16, String> {
+    cody_symbols()
+        .into_iter()
+        .map(|(name, address)| (address, name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cody_symbols() {
+        let symbols: HashMap<_, _> = cody_symbols().into_iter().collect();
+        assert_eq!(symbols.len(), cody_symbols().len(), "duplicate names");
+        assert_eq!(symbols["VIA_IFR"], 0x9F0D);
+        assert_eq!(symbols["UART2_TXBF"], 0xD4B0);
+
+        let addresses = cody_addresses();
+        assert_eq!(addresses[&0xD001], "VID_CTRL");
+        assert_eq!(addresses[&0x9F00], "VIA_IORB");
+        assert_eq!(addresses[&0xD480], "UART1_CNTL");
+    }
+}
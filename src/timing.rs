@@ -0,0 +1,177 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Video standard the propeller generates, which decides the frame rate and the length of vertical blanking
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VideoStandard {
+    /// 262 lines at 59.94 Hz
+    #[default]
+    Ntsc,
+    /// 312 lines at 50 Hz
+    Pal,
+}
+
+impl VideoStandard {
+    /// (half-)frames per second
+    pub fn frame_rate(self) -> f64 {
+        match self {
+            Self::Ntsc => 60.0 / 1.001,
+            Self::Pal => 50.0,
+        }
+    }
+
+    /// Lines in a (half-)frame
+    pub const fn lines_per_frame(self) -> usize {
+        match self {
+            Self::Ntsc => 262,
+            Self::Pal => 312,
+        }
+    }
+
+    /// Lines at the start of a (half-)frame during which the blanking register reads 1.
+    ///
+    /// Everything except the 220 lines of top border and screen area counts, for NTSC that is
+    /// 9 lines of VSYNC, 12 blank lines and the 21 lines of the bottom border.
+    pub const fn blanking_lines(self) -> usize {
+        self.lines_per_frame() - 220
+    }
+}
+
+/// Cpu clock and video timing, every device and the frontend derive their timing from it.
+///
+/// The cycle counter is the only clock: a frame is a whole number of cycles and everything else is derived from
+/// [`Self::frame_cycles`] and [`Self::line`], so the blanking register, the renderer and the frontend's pacing agree to
+/// the cycle. The resulting frame rate is slightly off the nominal [`VideoStandard::frame_rate`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct TimingProfile {
+    /// cpu cycles per second
+    pub cpu_frequency: u32,
+    pub video: VideoStandard,
+}
+
+impl Default for TimingProfile {
+    fn default() -> Self {
+        Self::NTSC
+    }
+}
+
+impl TimingProfile {
+    /// The WD65C02 runs at 1MHz
+    pub const CODY_FREQUENCY: u32 = 1000000;
+    /// Lowest cpu clock, slower clocks leave too few cycles for the lines of a frame
+    pub const MIN_FREQUENCY: u32 = 1000;
+    /// Highest cpu clock, 1000 times the real hardware
+    pub const MAX_FREQUENCY: u32 = 1000000000;
+    pub const NTSC: Self = Self::new(Self::CODY_FREQUENCY, VideoStandard::Ntsc);
+    pub const PAL: Self = Self::new(Self::CODY_FREQUENCY, VideoStandard::Pal);
+
+    pub const fn new(cpu_frequency: u32, video: VideoStandard) -> Self {
+        Self {
+            cpu_frequency,
+            video,
+        }
+    }
+
+    /// Check that `cpu_frequency` is within [`Self::MIN_FREQUENCY`] and [`Self::MAX_FREQUENCY`]
+    pub fn check_frequency(cpu_frequency: u32) -> Result<u32, String> {
+        if (Self::MIN_FREQUENCY..=Self::MAX_FREQUENCY).contains(&cpu_frequency) {
+            Ok(cpu_frequency)
+        } else {
+            Err(format!(
+                "cpu frequency {cpu_frequency} Hz is not in {}..={} Hz",
+                Self::MIN_FREQUENCY,
+                Self::MAX_FREQUENCY
+            ))
+        }
+    }
+
+    /// Real time of one (half-)frame, the time its cycles take
+    pub fn frame_duration(&self) -> Duration {
+        self.cycles_duration(self.frame_cycles())
+    }
+
+    /// Real time of `cycles` cpu cycles
+    pub fn cycles_duration(&self, cycles: usize) -> Duration {
+        Duration::from_secs_f64(cycles as f64 / self.cpu_frequency as f64)
+    }
+
+    /// Cpu cycles in a (half-)frame
+    pub fn frame_cycles(&self) -> usize {
+        (self.cpu_frequency as f64 / self.video.frame_rate()) as usize
+    }
+
+    /// Cpu cycles at the start of a (half-)frame during which the blanking register reads 1
+    pub fn blanking_cycles(&self) -> usize {
+        self.line_start(self.video.blanking_lines())
+    }
+
+    /// Line of the current (half-)frame the beam is on at `cycle`
+    pub fn line(&self, cycle: usize) -> usize {
+        let frame_cycles = self.frame_cycles();
+        (cycle % frame_cycles) * self.video.lines_per_frame() / frame_cycles
+    }
+
+    /// First cycle of a (half-)frame at which the beam is on `line`
+    pub fn line_start(&self, line: usize) -> usize {
+        (line * self.frame_cycles()).div_ceil(self.video.lines_per_frame())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ntsc() {
+        let timing = TimingProfile::NTSC;
+        assert_eq!(timing.frame_cycles(), 16683);
+        assert_eq!(timing.blanking_cycles(), 2675);
+        assert_eq!(timing.frame_duration(), Duration::from_micros(16683));
+        assert_eq!(timing.line(0), 0);
+        assert_eq!(timing.line(timing.frame_cycles() - 1), 261);
+        assert_eq!(timing.line(timing.frame_cycles()), 0);
+    }
+
+    #[test]
+    fn test_pal_and_frequency() {
+        let timing = TimingProfile::PAL;
+        assert_eq!(timing.frame_cycles(), 20000);
+        assert_eq!(timing.blanking_cycles(), 5898);
+
+        let fast = TimingProfile::new(2 * TimingProfile::CODY_FREQUENCY, VideoStandard::Pal);
+        assert_eq!(fast.frame_cycles(), 40000);
+        assert_eq!(fast.cycles_duration(2), Duration::from_micros(1));
+        assert_eq!(fast.frame_duration(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_check_frequency() {
+        assert_eq!(TimingProfile::check_frequency(1000), Ok(1000));
+        assert!(TimingProfile::check_frequency(0).is_err());
+        assert!(TimingProfile::check_frequency(999).is_err());
+        assert!(TimingProfile::check_frequency(TimingProfile::MAX_FREQUENCY + 1).is_err());
+        let slowest = TimingProfile::new(TimingProfile::MIN_FREQUENCY, VideoStandard::Pal);
+        assert!(slowest.frame_cycles() > 0);
+    }
+
+    #[test]
+    fn test_clock_agrees() {
+        for timing in [TimingProfile::NTSC, TimingProfile::PAL] {
+            let blanking_lines = timing.video.blanking_lines();
+            for cycle in 0..timing.frame_cycles() {
+                let line = timing.line(cycle);
+                assert_eq!(
+                    cycle < timing.blanking_cycles(),
+                    line < blanking_lines,
+                    "{cycle}"
+                );
+                assert!(timing.line_start(line) <= cycle && cycle < timing.line_start(line + 1));
+            }
+            assert_eq!(
+                timing.line_start(timing.video.lines_per_frame()),
+                timing.frame_cycles()
+            );
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::device::uart::UartActivity;
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event;
+use ratatui::crossterm::event::{Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Collects log output so it can be shown inside the dashboard instead of garbling the terminal
+#[derive(Debug, Clone, Default)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl LogBuffer {
+    const MAX_LINES: usize = 256;
+
+    fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+impl Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut lines = self.lines.lock().unwrap();
+        for line in String::from_utf8_lossy(buf).lines() {
+            lines.push_back(line.to_string());
+        }
+        while lines.len() > Self::MAX_LINES {
+            lines.pop_front();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Snapshot of the emulator state shown on the dashboard
+#[derive(Debug, Clone)]
+pub struct DashboardStats {
+    pub running: bool,
+    pub cycle: usize,
+    pub frames: usize,
+    /// emulation speed relative to the real Cody
+    pub speed: f64,
+    pub irqs: usize,
+    pub nmis: usize,
+    pub uart1: UartActivity,
+}
+
+/// Terminal dashboard for headless runs, press `q` or `Esc` to quit
+pub struct Dashboard {
+    terminal: DefaultTerminal,
+    logs: LogBuffer,
+    last_draw: Option<Instant>,
+}
+
+impl Dashboard {
+    pub fn new(logs: LogBuffer) -> Self {
+        Self {
+            terminal: ratatui::init(),
+            logs,
+            last_draw: None,
+        }
+    }
+
+    /// Redraw if necessary and handle input, returns false if the user wants to quit
+    pub fn update(&mut self, stats: &DashboardStats) -> bool {
+        if self
+            .last_draw
+            .is_some_and(|last_draw| last_draw.elapsed() < REDRAW_INTERVAL)
+        {
+            return true;
+        }
+        self.last_draw = Some(Instant::now());
+
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read()
+                && key.kind == KeyEventKind::Press
+                && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+            {
+                return false;
+            }
+        }
+
+        let logs = self.logs.lines();
+        let result = self.terminal.draw(|frame| {
+            let [status_area, uart_area, log_area] = Layout::vertical([
+                Constraint::Length(4),
+                Constraint::Fill(1),
+                Constraint::Fill(1),
+            ])
+            .areas(frame.area());
+
+            let status = Paragraph::new(vec![
+                Line::from(format!(
+                    "{}  cycles: {}  frames: {}  speed: {:.0}%",
+                    if stats.running { "running" } else { "stopped" },
+                    stats.cycle,
+                    stats.frames,
+                    stats.speed * 100.0
+                )),
+                Line::from(format!(
+                    "IRQs: {}  NMIs: {}  UART1 rx: {} tx: {}",
+                    stats.irqs, stats.nmis, stats.uart1.rx_bytes, stats.uart1.tx_bytes
+                )),
+            ])
+            .block(Block::bordered().title("Cody (q to quit)"));
+            frame.render_widget(status, status_area);
+
+            let uart_lines: Vec<_> = stats.uart1.tx_lines().map(Line::from).collect();
+            let uart_scroll = uart_lines
+                .len()
+                .saturating_sub(uart_area.height.saturating_sub(2) as usize);
+            let uart = Paragraph::new(uart_lines)
+                .scroll((uart_scroll as u16, 0))
+                .block(Block::bordered().title("UART1 output"));
+            frame.render_widget(uart, uart_area);
+
+            let log_scroll = logs
+                .len()
+                .saturating_sub(log_area.height.saturating_sub(2) as usize);
+            let log = Paragraph::new(logs.into_iter().map(Line::from).collect::<Vec<_>>())
+                .scroll((log_scroll as u16, 0))
+                .block(Block::bordered().title("Log"));
+            frame.render_widget(log, log_area);
+        });
+        result.is_ok()
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
@@ -0,0 +1,182 @@
+use std::io::Write;
+
+/// Signal that can be exported to a Value Change Dump
+#[derive(Debug, Copy, Clone, Eq, PartialEq, clap::ValueEnum)]
+pub enum VcdSignal {
+    /// IRQ line of the cpu
+    Irq,
+    /// NMI line of the cpu
+    Nmi,
+    /// vertical blanking, as read from the blanking register
+    Vblank,
+    /// timer 1 interrupt flag of the VIA, set on underflow
+    ViaT1,
+    /// timer 2 interrupt flag of the VIA, set on underflow
+    ViaT2,
+    /// bit 7 of VIA port B
+    ViaPb7,
+    /// byte sent by UART1, unknown between bytes so repeated bytes stay visible
+    Uart1Tx,
+}
+
+impl VcdSignal {
+    pub const ALL: [Self; 7] = [
+        Self::Irq,
+        Self::Nmi,
+        Self::Vblank,
+        Self::ViaT1,
+        Self::ViaT2,
+        Self::ViaPb7,
+        Self::Uart1Tx,
+    ];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Irq => "irq",
+            Self::Nmi => "nmi",
+            Self::Vblank => "vblank",
+            Self::ViaT1 => "via_t1",
+            Self::ViaT2 => "via_t2",
+            Self::ViaPb7 => "via_pb7",
+            Self::Uart1Tx => "uart1_tx",
+        }
+    }
+
+    const fn width(self) -> u8 {
+        match self {
+            Self::Uart1Tx => 8,
+            _ => 1,
+        }
+    }
+}
+
+/// Writes the values of [`VcdSignal`]s whenever they change, in nanoseconds of emulated time
+pub struct VcdWriter {
+    w: Box<dyn Write>,
+    signals: Vec<VcdSignal>,
+    cpu_frequency: u32,
+    /// values of the last sample, `None` is unknown
+    values: Vec<Option<u8>>,
+    /// time of the last timestamp written, `None` before the first sample
+    time: Option<u128>,
+}
+
+impl VcdWriter {
+    /// Write the header declaring `signals`
+    pub fn new(
+        mut w: Box<dyn Write>,
+        signals: Vec<VcdSignal>,
+        cpu_frequency: u32,
+    ) -> std::io::Result<Self> {
+        writeln!(
+            w,
+            "$version cody_emulator {} $end",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(w, "$timescale 1ns $end")?;
+        writeln!(w, "$scope module cody $end")?;
+        for (i, signal) in signals.iter().enumerate() {
+            writeln!(
+                w,
+                "$var wire {} {} {} $end",
+                signal.width(),
+                identifier(i),
+                signal.name()
+            )?;
+        }
+        writeln!(w, "$upscope $end")?;
+        writeln!(w, "$enddefinitions $end")?;
+        Ok(Self {
+            w,
+            values: vec![None; signals.len()],
+            signals,
+            cpu_frequency,
+            time: None,
+        })
+    }
+
+    pub fn signals(&self) -> &[VcdSignal] {
+        &self.signals
+    }
+
+    /// Record the values of the signals at `cycle` in the order of [`Self::signals`], only changes are written
+    pub fn sample(&mut self, cycle: usize, values: &[Option<u8>]) -> std::io::Result<()> {
+        let time = cycle as u128 * 1_000_000_000 / self.cpu_frequency as u128;
+        // times have to increase, even if the cycle counter does not
+        let time = self.time.map_or(time, |last| time.max(last));
+        let first = self.time.is_none();
+        for (i, (&value, last)) in values.iter().zip(&mut self.values).enumerate() {
+            if value == *last && !first {
+                continue;
+            }
+            if self.time != Some(time) {
+                writeln!(self.w, "#{time}")?;
+                self.time = Some(time);
+            }
+            *last = value;
+            let id = identifier(i);
+            match (self.signals[i].width(), value) {
+                (1, Some(value)) => writeln!(self.w, "{}{id}", value & 1)?,
+                (1, None) => writeln!(self.w, "x{id}")?,
+                (_, Some(value)) => writeln!(self.w, "b{value:08b} {id}")?,
+                (_, None) => writeln!(self.w, "bx {id}")?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// Short identifier of the `i`th signal
+fn identifier(i: usize) -> char {
+    (b'!' + i as u8) as char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Writer whose output can still be read after it was moved into a [`VcdWriter`]
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_vcd() {
+        let output = Shared::default();
+        let mut vcd = VcdWriter::new(
+            Box::new(output.clone()),
+            vec![VcdSignal::Irq, VcdSignal::Uart1Tx],
+            1_000_000,
+        )
+        .unwrap();
+        vcd.sample(0, &[Some(0), None]).unwrap();
+        vcd.sample(2, &[Some(0), None]).unwrap();
+        vcd.sample(5, &[Some(1), Some(b'A')]).unwrap();
+        vcd.sample(7, &[Some(1), None]).unwrap();
+        vcd.sample(12, &[Some(0), None]).unwrap();
+
+        let output = String::from_utf8(output.0.take()).unwrap();
+        let (header, changes) = output.split_once("$enddefinitions $end\n").unwrap();
+        assert!(header.contains("$var wire 1 ! irq $end\n$var wire 8 \" uart1_tx $end\n"));
+        assert!(header.contains("$timescale 1ns $end"));
+        assert_eq!(
+            changes,
+            "#0\n0!\nbx \"\n#5000\n1!\nb01000001 \"\n#7000\nbx \"\n#12000\n0!\n"
+        );
+    }
+}
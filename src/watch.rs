@@ -0,0 +1,291 @@
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Number of changes kept in [`Watches::log`]
+const LOG_SIZE: usize = 256;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    S,
+    P,
+    Pc,
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::A => "A",
+            Self::X => "X",
+            Self::Y => "Y",
+            Self::S => "S",
+            Self::P => "P",
+            Self::Pc => "PC",
+        })
+    }
+}
+
+/// A single watched value
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Location {
+    Register(Register),
+    Byte(u16),
+    /// little endian
+    Word(u16),
+}
+
+impl Location {
+    pub fn read<M: Memory>(self, cpu: &mut Cpu<M>) -> u16 {
+        match self {
+            Self::Register(Register::A) => cpu.a as u16,
+            Self::Register(Register::X) => cpu.x as u16,
+            Self::Register(Register::Y) => cpu.y as u16,
+            Self::Register(Register::S) => cpu.s as u16,
+            Self::Register(Register::P) => cpu.p.into_bits() as u16,
+            Self::Register(Register::Pc) => cpu.pc,
+            Self::Byte(address) => cpu.memory.peek_u8(address) as u16,
+            Self::Word(address) => u16::from_le_bytes([
+                cpu.memory.peek_u8(address),
+                cpu.memory.peek_u8(address.wrapping_add(1)),
+            ]),
+        }
+    }
+
+    /// Format `value` with as many digits as the location holds
+    pub fn format_value(self, value: u16) -> String {
+        match self {
+            Self::Register(Register::Pc) | Self::Word(_) => format!("0x{value:04X}"),
+            _ => format!("0x{value:02X}"),
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Register(register) => write!(f, "{register}"),
+            Self::Byte(address) => write!(f, "0x{address:04X}"),
+            Self::Word(address) => write!(f, "0x{address:04X}.w"),
+        }
+    }
+}
+
+/// Register, byte, word or range of bytes that is checked for changes after every instruction
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Watch {
+    Location(Location),
+    /// inclusive range of bytes, changes are reported per address
+    Range(u16, u16),
+}
+
+impl Watch {
+    pub fn locations(self) -> Vec<Location> {
+        match self {
+            Self::Location(location) => vec![location],
+            Self::Range(start, end) => (start..=end).map(Location::Byte).collect(),
+        }
+    }
+}
+
+impl FromStr for Watch {
+    type Err = String;
+
+    /// `a`, `x`, `y`, `s`, `p`, `pc`, `ADDRESS`, `ADDRESS.w` or `START-END`, numbers are decimal or hex with a `0x` prefix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let register = match s.to_ascii_lowercase().as_str() {
+            "a" => Some(Register::A),
+            "x" => Some(Register::X),
+            "y" => Some(Register::Y),
+            "s" => Some(Register::S),
+            "p" => Some(Register::P),
+            "pc" => Some(Register::Pc),
+            _ => None,
+        };
+        if let Some(register) = register {
+            return Ok(Self::Location(Location::Register(register)));
+        }
+
+        let address = |text: &str| {
+            clap_num::maybe_hex::<u16>(text.trim()).map_err(|_| {
+                format!("expected a register, ADDRESS, ADDRESS.w or START-END, got {s:?}")
+            })
+        };
+        if let Some(word) = s.strip_suffix(".w") {
+            Ok(Self::Location(Location::Word(address(word)?)))
+        } else if let Some((start, end)) = s.split_once('-') {
+            let (start, end) = (address(start)?, address(end)?);
+            if start > end {
+                return Err(format!("range {s:?} ends before it starts"));
+            }
+            Ok(Self::Range(start, end))
+        } else {
+            Ok(Self::Location(Location::Byte(address(s)?)))
+        }
+    }
+}
+
+impl Display for Watch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Location(location) => write!(f, "{location}"),
+            Self::Range(start, end) => write!(f, "0x{start:04X}-0x{end:04X}"),
+        }
+    }
+}
+
+/// A watched value that changed during one instruction
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct WatchChange {
+    pub location: Location,
+    pub old: u16,
+    pub new: u16,
+    /// cpu cycle after the instruction
+    pub cycle: usize,
+    /// address of the instruction that changed the value
+    pub pc: u16,
+}
+
+impl Display for WatchChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} changed from {} to {} by instruction at 0x{:04X} (cycle {})",
+            self.location,
+            self.location.format_value(self.old),
+            self.location.format_value(self.new),
+            self.pc,
+            self.cycle
+        )
+    }
+}
+
+/// Values checked after every instruction together with a log of their recent changes
+#[derive(Debug, Clone, Default)]
+pub struct Watches {
+    watches: Vec<Watch>,
+    /// last value of every watched location
+    values: BTreeMap<Location, u16>,
+    log: VecDeque<WatchChange>,
+}
+
+impl Watches {
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    /// Start watching, the current value is the baseline for the first change
+    pub fn add<M: Memory>(&mut self, watch: Watch, cpu: &mut Cpu<M>) {
+        for location in watch.locations() {
+            self.values.insert(location, location.read(cpu));
+        }
+        self.watches.push(watch);
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        self.watches.remove(index);
+        let watched: Vec<_> = self.watches.iter().flat_map(|w| w.locations()).collect();
+        self.values.retain(|location, _| watched.contains(location));
+    }
+
+    /// Last seen value of a watched location
+    pub fn value(&self, location: Location) -> Option<u16> {
+        self.values.get(&location).copied()
+    }
+
+    /// The most recent changes, oldest first
+    pub fn log(&self) -> &VecDeque<WatchChange> {
+        &self.log
+    }
+
+    /// Compare all watched values to the last check, `pc` is the address of the instruction executed since then
+    pub fn check<M: Memory>(&mut self, cpu: &mut Cpu<M>, pc: u16) -> Vec<WatchChange> {
+        let mut changes = vec![];
+        for (&location, value) in &mut self.values {
+            let new = location.read(cpu);
+            if new != *value {
+                changes.push(WatchChange {
+                    location,
+                    old: *value,
+                    new,
+                    cycle: cpu.cycle(),
+                    pc,
+                });
+                *value = new;
+            }
+        }
+        for &change in &changes {
+            if self.log.len() == LOG_SIZE {
+                self.log.pop_front();
+            }
+            self.log.push_back(change);
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::contiguous::Contiguous;
+
+    #[test]
+    fn test_parse_watch() {
+        assert_eq!(
+            "pc".parse(),
+            Ok(Watch::Location(Location::Register(Register::Pc)))
+        );
+        assert_eq!("0x10".parse(), Ok(Watch::Location(Location::Byte(0x10))));
+        assert_eq!(
+            "0xD000.w".parse(),
+            Ok(Watch::Location(Location::Word(0xD000)))
+        );
+        assert_eq!("0x10-0x1F".parse(), Ok(Watch::Range(0x10, 0x1F)));
+        assert!("0x1F-0x10".parse::<Watch>().is_err());
+        assert!("q".parse::<Watch>().is_err());
+    }
+
+    #[test]
+    fn test_watches() {
+        // LDA #$01, STA $11, INC $11, STP
+        let program = [0xA9, 0x01, 0x85, 0x11, 0xE6, 0x11, 0xDB];
+        let mut memory = Contiguous::new_ram(0x10000);
+        memory.force_write_all(0x0200, &program);
+        memory.write_u16(0xFFFC, 0x0200);
+        let mut cpu = Cpu::new(memory);
+
+        let mut watches = Watches::default();
+        watches.add("a".parse().unwrap(), &mut cpu);
+        watches.add("0x10-0x12".parse().unwrap(), &mut cpu);
+
+        let mut changes = vec![];
+        while cpu.is_running() {
+            let pc = cpu.pc;
+            cpu.step_instruction();
+            changes.extend(watches.check(&mut cpu, pc));
+        }
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|c| (c.location, c.old, c.new, c.pc))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (Location::Register(Register::A), 0, 1, 0x0200),
+                (Location::Byte(0x11), 0, 1, 0x0202),
+                (Location::Byte(0x11), 1, 2, 0x0204),
+            ]
+        );
+        assert_eq!(watches.log().len(), 3);
+        assert_eq!(watches.value(Location::Byte(0x11)), Some(2));
+
+        watches.remove(1);
+        assert_eq!(watches.value(Location::Byte(0x11)), None);
+        assert_eq!(watches.value(Location::Register(Register::A)), Some(1));
+    }
+}
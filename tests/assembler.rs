@@ -1,9 +1,17 @@
-use cody_emulator::assembler::{MnemonicDSL, Parameter, assemble};
+use cody_emulator::assembler::{
+    Instruction, ListingFormat, MnemonicDSL, Parameter, SourceLocation, assemble, assemble_at,
+    disassemble, disassemble_code, find_code, parse, parse_symbols, parse_with_constants,
+    vector_entries, write_listing,
+};
 use cody_emulator::cpu;
 use cody_emulator::cpu::Cpu;
 use cody_emulator::memory::Memory;
 use cody_emulator::memory::contiguous::Contiguous;
-use cody_emulator::opcode::Opcode;
+use cody_emulator::opcode::{AddressingMode, OPCODES, Opcode, get_instructions};
+use cody_emulator::symbols::{cody_addresses, cody_symbols};
+use itertools::Itertools;
+use proptest::prelude::*;
+use std::collections::HashMap;
 
 #[test]
 pub fn test_assemble_labels_1() {
@@ -66,3 +74,332 @@ pub fn test_assemble_bbs_labels() {
 
     assert_eq!(cpu.a, 2);
 }
+
+#[test]
+pub fn test_assemble_text() {
+    let source = "
+        ; sum 1..=5 into A
+        COUNT = 5
+
+        start:  LDA #0
+                LDX #COUNT
+        loop:   STX $10
+                CLC
+                ADC $10
+                DEX
+                BNE loop
+                JMP done
+                NOP
+        done:   STP
+    ";
+    let program = parse(source).unwrap();
+    let mut binary = vec![];
+    assemble_at(&program, 0x0200, &mut binary).unwrap();
+
+    let mut memory = Contiguous::new_ram(0x10000);
+    memory.force_write_all(0x0200, &binary);
+    memory.write_u16(cpu::RESET_VECTOR, 0x0200);
+    let mut cpu = Cpu::new(memory);
+    cpu.run();
+
+    assert_eq!(cpu.a, 15);
+}
+
+#[test]
+pub fn test_parse_errors() {
+    assert!(parse("LDA #$100").is_err());
+    assert!(parse("FOO $10").is_err());
+    assert!(parse("LDA ($10").is_err());
+    assert!(parse("LDA #label").is_err());
+    assert!(parse("label:").is_err());
+}
+
+#[test]
+pub fn test_parse_equates() {
+    let program = parse(
+        "
+        .equ VIA_BASE = $9F00
+        .EQU VIA_IFR = VIA_BASE + $0D
+        .set COUNT = 1
+        .set COUNT = COUNT + 2
+                LDA #COUNT
+                STA VIA_IFR
+                STA VIA_BASE+1,X
+        ",
+    )
+    .unwrap();
+    let mut binary = vec![];
+    assemble(&program, &mut binary).unwrap();
+    assert_eq!(binary, [0xA9, 0x03, 0x8D, 0x0D, 0x9F, 0x9D, 0x01, 0x9F]);
+
+    assert!(parse("FOO = 1\nFOO = 2").is_err());
+    assert!(parse(".equ FOO = 1\n.set FOO = 2").is_err());
+    assert!(parse(".set FOO = 1\n.equ FOO = 2").is_err());
+    assert!(parse(".def FOO = 1").is_err());
+    assert!(parse("FOO = BAR + 1").is_err());
+    assert!(parse("FOO = $FFFF + 1").is_err());
+}
+
+#[test]
+pub fn test_parse_cody_symbols() {
+    let constants = cody_symbols().into_iter().collect();
+    let program =
+        parse_with_constants("LDA VID_CTRL\nVIA_IFR = $10\nSTA VIA_IFR", constants).unwrap();
+    let mut binary = vec![];
+    assemble(&program, &mut binary).unwrap();
+    assert_eq!(binary, [0xAD, 0x01, 0xD0, 0x85, 0x10]);
+
+    // without the predefined symbols it is a label
+    assert_eq!(
+        parse("LDA VID_CTRL").unwrap(),
+        [Opcode::LDA.with(Parameter::label("VID_CTRL"))]
+    );
+}
+
+#[test]
+pub fn test_error_locations() {
+    let error = parse("LDA #1\n  LDA ($10").unwrap_err();
+    assert_eq!(
+        error.location(),
+        Some(SourceLocation {
+            line: 2,
+            column: 7,
+            len: 4
+        })
+    );
+
+    let source = "start:  LDA #1\n\tBNE missing ; typo";
+    let program = parse(source).unwrap();
+    let error = assemble(&program, std::io::sink()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "line 2, column 2: unknown label: missing"
+    );
+    assert_eq!(
+        error.render("program.asm", source),
+        "error: unknown label: missing\n --> program.asm:2:2\n  |\n2 | \tBNE missing ; typo\n  | \t^^^^^^^^^^^"
+    );
+
+    // instructions that were not parsed are counted instead
+    let error = assemble(
+        &[Opcode::BRA.with(Parameter::label("missing"))],
+        std::io::sink(),
+    )
+    .unwrap_err();
+    assert_eq!(error.location(), None);
+    assert_eq!(error.to_string(), "instruction 1: unknown label: missing");
+}
+
+#[test]
+pub fn test_disassemble() {
+    let data = [
+        0xA9, 0x01, 0x8D, 0x02, 0xD0, 0xD0, 0xF9, 0x0F, 0x10, 0xFD, 0x02, 0x4C,
+    ];
+    let symbols = parse_symbols("VID_BORDER = $D002 ; border color").unwrap();
+    let listing: Vec<_> = disassemble(&data, 0xE000)
+        .iter()
+        .map(|insn| insn.format(&symbols))
+        .collect();
+    assert_eq!(
+        listing,
+        [
+            "E000  A9 01     LDA #$01",
+            "E002  8D 02 D0  STA VID_BORDER",
+            "E005  D0 F9     BNE $E000",
+            "E007  0F 10 FD  BBR0 $10,$E007",
+            "E00A  02        .byte $02",
+            "E00B  4C        .byte $4C",
+        ]
+    );
+}
+
+#[test]
+pub fn test_disassemble_code() {
+    let data = [
+        0x4C, 0x05, 0xE0, 0x01, 0x02, 0x20, 0x0A, 0xE0, 0x80, 0xFE, 0x60, 0xA9, 0xFF,
+    ];
+    let entries = vector_entries(&data, 0xE000);
+    assert_eq!(entries, [0xE000]);
+    let listing = |entries: &[u16]| -> Vec<_> {
+        let code = find_code(&data, 0xE000, entries.iter().copied());
+        disassemble_code(&data, 0xE000, &code)
+            .iter()
+            .map(|insn| insn.format(&HashMap::new()))
+            .collect()
+    };
+    assert_eq!(
+        listing(&entries),
+        [
+            "E000  4C 05 E0  JMP $E005",
+            "E003  01 02     .byte $01,$02",
+            "E005  20 0A E0  JSR $E00A",
+            "E008  80 FE     BRA $E008",
+            "E00A  60        RTS",
+            "E00B  A9 FF     .byte $A9,$FF",
+        ]
+    );
+    // executed addresses add code that is only reached indirectly
+    assert_eq!(listing(&[0xE000, 0xE00B])[5], "E00B  A9 FF     LDA #$FF");
+
+    let mut rom = vec![0xEA; 0x2000];
+    rom[0x1FFC..0x1FFE].copy_from_slice(&[0x00, 0xE0]);
+    assert_eq!(vector_entries(&rom, 0xE000), [0xEAEA, 0xE000, 0xEAEA]);
+}
+
+#[test]
+pub fn test_write_source() {
+    // LDA $0010 (absolute), STA $D001, JSR $E00C, BRA $E009, .byte $01, RTS
+    let data = [
+        0xAD, 0x10, 0x00, 0x8D, 0x01, 0xD0, 0x20, 0x0C, 0xE0, 0x80, 0xFE, 0x01, 0x60,
+    ];
+    let code = find_code(&data, 0xE000, [0xE000]);
+    let instructions = disassemble_code(&data, 0xE000, &code);
+    let source = |format: ListingFormat| {
+        let mut source = vec![];
+        write_listing(&instructions, &cody_addresses(), format, &mut source).unwrap();
+        String::from_utf8(source).unwrap()
+    };
+
+    let native = source(ListingFormat::Native);
+    assert_eq!(
+        native.lines().take(5).collect::<Vec<_>>(),
+        [
+            "VID_CTRL = $D001",
+            ".org $E000",
+            "        .byte $AD,$10,$00       ; E000  AD 10 00  LDA $0010",
+            "        STA VID_CTRL            ; E003  8D 01 D0",
+            "        JSR LE00C               ; E006  20 0C E0",
+        ]
+    );
+    assert!(native.contains("LE009:  BRA LE009"));
+    let mut binary = vec![];
+    assemble(&parse(&native).unwrap(), &mut binary).unwrap();
+    assert_eq!(binary, data);
+
+    let ca65 = source(ListingFormat::Ca65);
+    assert!(ca65.starts_with(".setcpu \"W65C02\"\nVID_CTRL = $D001\n.org $E000\n"));
+    assert!(ca65.contains("        LDA a:$0010             ; E000  AD 10 00\n"));
+}
+
+#[test]
+pub fn test_assemble_org_and_byte() {
+    let program = parse(
+        "
+        .org $0200
+        start:  .byte 1, $FF
+                JMP start
+        .org $0207
+        end:    JMP end
+        ",
+    )
+    .unwrap();
+    let mut binary = vec![];
+    assemble(&program, &mut binary).unwrap();
+    assert_eq!(
+        binary,
+        [0x01, 0xFF, 0x4C, 0x00, 0x02, 0x00, 0x00, 0x4C, 0x07, 0x02]
+    );
+
+    assert!(parse(".byte $100").is_err());
+    let program = parse("NOP\nNOP\n.org $0001").unwrap();
+    assert!(assemble_at(&program, 0x0000, std::io::sink()).is_err());
+}
+
+#[test]
+pub fn test_assemble_numeric_operands() {
+    let program = parse(
+        "
+        LDA ($10),Y
+        BNE $0200
+        BBS0 $10,$0200
+        ",
+    )
+    .unwrap();
+    let mut binary = vec![];
+    assemble_at(&program, 0x0200, &mut binary).unwrap();
+    assert_eq!(binary, [0xB1, 0x10, 0xD0, 0xFC, 0x8F, 0x10, 0xF9]);
+}
+
+const ORIGIN: u16 = 0x0200;
+
+/// Instructions for (index into [`OPCODES`], operand, byte operand), branches target their byte operand as offset.
+///
+/// Absolute operands that fit into the zero page are only generated if there is no zero page form,
+/// the assembler would pick that one instead.
+fn build_program(raw: Vec<(usize, u16, u8)>) -> Vec<Instruction> {
+    let mut next = ORIGIN;
+    raw.into_iter()
+        .map(|(index, value, byte)| {
+            let meta = &OPCODES[index];
+            next += meta.width();
+            let absolute = |zero_page_mode: AddressingMode| {
+                let has_zero_page = get_instructions(meta.opcode)
+                    .iter()
+                    .any(|candidate| candidate.parameter_1 == zero_page_mode);
+                Parameter::Absolute(if has_zero_page {
+                    value.max(0x100)
+                } else {
+                    value
+                })
+            };
+            let zero_page = Parameter::Absolute(byte as u16);
+            let branch =
+                |offset: u8| Parameter::Absolute(next.wrapping_add_signed(offset as i8 as i16));
+            let parameter = match meta.parameter_1 {
+                AddressingMode::None => Parameter::None,
+                AddressingMode::Accumulator => Parameter::A,
+                AddressingMode::Immediate => Parameter::Immediate(byte),
+                AddressingMode::Absolute => absolute(AddressingMode::ZeroPage),
+                AddressingMode::AbsoluteIndexedX => {
+                    Parameter::list([absolute(AddressingMode::ZeroPageIndexedX), Parameter::X])
+                }
+                AddressingMode::AbsoluteIndexedY => {
+                    Parameter::list([absolute(AddressingMode::ZeroPageIndexedY), Parameter::Y])
+                }
+                AddressingMode::AbsoluteIndirect => {
+                    Parameter::Indirect(Box::new(absolute(AddressingMode::ZeroPageIndirect)))
+                }
+                AddressingMode::AbsoluteIndexedIndirectX => {
+                    Parameter::Indirect(Box::new(Parameter::list([
+                        absolute(AddressingMode::ZeroPageIndexedIndirectX),
+                        Parameter::X,
+                    ])))
+                }
+                AddressingMode::ProgramCounterRelative => branch(byte),
+                // BBR and BBS
+                AddressingMode::ZeroPage
+                    if meta.parameter_2 == AddressingMode::ProgramCounterRelative =>
+                {
+                    Parameter::list([Parameter::Absolute(value & 0xFF), branch(byte)])
+                }
+                AddressingMode::ZeroPage => zero_page,
+                AddressingMode::ZeroPageIndexedX => Parameter::list([zero_page, Parameter::X]),
+                AddressingMode::ZeroPageIndexedY => Parameter::list([zero_page, Parameter::Y]),
+                AddressingMode::ZeroPageIndirect => Parameter::Indirect(Box::new(zero_page)),
+                AddressingMode::ZeroPageIndexedIndirectX => {
+                    Parameter::Indirect(Box::new(Parameter::list([zero_page, Parameter::X])))
+                }
+                AddressingMode::ZeroPageIndirectIndexedY => {
+                    Parameter::list([Parameter::Indirect(Box::new(zero_page)), Parameter::Y])
+                }
+            };
+            meta.opcode.with(parameter)
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn test_disassemble_round_trip(
+        program in prop::collection::vec((0..OPCODES.len(), any::<u16>(), any::<u8>()), 1..64)
+            .prop_map(build_program)
+    ) {
+        let mut binary = vec![];
+        assemble_at(&program, ORIGIN, &mut binary).unwrap();
+        let source = disassemble(&binary, ORIGIN)
+            .iter()
+            .map(|instruction| instruction.source(&HashMap::new()).unwrap())
+            .join("\n");
+        prop_assert_eq!(parse(&source).unwrap(), program);
+    }
+}
@@ -0,0 +1,125 @@
+//! Klaus Dormann's functional, extended opcode, interrupt and decimal tests, see `tests/dormann/README.md`.
+//!
+//! Ignored by default because the binaries are not part of the repository.
+
+use cody_emulator::cpu::Cpu;
+use cody_emulator::interrupt::Interrupt;
+use cody_emulator::memory::Memory;
+use cody_emulator::memory::contiguous::Contiguous;
+use std::path::Path;
+
+const START: u16 = 0x0400;
+/// Upper bound for a run, the functional test takes about 100 million cycles
+const MAX_CYCLES: usize = 500_000_000;
+/// Feedback register of the interrupt test, bit 0 drives IRQ and bit 1 NMI
+const INTERRUPT_PORT: u16 = 0xBFFC;
+/// Start of Bruce Clark's decimal test
+const DECIMAL_START: u16 = 0x0200;
+/// The decimal test leaves 0 here if it passed and 1 if it failed
+const DECIMAL_ERROR: u16 = 0x000B;
+
+/// 64K image of a test, loaded at 0x0000
+fn image(name: &str) -> Contiguous {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/dormann")
+        .join(name);
+    let data = std::fs::read(&path)
+        .unwrap_or_else(|e| panic!("{}: {e}, see tests/dormann/README.md", path.display()));
+    let mut memory = Contiguous::new_ram(0x10000);
+    memory.force_write_all(0x0000, &data);
+    memory
+}
+
+/// Run from [`START`] until the program counter stops moving, the tests jump to themselves on success and failure
+fn run_until_trapped<M: Memory>(memory: M) -> u16 {
+    let mut cpu = Cpu::new(memory);
+    cpu.pc = START;
+    loop {
+        let pc = cpu.pc;
+        cpu.step_instruction();
+        if cpu.pc == pc {
+            return pc;
+        }
+        assert!(
+            cpu.cycle() < MAX_CYCLES,
+            "no trap within {MAX_CYCLES} cycles, pc=0x{pc:04X}"
+        );
+    }
+}
+
+/// RAM with the interrupt test's feedback register driving the interrupt lines
+struct InterruptInjector {
+    memory: Contiguous,
+}
+
+impl Memory for InterruptInjector {
+    fn read_u8(&mut self, address: u16) -> u8 {
+        self.memory.read_u8(address)
+    }
+
+    fn write_u8(&mut self, address: u16, value: u8) {
+        self.memory.write_u8(address, value);
+    }
+
+    fn update(&mut self, _cycle: usize) -> Interrupt {
+        let port = self.memory.read_u8(INTERRUPT_PORT);
+        let irq = if port & 0x01 != 0 {
+            Interrupt::irq()
+        } else {
+            Interrupt::none()
+        };
+        let nmi = if port & 0x02 != 0 {
+            Interrupt::nmi()
+        } else {
+            Interrupt::none()
+        };
+        irq.or(nmi)
+    }
+}
+
+#[test]
+#[ignore]
+fn functional_test() {
+    let pc = run_until_trapped(image("6502_functional_test.bin"));
+    assert_eq!(pc, 0x3469, "trapped at 0x{pc:04X}");
+}
+
+#[test]
+#[ignore]
+fn extended_opcodes_test() {
+    let pc = run_until_trapped(image("65C02_extended_opcodes_test.bin"));
+    assert_eq!(pc, 0x24F1, "trapped at 0x{pc:04X}");
+}
+
+#[test]
+#[ignore]
+fn interrupt_test() {
+    let mut memory = image("6502_interrupt_test.bin");
+    memory.force_write_u8(INTERRUPT_PORT, 0);
+    let pc = run_until_trapped(InterruptInjector { memory });
+    assert_eq!(pc, 0x06F5, "trapped at 0x{pc:04X}");
+}
+
+#[test]
+#[ignore]
+fn decimal_test() {
+    let mut cpu = Cpu::new(image("6502_decimal_test.bin"));
+    cpu.pc = DECIMAL_START;
+    // the test ends with STP
+    while cpu.is_running() {
+        cpu.step_instruction();
+        assert!(
+            cpu.cycle() < MAX_CYCLES,
+            "no STP within {MAX_CYCLES} cycles, pc=0x{:04X}",
+            cpu.pc
+        );
+    }
+    let error = cpu.memory.read_u8(DECIMAL_ERROR);
+    assert_eq!(
+        error,
+        0,
+        "failed at N1=0x{:02X} N2=0x{:02X}",
+        cpu.memory.read_u8(0x0000),
+        cpu.memory.read_u8(0x0001)
+    );
+}
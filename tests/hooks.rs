@@ -0,0 +1,65 @@
+use cody_emulator::cpu;
+use cody_emulator::cpu::{Cpu, HookAction};
+use cody_emulator::memory::Memory;
+use cody_emulator::memory::contiguous::Contiguous;
+
+fn cpu(program: &[u8]) -> Cpu<Contiguous> {
+    let mut memory = Contiguous::new_ram(0x10000);
+    memory.force_write_all(0x0200, program);
+    memory.write_u16(cpu::RESET_VECTOR, 0x0200);
+    Cpu::new(memory)
+}
+
+// LDA #1, LDX #2, LDY #3, STP
+const PROGRAM: [u8; 7] = [0xA9, 0x01, 0xA2, 0x02, 0xA0, 0x03, 0xDB];
+
+#[test]
+fn pre_hook_skips_and_delays() {
+    let mut cpu = cpu(&PROGRAM);
+    let start = cpu.cycle();
+    cpu.set_pre_instruction_hook(|cpu| match cpu.pc {
+        // replace LDX #2 by its effect
+        0x0202 => {
+            cpu.x = 0x20;
+            cpu.pc += 2;
+            HookAction::Skip(5)
+        }
+        0x0204 => HookAction::Delay(10),
+        _ => HookAction::Continue,
+    });
+
+    assert_eq!(cpu.step_instruction(), 2);
+    assert_eq!(cpu.step_instruction(), 5);
+    assert_eq!(cpu.last_instruction(), None);
+    assert_eq!(cpu.step_instruction(), 2 + 10);
+    cpu.run();
+    assert_eq!((cpu.a, cpu.x, cpu.y), (1, 0x20, 3));
+    assert_eq!(cpu.cycle() - start, 2 + 5 + 12 + 3);
+}
+
+#[test]
+fn post_hook_sees_the_executed_instruction() {
+    let mut cpu = cpu(&PROGRAM);
+    cpu.set_post_instruction_hook(|cpu| {
+        // count instructions in zero page
+        let count = cpu.memory.read_u8(0x00);
+        cpu.memory.write_u8(0x00, count + 1);
+        if cpu.last_instruction() == Some((0x0202, 0xA2)) {
+            HookAction::Stop
+        } else {
+            HookAction::Delay(1)
+        }
+    });
+
+    assert_eq!(cpu.step_instruction(), 2 + 1);
+    assert_eq!(cpu.step_instruction(), 2);
+    assert!(!cpu.is_running());
+    assert_eq!(cpu.memory.read_u8(0x00), 2);
+    assert_eq!(cpu.y, 0);
+
+    cpu.clear_instruction_hooks();
+    cpu.reset();
+    cpu.run();
+    assert_eq!(cpu.memory.read_u8(0x00), 2);
+    assert_eq!(cpu.y, 3);
+}
@@ -1,2 +1,7 @@
 pub mod assembler;
+pub mod dormann;
+pub mod hooks;
+pub mod lockstep;
 pub mod opcode;
+pub mod via;
+pub mod vid;
@@ -0,0 +1,106 @@
+use cody_emulator::cpu;
+use cody_emulator::cpu::Engine;
+use cody_emulator::device::via::{CodyKeyCode, KeyState};
+use cody_emulator::lockstep::{Lockstep, MachineLockstep};
+use cody_emulator::machine::Machine;
+use cody_emulator::memory::Memory;
+use cody_emulator::memory::contiguous::Contiguous;
+use cody_emulator::replay::InputEvent;
+
+fn machine(program: &[u8]) -> Contiguous {
+    let mut memory = Contiguous::new_ram(0x10000);
+    memory.force_write_all(0x0200, program);
+    memory.write_u16(cpu::RESET_VECTOR, 0x0200);
+    memory
+}
+
+#[test]
+fn identical_machines_agree() {
+    // LDA #1, STA $10, INC $10, STP
+    let program = [0xA9, 0x01, 0x85, 0x10, 0xE6, 0x10, 0xDB];
+    let mut lockstep = Lockstep::new(machine(&program), machine(&program));
+    lockstep.run(100).unwrap();
+    assert_eq!(lockstep.instructions(), 4);
+}
+
+#[test]
+fn first_divergent_instruction_is_reported() {
+    // same as above, but the right machine stores to $11
+    let left = [0xA9, 0x01, 0x85, 0x10, 0xE6, 0x10, 0xDB];
+    let right = [0xA9, 0x01, 0x85, 0x11, 0xE6, 0x10, 0xDB];
+    let mut lockstep = Lockstep::new(machine(&left), machine(&right));
+    let divergence = lockstep.run(100).unwrap_err();
+    assert_eq!(divergence.instruction, 1);
+    assert_eq!(divergence.pc, 0x0202);
+    assert_eq!(divergence.left.pc, divergence.right.pc);
+    assert_ne!(divergence.left.accesses, divergence.right.accesses);
+}
+
+fn cody(program: &[u8], engine: Engine) -> Machine {
+    Machine::builder()
+        .rom(program.to_vec())
+        .engine(engine)
+        .build()
+        .unwrap()
+}
+
+/// Press and release A while the program runs
+fn input() -> Vec<InputEvent> {
+    let mut key_state = KeyState::default();
+    let released = key_state.rows();
+    key_state.set_pressed(CodyKeyCode::KeyA, true);
+    vec![
+        InputEvent {
+            cycle: 40_000,
+            rows: key_state.rows(),
+        },
+        InputEvent {
+            cycle: 90_000,
+            rows: released,
+        },
+    ]
+}
+
+#[test]
+fn engines_agree_over_frames() {
+    let program = [
+        // copy the routine to $0200: LDX #12, LDA routine,X, STA $0200,X, DEX, BPL copy
+        &[
+            0xA2, 0x0C, 0xBD, 0x20, 0xE0, 0x9D, 0x00, 0x02, 0xCA, 0x10, 0xF7,
+        ][..],
+        // start timer 1 free-running: LDA #$40, STA ACR, LDA #$FF, STA T1CL, STA T1CH
+        &[
+            0xA9, 0x40, 0x8D, 0x0B, 0x9F, 0xA9, 0xFF, 0x8D, 0x04, 0x9F, 0x8D, 0x05, 0x9F,
+        ],
+        // loop: JSR $0200, INC $0201 to patch the operand of the routine, BRA loop
+        &[0x20, 0x00, 0x02, 0xEE, 0x01, 0x02, 0x80, 0xF8],
+        // routine: LDA #0, EOR T1CL, EOR IORA, STA $A000,Y, INY, RTS
+        &[
+            0xA9, 0x00, 0x4D, 0x04, 0x9F, 0x4D, 0x01, 0x9F, 0x99, 0x00, 0xA0, 0xC8, 0x60,
+        ],
+    ]
+    .concat();
+    let mut lockstep = MachineLockstep::new(
+        cody(&program, Engine::Interpreter),
+        cody(&program, Engine::Cached),
+        input(),
+    );
+    lockstep.run(10).unwrap();
+    assert_eq!(lockstep.frames(), 10);
+}
+
+#[test]
+fn first_divergent_frame_is_reported() {
+    // INC $10, BRA -4 and INC $11, BRA -4
+    let left = [0xE6, 0x10, 0x80, 0xFC];
+    let right = [0xE6, 0x11, 0x80, 0xFC];
+    let mut lockstep = MachineLockstep::new(
+        cody(&left, Engine::Interpreter),
+        cody(&right, Engine::Interpreter),
+        vec![],
+    );
+    let divergence = lockstep.run(10).unwrap_err();
+    assert_eq!(divergence.frame, 0);
+    assert_eq!(divergence.left.cpu, divergence.right.cpu);
+    assert_ne!(divergence.left.hash, divergence.right.hash);
+}
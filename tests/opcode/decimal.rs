@@ -0,0 +1,55 @@
+//! Decimal mode on every valid BCD operand, compared to decimal arithmetic on the numbers they encode.
+//!
+//! Invalid BCD operands are covered by Bruce Clark's decimal test in `tests/dormann.rs`.
+
+use cody_emulator::cpu;
+use cody_emulator::cpu::Cpu;
+use cody_emulator::memory::Memory;
+use cody_emulator::memory::contiguous::Contiguous;
+
+/// Run `opcode` with an immediate operand in decimal mode, returns the accumulator and the carry
+fn execute(cpu: &mut Cpu<Contiguous>, opcode: u8, a: u8, b: u8, c: bool) -> (u8, bool) {
+    cpu.memory.force_write_all(0x0200, &[opcode, b]);
+    cpu.pc = 0x0200;
+    cpu.a = a;
+    cpu.p.set_carry(c);
+    cpu.p.set_decimal_mode(true);
+    // one cycle more than in binary mode
+    assert_eq!(cpu.step_instruction(), 3);
+
+    // the 65C02 sets N and Z from the decimal result
+    assert_eq!(cpu.p.negative(), cpu.a & 0x80 != 0);
+    assert_eq!(cpu.p.zero(), cpu.a == 0);
+    (cpu.a, cpu.p.carry())
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+#[test]
+fn decimal_arithmetic_on_valid_bcd() {
+    let mut memory = Contiguous::new_ram(0x10000);
+    memory.write_u16(cpu::RESET_VECTOR, 0x0200);
+    let mut cpu = Cpu::new(memory);
+    for x in 0..100 {
+        for y in 0..100 {
+            for c in [false, true] {
+                let sum = x + y + c as u8;
+                assert_eq!(
+                    execute(&mut cpu, 0x69, to_bcd(x), to_bcd(y), c),
+                    (to_bcd(sum % 100), sum >= 100),
+                    "{x} + {y} + {c}"
+                );
+
+                let difference = x as i16 - y as i16 - !c as i16;
+                assert_eq!(
+                    execute(&mut cpu, 0xE9, to_bcd(x), to_bcd(y), c),
+                    (to_bcd(difference.rem_euclid(100) as u8), difference >= 0),
+                    "{x} - {y} - {}",
+                    !c
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use cody_emulator::cpu;
+use cody_emulator::cpu::{Cpu, IllegalOpcodePolicy};
+use cody_emulator::memory::Memory;
+use cody_emulator::memory::contiguous::Contiguous;
+
+fn run_program(program: &[u8], policy: IllegalOpcodePolicy) -> Cpu<Contiguous> {
+    let mut memory = Contiguous::new_ram(0x10000);
+    memory.force_write_all(0x0200, program);
+    memory.write_u16(cpu::RESET_VECTOR, 0x0200);
+    let mut cpu = Cpu::new(memory);
+    cpu.set_illegal_opcode_policy(policy);
+    cpu.run();
+    cpu
+}
+
+#[test]
+fn nop_skips_and_counts() {
+    // 0x03 is undefined, 0xA9 0x01 = LDA #1, 0xDB = STP
+    let cpu = run_program(&[0x03, 0x03, 0xA9, 0x01, 0xDB], IllegalOpcodePolicy::Nop);
+    assert_eq!(cpu.a, 1);
+    assert_eq!(cpu.illegal_opcodes().get(&0x03), Some(&2));
+}
+
+#[test]
+fn trap_halts_at_opcode() {
+    let cpu = run_program(&[0xEA, 0x03, 0xA9, 0x01, 0xDB], IllegalOpcodePolicy::Trap);
+    assert!(!cpu.is_running());
+    assert_eq!(cpu.pc, 0x0201);
+    assert_eq!(cpu.a, 0);
+    assert_eq!(cpu.illegal_opcodes().get(&0x03), Some(&1));
+}
+
+#[test]
+fn nmos_lax() {
+    // LDX #0, LAX ($10,X) with ($10) = $0300 containing 0x42, STP
+    let mut memory = Contiguous::new_ram(0x10000);
+    memory.force_write_all(0x0200, &[0xA2, 0x00, 0xA3, 0x10, 0xDB]);
+    memory.force_write_u16(0x0010, 0x0300);
+    memory.force_write_u8(0x0300, 0x42);
+    memory.write_u16(cpu::RESET_VECTOR, 0x0200);
+    let mut cpu = Cpu::new(memory);
+    cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Nmos);
+    cpu.run();
+
+    assert_eq!(cpu.a, 0x42);
+    assert_eq!(cpu.x, 0x42);
+}
+
+#[test]
+fn nmos_unsupported_falls_back_to_nop() {
+    // 0x93 (SHA) is unstable and not emulated
+    let cpu = run_program(&[0x93, 0xA9, 0x01, 0xDB], IllegalOpcodePolicy::Nmos);
+    assert_eq!(cpu.a, 1);
+}
@@ -1,4 +1,9 @@
 pub mod adc;
 pub mod cmp;
+pub mod decimal;
+pub mod illegal;
+pub mod reset;
+pub mod rmw;
 pub mod sbc;
 pub mod sbc_decimal;
+pub mod wai;
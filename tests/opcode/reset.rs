@@ -0,0 +1,34 @@
+use cody_emulator::cpu;
+use cody_emulator::cpu::Cpu;
+use cody_emulator::memory::Memory;
+use cody_emulator::memory::contiguous::Contiguous;
+use cody_emulator::memory::logging::{LoggingMemory, MemoryAccess};
+
+#[test]
+fn reset_sequence() {
+    let mut memory = Contiguous::new_ram(0x10000);
+    memory.write_u16(cpu::RESET_VECTOR, 0x0200);
+    let mut cpu = Cpu::new(LoggingMemory::new(memory));
+    assert_eq!(cpu.pc, 0x0200);
+    assert_eq!(cpu.s, cpu::INITIAL_STACK_POINTER);
+    assert_eq!(cpu.cycle(), 7);
+
+    cpu.a = 0x42;
+    cpu.memory.reset_log();
+    cpu.reset();
+    assert_eq!(cpu.a, 0x42);
+    assert_eq!(cpu.s, cpu::INITIAL_STACK_POINTER - 3);
+    assert_eq!(cpu.cycle(), 2 * 7);
+    assert_eq!(
+        cpu.memory.log(),
+        [
+            MemoryAccess::read(0x0200, 0),
+            MemoryAccess::read(0x0200, 0),
+            MemoryAccess::read(0x01FD, 0),
+            MemoryAccess::read(0x01FC, 0),
+            MemoryAccess::read(0x01FB, 0),
+            MemoryAccess::read(0xFFFC, 0x00),
+            MemoryAccess::read(0xFFFD, 0x02),
+        ]
+    );
+}
@@ -0,0 +1,60 @@
+use cody_emulator::cpu;
+use cody_emulator::cpu::Cpu;
+use cody_emulator::memory::Memory;
+use cody_emulator::memory::contiguous::Contiguous;
+use cody_emulator::memory::logging::{LoggingMemory, MemoryAccess};
+
+fn step(program: &[u8], x: u8) -> (u8, Vec<MemoryAccess>) {
+    let mut memory = Contiguous::new_ram(0x10000);
+    memory.force_write_all(0x0200, program);
+    memory.force_write_u8(0x0310, 0x41);
+    memory.force_write_u8(0x0010, 0x41);
+    memory.write_u16(cpu::RESET_VECTOR, 0x0200);
+    let mut cpu = Cpu::new(LoggingMemory::new(memory));
+    cpu.x = x;
+    cpu.memory.reset_log();
+    let cycles = cpu.step_instruction();
+    (cycles, cpu.memory.log().to_vec())
+}
+
+#[test]
+fn inc_zero_page_reads_twice() {
+    // INC $10
+    let (cycles, log) = step(&[0xE6, 0x10], 0);
+    assert_eq!(cycles, 5);
+    assert_eq!(
+        log,
+        [
+            MemoryAccess::read(0x0200, 0xE6),
+            MemoryAccess::read(0x0201, 0x10),
+            MemoryAccess::read(0x0010, 0x41),
+            MemoryAccess::read(0x0010, 0x41),
+            MemoryAccess::write(0x0010, 0x42),
+        ]
+    );
+}
+
+#[test]
+fn inc_absolute_x_always_takes_index_cycle() {
+    // INC $0300,X
+    let (cycles, log) = step(&[0xFE, 0x00, 0x03], 0x10);
+    assert_eq!(cycles, 7);
+    assert_eq!(log.len(), 7);
+    assert_eq!(log[3], MemoryAccess::read(0x0202, 0x03));
+    assert_eq!(log[6], MemoryAccess::write(0x0310, 0x42));
+}
+
+#[test]
+fn asl_absolute_x_index_cycle_on_page_cross() {
+    // ASL $0300,X
+    let (cycles, log) = step(&[0x1E, 0x00, 0x03], 0x10);
+    assert_eq!(cycles, 6);
+    assert_eq!(log.len(), 6);
+    assert_eq!(log[5], MemoryAccess::write(0x0310, 0x82));
+
+    // ASL $02F0,X
+    let (cycles, log) = step(&[0x1E, 0xF0, 0x02], 0x20);
+    assert_eq!(cycles, 7);
+    assert_eq!(log.len(), 7);
+    assert_eq!(log[3], MemoryAccess::read(0x0202, 0x02));
+}
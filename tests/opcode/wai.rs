@@ -0,0 +1,85 @@
+use cody_emulator::cpu;
+use cody_emulator::cpu::Cpu;
+use cody_emulator::interrupt::Interrupt;
+use cody_emulator::memory::Memory;
+use cody_emulator::memory::contiguous::Contiguous;
+
+/// RAM with a device that holds the IRQ line from `irq_from` on
+struct IrqAt {
+    memory: Contiguous,
+    irq_from: usize,
+}
+
+impl Memory for IrqAt {
+    fn read_u8(&mut self, address: u16) -> u8 {
+        self.memory.read_u8(address)
+    }
+
+    fn write_u8(&mut self, address: u16, value: u8) {
+        self.memory.write_u8(address, value);
+    }
+
+    fn update(&mut self, cycle: usize) -> Interrupt {
+        if cycle >= self.irq_from {
+            Interrupt::irq()
+        } else {
+            Interrupt::none()
+        }
+    }
+}
+
+fn cpu(program: &[u8], irq_from: usize) -> Cpu<IrqAt> {
+    let mut memory = Contiguous::new_ram(0x10000);
+    memory.force_write_all(0x0200, program);
+    // IRQ handler: LDA #2, STP
+    memory.force_write_all(0x0300, &[0xA9, 0x02, 0xDB]);
+    memory.write_u16(cpu::RESET_VECTOR, 0x0200);
+    memory.write_u16(cpu::IRQ_VECTOR, 0x0300);
+    Cpu::new(IrqAt { memory, irq_from })
+}
+
+#[test]
+fn wai_wakes_with_irq_disabled() {
+    // SEI, WAI, LDA #1, STP
+    let mut cpu = cpu(&[0x78, 0xCB, 0xA9, 0x01, 0xDB], 100);
+    cpu.step_instruction();
+    cpu.step_instruction();
+    while cpu.cycle() < 100 {
+        assert!(cpu.is_waiting());
+        assert_eq!(cpu.step_instruction(), 1);
+    }
+    // continues with LDA #1 without taking the interrupt
+    assert_eq!(cpu.step_instruction(), 2);
+    assert_eq!(cpu.a, 1);
+    assert_eq!(cpu.cycle(), 100 + 2);
+    cpu.run();
+    assert_eq!(cpu.irq_count(), 0);
+    assert_eq!(cpu.a, 1);
+}
+
+#[test]
+fn wai_takes_enabled_irq() {
+    // CLI, WAI, LDA #1, STP
+    let mut cpu = cpu(&[0x58, 0xCB, 0xA9, 0x01, 0xDB], 100);
+    cpu.step_instruction();
+    cpu.step_instruction();
+    while cpu.is_waiting() {
+        cpu.step_instruction();
+    }
+    assert_eq!(cpu.irq_count(), 1);
+    // interrupt sequence and LDA #2
+    assert_eq!(cpu.a, 2);
+    assert_eq!(cpu.cycle(), 100 + 7 + 2);
+}
+
+#[test]
+fn reset_resumes_after_stp() {
+    // LDA #1, STP
+    let mut cpu = cpu(&[0xA9, 0x01, 0xDB], usize::MAX);
+    cpu.run();
+    assert!(!cpu.is_running());
+    assert_eq!(cpu.step_instruction(), 0);
+    cpu.reset();
+    assert!(cpu.is_running());
+    assert_eq!(cpu.pc, 0x0200);
+}
@@ -0,0 +1,41 @@
+//! VIA timers started by a program and polled after a counted number of NOPs.
+
+use cody_emulator::machine::Machine;
+use cody_emulator::memory::Memory;
+
+/// Start timer 1 with `latch` in the mode of `acr`, run `nops` NOPs and store the flags in $00.
+///
+/// The start and the read of the flags are absolute accesses 4 + 2 * `nops` cycles apart.
+fn flags_after(acr: u8, latch: u16, nops: usize) -> u8 {
+    let [lo, hi] = latch.to_le_bytes();
+    // LDA #acr, STA ACR, LDA #lo, STA T1CL, LDA #hi, STA T1CH
+    let mut program = vec![
+        0xA9, acr, 0x8D, 0x0B, 0x9F, 0xA9, lo, 0x8D, 0x04, 0x9F, 0xA9, hi, 0x8D, 0x05, 0x9F,
+    ];
+    program.extend(std::iter::repeat_n(0xEA, nops));
+    // LDA IFR, STA $00, STP
+    program.extend([0xAD, 0x0D, 0x9F, 0x85, 0x00, 0xDB]);
+
+    let mut machine = Machine::builder().rom(program).build().unwrap();
+    while machine.cpu.is_running() {
+        machine.step_instruction();
+    }
+    machine.cpu.memory.read_u8(0x00)
+}
+
+#[test]
+fn one_shot_time_out() {
+    // the flag is set N + 1.5 cycles after the write that started the timer
+    for latch in [0x10, 0x11, 0x100] {
+        let nops = (latch as usize + 2 - 4).div_ceil(2);
+        assert_eq!(flags_after(0x00, latch, nops - 1) & 0x40, 0, "{latch}");
+        assert_eq!(flags_after(0x00, latch, nops) & 0x40, 0x40, "{latch}");
+    }
+}
+
+#[test]
+fn free_running_time_out() {
+    // the first time out of a free-running timer comes as late as in one-shot mode
+    assert_eq!(flags_after(0x40, 0x10, 6) & 0x40, 0);
+    assert_eq!(flags_after(0x40, 0x10, 7) & 0x40, 0x40);
+}
@@ -0,0 +1,181 @@
+//! Rendered frames compared against the PNGs in `tests/golden`, see `tests/golden/README.md`.
+
+use cody_emulator::device::vid::{
+    BORDER_Y, Border, Color, Firmware, HEIGHT, Palette, WIDTH, render_pixels,
+};
+use cody_emulator::memory::Memory;
+use cody_emulator::memory::contiguous::Contiguous;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+/// Video registers and memory shared by all scenes: screen at A400, characters at A000 and color memory at AC00
+fn scene(control: u8) -> Contiguous {
+    let mut memory = Contiguous::new_ram(0x10000);
+    memory.write_u8(0xD001, control);
+    memory.write_u8(0xD002, 0x3B); // dark gray border
+    memory.write_u8(0xD003, 0x10);
+    memory.write_u8(0xD005, 0x74); // purple and yellow as shared multicolor colors
+    for character in 0..8u16 {
+        // stripes and checkers of different widths
+        for row in 0..8 {
+            let pattern =
+                [0xFF, 0xAA, 0xCC, 0xF0, 0x81, 0x3C, 0x55, 0x0F][(character + row) as usize % 8];
+            memory.write_u8(0xA000 + 8 * character + row, pattern);
+        }
+    }
+    for tile in 0..1000u16 {
+        memory.write_u8(0xA400 + tile, (tile % 7) as u8 + 1);
+        memory.write_u8(
+            0xAC00 + tile,
+            ((tile % 15) as u8 + 1) | ((((tile / 40) % 16) as u8) << 4),
+        );
+    }
+    memory
+}
+
+/// Sprite 0 at the top left and sprite 1 partly hidden below it, both from the default sprite bank at D080
+fn add_sprites(memory: &mut Contiguous) {
+    memory.write_u8(0xD006, 0x05); // green as common sprite color
+    for (index, (x, y, colors, pointer)) in [(30, 40, 0x2A, 0x40), (36, 50, 0x6E, 0x41)]
+        .into_iter()
+        .enumerate()
+    {
+        let start = 0xD080 + 4 * index as u16;
+        memory.force_write_all(start, &[x, y, colors, pointer]);
+    }
+    for (offset, byte) in (0..2 * 0x40)
+        .map(|i| [0x1B, 0xE4, 0x00, 0xFF][i % 4])
+        .enumerate()
+    {
+        memory.write_u8(0xB000 + offset as u16, byte);
+    }
+}
+
+fn render(memory: &mut Contiguous) -> Vec<Color> {
+    let mut pixels = vec![Color::default(); (WIDTH * HEIGHT) as usize];
+    render_pixels(
+        memory,
+        &Palette::default(),
+        Border::Normal,
+        Firmware::default(),
+        &mut pixels,
+    );
+    pixels
+}
+
+/// Compare `pixels` to `tests/golden/<name>.png`, which is written instead if `UPDATE_GOLDEN` is set
+fn assert_golden(name: &str, pixels: &[Color]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"));
+    let actual: &[u8] = bytemuck::cast_slice(pixels);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let file = BufWriter::new(File::create(&path).unwrap());
+        let mut encoder = png::Encoder::new(file, WIDTH, HEIGHT);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(actual).unwrap();
+        eprintln!("Wrote {}", path.display());
+        return;
+    }
+
+    assert!(
+        path.exists(),
+        "{} is missing, run with UPDATE_GOLDEN=1 to create it",
+        path.display()
+    );
+    let decoder = png::Decoder::new(BufReader::new(File::open(&path).unwrap()));
+    let mut reader = decoder.read_info().unwrap();
+    let (width, height) = (reader.info().width, reader.info().height);
+    assert_eq!(
+        (width, height),
+        (WIDTH, HEIGHT),
+        "size of {}",
+        path.display()
+    );
+    let mut expected = vec![0; (4 * width * height) as usize];
+    reader.next_frame(&mut expected).unwrap();
+    let first_difference = expected
+        .chunks(4)
+        .zip(actual.chunks(4))
+        .position(|(expected, actual)| expected != actual);
+    if let Some(index) = first_difference {
+        panic!(
+            "{name} differs from {} first at x={}, y={}, rerun with UPDATE_GOLDEN=1 if the change is intended",
+            path.display(),
+            index % WIDTH as usize,
+            index / WIDTH as usize
+        );
+    }
+}
+
+#[test]
+fn multicolor_text() {
+    let mut memory = scene(0x00);
+    add_sprites(&mut memory);
+    assert_golden("multicolor_text", &render(&mut memory));
+}
+
+#[test]
+fn multicolor_fine_scroll() {
+    let mut memory = scene(0x06);
+    memory.write_u8(0xD004, 0x23); // 2 pixels right, 3 lines down
+    add_sprites(&mut memory);
+    assert_golden("multicolor_fine_scroll", &render(&mut memory));
+}
+
+#[test]
+fn multicolor_row_effects() {
+    let mut memory = scene(0x0E);
+    add_sprites(&mut memory);
+    // from tile row 5 on scroll by 1 pixel, from tile row 10 on use other colors and from 15 on hide the sprites
+    memory.force_write_all(
+        0xD040,
+        &[0x80 | 0x20 | 5, 0x80 | 0x40 | 10, 0x80 | 0x60 | 15],
+    );
+    memory.force_write_all(0xD060, &[0x11, 0x9C, 0x40]);
+    assert_golden("multicolor_row_effects", &render(&mut memory));
+}
+
+#[test]
+fn hires_text() {
+    let mut memory = scene(0x26);
+    // neither fine scrolling nor sprites show up in hires mode
+    memory.write_u8(0xD004, 0x23);
+    add_sprites(&mut memory);
+    let pixels = render(&mut memory);
+
+    assert!(pixels == render(&mut scene(0x20)));
+    assert_golden("hires_text", &pixels);
+}
+
+#[test]
+fn hires_row_effects() {
+    let mut memory = scene(0x28);
+    add_sprites(&mut memory);
+    // from tile row 8 on show the screen at A800, which is all character 0
+    memory.force_write_all(0xD040, &[0x80 | 8, 0x80 | 0x40 | 12]);
+    memory.force_write_all(0xD060, &[0x20, 0x9C]);
+    let pixels = render(&mut memory);
+
+    // the switch happens exactly at the first line of the tile row
+    let without = render(&mut scene(0x20));
+    let line = |pixels: &[Color], y: u32| {
+        let start = ((BORDER_Y + y) * WIDTH) as usize;
+        pixels[start..start + WIDTH as usize].to_vec()
+    };
+    assert!(line(&pixels, 8 * 8 - 1) == line(&without, 8 * 8 - 1));
+    assert!(line(&pixels, 8 * 8) != line(&without, 8 * 8));
+    assert_golden("hires_row_effects", &pixels);
+}
+
+#[test]
+fn hires_bitmap() {
+    let mut memory = scene(0x30);
+    for offset in 0..8000u16 {
+        memory.write_u8(0xA400 + offset, (offset.wrapping_mul(37) >> 3) as u8);
+    }
+    assert_golden("hires_bitmap", &render(&mut memory));
+}